@@ -0,0 +1,63 @@
+use std::fs;
+use std::path::Path;
+use crate::{
+    commands::parse::{load_telemetry, process_telemetry},
+    formats::{csv_mapping::CsvMapping, gpx::GpxTrack, timing::TimestampPolicy},
+    processing::Processing,
+    telemetry::TelemetryData,
+    error::OverlogError,
+};
+
+#[allow(clippy::too_many_arguments)]
+pub async fn convert_telemetry(
+    input: String,
+    output: String,
+    from: Option<String>,
+    to: Option<String>,
+    track: Option<GpxTrack>,
+    csv_mapping: Option<CsvMapping>,
+    columns: Vec<String>,
+    times: TimestampPolicy,
+    processing: Processing,
+) -> Result<(), OverlogError> {
+    let output_path = Path::new(&output);
+    let target = output_format(output_path, to)?;
+    if !columns.is_empty() && target != "csv" {
+        return Err(OverlogError::InvalidInput("--output-column only applies to CSV output".to_string()));
+    }
+
+    let mut telemetry = load_telemetry(Path::new(&input), from, track.as_ref(), csv_mapping.as_ref(), &times)?;
+    process_telemetry(&mut telemetry, &processing)?;
+
+    let bytes = encode(&telemetry, &target, &columns)?;
+    fs::write(output_path, bytes)?;
+    println!("Converted {} points to {}: {}", telemetry.points.len(), target, output);
+    Ok(())
+}
+
+/// Output format from `--to`, or from the extension of the output file
+pub fn output_format(output: &Path, to: Option<String>) -> Result<String, OverlogError> {
+    match to {
+        Some(format) => Ok(format.to_lowercase()),
+        None => output
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(str::to_lowercase)
+            .ok_or_else(|| OverlogError::InvalidInput(format!("Can't tell the output format of {}, use --to", output.display()))),
+    }
+}
+
+/// Encode telemetry in an output format; `columns` selects CSV columns, all
+/// of them when empty
+pub fn encode(telemetry: &TelemetryData, target: &str, columns: &[String]) -> Result<Vec<u8>, OverlogError> {
+    Ok(match target {
+        "gpx" => telemetry.to_gpx().into_bytes(),
+        "csv" => telemetry.to_csv((!columns.is_empty()).then_some(columns))?.into_bytes(),
+        "geojson" => telemetry.to_geojson().into_bytes(),
+        "kml" => telemetry.to_kml().into_bytes(),
+        "kmz" => telemetry.to_kmz()?,
+        "fit" => telemetry.to_fit(),
+        "json" => serde_json::to_string_pretty(telemetry)?.into_bytes(),
+        _ => return Err(OverlogError::UnsupportedFormat(target.to_string())),
+    })
+}
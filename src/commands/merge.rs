@@ -0,0 +1,63 @@
+use std::fs;
+use std::path::Path;
+use crate::{
+    commands::parse::{load_telemetry, process_telemetry},
+    formats::timing::TimestampPolicy,
+    processing::{
+        merge::{MergeOptions, MergeSource, Preference, SourceValue},
+        Processing,
+    },
+    telemetry::TelemetryData,
+    error::OverlogError,
+};
+
+/// Merge telemetry files, highest priority first, and write the combined
+/// log as JSON
+pub async fn merge_telemetry(
+    inputs: Vec<String>,
+    output: Option<String>,
+    offsets: Vec<SourceValue>,
+    drifts: Vec<SourceValue>,
+    preferences: Vec<Preference>,
+    times: TimestampPolicy,
+    processing: Processing,
+) -> Result<(), OverlogError> {
+    if let Some(setting) = offsets.iter().chain(&drifts).find(|s| s.source >= inputs.len()) {
+        return Err(OverlogError::InvalidInput(format!(
+            "There is no input {}, only {}",
+            setting.source + 1,
+            inputs.len()
+        )));
+    }
+
+    let mut sources = Vec::new();
+    for (i, input) in inputs.iter().enumerate() {
+        let mut source = MergeSource::from(load_telemetry(Path::new(input), None, None, None, &times)?);
+        if source.telemetry.metadata.source.is_empty() {
+            source.telemetry.metadata.source = input.clone();
+        }
+        for offset in offsets.iter().filter(|s| s.source == i) {
+            source.offset = offset.value;
+        }
+        for drift in drifts.iter().filter(|s| s.source == i) {
+            source.drift_ppm = drift.value;
+        }
+        sources.push(source);
+    }
+
+    let options = MergeOptions { interpolation: processing.interpolation.clone(), ..Default::default() }.with_preferences(preferences);
+    let mut telemetry = TelemetryData::merge(sources, &options)?;
+    process_telemetry(&mut telemetry, &processing)?;
+
+    let json_output = serde_json::to_string_pretty(&telemetry)?;
+    match output {
+        Some(output_path) => {
+            fs::write(&output_path, json_output)?;
+            println!("Merged {} files into {} points: {}", inputs.len(), telemetry.points.len(), output_path);
+        }
+        None => {
+            println!("{}", json_output);
+        }
+    }
+    Ok(())
+}
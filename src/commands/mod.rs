@@ -1,5 +1,11 @@
+pub mod convert;
+pub mod merge;
 pub mod parse;
 pub mod render;
+pub mod slice;
 
+pub use convert::convert_telemetry;
+pub use merge::merge_telemetry;
 pub use parse::parse_telemetry;
-pub use render::{render_overlay, burn_overlay}; 
\ No newline at end of file
+pub use render::{render_overlay, burn_overlay};
+pub use slice::{split_telemetry, trim_telemetry}; 
\ No newline at end of file
@@ -1,27 +1,24 @@
 use std::fs;
+use std::io::Read;
 use std::path::Path;
-use crate::{telemetry::TelemetryData, error::OverlogError};
+use crate::{
+    formats::{csv_mapping::CsvMapping, gpx::GpxTrack, registry, stream, timing::TimestampPolicy},
+    processing::Processing,
+    telemetry::{Outlier, OutlierAction, OutlierReason, TelemetryData, TelemetryMetadata, TimeReport},
+    error::OverlogError,
+};
 
 pub async fn parse_telemetry(
     input: String,
     output: Option<String>,
     format: Option<String>,
+    track: Option<GpxTrack>,
+    csv_mapping: Option<CsvMapping>,
+    times: TimestampPolicy,
+    processing: Processing,
 ) -> Result<(), OverlogError> {
-    let input_path = Path::new(&input);
-    
-    if !input_path.exists() {
-        return Err(OverlogError::InvalidInput(format!("Input file not found: {}", input)));
-    }
-    
-    let content = fs::read_to_string(input_path)?;
-    let detected_format = format.unwrap_or_else(|| detect_format(input_path));
-    
-    let telemetry = match detected_format.as_str() {
-        "gpx" => TelemetryData::from_gpx(&content)?,
-        "csv" => TelemetryData::from_csv(&content)?,
-        "json" => TelemetryData::from_json(&content)?,
-        _ => return Err(OverlogError::UnsupportedFormat(detected_format)),
-    };
+    let mut telemetry = load_telemetry(Path::new(&input), format, track.as_ref(), csv_mapping.as_ref(), &times)?;
+    process_telemetry(&mut telemetry, &processing)?;
     
     let json_output = serde_json::to_string_pretty(&telemetry)?;
     
@@ -38,16 +35,115 @@ pub async fn parse_telemetry(
     Ok(())
 }
 
-fn detect_format(path: &Path) -> String {
-    if let Some(extension) = path.extension() {
-        match extension.to_str().unwrap_or("").to_lowercase().as_str() {
-            "gpx" => "gpx".to_string(),
-            "csv" => "csv".to_string(),
-            "json" => "json".to_string(),
-            "tcx" => "tcx".to_string(),
-            _ => "unknown".to_string(),
+/// Read a telemetry file in the given format, detecting it from the
+/// contents and extension when not specified
+///
+/// Times that were not read from the log as absolute times are reported on
+/// stderr.
+pub fn load_telemetry(
+    input_path: &Path,
+    format: Option<String>,
+    track: Option<&GpxTrack>,
+    csv_mapping: Option<&CsvMapping>,
+    times: &TimestampPolicy,
+) -> Result<TelemetryData, OverlogError> {
+    if !input_path.exists() {
+        return Err(OverlogError::InvalidInput(format!("Input file not found: {}", input_path.display())));
+    }
+    
+    let registry = registry::registry();
+    let reader = stream::open(input_path)?;
+    
+    // A mapping spec implies a CSV layout of its own
+    let (parser, mut reader) = match (format, csv_mapping) {
+        (Some(name), _) => (registry.get(&name).ok_or(OverlogError::UnsupportedFormat(name))?, reader),
+        (None, Some(_)) => (registry.get("csv").ok_or_else(|| OverlogError::UnsupportedFormat("csv".to_string()))?, reader),
+        (None, None) => registry.detect_reader(reader, stream::extension(input_path).as_deref())?,
+    };
+    tracing::debug!("Reading {} as {}", input_path.display(), parser.name());
+    
+    let mut telemetry = match (parser.name(), csv_mapping) {
+        ("gpx", _) => {
+            let mut content = String::new();
+            reader.read_to_string(&mut content)?;
+            TelemetryData::from_gpx_with(&content, track, times)?
+        }
+        ("srt", _) => {
+            let mut content = String::new();
+            reader.read_to_string(&mut content)?;
+            TelemetryData::from_dji_srt_with(&content, times)?
+        }
+        ("csv", Some(mapping)) => TelemetryData::from_csv_reader(reader, mapping)?,
+        _ => parser.parse_reader(reader)?,
+    };
+    telemetry.apply_timestamp_policy(times);
+    report_times(&telemetry.metadata.times, telemetry.points.len());
+    Ok(telemetry)
+}
+
+/// Run the processing passes, reporting rejected outliers on stderr
+pub fn process_telemetry(telemetry: &mut TelemetryData, processing: &Processing) -> Result<(), OverlogError> {
+    let known = telemetry.metadata.outliers.len();
+    processing.apply(telemetry)?;
+    if telemetry.metadata.outliers.len() > known {
+        report_outliers(&telemetry.metadata.outliers);
+    }
+    if processing.laps.is_some() {
+        report_laps(&telemetry.metadata);
+    }
+    Ok(())
+}
+
+/// List the timed laps with their sectors, marking the fastest
+fn report_laps(metadata: &TelemetryMetadata) {
+    if metadata.lap_times.is_empty() {
+        eprintln!("Warning: no complete lap between two crossings of the start/finish line");
+        return;
+    }
+    let time = |seconds: f64| format!("{}:{:06.3}", (seconds / 60.0).floor(), seconds % 60.0);
+    for lap in &metadata.lap_times {
+        let sectors: Vec<String> = lap.sectors.iter().map(|s| format!("{:.3}", s)).collect();
+        let best = if metadata.best_lap == Some(lap.number) { " (best)" } else { "" };
+        match sectors.is_empty() {
+            true => eprintln!("Lap {}: {}{}", lap.number, time(lap.time), best),
+            false => eprintln!("Lap {}: {} [{}]{}", lap.number, time(lap.time), sectors.join(", "), best),
         }
-    } else {
-        "unknown".to_string()
     }
-} 
\ No newline at end of file
+}
+
+/// Tell the user which positions and values were rejected
+fn report_outliers(outliers: &[Outlier]) {
+    eprintln!("Rejected {} outliers:", outliers.len());
+    for outlier in outliers {
+        let reason = match outlier.reason {
+            OutlierReason::Speed => format!("speed {:.1} m/s", outlier.measure),
+            OutlierReason::Acceleration => format!("acceleration {:.1} m/s²", outlier.measure),
+            OutlierReason::Hdop => format!("HDOP {:.1}", outlier.measure),
+            OutlierReason::Satellites => format!("{} satellites", outlier.measure),
+            OutlierReason::Hampel => format!("{:.3} from the local median", outlier.measure),
+        };
+        let action = match outlier.action {
+            OutlierAction::Removed => "removed",
+            OutlierAction::Interpolated => "interpolated",
+            OutlierAction::Cleared => "cleared",
+        };
+        eprintln!("  {} {}: {}, {}", outlier.timestamp.to_rfc3339(), outlier.name, reason, action);
+    }
+}
+
+/// Tell the user about times that were not read from the log
+fn report_times(report: &TimeReport, total: usize) {
+    if report.relative {
+        match report.start {
+            Some(start) => eprintln!("Note: the log has relative times, placed after {}", start.to_rfc3339()),
+            None => eprintln!("Warning: the log has relative times, placed after 1970-01-01; use --start-time to set its start"),
+        }
+    }
+    let synthesized: usize = report.synthesized.iter().map(|run| run.count).sum();
+    if synthesized > 0 {
+        eprintln!("Warning: synthesized the times of {} of {} points:", synthesized, total);
+        for run in &report.synthesized {
+            eprintln!("  {} points from {} to {}", run.count, run.first.to_rfc3339(), run.last.to_rfc3339());
+        }
+    }
+}
@@ -1,6 +1,7 @@
 use std::fs;
 use crate::{telemetry::TelemetryData, renderer::OverlayRenderer, video::VideoProcessor, error::OverlogError};
 
+#[allow(clippy::too_many_arguments)]
 pub async fn render_overlay(
     input: String,
     output: String,
@@ -9,13 +10,14 @@ pub async fn render_overlay(
     duration: Option<f64>,
     fps: u32,
     style: String,
+    channels: Vec<String>,
 ) -> Result<(), OverlogError> {
     // Load telemetry data
     let content = fs::read_to_string(&input)?;
     let telemetry: TelemetryData = serde_json::from_str(&content)?;
     
     // Create renderer
-    let renderer = OverlayRenderer::new(width, height, style)?;
+    let renderer = OverlayRenderer::new(width, height, style)?.with_channels(channels);
     
     // Determine duration
     let video_duration = duration.unwrap_or_else(|| {
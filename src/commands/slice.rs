@@ -0,0 +1,76 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::{
+    commands::{
+        convert::{encode, output_format},
+        parse::{load_telemetry, process_telemetry},
+    },
+    formats::{csv_mapping::CsvMapping, gpx::GpxTrack, timing::TimestampPolicy},
+    processing::{slice::Slice, Processing},
+    error::OverlogError,
+};
+
+/// Cut a log down to a time range, a stretch of track or a lap
+#[allow(clippy::too_many_arguments)]
+pub async fn trim_telemetry(
+    input: String,
+    output: String,
+    from: Option<String>,
+    to: Option<String>,
+    track: Option<GpxTrack>,
+    csv_mapping: Option<CsvMapping>,
+    slice: Slice,
+    times: TimestampPolicy,
+    processing: Processing,
+) -> Result<(), OverlogError> {
+    let output_path = Path::new(&output);
+    let target = output_format(output_path, to)?;
+
+    let mut telemetry = load_telemetry(Path::new(&input), from, track.as_ref(), csv_mapping.as_ref(), &times)?;
+    process_telemetry(&mut telemetry, &processing)?;
+    let piece = telemetry.slice(&slice)?;
+
+    fs::write(output_path, encode(&piece, &target, &[])?)?;
+    println!("Kept {} of {} points: {}", piece.points.len(), telemetry.points.len(), output);
+    Ok(())
+}
+
+/// Split a log at gaps longer than `gap` seconds, writing the pieces next
+/// to `output` with numbered names (`ride.gpx` becomes `ride-1.gpx`,
+/// `ride-2.gpx`, ...)
+#[allow(clippy::too_many_arguments)]
+pub async fn split_telemetry(
+    input: String,
+    output: String,
+    from: Option<String>,
+    to: Option<String>,
+    track: Option<GpxTrack>,
+    csv_mapping: Option<CsvMapping>,
+    gap: f64,
+    times: TimestampPolicy,
+    processing: Processing,
+) -> Result<(), OverlogError> {
+    if !(gap.is_finite() && gap > 0.0) {
+        return Err(OverlogError::InvalidInput(format!("Invalid gap of {} seconds", gap)));
+    }
+    let output_path = Path::new(&output);
+    let target = output_format(output_path, to)?;
+
+    let mut telemetry = load_telemetry(Path::new(&input), from, track.as_ref(), csv_mapping.as_ref(), &times)?;
+    process_telemetry(&mut telemetry, &processing)?;
+    let pieces = telemetry.split_at_gaps(gap);
+
+    for (i, piece) in pieces.iter().enumerate() {
+        let path = numbered(output_path, i + 1, &target);
+        fs::write(&path, encode(piece, &target, &[])?)?;
+        println!("Piece {}: {} points, {:.1} s: {}", i + 1, piece.points.len(), piece.metadata.duration.unwrap_or(0.0), path.display());
+    }
+    Ok(())
+}
+
+/// `ride.gpx` with a number, as `ride-2.gpx`
+fn numbered(path: &Path, number: usize, extension: &str) -> PathBuf {
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("piece");
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or(extension);
+    path.with_file_name(format!("{}-{}.{}", stem, number, extension))
+}
@@ -11,8 +11,11 @@ pub enum OverlogError {
     #[error("CSV parsing error: {0}")]
     Csv(#[from] csv::Error),
     
-    #[error("GPX parsing error: {0}")]
-    Gpx(#[from] gpx::errors::GpxError),
+    #[error("XML parsing error: {0}")]
+    Xml(#[from] roxmltree::Error),
+    
+    #[error("Archive error: {0}")]
+    Zip(#[from] zip::result::ZipError),
     
     #[error("FFmpeg error: {0}")]
     Ffmpeg(String),
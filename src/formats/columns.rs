@@ -0,0 +1,317 @@
+//! Column targets and unit conversion shared by the tabular importers
+
+use std::str::FromStr;
+use crate::{
+    error::OverlogError,
+    telemetry::{channel, TelemetryPoint},
+};
+
+const STANDARD_GRAVITY: f64 = 9.80665;
+
+/// The `TelemetryPoint` field a source column is stored in
+///
+/// Lateral acceleration goes to `g_force_x`, longitudinal to `g_force_y`
+/// and vertical to `g_force_z`.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Field {
+    Latitude,
+    Longitude,
+    Altitude,
+    Speed,
+    Heading,
+    GForceX,
+    GForceY,
+    GForceZ,
+    Acceleration,
+    Rpm,
+    Throttle,
+    Brake,
+    Steering,
+    Channel(String),
+}
+
+impl Field {
+    pub fn channel(name: &str) -> Self {
+        Field::Channel(name.to_string())
+    }
+
+    /// Every `TelemetryPoint` field, in declaration order
+    pub const POINT_FIELDS: [Field; 13] = [
+        Field::Latitude,
+        Field::Longitude,
+        Field::Altitude,
+        Field::Speed,
+        Field::Heading,
+        Field::GForceX,
+        Field::GForceY,
+        Field::GForceZ,
+        Field::Acceleration,
+        Field::Rpm,
+        Field::Throttle,
+        Field::Brake,
+        Field::Steering,
+    ];
+
+    /// Field or channel name, as accepted by `from_str`
+    pub fn name(&self) -> &str {
+        match self {
+            Field::Latitude => "latitude",
+            Field::Longitude => "longitude",
+            Field::Altitude => "altitude",
+            Field::Speed => "speed",
+            Field::Heading => "heading",
+            Field::GForceX => "g_force_x",
+            Field::GForceY => "g_force_y",
+            Field::GForceZ => "g_force_z",
+            Field::Acceleration => "acceleration",
+            Field::Rpm => "rpm",
+            Field::Throttle => "throttle",
+            Field::Brake => "brake",
+            Field::Steering => "steering",
+            Field::Channel(name) => name,
+        }
+    }
+
+    /// Numeric value of the field in a point
+    pub fn get(&self, point: &TelemetryPoint) -> Option<f64> {
+        match self {
+            Field::Latitude => point.latitude,
+            Field::Longitude => point.longitude,
+            Field::Altitude => point.altitude,
+            Field::Speed => point.speed,
+            Field::Heading => point.heading,
+            Field::GForceX => point.g_force_x,
+            Field::GForceY => point.g_force_y,
+            Field::GForceZ => point.g_force_z,
+            Field::Acceleration => point.acceleration,
+            Field::Rpm => point.rpm,
+            Field::Throttle => point.throttle,
+            Field::Brake => point.brake,
+            Field::Steering => point.steering,
+            Field::Channel(name) => point.channel(name),
+        }
+    }
+
+    /// Store an already converted value in a point
+    pub fn set(&self, point: &mut TelemetryPoint, value: f64) {
+        match (self.slot(point), self) {
+            (Some(slot), _) => *slot = Some(value),
+            (None, Field::Channel(name)) => point.set_channel(name, value),
+            (None, _) => {}
+        }
+    }
+
+    /// Remove the value from a point
+    pub fn clear(&self, point: &mut TelemetryPoint) {
+        match (self.slot(point), self) {
+            (Some(slot), _) => *slot = None,
+            (None, Field::Channel(name)) => {
+                point.channels.remove(name);
+            }
+            (None, _) => {}
+        }
+    }
+
+    /// The point field behind a non-channel field
+    fn slot<'a>(&self, point: &'a mut TelemetryPoint) -> Option<&'a mut Option<f64>> {
+        let slot = match self {
+            Field::Latitude => &mut point.latitude,
+            Field::Longitude => &mut point.longitude,
+            Field::Altitude => &mut point.altitude,
+            Field::Speed => &mut point.speed,
+            Field::Heading => &mut point.heading,
+            Field::GForceX => &mut point.g_force_x,
+            Field::GForceY => &mut point.g_force_y,
+            Field::GForceZ => &mut point.g_force_z,
+            Field::Acceleration => &mut point.acceleration,
+            Field::Rpm => &mut point.rpm,
+            Field::Throttle => &mut point.throttle,
+            Field::Brake => &mut point.brake,
+            Field::Steering => &mut point.steering,
+            Field::Channel(_) => return None,
+        };
+        Some(slot)
+    }
+}
+
+impl FromStr for Field {
+    type Err = OverlogError;
+
+    /// Accepts `TelemetryPoint` field names; anything else names a channel
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        let field = match name.trim() {
+            "" => return Err(OverlogError::Config("Empty field name".to_string())),
+            "latitude" => Field::Latitude,
+            "longitude" => Field::Longitude,
+            "altitude" => Field::Altitude,
+            "speed" => Field::Speed,
+            "heading" => Field::Heading,
+            "g_force_x" => Field::GForceX,
+            "g_force_y" => Field::GForceY,
+            "g_force_z" => Field::GForceZ,
+            "acceleration" => Field::Acceleration,
+            "rpm" => Field::Rpm,
+            "throttle" => Field::Throttle,
+            "brake" => Field::Brake,
+            "steering" => Field::Steering,
+            other => Field::channel(other),
+        };
+        Ok(field)
+    }
+}
+
+/// Unit a source column is recorded in
+///
+/// Values are converted to the units `TelemetryPoint` uses: m/s, meters,
+/// degrees, g and fractions (0-1) for pedal positions.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Unit {
+    /// Already in the target unit
+    Native,
+    KilometersPerHour,
+    MilesPerHour,
+    Knots,
+    Feet,
+    MetersPerSecondSquared,
+    Percent,
+    Radians,
+}
+
+impl Unit {
+    /// Guess a unit from a header or unit label such as `Speed (KPH)` or `m/s2`
+    pub fn infer(label: &str) -> Self {
+        let label = label.to_ascii_lowercase();
+        let has = |pattern: &str| {
+            label
+                .split(|c: char| !(c.is_ascii_alphanumeric() || c == '/' || c == '^' || c == '²'))
+                .any(|word| word == pattern)
+        };
+
+        if has("km/h") || has("kph") || has("kmh") {
+            Unit::KilometersPerHour
+        } else if has("mph") {
+            Unit::MilesPerHour
+        } else if has("knots") || has("kn") || has("kts") {
+            Unit::Knots
+        } else if has("ft") || has("feet") {
+            Unit::Feet
+        } else if has("m/s2") || has("m/s²") || has("m/s^2") {
+            Unit::MetersPerSecondSquared
+        } else if label.contains('%') {
+            Unit::Percent
+        } else if has("rad") {
+            Unit::Radians
+        } else {
+            Unit::Native
+        }
+    }
+
+    pub fn convert(self, value: f64) -> f64 {
+        match self {
+            Unit::Native => value,
+            Unit::KilometersPerHour => crate::geo::kmh_to_ms(value),
+            Unit::MilesPerHour => crate::geo::mph_to_ms(value),
+            Unit::Knots => value * 0.514444,
+            Unit::Feet => value * 0.3048,
+            Unit::MetersPerSecondSquared => value / STANDARD_GRAVITY,
+            Unit::Percent => value / 100.0,
+            Unit::Radians => value.to_degrees(),
+        }
+    }
+
+    /// Inverse of `convert`: express a `TelemetryPoint` value in this unit
+    pub fn convert_back(self, value: f64) -> f64 {
+        match self {
+            Unit::Native => value,
+            Unit::KilometersPerHour => crate::geo::ms_to_kmh(value),
+            Unit::MilesPerHour => crate::geo::ms_to_mph(value),
+            Unit::Knots => value / 0.514444,
+            Unit::Feet => value / 0.3048,
+            Unit::MetersPerSecondSquared => value * STANDARD_GRAVITY,
+            Unit::Percent => value * 100.0,
+            Unit::Radians => value.to_radians(),
+        }
+    }
+}
+
+impl FromStr for Unit {
+    type Err = OverlogError;
+
+    fn from_str(label: &str) -> Result<Self, Self::Err> {
+        let unit = match label.trim().to_ascii_lowercase().as_str() {
+            "" | "native" | "si" | "m/s" | "m" | "deg" | "degrees" | "g" | "fraction" => Unit::Native,
+            "km/h" | "kph" | "kmh" => Unit::KilometersPerHour,
+            "mph" => Unit::MilesPerHour,
+            "kn" | "kt" | "kts" | "knots" => Unit::Knots,
+            "ft" | "feet" => Unit::Feet,
+            "m/s2" | "m/s²" | "m/s^2" => Unit::MetersPerSecondSquared,
+            "%" | "percent" => Unit::Percent,
+            "rad" | "radians" => Unit::Radians,
+            other => return Err(OverlogError::Config(format!("Unknown unit '{}'", other))),
+        };
+        Ok(unit)
+    }
+}
+
+/// Map a normalized (lower case) motorsport column header to a field
+///
+/// Covers the names used by VBOX, RaceChrono, Harry's LapTimer and
+/// TrackAddict exports.
+pub(crate) fn motorsport_field(header: &str) -> Option<Field> {
+    let header = header.trim().trim_matches('"').to_ascii_lowercase();
+    // Drop unit suffixes and source markers: "speed (kph)", "engine speed (rpm) *obd"
+    let name = header
+        .split(['(', '[', '*'])
+        .next()
+        .unwrap_or("")
+        .trim()
+        .replace(['_', '-'], " ");
+
+    let field = match name.as_str() {
+        "lat" | "latitude" => Field::Latitude,
+        "long" | "lon" | "lng" | "longitude" => Field::Longitude,
+        "alt" | "altitude" | "height" | "elevation" => Field::Altitude,
+        "speed" | "velocity" | "gps speed" => Field::Speed,
+        "heading" | "bearing" | "course" => Field::Heading,
+        "lat acc" | "latacc" | "lateral acceleration" | "lat accel" | "accel x" | "lateral g" => Field::GForceX,
+        "long acc" | "longacc" | "lon acc" | "lonacc" | "longitudinal acceleration" | "long accel"
+        | "accel y" | "longitudinal g" => Field::GForceY,
+        "accel z" | "vertical acceleration" | "vertical g" => Field::GForceZ,
+        "acceleration" | "combined acceleration" | "combined g" => Field::Acceleration,
+        "rpm" | "engine rpm" | "engine speed" => Field::Rpm,
+        "throttle" | "throttle position" | "accelerator pedal position" => Field::Throttle,
+        "brake" | "brake position" | "brake pressure" => Field::Brake,
+        "steering" | "steering angle" => Field::Steering,
+        "sats" | "satellites" => Field::channel(channel::SATELLITES),
+        "distance" | "distance traveled" => Field::channel(channel::DISTANCE),
+        "lap" | "lap #" => Field::channel(channel::LAP),
+        _ => return None,
+    };
+    Some(field)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unit_inference() {
+        assert_eq!(Unit::infer("Speed (KPH)"), Unit::KilometersPerHour);
+        assert_eq!(Unit::infer("km/h"), Unit::KilometersPerHour);
+        assert_eq!(Unit::infer("Altitude (ft)"), Unit::Feet);
+        assert_eq!(Unit::infer("m/s2"), Unit::MetersPerSecondSquared);
+        assert_eq!(Unit::infer("Throttle Position (%) *OBD"), Unit::Percent);
+        assert_eq!(Unit::infer("Latitude"), Unit::Native);
+        assert!((Unit::Knots.convert(1.0) - 0.514444).abs() < 1e-9);
+        assert!((Unit::MilesPerHour.convert_back(Unit::MilesPerHour.convert(30.0)) - 30.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_motorsport_headers() {
+        assert_eq!(motorsport_field("\"Speed (KPH)\""), Some(Field::Speed));
+        assert_eq!(motorsport_field("Engine Speed (RPM) *OBD"), Some(Field::Rpm));
+        assert_eq!(motorsport_field("long_acc"), Some(Field::GForceY));
+        assert_eq!(motorsport_field("Lap #"), Some(Field::channel(channel::LAP)));
+        assert_eq!(motorsport_field("Fragment ID"), None);
+    }
+}
@@ -0,0 +1,568 @@
+//! User-declared layouts for CSV logs that don't use overlog's own columns,
+//! and the CSV writer
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    io::BufRead,
+    path::Path,
+    str::FromStr,
+};
+use chrono::{DateTime, Duration, NaiveDateTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use crate::{
+    error::OverlogError,
+    telemetry::{ChannelValue, TelemetryData, TelemetryPoint},
+};
+use super::{
+    columns::{Field, Unit},
+    format_rfc3339, parse_rfc3339,
+    timing::Zone,
+};
+
+/// How to read a CSV file: layout, column targets, units and time base
+///
+/// Loaded from a JSON file such as
+///
+/// ```json
+/// {
+///   "delimiter": ";",
+///   "decimal_comma": true,
+///   "skip_lines": 2,
+///   "timestamp": { "column": "Time", "format": "epoch_millis" },
+///   "columns": {
+///     "Speed (kph)": { "field": "speed", "unit": "km/h" },
+///     "Water temp": { "field": "coolant_temp" }
+///   }
+/// }
+/// ```
+///
+/// Columns that aren't listed keep their header as field or channel name.
+/// Targets that aren't `TelemetryPoint` fields become channels; the unit of
+/// a channel is kept as its label rather than converted.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CsvMapping {
+    /// Field separator, `,` (or `;` with decimal commas) when unset
+    pub delimiter: Option<char>,
+    /// Numbers are written with a decimal comma (`12,5`)
+    pub decimal_comma: bool,
+    /// Lines to drop before the header row
+    pub skip_lines: usize,
+    pub timestamp: TimestampSpec,
+    /// Source column name to target
+    pub columns: BTreeMap<String, ColumnSpec>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TimestampSpec {
+    pub column: String,
+    pub format: TimestampFormat,
+    /// IANA name (`Europe/Berlin`) or offset (`+02:00`) for local times
+    pub timezone: Option<String>,
+    /// Start of the recording for relative times
+    pub start: Option<DateTime<Utc>>,
+}
+
+impl Default for TimestampSpec {
+    fn default() -> Self {
+        Self {
+            column: "timestamp".to_string(),
+            format: TimestampFormat::Rfc3339,
+            timezone: None,
+            start: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimestampFormat {
+    Rfc3339,
+    EpochSeconds,
+    EpochMillis,
+    /// Seconds since `TimestampSpec::start`
+    RelativeSeconds,
+    /// chrono format string for local date and time, e.g. `%d/%m/%Y %H:%M:%S%.f`
+    Local(String),
+}
+
+impl FromStr for TimestampFormat {
+    type Err = OverlogError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let format = match value {
+            "rfc3339" | "iso8601" => TimestampFormat::Rfc3339,
+            "epoch" | "epoch_s" | "epoch-s" | "epoch_seconds" => TimestampFormat::EpochSeconds,
+            "epoch_ms" | "epoch-ms" | "epoch_millis" => TimestampFormat::EpochMillis,
+            "relative" | "relative_seconds" => TimestampFormat::RelativeSeconds,
+            pattern if pattern.contains('%') => TimestampFormat::Local(pattern.to_string()),
+            other => return Err(OverlogError::Config(format!("Unknown timestamp format '{}'", other))),
+        };
+        Ok(format)
+    }
+}
+
+/// Target for one source column
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ColumnSpec {
+    /// `TelemetryPoint` field or channel name
+    pub field: String,
+    /// Unit the column is recorded in, the target unit when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unit: Option<String>,
+}
+
+impl CsvMapping {
+    /// Load a mapping spec from a JSON file
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, OverlogError> {
+        let content = fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    /// Add a column rule written as `SOURCE=FIELD[:UNIT]`, e.g. `Speed (kph)=speed:km/h`
+    pub fn add_column_rule(&mut self, rule: &str) -> Result<(), OverlogError> {
+        let (source, target) = rule
+            .rsplit_once('=')
+            .ok_or_else(|| OverlogError::Config(format!("Column rule '{}' is not SOURCE=FIELD[:UNIT]", rule)))?;
+        let (field, unit) = match target.split_once(':') {
+            Some((field, unit)) => (field, Some(unit.to_string())),
+            None => (target, None),
+        };
+        self.columns.insert(source.trim().to_string(), ColumnSpec { field: field.trim().to_string(), unit });
+        Ok(())
+    }
+
+    fn delimiter(&self) -> u8 {
+        match self.delimiter {
+            Some(c) => c as u8,
+            None if self.decimal_comma => b';',
+            None => b',',
+        }
+    }
+}
+
+impl TelemetryData {
+    /// Parse a CSV file laid out as described by `mapping`
+    pub fn from_csv_with_mapping(csv_data: &str, mapping: &CsvMapping) -> Result<Self, OverlogError> {
+        Self::from_csv_reader(csv_data.as_bytes(), mapping)
+    }
+
+    /// Parse CSV from a reader, row by row, laid out as described by `mapping`
+    pub fn from_csv_reader(reader: impl BufRead, mapping: &CsvMapping) -> Result<Self, OverlogError> {
+        let mut telemetry = TelemetryData::new();
+        telemetry.metadata.format = "csv".to_string();
+        telemetry.points = CsvPoints::new(reader, mapping)?.collect::<Result<_, _>>()?;
+        telemetry.metadata.times.relative =
+            mapping.timestamp.format == TimestampFormat::RelativeSeconds && mapping.timestamp.start.is_none();
+        telemetry.calculate_metadata();
+        Ok(telemetry)
+    }
+
+    /// Write CSV with an RFC 3339 `timestamp` column
+    ///
+    /// `columns` lists `FIELD[:UNIT]` specs such as `speed:km/h`; by default
+    /// every field that has values and every channel is written in native
+    /// units. Headers carry the unit as `speed (km/h)`, which `from_csv`
+    /// reads back. Channels are written as recorded and can't be converted.
+    pub fn to_csv(&self, columns: Option<&[String]>) -> Result<String, OverlogError> {
+        let columns = match columns {
+            Some(specs) => specs.iter().map(|spec| self.output_column(spec)).collect::<Result<Vec<_>, _>>()?,
+            None => self.default_output_columns(),
+        };
+
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        let mut record = vec!["timestamp".to_string()];
+        record.extend(columns.iter().map(|(_, _, header)| header.clone()));
+        writer.write_record(&record)?;
+
+        for point in &self.points {
+            record.clear();
+            record.push(format_rfc3339(&point.timestamp));
+            for (field, unit, _) in &columns {
+                let cell = match (field, unit) {
+                    (Field::Channel(name), _) => match point.channels.get(name).map(|c| &c.value) {
+                        Some(ChannelValue::Number(value)) => value.to_string(),
+                        Some(ChannelValue::Text(text)) => text.clone(),
+                        None => String::new(),
+                    },
+                    (field, unit) => field.get(point).map(|v| unit.convert_back(v).to_string()).unwrap_or_default(),
+                };
+                record.push(cell);
+            }
+            writer.write_record(&record)?;
+        }
+
+        let bytes = writer.into_inner().map_err(|e| e.into_error())?;
+        String::from_utf8(bytes).map_err(|e| OverlogError::Telemetry(format!("Invalid UTF-8 in CSV output: {}", e)))
+    }
+
+    fn output_column(&self, spec: &str) -> Result<(Field, Unit, String), OverlogError> {
+        let (name, unit) = match spec.split_once(':') {
+            Some((name, unit)) => (name, Some(unit.trim())),
+            None => (spec, None),
+        };
+        match (name.parse()?, unit) {
+            (Field::Channel(name), Some(unit)) => Err(OverlogError::Config(format!(
+                "Channel '{}' can't be converted to '{}'",
+                name, unit
+            ))),
+            (Field::Channel(name), None) => {
+                let header = channel_header(&name, self.channel_unit(&name));
+                Ok((Field::Channel(name), Unit::Native, header))
+            }
+            (field, Some(label)) => {
+                let header = format!("{} ({})", field.name(), label);
+                Ok((field, label.parse()?, header))
+            }
+            (field, None) => {
+                let header = field.name().to_string();
+                Ok((field, Unit::Native, header))
+            }
+        }
+    }
+
+    fn default_output_columns(&self) -> Vec<(Field, Unit, String)> {
+        let mut columns: Vec<(Field, Unit, String)> = Field::POINT_FIELDS
+            .iter()
+            .filter(|field| self.points.iter().any(|p| field.get(p).is_some()))
+            .map(|field| (field.clone(), Unit::Native, field.name().to_string()))
+            .collect();
+        let channels: BTreeSet<&String> = self.points.iter().flat_map(|p| p.channels.keys()).collect();
+        for name in channels {
+            let header = channel_header(name, self.channel_unit(name));
+            columns.push((Field::Channel(name.clone()), Unit::Native, header));
+        }
+        columns
+    }
+
+    fn channel_unit(&self, name: &str) -> Option<&str> {
+        self.points.iter().find_map(|p| p.channels.get(name)?.unit.as_deref())
+    }
+}
+
+fn channel_header(name: &str, unit: Option<&str>) -> String {
+    match unit {
+        Some(unit) => format!("{} ({})", name, unit),
+        None => name.to_string(),
+    }
+}
+
+/// Points of a CSV file, read one row at a time
+///
+/// Only the current row is held in memory, so logs of any length can be
+/// processed as they are read.
+pub struct CsvPoints<R> {
+    reader: csv::Reader<R>,
+    record: csv::StringRecord,
+    time_index: usize,
+    columns: Vec<(usize, Column)>,
+    clock: Clock,
+    decimal_comma: bool,
+    row: usize,
+}
+
+impl<R: BufRead> CsvPoints<R> {
+    /// Read the header row and prepare the column targets
+    pub fn new(mut reader: R, mapping: &CsvMapping) -> Result<Self, OverlogError> {
+        if mapping.delimiter.is_some_and(|c| !c.is_ascii()) {
+            return Err(OverlogError::Config("CSV delimiter must be an ASCII character".to_string()));
+        }
+        let mut line = Vec::new();
+        for _ in 0..mapping.skip_lines {
+            line.clear();
+            reader.read_until(b'\n', &mut line)?;
+        }
+
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(mapping.delimiter())
+            .flexible(true)
+            .trim(csv::Trim::All)
+            .from_reader(reader);
+        let headers = reader.headers()?.clone();
+
+        let time_index = headers
+            .iter()
+            .position(|h| h == mapping.timestamp.column)
+            .ok_or_else(|| OverlogError::Config(format!("Timestamp column '{}' not found", mapping.timestamp.column)))?;
+        let clock = Clock::new(&mapping.timestamp, mapping.decimal_comma)?;
+
+        let mut columns: Vec<(usize, Column)> = Vec::new();
+        for (index, header) in headers.iter().enumerate() {
+            if index == time_index || header.is_empty() {
+                continue;
+            }
+            let column = match mapping.columns.get(header) {
+                Some(spec) => match spec.field.parse()? {
+                    Field::Channel(name) => Column::Channel(name, spec.unit.clone()),
+                    field => {
+                        let unit = spec.unit.as_deref().map(Unit::from_str).transpose()?;
+                        Column::Field(field, unit.unwrap_or(Unit::Native))
+                    }
+                },
+                None => header_column(header)?,
+            };
+            columns.push((index, column));
+        }
+        for source in mapping.columns.keys() {
+            if !headers.iter().any(|h| h == source) {
+                tracing::warn!("Mapped CSV column '{}' not found", source);
+            }
+        }
+
+        Ok(Self {
+            reader,
+            record: csv::StringRecord::new(),
+            time_index,
+            columns,
+            clock,
+            decimal_comma: mapping.decimal_comma,
+            row: 0,
+        })
+    }
+
+    fn point(&self) -> Result<Option<TelemetryPoint>, OverlogError> {
+        let record = &self.record;
+        let Some(raw_time) = record.get(self.time_index).filter(|t| !t.is_empty()) else { return Ok(None) };
+        let timestamp = self
+            .clock
+            .timestamp(raw_time)
+            .map_err(|e| OverlogError::Telemetry(format!("Row {}: {}", self.row, e)))?;
+
+        let mut point = TelemetryPoint { timestamp, ..Default::default() };
+        for (index, column) in &self.columns {
+            let Some(cell) = record.get(*index).filter(|c| !c.is_empty()) else { continue };
+            let value = number(cell, self.decimal_comma).parse::<f64>();
+            match (column, value) {
+                (Column::Field(field, unit), Ok(value)) => field.set(&mut point, unit.convert(value)),
+                (Column::Field(..), Err(_)) => {}
+                (Column::Channel(name, unit), Ok(value)) => match unit {
+                    Some(unit) => point.set_channel_with_unit(name, value, Some(unit)),
+                    None => point.set_channel(name, value),
+                },
+                (Column::Channel(name, unit), Err(_)) => point.set_channel_with_unit(name, cell, unit.as_deref()),
+            }
+        }
+        Ok(Some(point))
+    }
+}
+
+impl<R: BufRead> Iterator for CsvPoints<R> {
+    type Item = Result<TelemetryPoint, OverlogError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.reader.read_record(&mut self.record) {
+                Ok(true) => {}
+                Ok(false) => return None,
+                Err(e) => return Some(Err(e.into())),
+            }
+            self.row += 1;
+            // Rows without a timestamp are skipped
+            if let Some(point) = self.point().transpose() {
+                return Some(point);
+            }
+        }
+    }
+}
+
+/// Target of a column that isn't in the mapping, from its header
+///
+/// A trailing `(unit)` is honoured: `speed (km/h)` is converted and
+/// `coolant_temp (°C)` becomes a channel with that unit.
+fn header_column(header: &str) -> Result<Column, OverlogError> {
+    let (name, unit) = match header.strip_suffix(')').and_then(|h| h.rsplit_once(" (")) {
+        Some((name, unit)) => (name, Some(unit)),
+        None => (header, None),
+    };
+    let column = match (name.parse()?, unit) {
+        (Field::Channel(name), unit) => Column::Channel(name, unit.map(str::to_string)),
+        (field, None) => Column::Field(field, Unit::Native),
+        (field, Some(unit)) => match unit.parse() {
+            Ok(unit) => Column::Field(field, unit),
+            Err(_) => Column::Channel(header.to_string(), None),
+        },
+    };
+    Ok(column)
+}
+
+/// Where the values of a source column go
+enum Column {
+    Field(Field, Unit),
+    /// Channel name and unit label
+    Channel(String, Option<String>),
+}
+
+fn number(cell: &str, decimal_comma: bool) -> String {
+    if decimal_comma {
+        cell.replace(',', ".")
+    } else {
+        cell.to_string()
+    }
+}
+
+/// Turns timestamp cells into UTC according to a `TimestampSpec`
+struct Clock {
+    format: TimestampFormat,
+    zone: Zone,
+    start: DateTime<Utc>,
+    decimal_comma: bool,
+}
+
+impl Clock {
+    fn new(spec: &TimestampSpec, decimal_comma: bool) -> Result<Self, OverlogError> {
+        Ok(Self {
+            format: spec.format.clone(),
+            zone: Zone::new(spec.timezone.as_deref())?,
+            start: spec.start.unwrap_or(DateTime::<Utc>::UNIX_EPOCH),
+            decimal_comma,
+        })
+    }
+
+    fn timestamp(&self, value: &str) -> Result<DateTime<Utc>, OverlogError> {
+        let seconds = || {
+            number(value, self.decimal_comma)
+                .parse::<f64>()
+                .map_err(|_| OverlogError::Telemetry(format!("Invalid timestamp '{}'", value)))
+        };
+        match &self.format {
+            TimestampFormat::Rfc3339 => parse_rfc3339(value),
+            TimestampFormat::EpochSeconds => from_micros(seconds()? * 1e6),
+            TimestampFormat::EpochMillis => from_micros(seconds()? * 1e3),
+            TimestampFormat::RelativeSeconds => {
+                Ok(self.start + Duration::microseconds((seconds()? * 1e6).round() as i64))
+            }
+            TimestampFormat::Local(pattern) => {
+                let local = NaiveDateTime::parse_from_str(value, pattern)
+                    .map_err(|e| OverlogError::Telemetry(format!("Invalid timestamp '{}': {}", value, e)))?;
+                self.zone.to_utc(local)
+            }
+        }
+    }
+}
+
+fn from_micros(micros: f64) -> Result<DateTime<Utc>, OverlogError> {
+    Utc.timestamp_micros(micros.round() as i64)
+        .single()
+        .ok_or_else(|| OverlogError::Telemetry(format!("Timestamp out of range: {} µs", micros)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::telemetry::{channel, ChannelValue};
+
+    #[test]
+    fn test_mapping_from_json() {
+        let csv = "Logger v2\nexported 2024-01-15\nTime;Speed (kph);Alt (ft);Lat acc (m/s2);Water temp;Gear\n\
+            1705312800000;36,0;100;9,80665;88,5;N\n\
+            1705312800500;72,0;110;0;89;1\n";
+        let mapping: CsvMapping = serde_json::from_str(
+            r#"{
+                "decimal_comma": true,
+                "skip_lines": 2,
+                "timestamp": { "column": "Time", "format": "epoch_millis" },
+                "columns": {
+                    "Speed (kph)": { "field": "speed", "unit": "km/h" },
+                    "Alt (ft)": { "field": "altitude", "unit": "ft" },
+                    "Lat acc (m/s2)": { "field": "g_force_x", "unit": "m/s2" },
+                    "Water temp": { "field": "coolant_temp", "unit": "°C" }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let telemetry = TelemetryData::from_csv_with_mapping(csv, &mapping).unwrap();
+        assert_eq!(telemetry.points.len(), 2);
+
+        let first = &telemetry.points[0];
+        assert_eq!(first.timestamp.to_rfc3339(), "2024-01-15T10:00:00+00:00");
+        assert!((first.speed.unwrap() - 10.0).abs() < 1e-9);
+        assert!((first.altitude.unwrap() - 30.48).abs() < 1e-9);
+        assert!((first.g_force_x.unwrap() - 1.0).abs() < 1e-9);
+        assert_eq!(first.channel("coolant_temp"), Some(88.5));
+        assert_eq!(first.channels["coolant_temp"].unit.as_deref(), Some("°C"));
+        assert_eq!(first.channels["Gear"].value, ChannelValue::from("N"));
+        assert_eq!(telemetry.points[1].channel("Gear"), Some(1.0));
+        assert_eq!((telemetry.points[1].timestamp - first.timestamp).num_milliseconds(), 500);
+    }
+
+    #[test]
+    fn test_local_time_and_rules() {
+        let csv = "date,lat,lon,spd\n15/01/2024 11:00:00.5,40.7128,-74.006,10\n";
+        let mut mapping = CsvMapping {
+            timestamp: TimestampSpec {
+                column: "date".to_string(),
+                format: "%d/%m/%Y %H:%M:%S%.f".parse().unwrap(),
+                timezone: Some("Europe/Berlin".to_string()),
+                start: None,
+            },
+            ..Default::default()
+        };
+        mapping.add_column_rule("lat=latitude").unwrap();
+        mapping.add_column_rule("lon=longitude").unwrap();
+        mapping.add_column_rule("spd=speed:knots").unwrap();
+
+        let telemetry = TelemetryData::from_csv_with_mapping(csv, &mapping).unwrap();
+        let point = &telemetry.points[0];
+        assert_eq!(point.timestamp.to_rfc3339(), "2024-01-15T10:00:00.500+00:00");
+        assert_eq!(point.latitude, Some(40.7128));
+        assert!((point.speed.unwrap() - 5.14444).abs() < 1e-9);
+        assert!(point.channel(channel::DISTANCE).is_none());
+        assert!(point.channels.is_empty());
+    }
+
+    #[test]
+    fn test_relative_time_and_fixed_offset() {
+        let start = "2024-01-15T10:00:00Z".parse().unwrap();
+        let mut mapping = CsvMapping {
+            timestamp: TimestampSpec {
+                column: "t".to_string(),
+                format: TimestampFormat::RelativeSeconds,
+                timezone: None,
+                start: Some(start),
+            },
+            ..Default::default()
+        };
+        let telemetry = TelemetryData::from_csv_with_mapping("t,speed\n0.25,3\n", &mapping).unwrap();
+        assert_eq!(telemetry.points[0].timestamp.to_rfc3339(), "2024-01-15T10:00:00.250+00:00");
+        assert_eq!(telemetry.points[0].speed, Some(3.0));
+
+        mapping.timestamp.format = TimestampFormat::Local("%Y-%m-%d %H:%M:%S".to_string());
+        mapping.timestamp.timezone = Some("-05:00".to_string());
+        let telemetry = TelemetryData::from_csv_with_mapping("t,speed\n2024-01-15 05:00:00,3\n", &mapping).unwrap();
+        assert_eq!(telemetry.points[0].timestamp.to_rfc3339(), "2024-01-15T10:00:00+00:00");
+
+        assert!(mapping.add_column_rule("speed").is_err());
+        mapping.timestamp.column = "missing".to_string();
+        assert!(TelemetryData::from_csv_with_mapping("t,speed\n0,1\n", &mapping).is_err());
+    }
+
+    #[test]
+    fn test_csv_writer_round_trip() {
+        let csv = "timestamp,speed,latitude,coolant_temp (°C),gear\n\
+            2024-01-15T10:00:00Z,10,40.5,88.5,N\n\
+            2024-01-15T10:00:00.5Z,20,40.6,,1\n";
+        let telemetry = TelemetryData::from_csv(csv).unwrap();
+        assert_eq!(telemetry.points[0].channels["coolant_temp"].unit.as_deref(), Some("°C"));
+
+        let written = telemetry.to_csv(None).unwrap();
+        assert!(written.starts_with("timestamp,latitude,speed,coolant_temp (°C),gear\n"));
+        let restored = TelemetryData::from_csv(&written).unwrap();
+        for (restored, original) in restored.points.iter().zip(&telemetry.points) {
+            assert_eq!(restored.timestamp, original.timestamp);
+            assert_eq!(restored.speed, original.speed);
+            assert_eq!(restored.latitude, original.latitude);
+            assert_eq!(restored.channels, original.channels);
+        }
+
+        let columns = ["speed:km/h".to_string(), "gear".to_string()];
+        let written = telemetry.to_csv(Some(&columns)).unwrap();
+        assert!(written.contains("timestamp,speed (km/h),gear\n2024-01-15T10:00:00Z,36,N\n"));
+        let restored = TelemetryData::from_csv(&written).unwrap();
+        assert_eq!(restored.points[1].speed, Some(20.0));
+
+        assert!(telemetry.to_csv(Some(&["gear:km/h".to_string()])).is_err());
+    }
+}
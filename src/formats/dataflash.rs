@@ -0,0 +1,362 @@
+//! ArduPilot DataFlash (`.bin`) log decoder
+//!
+//! Every message starts with the bytes `A3 95` and a message type. `FMT`
+//! messages (type 128) describe the length, field types and field labels of
+//! every other message type, so the log is decoded without a fixed schema.
+
+use std::{collections::HashMap, io::Read};
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use crate::{
+    error::OverlogError,
+    telemetry::{channel, TelemetryData},
+};
+use super::{stream::Lookahead, Timeline, TimelinePoints, TimelineSource};
+
+const HEADER: [u8; 2] = [0xA3, 0x95];
+const FMT_TYPE: u8 = 128;
+/// Length of an FMT message including its header
+const FMT_LENGTH: usize = 89;
+const STANDARD_GRAVITY: f64 = 9.80665;
+/// GPS time runs ahead of UTC by the accumulated leap seconds
+const GPS_LEAP_SECONDS: i64 = 18;
+const EMPTY: &str = "No timed DataFlash messages found";
+
+/// Check for the DataFlash header followed by the self-describing FMT message
+pub(crate) fn is_dataflash(data: &[u8]) -> bool {
+    data.starts_with(&[HEADER[0], HEADER[1], FMT_TYPE])
+}
+
+impl TelemetryData {
+    /// Parse an ArduPilot DataFlash binary log
+    ///
+    /// GPS, ATT, BARO, IMU, RCIN, RCOU, BAT/CURR and MODE messages are each
+    /// kept at their own rate on the boot-time (`TimeUS`) timeline. The first
+    /// GPS message with a 3D fix ties that timeline to UTC.
+    pub fn from_dataflash(data: &[u8]) -> Result<Self, OverlogError> {
+        Self::from_dataflash_reader(data)
+    }
+
+    /// Parse a DataFlash log from a reader, message by message
+    pub fn from_dataflash_reader(reader: impl Read) -> Result<Self, OverlogError> {
+        let timeline = DataFlashReader::new(reader)?.read_timeline()?;
+        if timeline.is_empty() {
+            return Err(OverlogError::Telemetry(EMPTY.to_string()));
+        }
+        Ok(timeline.into_telemetry("dataflash", None))
+    }
+}
+
+/// Points of a DataFlash log, yielded while it is read
+pub(crate) fn points<R: Read>(reader: R) -> Result<TimelinePoints<DataFlashReader<R>>, OverlogError> {
+    Ok(TimelinePoints::new(DataFlashReader::new(reader)?, EMPTY))
+}
+
+/// DataFlash messages decoded one at a time
+pub(crate) struct DataFlashReader<R> {
+    input: Lookahead<R>,
+    formats: HashMap<u8, Format>,
+}
+
+impl<R: Read> DataFlashReader<R> {
+    fn new(reader: R) -> Result<Self, OverlogError> {
+        let mut input = Lookahead::new(reader);
+        if !input.peek(3)?.is_some_and(is_dataflash) {
+            return Err(OverlogError::Telemetry("Not an ArduPilot DataFlash log".to_string()));
+        }
+        Ok(Self { input, formats: HashMap::new() })
+    }
+}
+
+impl<R: Read> TimelineSource for DataFlashReader<R> {
+    fn read_message(&mut self, timeline: &mut Timeline) -> Result<bool, OverlogError> {
+        loop {
+            let Some(header) = self.input.peek(3)? else { return Ok(false) };
+            if header[..2] != HEADER {
+                // Skip corrupt bytes until the next message header
+                self.input.consume(1);
+                continue;
+            }
+            let msg_type = header[2];
+
+            if msg_type == FMT_TYPE {
+                let Some(message) = self.input.peek(FMT_LENGTH)? else { return Ok(false) };
+                let format = Format::parse(&message[3..]);
+                self.formats.insert(format.msg_type, format);
+                self.input.consume(FMT_LENGTH);
+                return Ok(true);
+            }
+
+            let Some(format) = self.formats.get(&msg_type).filter(|format| format.length > 3) else {
+                self.input.consume(1);
+                continue;
+            };
+            let Some(message) = self.input.peek(format.length)? else { return Ok(false) };
+            let message = format.decode(&message[3..]);
+            self.input.consume(format.length);
+
+            if let Some(time_us) = message.get("TimeUS") {
+                apply_message(timeline, &format.name, &message, time_us as i64);
+            }
+            return Ok(true);
+        }
+    }
+}
+
+fn apply_message(timeline: &mut Timeline, name: &str, message: &Message, time_us: i64) {
+    // Only the primary instance of multi-instance sensors is used
+    if message.get("I").or_else(|| message.get("Inst")).is_some_and(|i| i != 0.0) {
+        return;
+    }
+
+    match name {
+        "GPS" => {
+            let status = message.get("Status").unwrap_or(0.0);
+            if status < 2.0 {
+                return;
+            }
+            if status >= 3.0 {
+                if let Some(utc) = message.get("GWk").zip(message.get("GMS")).and_then(|(w, ms)| gps_time(w, ms)) {
+                    timeline.anchor(time_us, utc);
+                }
+            }
+            let point = timeline.at(time_us);
+            point.latitude = message.get("Lat");
+            point.longitude = message.get("Lng");
+            point.altitude = message.get("Alt");
+            point.speed = message.get("Spd");
+            point.heading = message.get("GCrs");
+            message.copy(point, &[("Status", channel::GPS_FIX), ("NSats", channel::SATELLITES), ("HDop", channel::GPS_HDOP)]);
+        }
+        "ATT" => {
+            let point = timeline.at(time_us);
+            message.copy(point, &[("Roll", channel::ROLL), ("Pitch", channel::PITCH), ("Yaw", channel::YAW)]);
+        }
+        "BARO" => {
+            let point = timeline.at(time_us);
+            message.copy(point, &[("Alt", channel::BARO_ALTITUDE), ("Press", channel::PRESSURE)]);
+        }
+        "IMU" => {
+            let point = timeline.at(time_us);
+            point.g_force_x = message.get("AccX").map(|a| a / STANDARD_GRAVITY);
+            point.g_force_y = message.get("AccY").map(|a| a / STANDARD_GRAVITY);
+            point.g_force_z = message.get("AccZ").map(|a| a / STANDARD_GRAVITY);
+            message.copy(point, &[("GyrX", channel::GYRO_X), ("GyrY", channel::GYRO_Y), ("GyrZ", channel::GYRO_Z)]);
+        }
+        "RCIN" | "RCOU" => {
+            let point = timeline.at(time_us);
+            for (label, value) in &message.fields {
+                let Some(index) = label.strip_prefix('C').and_then(|n| n.parse::<usize>().ok()) else { continue };
+                let name = if name == "RCIN" { channel::rc_in(index) } else { channel::rc_out(index) };
+                point.set_channel(&name, *value);
+            }
+        }
+        "BAT" | "CURR" => {
+            let point = timeline.at(time_us);
+            message.copy(point, &[
+                ("Volt", channel::BATTERY_VOLTAGE),
+                ("Curr", channel::BATTERY_CURRENT),
+                ("CurrTot", channel::BATTERY_CONSUMED),
+            ]);
+        }
+        "MODE" => {
+            if let Some(mode) = message.get("ModeNum").or_else(|| message.get("Mode")) {
+                timeline.at(time_us).set_channel(channel::FLIGHT_MODE, mode);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Convert a GPS week and millisecond-of-week to UTC
+fn gps_time(week: f64, ms: f64) -> Option<DateTime<Utc>> {
+    let epoch = NaiveDate::from_ymd_opt(1980, 1, 6)?.and_hms_opt(0, 0, 0)?.and_utc();
+    Some(epoch + Duration::weeks(week as i64) + Duration::milliseconds(ms as i64) - Duration::seconds(GPS_LEAP_SECONDS))
+}
+
+struct Format {
+    msg_type: u8,
+    /// Total message length including the 3 byte header
+    length: usize,
+    name: String,
+    types: Vec<u8>,
+    labels: Vec<String>,
+}
+
+impl Format {
+    /// Parse the body of an FMT message: Type, Length, Name[4], Format[16], Labels[64]
+    fn parse(body: &[u8]) -> Self {
+        Format {
+            msg_type: body[0],
+            length: body[1] as usize,
+            name: c_string(&body[2..6]),
+            types: c_string(&body[6..22]).into_bytes(),
+            labels: c_string(&body[22..86]).split(',').map(str::to_string).collect(),
+        }
+    }
+
+    fn decode(&self, body: &[u8]) -> Message {
+        let mut fields = Vec::with_capacity(self.types.len());
+        let mut pos = 0;
+
+        for (i, &t) in self.types.iter().enumerate() {
+            let size = field_size(t);
+            let Some(raw) = body.get(pos..pos + size) else { break };
+            pos += size;
+            if let (Some(value), Some(label)) = (decode_field(t, raw), self.labels.get(i)) {
+                fields.push((label.clone(), value));
+            }
+        }
+
+        Message { fields }
+    }
+}
+
+struct Message {
+    fields: Vec<(String, f64)>,
+}
+
+impl Message {
+    fn get(&self, label: &str) -> Option<f64> {
+        self.fields.iter().find(|(l, _)| l == label).map(|(_, v)| *v)
+    }
+
+    /// Copy labelled fields into the named channels of a point
+    fn copy(&self, point: &mut crate::telemetry::TelemetryPoint, mapping: &[(&str, &str)]) {
+        for (label, name) in mapping {
+            if let Some(value) = self.get(label) {
+                point.set_channel(name, value);
+            }
+        }
+    }
+}
+
+fn field_size(t: u8) -> usize {
+    match t {
+        b'b' | b'B' | b'M' => 1,
+        b'h' | b'H' | b'c' | b'C' => 2,
+        b'i' | b'I' | b'f' | b'e' | b'E' | b'L' | b'n' => 4,
+        b'd' | b'q' | b'Q' => 8,
+        b'N' => 16,
+        b'a' | b'Z' => 64,
+        _ => 0,
+    }
+}
+
+/// Decode a numeric field, applying the scaling implied by its type; strings yield `None`
+fn decode_field(t: u8, raw: &[u8]) -> Option<f64> {
+    let value = match t {
+        b'b' => raw[0] as i8 as f64,
+        b'B' | b'M' => raw[0] as f64,
+        b'h' => i16::from_le_bytes(raw.try_into().ok()?) as f64,
+        b'H' => u16::from_le_bytes(raw.try_into().ok()?) as f64,
+        b'c' => i16::from_le_bytes(raw.try_into().ok()?) as f64 / 100.0,
+        b'C' => u16::from_le_bytes(raw.try_into().ok()?) as f64 / 100.0,
+        b'i' => i32::from_le_bytes(raw.try_into().ok()?) as f64,
+        b'I' => u32::from_le_bytes(raw.try_into().ok()?) as f64,
+        b'e' => i32::from_le_bytes(raw.try_into().ok()?) as f64 / 100.0,
+        b'E' => u32::from_le_bytes(raw.try_into().ok()?) as f64 / 100.0,
+        b'L' => i32::from_le_bytes(raw.try_into().ok()?) as f64 / 1e7,
+        b'f' => f32::from_le_bytes(raw.try_into().ok()?) as f64,
+        b'd' => f64::from_le_bytes(raw.try_into().ok()?),
+        b'q' => i64::from_le_bytes(raw.try_into().ok()?) as f64,
+        b'Q' => u64::from_le_bytes(raw.try_into().ok()?) as f64,
+        _ => return None,
+    };
+    Some(value)
+}
+
+fn c_string(raw: &[u8]) -> String {
+    let end = raw.iter().position(|&b| b == 0).unwrap_or(raw.len());
+    String::from_utf8_lossy(&raw[..end]).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fmt(msg_type: u8, name: &str, types: &str, labels: &str) -> Vec<u8> {
+        let length = 3 + types.bytes().map(field_size).sum::<usize>();
+        let mut out = vec![0xA3, 0x95, FMT_TYPE, msg_type, length as u8];
+        for (text, size) in [(name, 4), (types, 16), (labels, 64)] {
+            let mut field = text.as_bytes().to_vec();
+            field.resize(size, 0);
+            out.extend(field);
+        }
+        out
+    }
+
+    fn sample_log() -> Vec<u8> {
+        let mut log = fmt(FMT_TYPE, "FMT", "BBnNZ", "Type,Length,Name,Format,Columns");
+        log.extend(fmt(130, "GPS", "QBIHBcLLefff", "TimeUS,Status,GMS,GWk,NSats,HDop,Lat,Lng,Alt,Spd,GCrs,VZ"));
+        log.extend(fmt(131, "ATT", "Qcc", "TimeUS,Roll,Pitch"));
+
+        let mut gps = vec![0xA3, 0x95, 130];
+        gps.extend(2_000_000u64.to_le_bytes());
+        gps.push(3);
+        gps.extend(0u32.to_le_bytes());
+        gps.extend(2297u16.to_le_bytes());
+        gps.push(12);
+        gps.extend(90i16.to_le_bytes());
+        gps.extend(407_128_000i32.to_le_bytes());
+        gps.extend((-740_060_000i32).to_le_bytes());
+        gps.extend(1000i32.to_le_bytes());
+        gps.extend(8.5f32.to_le_bytes());
+        gps.extend(90.0f32.to_le_bytes());
+        gps.extend(0.0f32.to_le_bytes());
+        log.extend(gps);
+
+        // Attitude at its own, faster rate
+        for (time, roll) in [(2_000_000u64, 150i16), (2_100_000, 250)] {
+            log.extend([0xA3, 0x95, 131]);
+            log.extend(time.to_le_bytes());
+            log.extend(roll.to_le_bytes());
+            log.extend(0i16.to_le_bytes());
+        }
+        log
+    }
+
+    #[test]
+    fn test_dataflash_messages() {
+        let log = sample_log();
+        assert!(is_dataflash(&log));
+        let telemetry = TelemetryData::from_dataflash(&log).unwrap();
+
+        assert_eq!(telemetry.points.len(), 2);
+        let first = &telemetry.points[0];
+        assert!((first.latitude.unwrap() - 40.7128).abs() < 1e-9);
+        assert!((first.longitude.unwrap() + 74.006).abs() < 1e-9);
+        assert_eq!(first.altitude, Some(10.0));
+        assert_eq!(first.speed, Some(8.5));
+        assert_eq!(first.channel(channel::SATELLITES), Some(12.0));
+        assert_eq!(first.channel(channel::ROLL), Some(1.5));
+
+        // Week 2297 starts 2024-01-14 in GPS time
+        assert_eq!(first.timestamp.to_rfc3339(), "2024-01-13T23:59:42+00:00");
+        let second = &telemetry.points[1];
+        assert_eq!((second.timestamp - first.timestamp).num_milliseconds(), 100);
+        assert_eq!(second.latitude, None);
+        assert_eq!(second.channel(channel::ROLL), Some(2.5));
+    }
+
+    #[test]
+    fn test_dataflash_points() {
+        let mut log = sample_log();
+        // Attitude before the first fix is held until the fix ties the timeline to UTC
+        log.extend([0xA3, 0x95, 131]);
+        log.extend(1_000_000u64.to_le_bytes());
+        log.extend(50i16.to_le_bytes());
+        log.extend(0i16.to_le_bytes());
+
+        let streamed: Vec<_> = points(std::io::Cursor::new(log.clone())).unwrap().collect::<Result<_, _>>().unwrap();
+        let telemetry = TelemetryData::from_dataflash(&log).unwrap();
+        assert_eq!(streamed.len(), 3);
+        for (streamed, parsed) in streamed.iter().zip(&telemetry.points) {
+            assert_eq!(streamed.timestamp, parsed.timestamp);
+            assert_eq!(streamed.channel(channel::ROLL), parsed.channel(channel::ROLL));
+        }
+
+        assert!(points(std::io::Cursor::new(b"not a log".to_vec())).is_err());
+        let formats_only = fmt(FMT_TYPE, "FMT", "BBnNZ", "Type,Length,Name,Format,Columns");
+        assert!(points(std::io::Cursor::new(formats_only)).unwrap().next().unwrap().is_err());
+    }
+}
@@ -0,0 +1,281 @@
+//! DJI drone `.SRT` subtitle telemetry decoder
+//!
+//! Two layouts are in the wild. Older aircraft (Phantom 3/4, Mavic Pro) write
+//! `HOME(lon,lat) 2017.08.05 14:11:51` and `GPS(lon,lat,alt) BAROMETER:1.9`
+//! followed by `ISO:100 Shutter:60 EV:0 Fnum:2.2`. Newer aircraft write a
+//! date line and bracketed pairs such as `[iso: 100] [shutter: 1/1000.0]
+//! [fnum: 280] [latitude: 47.1] [longitude: 8.1] [rel_alt: 1.3 abs_alt: 435.2]`.
+
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use crate::{
+    error::OverlogError,
+    telemetry::{channel, TelemetryData, TelemetryPoint},
+};
+use super::timing::{TimestampPolicy, Zone};
+
+impl TelemetryData {
+    /// Parse a DJI `.SRT` telemetry sidecar
+    ///
+    /// Each subtitle cue becomes one point placed at the cue start time, so
+    /// points line up with the frames of the matching video. The times are
+    /// relative to the start of the video, and the timeline starts at the
+    /// Unix epoch; see [`TelemetryData::from_dji_srt_with`] to place it.
+    pub fn from_dji_srt(srt_data: &str) -> Result<Self, OverlogError> {
+        Self::from_dji_srt_with(srt_data, &TimestampPolicy::default())
+    }
+
+    /// Parse a DJI `.SRT` telemetry sidecar, reading the drone's wall clock
+    /// in the policy's timezone
+    ///
+    /// The drone clock is local time without a zone, so it only anchors the
+    /// timeline when the policy names one. Otherwise the times stay relative
+    /// to the start of the video, to be placed with the policy's start time.
+    pub fn from_dji_srt_with(srt_data: &str, policy: &TimestampPolicy) -> Result<Self, OverlogError> {
+        let zone = policy.timezone.as_deref().map(|name| Zone::new(Some(name))).transpose()?;
+        let mut cues = Vec::new();
+        let mut origin: Option<DateTime<Utc>> = None;
+
+        let normalized = srt_data.replace("\r\n", "\n");
+        for block in normalized.split("\n\n").filter(|b| !b.trim().is_empty()) {
+            let mut lines = block.lines().map(str::trim).skip_while(|l| l.is_empty());
+            // Cue number, then "00:00:01,000 --> 00:00:02,000"
+            lines.next();
+            let Some(start) = lines.next().and_then(parse_cue_start) else {
+                return Err(OverlogError::Telemetry(format!("Invalid SRT cue: {}", block.trim())));
+            };
+            let text = strip_tags(&lines.collect::<Vec<_>>().join(" "));
+
+            let (point, wall_clock) = parse_cue(&text);
+            if let (None, Some(zone), Some(local)) = (origin, &zone, wall_clock) {
+                origin = Some(zone.to_utc(local)? - start);
+            }
+            cues.push((start, point));
+        }
+
+        if cues.is_empty() {
+            return Err(OverlogError::Telemetry("No SRT cues found".to_string()));
+        }
+
+        let mut telemetry = TelemetryData::new();
+        telemetry.metadata.format = "srt".to_string();
+        telemetry.metadata.times.relative = origin.is_none();
+        let origin = origin.unwrap_or(DateTime::<Utc>::UNIX_EPOCH);
+        telemetry.points = cues
+            .into_iter()
+            .map(|(start, mut point)| {
+                point.timestamp = origin + start;
+                point
+            })
+            .collect();
+
+        telemetry.calculate_metadata();
+        Ok(telemetry)
+    }
+}
+
+/// Parse the start of an SRT timing line (`hh:mm:ss,mmm --> ...`)
+fn parse_cue_start(line: &str) -> Option<Duration> {
+    let (start, _) = line.split_once("-->")?;
+    let (hms, millis) = start.trim().split_once([',', '.'])?;
+    let mut parts = hms.split(':').map(|p| p.parse::<i64>().ok());
+    let (hours, minutes, seconds) = (parts.next()??, parts.next()??, parts.next()??);
+    Some(Duration::milliseconds(((hours * 60 + minutes) * 60 + seconds) * 1000 + millis.parse::<i64>().ok()?))
+}
+
+fn strip_tags(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut in_tag = false;
+    for ch in text.chars() {
+        match ch {
+            '<' => in_tag = true,
+            '>' => in_tag = false,
+            _ if !in_tag => out.push(ch),
+            _ => {}
+        }
+    }
+    out
+}
+
+/// Decode the text of one cue into a point and its wall clock time, if any
+fn parse_cue(text: &str) -> (TelemetryPoint, Option<NaiveDateTime>) {
+    let mut point = TelemetryPoint::default();
+
+    // Old layout: GPS(longitude, latitude, altitude)
+    if let Some(args) = function_args(text, "GPS") {
+        point.longitude = args.first().and_then(|v| leading_number(v));
+        point.latitude = args.get(1).and_then(|v| leading_number(v));
+        // A bare third value is a satellite count on some models, altitudes carry an 'M'
+        if let Some(alt) = args.get(2).filter(|v| v.trim().ends_with(['M', 'm'])) {
+            point.altitude = leading_number(alt);
+        }
+    }
+
+    let flattened = text.replace(['[', ']', ','], " ");
+    let tokens: Vec<&str> = flattened.split_whitespace().collect();
+    let mut wall_clock = None;
+
+    let mut i = 0;
+    while i < tokens.len() {
+        // Date and time: "2021-06-12 14:22:37.620" or "2017.08.05 14:11:51"
+        if wall_clock.is_none() && i + 1 < tokens.len() {
+            if let Some(time) = parse_wall_clock(tokens[i], tokens[i + 1]) {
+                wall_clock = Some(time);
+                i += 2;
+                continue;
+            }
+        }
+
+        // "key: value", "key : value" or "key:value"
+        let (key, value, used) = match tokens[i].split_once(':') {
+            Some((key, "")) => (key, tokens.get(i + 1).copied(), 2),
+            Some((key, value)) => (key, Some(value), 1),
+            None if tokens.get(i + 1) == Some(&":") => (tokens[i], tokens.get(i + 2).copied(), 3),
+            None => {
+                i += 1;
+                continue;
+            }
+        };
+        if let Some(value) = value {
+            apply_pair(&mut point, &key.to_ascii_lowercase(), value);
+        }
+        i += used;
+    }
+
+    (point, wall_clock)
+}
+
+fn apply_pair(point: &mut TelemetryPoint, key: &str, value: &str) {
+    let Some(number) = leading_number(value) else { return };
+    // Newer firmware writes integers scaled by 100 (fnum) and 10 (focal_len)
+    let scaled = |divisor: f64| if value.contains('.') { number } else { number / divisor };
+
+    match key {
+        "latitude" => point.latitude = Some(number),
+        "longitude" | "longtitude" => point.longitude = Some(number),
+        "abs_alt" | "altitude" => point.altitude = Some(number),
+        "rel_alt" | "barometer" => point.set_channel(channel::RELATIVE_ALTITUDE, number),
+        "iso" => point.set_channel(channel::ISO, number),
+        "shutter" => {
+            // "1/1000.0" on newer firmware, the denominator alone on older firmware
+            let exposure = match value.split_once('/') {
+                Some((num, den)) => leading_number(num).zip(leading_number(den)).map(|(n, d)| n / d),
+                None if number > 0.0 => Some(1.0 / number),
+                None => None,
+            };
+            if let Some(exposure) = exposure {
+                point.set_channel(channel::EXPOSURE_TIME, exposure);
+            }
+        }
+        "fnum" => point.set_channel(channel::F_NUMBER, scaled(100.0)),
+        "focal_len" => point.set_channel(channel::FOCAL_LENGTH, scaled(10.0)),
+        "ev" => point.set_channel(channel::EXPOSURE_BIAS, number),
+        "ct" => point.set_channel(channel::COLOR_TEMPERATURE, number),
+        _ => {}
+    }
+}
+
+/// Parse the drone's local date and time
+fn parse_wall_clock(date: &str, time: &str) -> Option<NaiveDateTime> {
+    let joined = format!("{} {}", date, time);
+    ["%Y-%m-%d %H:%M:%S%.f", "%Y.%m.%d %H:%M:%S%.f", "%Y-%m-%d %H:%M:%S,%f"]
+        .iter()
+        .find_map(|fmt| NaiveDateTime::parse_from_str(&joined, fmt).ok())
+}
+
+/// Arguments of a `NAME(a,b,c)` group
+fn function_args<'a>(text: &'a str, name: &str) -> Option<Vec<&'a str>> {
+    let start = text.find(&format!("{}(", name))? + name.len() + 1;
+    let end = start + text[start..].find(')')?;
+    Some(text[start..end].split(',').map(str::trim).collect())
+}
+
+/// Parse the numeric prefix of a value such as `435.220` or `1.9M`
+fn leading_number(value: &str) -> Option<f64> {
+    let value = value.trim();
+    let end = value
+        .char_indices()
+        .find(|&(i, c)| !(c.is_ascii_digit() || c == '.' || ((c == '-' || c == '+') && i == 0)))
+        .map(|(i, _)| i)
+        .unwrap_or(value.len());
+    value[..end].parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new_layout() {
+        let srt = "1\n00:00:00,000 --> 00:00:00,033\n<font size=\"28\">FrameCnt: 1, DiffTime: 33ms\n\
+            2021-06-12 14:22:37.620\n\
+            [iso: 100] [shutter: 1/1000.0] [fnum: 280] [ev: 0] [ct: 5500] [color_md: default] [focal_len: 240] \
+            [latitude: 47.123456] [longitude: 8.123456] [rel_alt: 1.300 abs_alt: 435.220] </font>\n\n\
+            2\n00:00:00,033 --> 00:00:00,066\n<font size=\"28\">FrameCnt: 2, DiffTime: 33ms\n\
+            2021-06-12 14:22:37.653\n\
+            [iso: 100] [shutter: 1/1000.0] [fnum: 280] [ev: 0] [latitude: 47.123457] [longitude: 8.123457] \
+            [rel_alt: 1.400 abs_alt: 435.320] </font>\n";
+
+        let policy = TimestampPolicy { timezone: Some("Europe/Zurich".to_string()), ..Default::default() };
+        let telemetry = TelemetryData::from_dji_srt_with(srt, &policy).unwrap();
+        assert_eq!(telemetry.points.len(), 2);
+        assert!(!telemetry.metadata.times.relative);
+
+        // The drone clock is local summer time, two hours ahead of UTC
+        let first = &telemetry.points[0];
+        assert_eq!(first.timestamp.to_rfc3339(), "2021-06-12T12:22:37.620+00:00");
+        assert_eq!(first.latitude, Some(47.123456));
+        assert_eq!(first.longitude, Some(8.123456));
+        assert_eq!(first.altitude, Some(435.22));
+        assert_eq!(first.channel(channel::RELATIVE_ALTITUDE), Some(1.3));
+        assert_eq!(first.channel(channel::ISO), Some(100.0));
+        assert_eq!(first.channel(channel::EXPOSURE_TIME), Some(0.001));
+        assert_eq!(first.channel(channel::F_NUMBER), Some(2.8));
+        assert_eq!(first.channel(channel::FOCAL_LENGTH), Some(24.0));
+
+        let offset = telemetry.points[1].timestamp - first.timestamp;
+        assert_eq!(offset.num_milliseconds(), 33);
+    }
+
+    #[test]
+    fn test_old_layout() {
+        let srt = "1\r\n00:00:01,000 --> 00:00:02,000\r\nHOME(149.0251,-20.2532) 2017.08.05 14:11:51\r\n\
+            GPS(149.0251,-20.2533,16) BAROMETER:1.9M\r\nISO:100 Shutter:60 EV:0 Fnum:2.2\r\n";
+
+        let policy = TimestampPolicy { timezone: Some("+10:00".to_string()), ..Default::default() };
+        let telemetry = TelemetryData::from_dji_srt_with(srt, &policy).unwrap();
+        let point = &telemetry.points[0];
+
+        // The cue starts one second into the video
+        assert_eq!(point.timestamp.to_rfc3339(), "2017-08-05T04:11:51+00:00");
+        assert_eq!(telemetry.points.len(), 1);
+        assert_eq!(point.latitude, Some(-20.2533));
+        assert_eq!(point.longitude, Some(149.0251));
+        assert_eq!(point.altitude, None);
+        assert_eq!(point.channel(channel::RELATIVE_ALTITUDE), Some(1.9));
+        assert_eq!(point.channel(channel::F_NUMBER), Some(2.2));
+        assert!((point.channel(channel::EXPOSURE_TIME).unwrap() - 1.0 / 60.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_relative_times() {
+        let srt = "1\n00:00:01,500 --> 00:00:02,000\nGPS(149.0251,-20.2533,16)\n";
+
+        let mut telemetry = TelemetryData::from_dji_srt(srt).unwrap();
+        assert!(telemetry.metadata.times.relative);
+        assert_eq!(telemetry.points[0].timestamp.to_rfc3339(), "1970-01-01T00:00:01.500+00:00");
+
+        let start = "2024-01-15T10:00:00Z".parse().unwrap();
+        telemetry.apply_timestamp_policy(&TimestampPolicy { start: Some(start), ..Default::default() });
+        assert_eq!(telemetry.points[0].timestamp.to_rfc3339(), "2024-01-15T10:00:01.500+00:00");
+
+        // A wall clock without a zone doesn't make the times absolute
+        let dated = srt.replace("GPS", "2017.08.05 14:11:51 GPS");
+        let telemetry = TelemetryData::from_dji_srt(&dated).unwrap();
+        assert!(telemetry.metadata.times.relative);
+        assert_eq!(telemetry.points[0].timestamp.to_rfc3339(), "1970-01-01T00:00:01.500+00:00");
+        let policy = TimestampPolicy { timezone: Some("UTC".to_string()), ..Default::default() };
+        assert!(!TelemetryData::from_dji_srt_with(&dated, &policy).unwrap().metadata.times.relative);
+        let policy = TimestampPolicy { timezone: Some("Mars/Olympus".to_string()), ..Default::default() };
+        assert!(TelemetryData::from_dji_srt_with(&dated, &policy).is_err());
+    }
+}
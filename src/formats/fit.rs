@@ -0,0 +1,828 @@
+//! Garmin/ANT+ Flexible and Interoperable Data Transfer (FIT) decoder and encoder
+
+use std::collections::{BTreeSet, HashMap};
+use chrono::{DateTime, TimeZone, Utc};
+use crate::{
+    error::OverlogError,
+    telemetry::{channel, Event, EventKind, Lap, TelemetryData, TelemetryPoint},
+};
+use super::columns::Field;
+
+/// Seconds between the Unix epoch and the FIT epoch (1989-12-31T00:00:00Z)
+const FIT_EPOCH_OFFSET: i64 = 631_065_600;
+
+/// Global message numbers from the FIT profile
+const MESG_FILE_ID: u16 = 0;
+const MESG_SESSION: u16 = 18;
+const MESG_LAP: u16 = 19;
+const MESG_RECORD: u16 = 20;
+const MESG_EVENT: u16 = 21;
+const MESG_ACTIVITY: u16 = 34;
+const MESG_FIELD_DESCRIPTION: u16 = 206;
+const MESG_DEVELOPER_DATA_ID: u16 = 207;
+
+/// Field number shared by every message that carries a timestamp
+const FIELD_TIMESTAMP: u8 = 253;
+
+impl TelemetryData {
+    /// Parse a binary FIT activity file
+    ///
+    /// `record` messages become points, `lap` messages become [`Lap`]s, timer
+    /// and marker `event` messages become [`Event`]s and developer fields are
+    /// stored as channels under the name given in their field description.
+    pub fn from_fit(data: &[u8]) -> Result<Self, OverlogError> {
+        let mut decoder = Decoder::default();
+        let mut telemetry = TelemetryData::new();
+        telemetry.metadata.format = "fit".to_string();
+
+        // Chained FIT files are concatenated header + records + CRC blocks
+        let mut pos = 0;
+        while pos < data.len() {
+            pos = decoder.file(data, pos, &mut telemetry)?;
+        }
+
+        telemetry.calculate_metadata();
+
+        // Prefer the device's own distance over a point-to-point sum
+        if let Some(distance) = decoder.session_distance {
+            telemetry.metadata.total_distance = Some(distance);
+        }
+
+        Ok(telemetry)
+    }
+
+    /// Write a FIT activity file
+    ///
+    /// Position, altitude, speed, heart rate, cadence, distance, power and
+    /// temperature go into their `record` profile fields; other fields and
+    /// numeric channels become float64 developer fields. Events, laps (one
+    /// covering the recording when there are none), a session and an activity
+    /// message follow. FIT timestamps have whole second resolution; a log
+    /// without points is dated at the FIT epoch, 1989-12-31T00:00:00Z.
+    pub fn to_fit(&self) -> Vec<u8> {
+        let mut encoder = Encoder::default();
+        // An empty log is dated at the FIT epoch, so the file doesn't depend on when it was written
+        let fit_epoch = DateTime::from_timestamp(FIT_EPOCH_OFFSET, 0).unwrap_or_default();
+        let start = self.points.first().map_or(fit_epoch, |p| p.timestamp);
+        let end = self.points.last().map(|p| p.timestamp).unwrap_or(start);
+
+        encoder.message(MESG_FILE_ID, &[
+            (0, 0x00, Some(4.0)), // type: activity
+            (1, 0x84, Some(255.0)), // manufacturer: development
+            (4, 0x86, Some(fit_seconds(&start))), // time_created
+        ]);
+
+        // Developer fields for everything without a profile field
+        let channels: BTreeSet<&String> = self
+            .points
+            .iter()
+            .flat_map(|p| p.channels.iter())
+            .filter(|(name, channel)| channel.value.as_f64().is_some() && !RECORD_CHANNELS.contains(&name.as_str()))
+            .map(|(name, _)| name)
+            .collect();
+        let developer: Vec<Field> = Field::POINT_FIELDS[4..]
+            .iter()
+            .cloned()
+            .chain(channels.into_iter().map(|name| Field::Channel(name.clone())))
+            .filter(|field| self.points.iter().any(|p| field.get(p).is_some()))
+            .take(usize::from(u8::MAX))
+            .collect();
+        if !developer.is_empty() {
+            encoder.message(MESG_DEVELOPER_DATA_ID, &[(3, 0x02, Some(0.0))]);
+            for (number, field) in developer.iter().enumerate() {
+                let units = match field {
+                    Field::Channel(name) => self.points.iter().find_map(|p| p.channels.get(name)?.unit.clone()),
+                    _ => None,
+                };
+                encoder.field_description(number as u8, field.name(), units.as_deref().unwrap_or_default());
+            }
+        }
+
+        let mut events = self.events.iter().peekable();
+        for point in &self.points {
+            while let Some(event) = events.next_if(|e| e.timestamp <= point.timestamp) {
+                encoder.event(event);
+            }
+            let developer_values: Vec<Option<f64>> = developer.iter().map(|field| field.get(point)).collect();
+            encoder.record(point, &developer_values);
+        }
+        for event in events {
+            encoder.event(event);
+        }
+
+        let whole = [Lap {
+            start_time: start,
+            end_time: Some(end),
+            total_time: None,
+            distance: self.metadata.total_distance,
+        }];
+        let laps = if self.laps.is_empty() { &whole[..] } else { &self.laps[..] };
+        for lap in laps {
+            let end_time = lap.end_time.unwrap_or(end);
+            let elapsed = lap
+                .total_time
+                .unwrap_or_else(|| crate::utils::seconds_between(lap.start_time, end_time));
+            encoder.message(MESG_LAP, &[
+                (FIELD_TIMESTAMP, 0x86, Some(fit_seconds(&end_time))),
+                (2, 0x86, Some(fit_seconds(&lap.start_time))),
+                (7, 0x86, Some(elapsed * 1000.0)),
+                (8, 0x86, Some(elapsed * 1000.0)),
+                (9, 0x86, lap.distance.map(|d| d * 100.0)),
+            ]);
+        }
+
+        let elapsed = crate::utils::seconds_between(start, end) * 1000.0;
+        encoder.message(MESG_SESSION, &[
+            (FIELD_TIMESTAMP, 0x86, Some(fit_seconds(&end))),
+            (2, 0x86, Some(fit_seconds(&start))),
+            (5, 0x00, Some(0.0)), // sport: generic
+            (7, 0x86, Some(elapsed)),
+            (8, 0x86, Some(elapsed)),
+            (9, 0x86, self.metadata.total_distance.map(|d| d * 100.0)),
+        ]);
+        encoder.message(MESG_ACTIVITY, &[
+            (FIELD_TIMESTAMP, 0x86, Some(fit_seconds(&end))),
+            (0, 0x86, Some(elapsed)),
+            (1, 0x84, Some(1.0)), // num_sessions
+            (2, 0x00, Some(0.0)), // type: manual
+            (3, 0x00, Some(26.0)), // event: activity
+            (4, 0x00, Some(1.0)), // event_type: stop
+        ]);
+
+        fit_file(&encoder.records)
+    }
+}
+
+/// Channels stored in `record` profile fields
+const RECORD_CHANNELS: [&str; 5] =
+    [channel::HEART_RATE, channel::CADENCE, channel::DISTANCE, channel::POWER, channel::TEMPERATURE];
+
+/// Builds the records section, defining each local message type on first use
+#[derive(Default)]
+struct Encoder {
+    records: Vec<u8>,
+    locals: Vec<u16>,
+}
+
+impl Encoder {
+    /// Local message type for a global message, writing its definition the
+    /// first time; `fields` are (number, size, base type)
+    fn define(&mut self, global: u16, fields: &[(u8, u8, u8)], developer_fields: &[(u8, u8, u8)]) -> u8 {
+        if let Some(local) = self.locals.iter().position(|&g| g == global) {
+            return local as u8;
+        }
+        let local = self.locals.len() as u8;
+        self.locals.push(global);
+
+        let developer_flag = if developer_fields.is_empty() { 0 } else { 0x20 };
+        self.records.extend_from_slice(&[0x40 | developer_flag | local, 0, 0]);
+        self.records.extend_from_slice(&global.to_le_bytes());
+        self.records.push(fields.len() as u8);
+        for &(number, size, base_type) in fields {
+            self.records.extend_from_slice(&[number, size, base_type]);
+        }
+        if !developer_fields.is_empty() {
+            self.records.push(developer_fields.len() as u8);
+            for &(number, size, index) in developer_fields {
+                self.records.extend_from_slice(&[number, size, index]);
+            }
+        }
+        local
+    }
+
+    /// Write a message of numeric fields, given as (number, base type, raw value)
+    fn message(&mut self, global: u16, fields: &[(u8, u8, Option<f64>)]) {
+        let definition: Vec<(u8, u8, u8)> = fields.iter().map(|&(n, t, _)| (n, base_type_size(t), t)).collect();
+        let local = self.define(global, &definition, &[]);
+        self.records.push(local);
+        for &(_, base_type, value) in fields {
+            encode_value(&mut self.records, base_type, value);
+        }
+    }
+
+    fn field_description(&mut self, number: u8, name: &str, units: &str) {
+        let local = self.define(
+            MESG_FIELD_DESCRIPTION,
+            &[(0, 1, 0x02), (1, 1, 0x02), (2, 1, 0x02), (3, 64, 0x07), (8, 16, 0x07)],
+            &[],
+        );
+        self.records.extend_from_slice(&[local, 0, number, 0x89]);
+        encode_text(&mut self.records, name, 64);
+        encode_text(&mut self.records, units, 16);
+    }
+
+    fn event(&mut self, event: &Event) {
+        let event_type = match event.kind {
+            EventKind::TimerStart => 0.0,
+            EventKind::TimerStop => 4.0,
+            EventKind::Marker => 3.0,
+        };
+        self.message(MESG_EVENT, &[
+            (FIELD_TIMESTAMP, 0x86, Some(fit_seconds(&event.timestamp))),
+            (0, 0x00, Some(0.0)), // event: timer
+            (1, 0x00, Some(event_type)),
+        ]);
+    }
+
+    fn record(&mut self, point: &TelemetryPoint, developer_values: &[Option<f64>]) {
+        let degrees_to_semicircles = |d: f64| d * (2_147_483_648.0 / 180.0);
+        let fields = [
+            (FIELD_TIMESTAMP, 0x86, Some(fit_seconds(&point.timestamp))),
+            (0, 0x85, point.latitude.map(degrees_to_semicircles)),
+            (1, 0x85, point.longitude.map(degrees_to_semicircles)),
+            (78, 0x86, point.altitude.map(|a| (a + 500.0) * 5.0)),
+            (73, 0x86, point.speed.map(|s| s * 1000.0)),
+            (3, 0x02, point.channel(channel::HEART_RATE)),
+            (4, 0x02, point.channel(channel::CADENCE)),
+            (5, 0x86, point.channel(channel::DISTANCE).map(|d| d * 100.0)),
+            (7, 0x84, point.channel(channel::POWER)),
+            (13, 0x01, point.channel(channel::TEMPERATURE)),
+        ];
+        let definition: Vec<(u8, u8, u8)> = fields.iter().map(|&(n, t, _)| (n, base_type_size(t), t)).collect();
+        let developer_definition: Vec<(u8, u8, u8)> = (0..developer_values.len()).map(|n| (n as u8, 8, 0)).collect();
+
+        let local = self.define(MESG_RECORD, &definition, &developer_definition);
+        self.records.push(local);
+        for (_, base_type, value) in fields {
+            encode_value(&mut self.records, base_type, value);
+        }
+        for &value in developer_values {
+            encode_value(&mut self.records, 0x89, value);
+        }
+    }
+}
+
+fn base_type_size(base_type: u8) -> u8 {
+    match base_type {
+        0x83 | 0x84 | 0x8B => 2,
+        0x85 | 0x86 | 0x88 | 0x8C => 4,
+        0x89 | 0x8E | 0x8F | 0x90 => 8,
+        _ => 1,
+    }
+}
+
+/// Append a value rounded to the base type, or its invalid value when
+/// missing or out of range
+fn encode_value(records: &mut Vec<u8>, base_type: u8, value: Option<f64>) {
+    let value = value.filter(|v| v.is_finite());
+    let fits = |min: f64, max: f64| value.map(f64::round).filter(|v| (min..max).contains(v));
+    match base_type {
+        0x01 => records.push(fits(-128.0, 127.0).map_or(0x7F, |v| v as i8 as u8)),
+        0x84 => records.extend_from_slice(&fits(0.0, 65535.0).map_or(u16::MAX, |v| v as u16).to_le_bytes()),
+        0x85 => records.extend_from_slice(
+            &fits(-2_147_483_648.0, 2_147_483_647.0).map_or(i32::MAX, |v| v as i32).to_le_bytes(),
+        ),
+        0x86 => records.extend_from_slice(&fits(0.0, 4_294_967_295.0).map_or(u32::MAX, |v| v as u32).to_le_bytes()),
+        0x89 => records.extend_from_slice(&value.map_or(u64::MAX, f64::to_bits).to_le_bytes()),
+        _ => records.push(fits(0.0, 255.0).map_or(0xFF, |v| v as u8)),
+    }
+}
+
+/// Append a null padded string of `size` bytes, truncated on a character boundary
+fn encode_text(records: &mut Vec<u8>, text: &str, size: usize) {
+    let mut end = text.len().min(size - 1);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    records.extend_from_slice(&text.as_bytes()[..end]);
+    records.resize(records.len() + size - end, 0);
+}
+
+fn fit_seconds(timestamp: &DateTime<Utc>) -> f64 {
+    (timestamp.timestamp() - FIT_EPOCH_OFFSET) as f64
+}
+
+#[derive(Debug, Clone, Copy)]
+struct FieldDefinition {
+    number: u8,
+    size: usize,
+    base_type: u8,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct DeveloperFieldDefinition {
+    number: u8,
+    size: usize,
+    developer_index: u8,
+}
+
+#[derive(Debug, Clone)]
+struct Definition {
+    global: u16,
+    big_endian: bool,
+    fields: Vec<FieldDefinition>,
+    developer_fields: Vec<DeveloperFieldDefinition>,
+}
+
+/// A developer field described by a `field_description` message
+#[derive(Debug, Clone)]
+struct DeveloperField {
+    name: String,
+    units: Option<String>,
+    base_type: u8,
+    scale: Option<f64>,
+    offset: Option<f64>,
+}
+
+#[derive(Debug, Clone)]
+enum Value {
+    Number(f64),
+    Text(String),
+}
+
+impl Value {
+    fn number(&self) -> Option<f64> {
+        match self {
+            Value::Number(n) => Some(*n),
+            Value::Text(_) => None,
+        }
+    }
+}
+
+struct Message {
+    global: u16,
+    fields: HashMap<u8, Value>,
+    developer_fields: Vec<(u8, u8, Value)>,
+}
+
+impl Message {
+    fn number(&self, field: u8) -> Option<f64> {
+        self.fields.get(&field).and_then(Value::number)
+    }
+
+    /// Read a field with the FIT profile scale and offset applied
+    fn scaled(&self, field: u8, scale: f64, offset: f64) -> Option<f64> {
+        self.number(field).map(|v| v / scale - offset)
+    }
+
+    fn time(&self, field: u8) -> Option<DateTime<Utc>> {
+        self.number(field).and_then(fit_time)
+    }
+}
+
+#[derive(Default)]
+struct Decoder {
+    definitions: HashMap<u8, Definition>,
+    developer_fields: HashMap<(u8, u8), DeveloperField>,
+    last_timestamp: Option<u32>,
+    session_distance: Option<f64>,
+}
+
+impl Decoder {
+    /// Decode one FIT file starting at `start` and return the position after its CRC
+    fn file(&mut self, data: &[u8], start: usize, telemetry: &mut TelemetryData) -> Result<usize, OverlogError> {
+        let invalid = |msg: &str| OverlogError::Telemetry(format!("Invalid FIT file: {}", msg));
+
+        let header_size = *data.get(start).ok_or_else(|| invalid("missing header"))? as usize;
+        let header = data.get(start..start + header_size).ok_or_else(|| invalid("truncated header"))?;
+        if header_size < 12 || &header[8..12] != b".FIT" {
+            return Err(invalid("missing .FIT signature"));
+        }
+
+        let data_size = u32::from_le_bytes(header[4..8].try_into().unwrap()) as usize;
+        let records_end = start + header_size + data_size;
+        let records = data.get(start + header_size..records_end).ok_or_else(|| invalid("truncated data"))?;
+        let crc_bytes = data.get(records_end..records_end + 2).ok_or_else(|| invalid("missing CRC"))?;
+
+        let expected = u16::from_le_bytes([crc_bytes[0], crc_bytes[1]]);
+        if expected != 0 && crc16(&data[start..records_end]) != expected {
+            return Err(invalid("CRC mismatch"));
+        }
+
+        // A new file starts with a clean set of local definitions
+        self.definitions.clear();
+
+        let mut pos = 0;
+        while pos < records.len() {
+            let record_header = records[pos];
+            pos += 1;
+
+            if record_header & 0x80 != 0 {
+                // Compressed timestamp header: 2 bit local type, 5 bit time offset
+                let local = (record_header >> 5) & 0x03;
+                let offset = (record_header & 0x1F) as u32;
+                let last = self.last_timestamp.ok_or_else(|| invalid("compressed timestamp before any timestamp"))?;
+                let mut timestamp = (last & !0x1F) + offset;
+                if offset < (last & 0x1F) {
+                    timestamp += 0x20;
+                }
+                self.last_timestamp = Some(timestamp);
+
+                let mut message = self.data_message(records, &mut pos, local)?;
+                message.fields.insert(FIELD_TIMESTAMP, Value::Number(timestamp as f64));
+                self.handle(message, telemetry);
+            } else if record_header & 0x40 != 0 {
+                let local = record_header & 0x0F;
+                let has_developer_data = record_header & 0x20 != 0;
+                let definition = read_definition(records, &mut pos, has_developer_data)
+                    .ok_or_else(|| invalid("truncated definition message"))?;
+                self.definitions.insert(local, definition);
+            } else {
+                let message = self.data_message(records, &mut pos, record_header & 0x0F)?;
+                if let Some(timestamp) = message.number(FIELD_TIMESTAMP) {
+                    self.last_timestamp = Some(timestamp as u32);
+                }
+                self.handle(message, telemetry);
+            }
+        }
+
+        Ok(records_end + 2)
+    }
+
+    fn data_message(&self, records: &[u8], pos: &mut usize, local: u8) -> Result<Message, OverlogError> {
+        let definition = self.definitions.get(&local).ok_or_else(|| {
+            OverlogError::Telemetry(format!("Invalid FIT file: undefined local message type {}", local))
+        })?;
+        let truncated = || OverlogError::Telemetry("Invalid FIT file: truncated data message".to_string());
+
+        let mut message = Message {
+            global: definition.global,
+            fields: HashMap::new(),
+            developer_fields: Vec::new(),
+        };
+
+        for field in &definition.fields {
+            let raw = records.get(*pos..*pos + field.size).ok_or_else(truncated)?;
+            *pos += field.size;
+            if let Some(value) = decode_value(raw, field.base_type, definition.big_endian) {
+                message.fields.insert(field.number, value);
+            }
+        }
+
+        for field in &definition.developer_fields {
+            let raw = records.get(*pos..*pos + field.size).ok_or_else(truncated)?;
+            *pos += field.size;
+            let base_type = self
+                .developer_fields
+                .get(&(field.developer_index, field.number))
+                .map(|d| d.base_type)
+                .unwrap_or(0x0D);
+            if let Some(value) = decode_value(raw, base_type, definition.big_endian) {
+                message.developer_fields.push((field.developer_index, field.number, value));
+            }
+        }
+
+        Ok(message)
+    }
+
+    fn handle(&mut self, message: Message, telemetry: &mut TelemetryData) {
+        match message.global {
+            MESG_RECORD => {
+                if let Some(point) = self.record(&message) {
+                    telemetry.points.push(point);
+                }
+            }
+            MESG_LAP => {
+                if let Some(start_time) = message.time(2) {
+                    telemetry.laps.push(Lap {
+                        start_time,
+                        end_time: message.time(FIELD_TIMESTAMP),
+                        total_time: message.scaled(7, 1000.0, 0.0),
+                        distance: message.scaled(9, 100.0, 0.0),
+                    });
+                }
+            }
+            MESG_SESSION => {
+                if let Some(distance) = message.scaled(9, 100.0, 0.0) {
+                    *self.session_distance.get_or_insert(0.0) += distance;
+                }
+            }
+            MESG_EVENT => {
+                if let Some(event) = event(&message) {
+                    telemetry.events.push(event);
+                }
+            }
+            MESG_FIELD_DESCRIPTION => {
+                let (Some(index), Some(number), Some(base_type)) =
+                    (message.number(0), message.number(1), message.number(2))
+                else {
+                    return;
+                };
+                let name = match message.fields.get(&3) {
+                    Some(Value::Text(name)) => name.clone(),
+                    _ => format!("developer_{}_{}", index, number),
+                };
+                self.developer_fields.insert(
+                    (index as u8, number as u8),
+                    DeveloperField {
+                        name,
+                        units: match message.fields.get(&8) {
+                            Some(Value::Text(units)) if !units.is_empty() => Some(units.clone()),
+                            _ => None,
+                        },
+                        base_type: base_type as u8,
+                        scale: message.number(6).filter(|&s| s != 0.0),
+                        offset: message.number(7),
+                    },
+                );
+            }
+            _ => {}
+        }
+    }
+
+    fn record(&self, message: &Message) -> Option<TelemetryPoint> {
+        let mut point = TelemetryPoint {
+            timestamp: message.time(FIELD_TIMESTAMP)?,
+            latitude: message.number(0).map(semicircles_to_degrees),
+            longitude: message.number(1).map(semicircles_to_degrees),
+            altitude: message.scaled(78, 5.0, 500.0).or_else(|| message.scaled(2, 5.0, 500.0)),
+            speed: message.scaled(73, 1000.0, 0.0).or_else(|| message.scaled(6, 1000.0, 0.0)),
+            ..Default::default()
+        };
+
+        let channels = [
+            (channel::HEART_RATE, message.number(3)),
+            (channel::CADENCE, message.number(4)),
+            (channel::DISTANCE, message.scaled(5, 100.0, 0.0)),
+            (channel::POWER, message.number(7)),
+            (channel::TEMPERATURE, message.number(13)),
+        ];
+        for (name, value) in channels {
+            if let Some(value) = value {
+                point.set_channel(name, value);
+            }
+        }
+
+        for (index, number, value) in &message.developer_fields {
+            let Some(field) = self.developer_fields.get(&(*index, *number)) else { continue };
+            let units = field.units.as_deref();
+            match value {
+                Value::Number(value) => {
+                    let value = value / field.scale.unwrap_or(1.0) - field.offset.unwrap_or(0.0);
+                    // Point fields written by `to_fit` come back as fields
+                    match field.name.parse() {
+                        Ok(Field::Channel(_)) | Err(_) => point.set_channel_with_unit(&field.name, value, units),
+                        Ok(point_field) => point_field.set(&mut point, value),
+                    }
+                }
+                Value::Text(text) => point.set_channel_with_unit(&field.name, text.as_str(), units),
+            }
+        }
+
+        Some(point)
+    }
+}
+
+fn event(message: &Message) -> Option<Event> {
+    let timestamp = message.time(FIELD_TIMESTAMP)?;
+    let is_timer = message.number(0)? == 0.0;
+    // event_type: 0 start, 1 stop, 3 marker, 4 stop_all, 8 stop_disable, 9 stop_disable_all
+    let kind = match message.number(1)? as u8 {
+        0 if is_timer => EventKind::TimerStart,
+        1 | 4 | 8 | 9 if is_timer => EventKind::TimerStop,
+        3 => EventKind::Marker,
+        _ => return None,
+    };
+    Some(Event { timestamp, kind })
+}
+
+fn read_definition(records: &[u8], pos: &mut usize, has_developer_data: bool) -> Option<Definition> {
+    let header = records.get(*pos..*pos + 5)?;
+    let big_endian = header[1] == 1;
+    let global = if big_endian {
+        u16::from_be_bytes([header[2], header[3]])
+    } else {
+        u16::from_le_bytes([header[2], header[3]])
+    };
+    let field_count = header[4] as usize;
+    *pos += 5;
+
+    let mut fields = Vec::with_capacity(field_count);
+    for _ in 0..field_count {
+        let f = records.get(*pos..*pos + 3)?;
+        fields.push(FieldDefinition { number: f[0], size: f[1] as usize, base_type: f[2] });
+        *pos += 3;
+    }
+
+    let mut developer_fields = Vec::new();
+    if has_developer_data {
+        let count = *records.get(*pos)? as usize;
+        *pos += 1;
+        for _ in 0..count {
+            let f = records.get(*pos..*pos + 3)?;
+            developer_fields.push(DeveloperFieldDefinition { number: f[0], size: f[1] as usize, developer_index: f[2] });
+            *pos += 3;
+        }
+    }
+
+    Some(Definition { global, big_endian, fields, developer_fields })
+}
+
+/// Decode the first element of a field, returning `None` for the base type's invalid value
+fn decode_value(raw: &[u8], base_type: u8, big_endian: bool) -> Option<Value> {
+    macro_rules! read {
+        ($t:ty) => {{
+            let bytes = raw.get(..std::mem::size_of::<$t>())?.try_into().ok()?;
+            if big_endian { <$t>::from_be_bytes(bytes) } else { <$t>::from_le_bytes(bytes) }
+        }};
+    }
+
+    let value = match base_type {
+        0x00 | 0x02 | 0x0D => {
+            let v = *raw.first()?;
+            (v != 0xFF).then_some(v as f64)
+        }
+        0x01 => {
+            let v = *raw.first()? as i8;
+            (v != 0x7F).then_some(v as f64)
+        }
+        0x0A => {
+            let v = *raw.first()?;
+            (v != 0).then_some(v as f64)
+        }
+        0x83 => {
+            let v = read!(i16);
+            (v != i16::MAX).then_some(v as f64)
+        }
+        0x84 => {
+            let v = read!(u16);
+            (v != u16::MAX).then_some(v as f64)
+        }
+        0x8B => {
+            let v = read!(u16);
+            (v != 0).then_some(v as f64)
+        }
+        0x85 => {
+            let v = read!(i32);
+            (v != i32::MAX).then_some(v as f64)
+        }
+        0x86 => {
+            let v = read!(u32);
+            (v != u32::MAX).then_some(v as f64)
+        }
+        0x8C => {
+            let v = read!(u32);
+            (v != 0).then_some(v as f64)
+        }
+        0x8E => {
+            let v = read!(i64);
+            (v != i64::MAX).then_some(v as f64)
+        }
+        0x8F => {
+            let v = read!(u64);
+            (v != u64::MAX).then_some(v as f64)
+        }
+        0x90 => {
+            let v = read!(u64);
+            (v != 0).then_some(v as f64)
+        }
+        0x88 => {
+            let v = read!(f32);
+            (v.to_bits() != u32::MAX).then_some(v as f64)
+        }
+        0x89 => {
+            let v = read!(f64);
+            (v.to_bits() != u64::MAX).then_some(v)
+        }
+        0x07 => {
+            let end = raw.iter().position(|&b| b == 0).unwrap_or(raw.len());
+            let text = String::from_utf8_lossy(&raw[..end]).into_owned();
+            return (!text.is_empty()).then_some(Value::Text(text));
+        }
+        _ => None,
+    };
+
+    value.map(Value::Number)
+}
+
+fn fit_time(seconds: f64) -> Option<DateTime<Utc>> {
+    Utc.timestamp_opt(FIT_EPOCH_OFFSET + seconds as i64, 0).single()
+}
+
+fn semicircles_to_degrees(semicircles: f64) -> f64 {
+    semicircles * (180.0 / 2_147_483_648.0)
+}
+
+/// CRC-16 as specified by the FIT SDK
+fn crc16(data: &[u8]) -> u16 {
+    const TABLE: [u16; 16] = [
+        0x0000, 0xCC01, 0xD801, 0x1400, 0xF001, 0x3C00, 0x2800, 0xE401,
+        0xA001, 0x6C00, 0x7800, 0xB401, 0x5000, 0x9C01, 0x8801, 0x4400,
+    ];
+
+    data.iter().fold(0u16, |mut crc, &byte| {
+        let tmp = TABLE[(crc & 0xF) as usize];
+        crc = (crc >> 4) & 0x0FFF;
+        crc = crc ^ tmp ^ TABLE[(byte & 0xF) as usize];
+        let tmp = TABLE[(crc & 0xF) as usize];
+        crc = (crc >> 4) & 0x0FFF;
+        crc ^ tmp ^ TABLE[((byte >> 4) & 0xF) as usize]
+    })
+}
+
+/// Wrap record bytes in a 14 byte header and trailing CRC
+fn fit_file(records: &[u8]) -> Vec<u8> {
+    let mut file = vec![14, 0x20];
+    file.extend_from_slice(&2132u16.to_le_bytes());
+    file.extend_from_slice(&(records.len() as u32).to_le_bytes());
+    file.extend_from_slice(b".FIT");
+    let header_crc = crc16(&file);
+    file.extend_from_slice(&header_crc.to_le_bytes());
+    file.extend_from_slice(records);
+    let crc = crc16(&file);
+    file.extend_from_slice(&crc.to_le_bytes());
+    file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_records() -> Vec<u8> {
+        let mut r = Vec::new();
+
+        // Local 0: record(timestamp, lat, long, enhanced_speed, heart_rate) + 1 developer field
+        r.extend_from_slice(&[0x60, 0, 0]);
+        r.extend_from_slice(&MESG_RECORD.to_le_bytes());
+        r.extend_from_slice(&[4, 253, 4, 0x86, 0, 4, 0x85, 1, 4, 0x85, 73, 4, 0x86]);
+        r.extend_from_slice(&[1, 0, 1, 0]);
+
+        // Local 1: field_description(developer index, field number, base type, name, units)
+        r.extend_from_slice(&[0x41, 0, 0]);
+        r.extend_from_slice(&MESG_FIELD_DESCRIPTION.to_le_bytes());
+        r.extend_from_slice(&[5, 0, 1, 0x02, 1, 1, 0x02, 2, 1, 0x02, 3, 8, 0x07, 8, 4, 0x07]);
+        r.extend_from_slice(&[0x01, 0, 0, 0x02]);
+        r.extend_from_slice(b"boost\0\0\0");
+        r.extend_from_slice(b"psi\0");
+
+        // Data for local 0 with a full timestamp
+        let degrees = |d: f64| ((d / 180.0) * 2_147_483_648.0) as i32;
+        r.push(0x00);
+        r.extend_from_slice(&1_000_000_000u32.to_le_bytes());
+        r.extend_from_slice(&degrees(40.7128).to_le_bytes());
+        r.extend_from_slice(&degrees(-74.006).to_le_bytes());
+        r.extend_from_slice(&8_330u32.to_le_bytes());
+        r.push(42);
+
+        // Compressed timestamp header for local 0, +2 seconds, invalid speed
+        r.push(0x80 | ((1_000_000_002u32 & 0x1F) as u8));
+        r.extend_from_slice(&0u32.to_le_bytes());
+        r.extend_from_slice(&degrees(40.7129).to_le_bytes());
+        r.extend_from_slice(&degrees(-74.0059).to_le_bytes());
+        r.extend_from_slice(&u32::MAX.to_le_bytes());
+        r.push(43);
+
+        r
+    }
+
+    #[test]
+    fn test_fit_records() {
+        let telemetry = TelemetryData::from_fit(&fit_file(&sample_records())).unwrap();
+
+        assert_eq!(telemetry.points.len(), 2);
+        let first = &telemetry.points[0];
+        assert_eq!(first.timestamp.timestamp(), 1_000_000_000 + FIT_EPOCH_OFFSET);
+        assert!((first.latitude.unwrap() - 40.7128).abs() < 1e-6);
+        assert!((first.longitude.unwrap() + 74.006).abs() < 1e-6);
+        assert_eq!(first.speed, Some(8.33));
+        assert_eq!(first.channel("boost"), Some(42.0));
+        assert_eq!(first.channels["boost"].unit.as_deref(), Some("psi"));
+
+        let second = &telemetry.points[1];
+        assert_eq!(second.timestamp.timestamp(), 1_000_000_002 + FIT_EPOCH_OFFSET);
+        assert_eq!(second.speed, None);
+        assert_eq!(second.channel("boost"), Some(43.0));
+    }
+
+    #[test]
+    fn test_fit_round_trip() {
+        let csv = "timestamp,latitude,longitude,altitude,speed,rpm,heart_rate,boost (bar)\n\
+            2024-01-15T10:00:00Z,40.7128,-74.006,10.2,8.33,3000,140,1.2\n\
+            2024-01-15T10:00:01Z,40.7129,-74.0059,,8.5,,141,\n";
+        let mut telemetry = TelemetryData::from_csv(csv).unwrap();
+        telemetry.events.push(Event { timestamp: telemetry.points[0].timestamp, kind: EventKind::TimerStart });
+
+        let restored = TelemetryData::from_fit(&telemetry.to_fit()).unwrap();
+        assert_eq!(restored.points.len(), 2);
+        assert_eq!(restored.events.len(), 1);
+        assert_eq!(restored.laps.len(), 1);
+        assert_eq!(restored.laps[0].total_time, Some(1.0));
+
+        let (first, original) = (&restored.points[0], &telemetry.points[0]);
+        assert_eq!(first.timestamp, original.timestamp);
+        assert!((first.latitude.unwrap() - 40.7128).abs() < 1e-6);
+        assert!((first.altitude.unwrap() - 10.2).abs() < 0.1);
+        assert_eq!(first.speed, Some(8.33));
+        assert_eq!(first.rpm, Some(3000.0));
+        assert_eq!(first.channel(channel::HEART_RATE), Some(140.0));
+        assert_eq!(first.channels["boost"], original.channels["boost"]);
+
+        let second = &restored.points[1];
+        assert_eq!(second.altitude, None);
+        assert_eq!(second.rpm, None);
+        assert_eq!(second.channel("boost"), None);
+
+        // Without points the file is dated at the FIT epoch, not the clock
+        let empty = TelemetryData::new().to_fit();
+        assert_eq!(empty, TelemetryData::new().to_fit());
+        assert!(TelemetryData::from_fit(&empty).unwrap().points.is_empty());
+    }
+
+    #[test]
+    fn test_fit_crc_mismatch() {
+        let mut file = fit_file(&sample_records());
+        let len = file.len();
+        file[len - 3] ^= 0xFF;
+        assert!(TelemetryData::from_fit(&file).is_err());
+    }
+}
@@ -0,0 +1,408 @@
+use std::collections::BTreeSet;
+use chrono::{DateTime, TimeZone, Utc};
+use geojson::{Feature, FeatureCollection, GeoJson, Geometry, JsonObject, JsonValue, Value};
+use crate::{
+    error::OverlogError,
+    telemetry::{channel, ChannelValue, Route, Segment, TelemetryData, TelemetryPoint, Waypoint},
+};
+use super::{columns::Field, format_rfc3339, parse_rfc3339, runs};
+
+impl TelemetryData {
+    /// Parse GeoJSON tracks
+    ///
+    /// Each LineString (or line of a MultiLineString) with per-coordinate
+    /// times becomes a [`Segment`]. Times are read from a `coordTimes` or
+    /// `times` property, or from `coordinateProperties.times` as written by
+    /// togeojson; the other `coordinateProperties` arrays (heart, cadence,
+    /// power, ...) become channels. Lines without times are kept as
+    /// [`Route`]s and Point features as [`Waypoint`]s.
+    pub fn from_geojson(geojson_data: &str) -> Result<Self, OverlogError> {
+        // geojson::Error carries whole JSON values, so keep only its message
+        let geojson: GeoJson = geojson_data
+            .parse()
+            .map_err(|e| OverlogError::Telemetry(format!("Invalid GeoJSON: {}", e)))?;
+        let mut telemetry = TelemetryData::new();
+        telemetry.metadata.format = "geojson".to_string();
+
+        let features = match geojson {
+            GeoJson::FeatureCollection(collection) => collection.features,
+            GeoJson::Feature(feature) => vec![feature],
+            GeoJson::Geometry(geometry) => vec![Feature { geometry: Some(geometry), ..Default::default() }],
+        };
+
+        for feature in &features {
+            read_feature(feature, &mut telemetry)?;
+        }
+
+        telemetry.calculate_metadata();
+        Ok(telemetry)
+    }
+
+    /// Write a GeoJSON FeatureCollection
+    ///
+    /// Each segment becomes a LineString Feature with `coordTimes` and a
+    /// `coordinateProperties` array per field and channel (`null` where a
+    /// point has no value). Waypoints are written as Points and routes as
+    /// LineStrings without times.
+    pub fn to_geojson(&self) -> String {
+        let mut features = Vec::new();
+
+        for waypoint in &self.waypoints {
+            let mut properties = JsonObject::new();
+            insert_text(&mut properties, "name", &waypoint.name);
+            insert_text(&mut properties, "description", &waypoint.description);
+            if let Some(timestamp) = &waypoint.timestamp {
+                properties.insert("time".to_string(), format_rfc3339(timestamp).into());
+            }
+            features.push(feature(Value::Point(waypoint_position(waypoint)), properties));
+        }
+
+        for route in &self.routes {
+            let mut properties = JsonObject::new();
+            insert_text(&mut properties, "name", &route.name);
+            let line = route.points.iter().map(waypoint_position).collect();
+            features.push(feature(Value::LineString(line), properties));
+        }
+
+        for (track, points) in runs(self) {
+            let points: Vec<&TelemetryPoint> = points
+                .iter()
+                .filter(|p| p.latitude.is_some() && p.longitude.is_some())
+                .collect();
+            if points.is_empty() {
+                continue;
+            }
+
+            let line = points
+                .iter()
+                .map(|p| {
+                    let mut position = vec![p.longitude.unwrap_or_default(), p.latitude.unwrap_or_default()];
+                    position.extend(p.altitude);
+                    position
+                })
+                .collect();
+
+            let mut coordinate_properties = JsonObject::new();
+            let channels: BTreeSet<&String> = points.iter().flat_map(|p| p.channels.keys()).collect();
+            let fields = Field::POINT_FIELDS[3..]
+                .iter()
+                .cloned()
+                .chain(channels.into_iter().map(|name| Field::Channel(name.clone())));
+            for field in fields {
+                let values: Vec<JsonValue> = points.iter().map(|p| json_value(p, &field)).collect();
+                if values.iter().any(|v| !v.is_null()) {
+                    coordinate_properties.insert(field.name().to_string(), values.into());
+                }
+            }
+
+            let mut properties = JsonObject::new();
+            insert_text(&mut properties, "name", &track.map(str::to_string));
+            let times: Vec<JsonValue> = points.iter().map(|p| format_rfc3339(&p.timestamp).into()).collect();
+            properties.insert("coordTimes".to_string(), times.into());
+            if !coordinate_properties.is_empty() {
+                properties.insert("coordinateProperties".to_string(), coordinate_properties.into());
+            }
+            features.push(feature(Value::LineString(line), properties));
+        }
+
+        GeoJson::FeatureCollection(FeatureCollection { bbox: None, features, foreign_members: None }).to_string()
+    }
+}
+
+fn feature(value: Value, properties: JsonObject) -> Feature {
+    Feature {
+        geometry: Some(Geometry::new(value)),
+        properties: Some(properties),
+        ..Default::default()
+    }
+}
+
+fn insert_text(properties: &mut JsonObject, key: &str, value: &Option<String>) {
+    if let Some(value) = value {
+        properties.insert(key.to_string(), value.clone().into());
+    }
+}
+
+fn waypoint_position(waypoint: &Waypoint) -> Vec<f64> {
+    let mut position = vec![waypoint.longitude, waypoint.latitude];
+    position.extend(waypoint.altitude);
+    position
+}
+
+fn json_value(point: &TelemetryPoint, field: &Field) -> JsonValue {
+    match field {
+        Field::Channel(name) => match point.channels.get(name).map(|c| &c.value) {
+            Some(ChannelValue::Number(value)) => (*value).into(),
+            Some(ChannelValue::Text(text)) => text.clone().into(),
+            None => JsonValue::Null,
+        },
+        field => field.get(point).map(JsonValue::from).unwrap_or(JsonValue::Null),
+    }
+}
+
+fn read_feature(feature: &Feature, telemetry: &mut TelemetryData) -> Result<(), OverlogError> {
+    let empty = JsonObject::new();
+    let properties = feature.properties.as_ref().unwrap_or(&empty);
+    let name = ["name", "title"]
+        .iter()
+        .find_map(|key| properties.get(*key).and_then(JsonValue::as_str))
+        .map(str::to_string);
+
+    let Some(geometry) = &feature.geometry else { return Ok(()) };
+    let mut lines = Vec::new();
+    collect_lines(geometry, &name, properties, telemetry, &mut lines);
+
+    // Per-coordinate arrays are nested per line for multi-line geometries
+    let multi = !matches!(geometry.value, Value::LineString(_));
+    for (index, line) in lines.iter().enumerate() {
+        let per_line = |value: &JsonValue| -> Option<Vec<JsonValue>> {
+            let array = value.as_array()?;
+            if multi {
+                array.get(index)?.as_array().cloned()
+            } else {
+                Some(array.clone())
+            }
+        };
+
+        let coordinate_properties = properties.get("coordinateProperties").and_then(JsonValue::as_object);
+        let times = ["coordTimes", "times"]
+            .iter()
+            .find_map(|key| properties.get(*key))
+            .or_else(|| coordinate_properties.and_then(|p| p.get("times")))
+            .and_then(per_line);
+
+        let Some(times) = times else {
+            telemetry.routes.push(Route {
+                name: name.clone(),
+                points: line.iter().filter_map(|p| position_waypoint(p, None, None)).collect(),
+            });
+            continue;
+        };
+        if times.len() != line.len() {
+            return Err(OverlogError::Telemetry(format!(
+                "GeoJSON line has {} coordinates but {} times",
+                line.len(),
+                times.len()
+            )));
+        }
+
+        let arrays: Vec<(Field, Vec<JsonValue>)> = coordinate_properties
+            .into_iter()
+            .flatten()
+            .filter(|(key, _)| key.as_str() != "times")
+            .filter_map(|(key, value)| Some((channel_name(key).parse().ok()?, per_line(value)?)))
+            .collect();
+
+        let first = telemetry.points.len();
+        for (i, (position, time)) in line.iter().zip(&times).enumerate() {
+            let (Some(&longitude), Some(&latitude)) = (position.first(), position.get(1)) else { continue };
+            let mut point = TelemetryPoint {
+                timestamp: json_time(time)?,
+                latitude: Some(latitude),
+                longitude: Some(longitude),
+                altitude: position.get(2).copied(),
+                ..Default::default()
+            };
+            for (field, values) in &arrays {
+                match (field, values.get(i)) {
+                    (Field::Channel(name), Some(JsonValue::String(text))) => {
+                        point.set_channel_with_unit(name, text.as_str(), None)
+                    }
+                    (Field::Channel(name), Some(value)) => {
+                        if let Some(value) = value.as_f64() {
+                            point.set_channel(name, value);
+                        }
+                    }
+                    (field, Some(value)) => {
+                        if let Some(value) = value.as_f64() {
+                            field.set(&mut point, value);
+                        }
+                    }
+                    (_, None) => {}
+                }
+            }
+            telemetry.points.push(point);
+        }
+
+        let points = &telemetry.points[first..];
+        if let (Some(start), Some(end)) = (points.first(), points.last()) {
+            telemetry.segments.push(Segment {
+                track: name.clone(),
+                start_time: start.timestamp,
+                end_time: end.timestamp,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Gather the lines of a geometry; points go straight to the waypoints
+fn collect_lines(
+    geometry: &Geometry,
+    name: &Option<String>,
+    properties: &JsonObject,
+    telemetry: &mut TelemetryData,
+    lines: &mut Vec<Vec<Vec<f64>>>,
+) {
+    match &geometry.value {
+        Value::LineString(line) => lines.push(line.clone()),
+        Value::MultiLineString(multi) => lines.extend(multi.iter().cloned()),
+        Value::Point(position) => {
+            let description = properties.get("description").and_then(JsonValue::as_str).map(str::to_string);
+            if let Some(mut waypoint) = position_waypoint(position, name.clone(), description) {
+                waypoint.timestamp = properties.get("time").and_then(|t| json_time(t).ok());
+                telemetry.waypoints.push(waypoint);
+            }
+        }
+        Value::GeometryCollection(geometries) => {
+            for geometry in geometries {
+                collect_lines(geometry, name, properties, telemetry, lines);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn position_waypoint(position: &[f64], name: Option<String>, description: Option<String>) -> Option<Waypoint> {
+    Some(Waypoint {
+        name,
+        description,
+        symbol: None,
+        latitude: *position.get(1)?,
+        longitude: *position.first()?,
+        altitude: position.get(2).copied(),
+        timestamp: None,
+    })
+}
+
+/// GeoJSON and our own JSON output share the `.json` extension
+pub(crate) fn looks_like_geojson(text: &str) -> bool {
+    ["\"FeatureCollection\"", "\"Feature\"", "\"LineString\"", "\"MultiLineString\""]
+        .iter()
+        .any(|marker| text.contains(marker))
+}
+
+/// RFC 3339 strings, or epoch numbers in seconds or milliseconds
+fn json_time(value: &JsonValue) -> Result<DateTime<Utc>, OverlogError> {
+    match value {
+        JsonValue::String(text) => parse_rfc3339(text),
+        JsonValue::Number(number) => {
+            let number = number.as_f64().unwrap_or_default();
+            let micros = if number.abs() > 1e11 { number * 1e3 } else { number * 1e6 };
+            Utc.timestamp_micros(micros.round() as i64)
+                .single()
+                .ok_or_else(|| OverlogError::Telemetry(format!("Timestamp out of range: {}", number)))
+        }
+        other => Err(OverlogError::Telemetry(format!("Invalid GeoJSON time: {}", other))),
+    }
+}
+
+/// Map togeojson's `coordinateProperties` keys to channel names
+fn channel_name(key: &str) -> &str {
+    match key {
+        "heart" | "heartRate" | "heartRates" | "hr" => channel::HEART_RATE,
+        "cadence" | "cadences" | "cad" => channel::CADENCE,
+        "power" | "powers" => channel::POWER,
+        "atemp" | "temperature" => channel::TEMPERATURE,
+        other => other,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_geojson_feature_collection() {
+        let geojson = r#"{
+            "type": "FeatureCollection",
+            "features": [
+                {
+                    "type": "Feature",
+                    "properties": {
+                        "name": "Ride",
+                        "coordinateProperties": {
+                            "times": [["2024-01-15T10:00:00Z", "2024-01-15T10:00:01Z"], ["2024-01-15T10:10:00Z"]],
+                            "heart": [[140, 141], [150]]
+                        }
+                    },
+                    "geometry": {
+                        "type": "MultiLineString",
+                        "coordinates": [[[-74.006, 40.7128, 10], [-74.0059, 40.7129, 11]], [[-74.0, 40.72]]]
+                    }
+                },
+                {
+                    "type": "Feature",
+                    "properties": { "name": "Cafe", "description": "Coffee stop" },
+                    "geometry": { "type": "Point", "coordinates": [-74.001, 40.715] }
+                },
+                {
+                    "type": "Feature",
+                    "properties": { "name": "Planned" },
+                    "geometry": { "type": "LineString", "coordinates": [[-74.0, 40.7], [-74.1, 40.8]] }
+                }
+            ]
+        }"#;
+
+        let telemetry = TelemetryData::from_geojson(geojson).unwrap();
+        assert_eq!(telemetry.points.len(), 3);
+        assert_eq!(telemetry.segments.len(), 2);
+        assert_eq!(telemetry.segments[0].track.as_deref(), Some("Ride"));
+
+        let first = &telemetry.points[0];
+        assert_eq!(first.latitude, Some(40.7128));
+        assert_eq!(first.longitude, Some(-74.006));
+        assert_eq!(first.altitude, Some(10.0));
+        assert_eq!(first.channel(channel::HEART_RATE), Some(140.0));
+        assert_eq!(telemetry.points[2].channel(channel::HEART_RATE), Some(150.0));
+
+        assert_eq!(telemetry.waypoints[0].name.as_deref(), Some("Cafe"));
+        assert_eq!(telemetry.waypoints[0].description.as_deref(), Some("Coffee stop"));
+        assert_eq!(telemetry.routes[0].points.len(), 2);
+    }
+
+    #[test]
+    fn test_geojson_round_trip() {
+        let csv = "timestamp,latitude,longitude,altitude,speed,gear\n\
+            2024-01-15T10:00:00Z,40.7128,-74.006,10,5,N\n\
+            2024-01-15T10:00:00.5Z,40.7129,-74.0059,,6,1\n";
+        let mut telemetry = TelemetryData::from_csv(csv).unwrap();
+        telemetry.points[0].set_channel(channel::HEART_RATE, 140.0);
+        telemetry.waypoints.push(Waypoint {
+            name: Some("Start".to_string()),
+            description: None,
+            symbol: None,
+            latitude: 40.7,
+            longitude: -74.0,
+            altitude: None,
+            timestamp: None,
+        });
+
+        let restored = TelemetryData::from_geojson(&telemetry.to_geojson()).unwrap();
+        assert_eq!(restored.points.len(), 2);
+        assert_eq!(restored.waypoints, telemetry.waypoints);
+        for (restored, original) in restored.points.iter().zip(&telemetry.points) {
+            assert_eq!(restored.timestamp, original.timestamp);
+            assert_eq!(restored.altitude, original.altitude);
+            assert_eq!(restored.speed, original.speed);
+            assert_eq!(restored.channels, original.channels);
+        }
+    }
+
+    #[test]
+    fn test_geojson_coord_times() {
+        let geojson = r#"{
+            "type": "Feature",
+            "properties": { "coordTimes": [1705312800000, 1705312801500] },
+            "geometry": { "type": "LineString", "coordinates": [[1.0, 2.0], [1.1, 2.1]] }
+        }"#;
+
+        let telemetry = TelemetryData::from_geojson(geojson).unwrap();
+        assert_eq!(telemetry.points[0].timestamp.to_rfc3339(), "2024-01-15T10:00:00+00:00");
+        assert_eq!(telemetry.points[1].timestamp.to_rfc3339(), "2024-01-15T10:00:01.500+00:00");
+
+        let mismatched = geojson.replace(", 1705312801500", "");
+        assert!(TelemetryData::from_geojson(&mismatched).is_err());
+    }
+}
@@ -0,0 +1,489 @@
+//! GoPro Metadata Format (GPMF) decoder
+//!
+//! GPMF is a KLV stream: a four character key, a one byte type, a one byte
+//! structure size and a big-endian u16 repeat count, followed by the payload
+//! padded to 32 bits. Type 0 marks a nested container (`DEVC`, `STRM`).
+
+use std::io::{Read, Seek};
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use crate::{
+    error::OverlogError,
+    telemetry::{channel, TelemetryData, TelemetryPoint},
+};
+use super::{mp4, Timeline};
+
+/// Standard gravity, used to convert accelerometer readings to g
+const STANDARD_GRAVITY: f64 = 9.80665;
+
+/// A GPMF payload placed on the media timeline
+struct Payload<'a> {
+    time: f64,
+    duration: f64,
+    data: &'a [u8],
+}
+
+impl TelemetryData {
+    /// Parse GoPro GPMF telemetry, either from an MP4 containing a `gpmd`
+    /// track or from a raw extracted `.bin` stream
+    ///
+    /// Point timestamps follow the video timeline: the first GPS lock is used
+    /// to find the UTC time of media time zero, and every sample is placed at
+    /// that time plus its offset in the video.
+    pub fn from_gpmf(data: &[u8]) -> Result<Self, OverlogError> {
+        if mp4::is_mp4(data) {
+            let (info, samples) = mp4::read_track(data, b"gpmd")?;
+            return decode(&track_payloads(&samples), creation_time(&info));
+        }
+        decode(&raw_payloads(data)?, None)
+    }
+
+    /// Parse GoPro GPMF telemetry like [`from_gpmf`](Self::from_gpmf) from a
+    /// seekable reader
+    ///
+    /// Of an MP4 only the `moov` box and the `gpmd` samples are read, so the
+    /// video is never loaded; a raw stream is read as a whole.
+    pub fn from_gpmf_reader(mut reader: impl Read + Seek) -> Result<Self, OverlogError> {
+        let mut head = Vec::new();
+        reader.by_ref().take(8).read_to_end(&mut head)?;
+        reader.rewind()?;
+        if !mp4::is_mp4(&head) {
+            let mut data = Vec::new();
+            reader.read_to_end(&mut data)?;
+            return Self::from_gpmf(&data);
+        }
+        let (info, samples) = mp4::read_track_from(&mut reader, b"gpmd")?;
+        decode(&track_payloads(&samples), creation_time(&info))
+    }
+}
+
+fn decode(payloads: &[Payload], creation_time: Option<DateTime<Utc>>) -> Result<TelemetryData, OverlogError> {
+    let mut decoder = Decoder::default();
+    for payload in payloads {
+        for klv in Klv::iter(payload.data) {
+            if &klv.key == b"DEVC" {
+                decoder.device(&klv, payload);
+            }
+        }
+    }
+
+    if decoder.timeline.is_empty() {
+        return Err(OverlogError::Telemetry("No GPMF samples found".to_string()));
+    }
+
+    // Media time zero in UTC: prefer GPS time, then the container creation time
+    Ok(decoder.timeline.into_telemetry("gpmf", creation_time))
+}
+
+fn track_payloads<'a>(samples: &'a [mp4::Sample]) -> Vec<Payload<'a>> {
+    samples
+        .iter()
+        .map(|s| Payload { time: s.time, duration: s.duration, data: &s.data })
+        .collect()
+}
+
+/// Creation time of the movie; `mvhd` times count from 1904-01-01
+fn creation_time(info: &mp4::MovieInfo) -> Option<DateTime<Utc>> {
+    info.creation_time
+        .filter(|&t| t > 0)
+        .and_then(|t| Utc.timestamp_opt(t as i64 - 2_082_844_800, 0).single())
+}
+
+/// Split a raw GPMF stream into payloads, one per top-level `DEVC`
+///
+/// Without a container the timing comes from the `STMP` microsecond
+/// timestamps when present, or one second per payload otherwise.
+fn raw_payloads(data: &[u8]) -> Result<Vec<Payload<'_>>, OverlogError> {
+    let mut payloads = Vec::new();
+    let mut first_stamp = None;
+
+    for (index, klv) in Klv::iter(data).enumerate() {
+        if &klv.key != b"DEVC" {
+            return Err(OverlogError::Telemetry(format!(
+                "Unexpected top-level GPMF key '{}'",
+                String::from_utf8_lossy(&klv.key)
+            )));
+        }
+
+        let stamp = Klv::iter(klv.data)
+            .filter(|k| &k.key == b"STRM")
+            .flat_map(|strm| Klv::iter(strm.data))
+            .find(|k| &k.key == b"STMP")
+            .and_then(|k| k.values().first().map(|v| v[0]));
+
+        let time = match (stamp, first_stamp) {
+            (Some(stamp), Some(first)) => (stamp - first) / 1_000_000.0,
+            (Some(stamp), None) => {
+                first_stamp = Some(stamp);
+                0.0
+            }
+            (None, _) => index as f64,
+        };
+
+        payloads.push(Payload { time, duration: 1.0, data: klv.data_with_header });
+    }
+
+    // Each payload lasts until the next one starts
+    for i in 1..payloads.len() {
+        let duration = payloads[i].time - payloads[i - 1].time;
+        if duration > 0.0 {
+            payloads[i - 1].duration = duration;
+        }
+    }
+    if payloads.len() > 1 {
+        payloads.last_mut().unwrap().duration = payloads[payloads.len() - 2].duration;
+    }
+
+    if payloads.is_empty() {
+        return Err(OverlogError::Telemetry("No GPMF payloads found".to_string()));
+    }
+    Ok(payloads)
+}
+
+#[derive(Default)]
+struct Decoder {
+    /// Decoded samples keyed by microseconds from media time zero
+    timeline: Timeline,
+}
+
+/// Sticky stream properties that apply to the data key that follows them
+#[derive(Default)]
+struct StreamState {
+    scale: Vec<f64>,
+    complex_type: Vec<u8>,
+    orientation: Option<Vec<u8>>,
+    gps_fix: Option<f64>,
+    gps_precision: Option<f64>,
+    gps_time: Option<DateTime<Utc>>,
+}
+
+impl Decoder {
+    fn device(&mut self, devc: &Klv, payload: &Payload) {
+        for strm in Klv::iter(devc.data).filter(|k| &k.key == b"STRM") {
+            let mut state = StreamState::default();
+
+            for klv in Klv::iter(strm.data) {
+                match &klv.key {
+                    b"SCAL" => state.scale = klv.values().into_iter().flatten().collect(),
+                    b"TYPE" => state.complex_type = klv.data[..klv.data_len()].to_vec(),
+                    b"ORIN" => state.orientation = Some(klv.data[..klv.data_len()].to_vec()),
+                    b"GPSF" => state.gps_fix = klv.values().first().map(|v| v[0]),
+                    b"GPSP" => state.gps_precision = klv.values().first().map(|v| v[0] / 100.0),
+                    b"GPSU" => state.gps_time = parse_gpsu(&klv.data[..klv.data_len()]),
+                    b"GPS5" | b"GPS9" | b"ACCL" | b"GYRO" | b"GRAV" | b"CORI" => {
+                        let values = klv.scaled_values(&state);
+                        self.stream(&klv.key, &values, &state, payload);
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    fn stream(&mut self, key: &[u8; 4], values: &[Vec<f64>], state: &StreamState, payload: &Payload) {
+        let count = values.len();
+        for (i, value) in values.iter().enumerate() {
+            let offset = payload.time + payload.duration * i as f64 / count as f64;
+            let micros = (offset * 1_000_000.0).round() as i64;
+
+            match key {
+                b"GPS5" => {
+                    // No usable position without at least a 2D lock
+                    if state.gps_fix.is_some_and(|fix| fix < 2.0) || value.len() < 5 {
+                        continue;
+                    }
+                    if let Some(time) = state.gps_time.filter(|_| i == 0) {
+                        self.timeline.anchor(micros, time);
+                    }
+                    let point = self.timeline.at(micros);
+                    set_gps(point, value);
+                    if let Some(fix) = state.gps_fix {
+                        point.set_channel(channel::GPS_FIX, fix);
+                    }
+                    if let Some(dop) = state.gps_precision {
+                        point.set_channel(channel::GPS_DOP, dop);
+                    }
+                }
+                b"GPS9" => {
+                    if value.len() < 9 || value[8] < 2.0 {
+                        continue;
+                    }
+                    if let Some(time) = gps9_time(value[5], value[6]) {
+                        self.timeline.anchor(micros, time);
+                    }
+                    let point = self.timeline.at(micros);
+                    set_gps(point, value);
+                    point.set_channel(channel::GPS_DOP, value[7]);
+                    point.set_channel(channel::GPS_FIX, value[8]);
+                }
+                b"ACCL" => {
+                    let [x, y, z] = orient(value, state.orientation.as_deref());
+                    let point = self.timeline.at(micros);
+                    point.g_force_x = Some(x / STANDARD_GRAVITY);
+                    point.g_force_y = Some(y / STANDARD_GRAVITY);
+                    point.g_force_z = Some(z / STANDARD_GRAVITY);
+                }
+                b"GYRO" => {
+                    let [x, y, z] = orient(value, state.orientation.as_deref());
+                    let point = self.timeline.at(micros);
+                    point.set_channel(channel::GYRO_X, x);
+                    point.set_channel(channel::GYRO_Y, y);
+                    point.set_channel(channel::GYRO_Z, z);
+                }
+                b"GRAV" if value.len() >= 3 => {
+                    let point = self.timeline.at(micros);
+                    point.set_channel(channel::GRAVITY_X, value[0]);
+                    point.set_channel(channel::GRAVITY_Y, value[1]);
+                    point.set_channel(channel::GRAVITY_Z, value[2]);
+                }
+                b"CORI" if value.len() >= 4 => {
+                    let point = self.timeline.at(micros);
+                    point.set_channel(channel::ORIENTATION_W, value[0]);
+                    point.set_channel(channel::ORIENTATION_X, value[1]);
+                    point.set_channel(channel::ORIENTATION_Y, value[2]);
+                    point.set_channel(channel::ORIENTATION_Z, value[3]);
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Latitude, longitude, altitude, 2D speed and 3D speed are shared by GPS5 and GPS9
+fn set_gps(point: &mut TelemetryPoint, value: &[f64]) {
+    point.latitude = Some(value[0]);
+    point.longitude = Some(value[1]);
+    point.altitude = Some(value[2]);
+    point.speed = Some(value[3]);
+    point.set_channel(channel::SPEED_3D, value[4]);
+}
+
+/// Reorder a three-axis sample into camera X, Y, Z using an `ORIN` string
+/// such as `"ZXY"` or `"YxZ"` (lower case means the axis is inverted)
+fn orient(value: &[f64], orientation: Option<&[u8]>) -> [f64; 3] {
+    let mut out = [
+        value.first().copied().unwrap_or(0.0),
+        value.get(1).copied().unwrap_or(0.0),
+        value.get(2).copied().unwrap_or(0.0),
+    ];
+
+    if let Some(orin) = orientation.filter(|o| o.len() >= 3) {
+        let mut mapped = [0.0; 3];
+        for (i, &axis) in orin.iter().take(3).enumerate() {
+            let sign = if axis.is_ascii_lowercase() { -1.0 } else { 1.0 };
+            match axis.to_ascii_uppercase() {
+                b'X' => mapped[0] = sign * out[i],
+                b'Y' => mapped[1] = sign * out[i],
+                b'Z' => mapped[2] = sign * out[i],
+                _ => return out,
+            }
+        }
+        out = mapped;
+    }
+
+    out
+}
+
+/// Parse a `GPSU` UTC string of the form `yymmddhhmmss.sss`
+fn parse_gpsu(data: &[u8]) -> Option<DateTime<Utc>> {
+    let text = std::str::from_utf8(data).ok()?.trim_end_matches('\0');
+    NaiveDateTime::parse_from_str(&format!("20{}", text), "%Y%m%d%H%M%S%.f")
+        .ok()
+        .map(|naive| naive.and_utc())
+}
+
+/// GPS9 carries days since 2000-01-01 and seconds since midnight
+fn gps9_time(days: f64, seconds: f64) -> Option<DateTime<Utc>> {
+    let epoch = NaiveDate::from_ymd_opt(2000, 1, 1)?.and_hms_opt(0, 0, 0)?.and_utc();
+    Some(epoch + Duration::days(days as i64) + Duration::microseconds((seconds * 1_000_000.0).round() as i64))
+}
+
+/// A single key-length-value entry
+struct Klv<'a> {
+    key: [u8; 4],
+    value_type: u8,
+    struct_size: usize,
+    repeat: usize,
+    /// Payload including alignment padding
+    data: &'a [u8],
+    /// The whole entry including its 8 byte header
+    data_with_header: &'a [u8],
+}
+
+impl<'a> Klv<'a> {
+    fn iter(data: &'a [u8]) -> impl Iterator<Item = Klv<'a>> + 'a {
+        let mut pos = 0usize;
+        std::iter::from_fn(move || {
+            let header = data.get(pos..pos + 8)?;
+            let key: [u8; 4] = header[0..4].try_into().ok()?;
+            if key == [0; 4] {
+                return None;
+            }
+            let struct_size = header[5] as usize;
+            let repeat = u16::from_be_bytes([header[6], header[7]]) as usize;
+            let padded = (struct_size * repeat + 3) & !3;
+            let end = (pos + 8 + padded).min(data.len());
+            let klv = Klv {
+                key,
+                value_type: header[4],
+                struct_size,
+                repeat,
+                data: &data[pos + 8..end],
+                data_with_header: &data[pos..end],
+            };
+            pos += 8 + padded;
+            Some(klv)
+        })
+    }
+
+    fn data_len(&self) -> usize {
+        (self.struct_size * self.repeat).min(self.data.len())
+    }
+
+    /// Decode numeric samples, one vector of elements per repeat
+    fn values(&self) -> Vec<Vec<f64>> {
+        let types = vec![self.value_type; self.struct_size / type_size(self.value_type).max(1)];
+        self.decode(&types)
+    }
+
+    /// Decode numeric samples and apply the stream's `SCAL` divisors
+    fn scaled_values(&self, state: &StreamState) -> Vec<Vec<f64>> {
+        let mut samples = if self.value_type == b'?' {
+            self.decode(&state.complex_type)
+        } else {
+            self.values()
+        };
+
+        if !state.scale.is_empty() {
+            for sample in &mut samples {
+                for (i, value) in sample.iter_mut().enumerate() {
+                    let divisor = if state.scale.len() == 1 { state.scale[0] } else { state.scale.get(i).copied().unwrap_or(1.0) };
+                    if divisor != 0.0 {
+                        *value /= divisor;
+                    }
+                }
+            }
+        }
+
+        samples
+    }
+
+    fn decode(&self, types: &[u8]) -> Vec<Vec<f64>> {
+        let mut samples = Vec::with_capacity(self.repeat);
+        for r in 0..self.repeat {
+            let Some(bytes) = self.data.get(r * self.struct_size..(r + 1) * self.struct_size) else { break };
+            let mut sample = Vec::with_capacity(types.len());
+            let mut pos = 0;
+            for &t in types {
+                let size = type_size(t);
+                let Some(raw) = bytes.get(pos..pos + size) else { break };
+                sample.push(decode_value(t, raw));
+                pos += size;
+            }
+            samples.push(sample);
+        }
+        samples
+    }
+}
+
+fn type_size(value_type: u8) -> usize {
+    match value_type {
+        b'b' | b'B' | b'c' => 1,
+        b's' | b'S' => 2,
+        b'l' | b'L' | b'f' | b'F' | b'q' => 4,
+        b'd' | b'j' | b'J' | b'Q' => 8,
+        b'U' => 16,
+        _ => 0,
+    }
+}
+
+fn decode_value(value_type: u8, raw: &[u8]) -> f64 {
+    match value_type {
+        b'b' => raw[0] as i8 as f64,
+        b'B' | b'c' => raw[0] as f64,
+        b's' => i16::from_be_bytes([raw[0], raw[1]]) as f64,
+        b'S' => u16::from_be_bytes([raw[0], raw[1]]) as f64,
+        b'l' => i32::from_be_bytes(raw.try_into().unwrap()) as f64,
+        b'L' => u32::from_be_bytes(raw.try_into().unwrap()) as f64,
+        b'f' => f32::from_be_bytes(raw.try_into().unwrap()) as f64,
+        b'q' => i32::from_be_bytes(raw.try_into().unwrap()) as f64 / 65536.0,
+        b'd' => f64::from_be_bytes(raw.try_into().unwrap()),
+        b'j' => i64::from_be_bytes(raw.try_into().unwrap()) as f64,
+        b'J' => u64::from_be_bytes(raw.try_into().unwrap()) as f64,
+        b'Q' => i64::from_be_bytes(raw.try_into().unwrap()) as f64 / 4_294_967_296.0,
+        _ => 0.0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn klv(key: &[u8; 4], value_type: u8, struct_size: u8, repeat: u16, data: &[u8]) -> Vec<u8> {
+        let mut out = key.to_vec();
+        out.push(value_type);
+        out.push(struct_size);
+        out.extend_from_slice(&repeat.to_be_bytes());
+        out.extend_from_slice(data);
+        while !out.len().is_multiple_of(4) {
+            out.push(0);
+        }
+        out
+    }
+
+    fn sample_stream() -> Vec<u8> {
+        let mut gps = klv(b"SCAL", b'l', 4, 5, &[
+            10_000_000i32.to_be_bytes(),
+            10_000_000i32.to_be_bytes(),
+            1000i32.to_be_bytes(),
+            1000i32.to_be_bytes(),
+            100i32.to_be_bytes(),
+        ].concat());
+        gps.extend(klv(b"GPSF", b'L', 4, 1, &3u32.to_be_bytes()));
+        gps.extend(klv(b"GPSU", b'U', 16, 1, b"240115100000.000"));
+        let fix: Vec<u8> = [407_128_000i32, -740_060_000, 10_000, 8_330, 850]
+            .iter()
+            .flat_map(|v| v.to_be_bytes())
+            .collect();
+        gps.extend(klv(b"GPS5", b'l', 20, 2, &[fix.clone(), fix].concat()));
+
+        let mut accl = klv(b"SCAL", b's', 2, 1, &100i16.to_be_bytes());
+        accl.extend(klv(b"ORIN", b'c', 1, 3, b"ZXY"));
+        accl.extend(klv(b"ACCL", b's', 6, 1, &[981i16.to_be_bytes(), 0i16.to_be_bytes(), 0i16.to_be_bytes()].concat()));
+
+        let mut devc = klv(b"STRM", 0, 1, gps.len() as u16, &gps);
+        devc.extend(klv(b"STRM", 0, 1, accl.len() as u16, &accl));
+        klv(b"DEVC", 0, 1, devc.len() as u16, &devc)
+    }
+
+    #[test]
+    fn test_raw_gpmf_stream() {
+        let telemetry = TelemetryData::from_gpmf(&sample_stream()).unwrap();
+
+        // Two GPS samples spread over the payload, the first shared with ACCL
+        assert_eq!(telemetry.points.len(), 2);
+        let first = &telemetry.points[0];
+        assert!((first.latitude.unwrap() - 40.7128).abs() < 1e-9);
+        assert!((first.longitude.unwrap() + 74.006).abs() < 1e-9);
+        assert_eq!(first.speed, Some(8.33));
+        assert_eq!(first.channel(channel::GPS_FIX), Some(3.0));
+        assert!((first.g_force_z.unwrap() - 9.81 / STANDARD_GRAVITY).abs() < 1e-9);
+        assert_eq!(first.timestamp.to_rfc3339(), "2024-01-15T10:00:00+00:00");
+        assert_eq!(telemetry.points[1].timestamp.timestamp_millis() - first.timestamp.timestamp_millis(), 500);
+    }
+
+    #[test]
+    fn test_gpmf_reader() {
+        let stream = sample_stream();
+        let telemetry = TelemetryData::from_gpmf_reader(std::io::Cursor::new(&stream)).unwrap();
+        assert_eq!(telemetry.points.len(), 2);
+        assert_eq!(telemetry.points[0].timestamp.to_rfc3339(), "2024-01-15T10:00:00+00:00");
+        assert!((telemetry.points[1].latitude.unwrap() - 40.7128).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_orientation() {
+        assert_eq!(orient(&[1.0, 2.0, 3.0], Some(b"ZXY")), [2.0, 3.0, 1.0]);
+        assert_eq!(orient(&[1.0, 2.0, 3.0], Some(b"YxZ")), [-2.0, 1.0, 3.0]);
+        assert_eq!(orient(&[1.0, 2.0, 3.0], None), [1.0, 2.0, 3.0]);
+    }
+}
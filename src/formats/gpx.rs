@@ -0,0 +1,560 @@
+use std::{fmt::Write, str::FromStr};
+use roxmltree::{Document, Node};
+use crate::{
+    error::OverlogError,
+    telemetry::{channel, ChannelValue, Route, Segment, TelemetryData, TelemetryPoint, Waypoint},
+};
+use super::{
+    columns::Field,
+    format_rfc3339, parse_rfc3339, runs,
+    timing::{self, TimestampPolicy},
+    xml::{child, child_f64, child_text, children, element_name, escape},
+};
+
+/// Namespace of the extension elements overlog writes for other fields and channels
+pub const OVERLOG_NS: &str = "https://github.com/makalin/overlog/xmlschemas/channels/v1";
+const TPX_NS: &str = "http://www.garmin.com/xmlschemas/TrackPointExtension/v2";
+
+/// Channels written to `gpxtpx:TrackPointExtension`, in schema order
+const TPX_CHANNELS: [(&str, &str); 4] = [
+    ("atemp", channel::TEMPERATURE),
+    ("wtemp", channel::WATER_TEMPERATURE),
+    ("hr", channel::HEART_RATE),
+    ("cad", channel::CADENCE),
+];
+
+/// Selects one `<trk>` of a GPX file
+#[derive(Debug, Clone, PartialEq)]
+pub enum GpxTrack {
+    /// 0-based position in the file
+    Index(usize),
+    /// Value of the track's `<name>`
+    Name(String),
+}
+
+impl FromStr for GpxTrack {
+    type Err = std::convert::Infallible;
+
+    /// Numbers select by index, anything else by name
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Ok(match value.parse() {
+            Ok(index) => GpxTrack::Index(index),
+            Err(_) => GpxTrack::Name(value.to_string()),
+        })
+    }
+}
+
+impl TelemetryData {
+    /// Parse a GPX 1.0 or 1.1 file, keeping every track
+    ///
+    /// Besides position, elevation and time, this reads the GPX 1.0
+    /// `<speed>`/`<course>` elements, the Garmin `TrackPointExtension`
+    /// (v1 and v2) and the power extensions written by Strava and Cluetrust.
+    /// Each `<trkseg>` is recorded as a [`Segment`]; waypoints and routes are
+    /// kept as [`Waypoint`]s and [`Route`]s.
+    ///
+    /// Track points without a `<time>` are an error; see
+    /// [`from_gpx_with`](Self::from_gpx_with) to synthesize their times.
+    pub fn from_gpx(gpx_data: &str) -> Result<Self, OverlogError> {
+        Self::from_gpx_with(gpx_data, None, &TimestampPolicy::default())
+    }
+
+    /// Parse a single track of a GPX file
+    pub fn from_gpx_track(gpx_data: &str, track: &GpxTrack) -> Result<Self, OverlogError> {
+        Self::from_gpx_with(gpx_data, Some(track), &TimestampPolicy::default())
+    }
+
+    /// Parse a GPX file, or one of its tracks, timing track points without a
+    /// `<time>` as the policy says
+    pub fn from_gpx_with(
+        gpx_data: &str,
+        track: Option<&GpxTrack>,
+        policy: &TimestampPolicy,
+    ) -> Result<Self, OverlogError> {
+        parse_gpx(gpx_data, track, policy)
+    }
+
+    /// Write GPX 1.1 with one `<trkseg>` per segment
+    ///
+    /// Heart rate, cadence, temperatures, speed and course go into the Garmin
+    /// `TrackPointExtension`, power into Strava's `<power>`, and every other
+    /// field or channel into elements of the [`OVERLOG_NS`] namespace, which
+    /// `from_gpx` reads back.
+    pub fn to_gpx(&self) -> String {
+        let mut gpx = String::new();
+        gpx.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        let _ = writeln!(
+            gpx,
+            "<gpx version=\"1.1\" creator=\"overlog\" xmlns=\"http://www.topografix.com/GPX/1/1\" \
+             xmlns:gpxtpx=\"{}\" xmlns:overlog=\"{}\">",
+            TPX_NS, OVERLOG_NS
+        );
+
+        for waypoint in &self.waypoints {
+            write_waypoint(&mut gpx, "wpt", waypoint, "  ");
+        }
+
+        for route in &self.routes {
+            gpx.push_str("  <rte>\n");
+            if let Some(name) = &route.name {
+                let _ = writeln!(gpx, "    <name>{}</name>", escape(name));
+            }
+            for point in &route.points {
+                write_waypoint(&mut gpx, "rtept", point, "    ");
+            }
+            gpx.push_str("  </rte>\n");
+        }
+
+        let mut current_track: Option<Option<&str>> = None;
+        for (track, points) in runs(self) {
+            if current_track != Some(track) {
+                if current_track.is_some() {
+                    gpx.push_str("  </trk>\n");
+                }
+                gpx.push_str("  <trk>\n");
+                if let Some(name) = track {
+                    let _ = writeln!(gpx, "    <name>{}</name>", escape(name));
+                }
+                current_track = Some(track);
+            }
+            gpx.push_str("    <trkseg>\n");
+            for point in points {
+                write_trackpoint(&mut gpx, point);
+            }
+            gpx.push_str("    </trkseg>\n");
+        }
+        if current_track.is_some() {
+            gpx.push_str("  </trk>\n");
+        }
+
+        gpx.push_str("</gpx>\n");
+        gpx
+    }
+}
+
+fn write_waypoint(gpx: &mut String, tag: &str, waypoint: &Waypoint, indent: &str) {
+    let _ = writeln!(gpx, "{}<{} lat=\"{}\" lon=\"{}\">", indent, tag, waypoint.latitude, waypoint.longitude);
+    if let Some(altitude) = waypoint.altitude {
+        let _ = writeln!(gpx, "{}  <ele>{}</ele>", indent, altitude);
+    }
+    if let Some(timestamp) = &waypoint.timestamp {
+        let _ = writeln!(gpx, "{}  <time>{}</time>", indent, format_rfc3339(timestamp));
+    }
+    for (element, value) in [("name", &waypoint.name), ("desc", &waypoint.description), ("sym", &waypoint.symbol)] {
+        if let Some(value) = value {
+            let _ = writeln!(gpx, "{}  <{}>{}</{}>", indent, element, escape(value), element);
+        }
+    }
+    let _ = writeln!(gpx, "{}</{}>", indent, tag);
+}
+
+fn write_trackpoint(gpx: &mut String, point: &TelemetryPoint) {
+    // GPX requires a position; points without one can't be written
+    let (Some(latitude), Some(longitude)) = (point.latitude, point.longitude) else { return };
+
+    let _ = writeln!(gpx, "      <trkpt lat=\"{}\" lon=\"{}\">", latitude, longitude);
+    if let Some(altitude) = point.altitude {
+        let _ = writeln!(gpx, "        <ele>{}</ele>", altitude);
+    }
+    let _ = writeln!(gpx, "        <time>{}</time>", format_rfc3339(&point.timestamp));
+
+    let mut tpx = String::new();
+    for (element, name) in TPX_CHANNELS {
+        if let Some(value) = point.channel(name) {
+            let _ = write!(tpx, "<gpxtpx:{0}>{1}</gpxtpx:{0}>", element, value);
+        }
+    }
+    if let Some(speed) = point.speed {
+        let _ = write!(tpx, "<gpxtpx:speed>{}</gpxtpx:speed>", speed);
+    }
+    if let Some(course) = point.heading {
+        let _ = write!(tpx, "<gpxtpx:course>{}</gpxtpx:course>", course);
+    }
+
+    let mut other = String::new();
+    for field in &Field::POINT_FIELDS[5..] {
+        if let Some(value) = field.get(point) {
+            let _ = write!(other, "<overlog:{0}>{1}</overlog:{0}>", field.name(), value);
+        }
+    }
+    for (name, channel) in &point.channels {
+        let written_elsewhere = name == channel::POWER || TPX_CHANNELS.iter().any(|(_, n)| n == name);
+        if written_elsewhere && channel.value.as_f64().is_some() {
+            continue;
+        }
+        let element = element_name(name);
+        let unit = match &channel.unit {
+            Some(unit) => format!(" unit=\"{}\"", escape(unit)),
+            None => String::new(),
+        };
+        let value = match &channel.value {
+            ChannelValue::Number(n) => n.to_string(),
+            ChannelValue::Text(text) => escape(text),
+        };
+        let _ = write!(other, "<overlog:{0}{1}>{2}</overlog:{0}>", element, unit, value);
+    }
+
+    let power = point.channel(channel::POWER);
+    if power.is_some() || !tpx.is_empty() || !other.is_empty() {
+        gpx.push_str("        <extensions>");
+        if let Some(power) = power {
+            let _ = write!(gpx, "<power>{}</power>", power);
+        }
+        if !tpx.is_empty() {
+            let _ = write!(gpx, "<gpxtpx:TrackPointExtension>{}</gpxtpx:TrackPointExtension>", tpx);
+        }
+        gpx.push_str(&other);
+        gpx.push_str("</extensions>\n");
+    }
+
+    gpx.push_str("      </trkpt>\n");
+}
+
+fn parse_gpx(gpx_data: &str, selected: Option<&GpxTrack>, policy: &TimestampPolicy) -> Result<TelemetryData, OverlogError> {
+    let doc = Document::parse(gpx_data)?;
+    let root = doc.root_element();
+    let mut telemetry = TelemetryData::new();
+    telemetry.metadata.format = "gpx".to_string();
+
+    let tracks: Vec<Node> = children(root, "trk").collect();
+    let tracks = match selected {
+        None => tracks,
+        Some(selector) => {
+            let found = match selector {
+                GpxTrack::Index(index) => tracks.get(*index).copied(),
+                GpxTrack::Name(name) => tracks.iter().copied().find(|t| child_text(*t, "name") == Some(name.as_str())),
+            };
+            match found {
+                Some(track) => vec![track],
+                None => {
+                    let names: Vec<String> = tracks
+                        .iter()
+                        .enumerate()
+                        .map(|(i, t)| format!("{}: {}", i, child_text(*t, "name").unwrap_or("(unnamed)")))
+                        .collect();
+                    return Err(OverlogError::InvalidInput(format!(
+                        "GPX track {:?} not found; available tracks: {}",
+                        selector,
+                        names.join(", ")
+                    )));
+                }
+            }
+        }
+    };
+
+    // Segments are recorded once missing times have been filled in
+    let mut timed = Vec::new();
+    let mut segments = Vec::new();
+    for track in tracks {
+        let name = child_text(track, "name").map(str::to_string);
+        for trkseg in children(track, "trkseg") {
+            let first = telemetry.points.len();
+            for trkpt in children(trkseg, "trkpt") {
+                let (point, has_time) = parse_trackpoint(trkpt)?;
+                telemetry.points.push(point);
+                timed.push(has_time);
+            }
+            if telemetry.points.len() > first {
+                segments.push((name.clone(), first, telemetry.points.len() - 1));
+            }
+        }
+    }
+
+    telemetry.metadata.times = timing::fill_missing(&mut telemetry.points, &timed, policy)?;
+    for (track, first, last) in segments {
+        telemetry.segments.push(Segment {
+            track,
+            start_time: telemetry.points[first].timestamp,
+            end_time: telemetry.points[last].timestamp,
+        });
+    }
+
+    for wpt in children(root, "wpt") {
+        telemetry.waypoints.push(parse_waypoint(wpt)?);
+    }
+
+    for rte in children(root, "rte") {
+        telemetry.routes.push(Route {
+            name: child_text(rte, "name").map(str::to_string),
+            points: children(rte, "rtept").map(parse_waypoint).collect::<Result<_, _>>()?,
+        });
+    }
+
+    telemetry.calculate_metadata();
+    Ok(telemetry)
+}
+
+fn coordinate(node: Node, name: &str) -> Result<f64, OverlogError> {
+    node.attribute(name)
+        .and_then(|value| value.trim().parse().ok())
+        .ok_or_else(|| OverlogError::Telemetry(format!("GPX {} without valid {}", node.tag_name().name(), name)))
+}
+
+fn parse_waypoint(node: Node) -> Result<Waypoint, OverlogError> {
+    let text = |name: &str| child_text(node, name).map(|s| s.trim().to_string());
+    Ok(Waypoint {
+        name: text("name"),
+        description: text("desc"),
+        symbol: text("sym"),
+        latitude: coordinate(node, "lat")?,
+        longitude: coordinate(node, "lon")?,
+        altitude: child_f64(node, "ele"),
+        timestamp: child_text(node, "time").map(parse_rfc3339).transpose()?,
+    })
+}
+
+/// A track point and whether it has a time of its own
+fn parse_trackpoint(node: Node) -> Result<(TelemetryPoint, bool), OverlogError> {
+    let time = child_text(node, "time").map(parse_rfc3339).transpose()?;
+    let timestamp = time.unwrap_or_default();
+
+    let mut point = TelemetryPoint {
+        timestamp,
+        latitude: Some(coordinate(node, "lat")?),
+        longitude: Some(coordinate(node, "lon")?),
+        altitude: child_f64(node, "ele"),
+        // GPX 1.0 only; 1.1 moved these into extensions
+        speed: child_f64(node, "speed"),
+        heading: child_f64(node, "course"),
+        ..Default::default()
+    };
+
+    if let Some(extensions) = child(node, "extensions") {
+        read_extensions(extensions, &mut point);
+    }
+
+    Ok((point, time.is_some()))
+}
+
+/// Read the leaf elements of `<extensions>` by local name
+///
+/// Covers `gpxtpx:TrackPointExtension` (hr, cad, atemp, wtemp, speed,
+/// course), Strava's bare `<power>`, Cluetrust `gpxdata:` (hr, cadence, temp,
+/// power), `gpxpx:PowerInWatts` and the fields and channels written by
+/// `to_gpx`.
+fn read_extensions(extensions: Node, point: &mut TelemetryPoint) {
+    let leaves = extensions
+        .descendants()
+        .filter(|n| n.is_element() && !n.children().any(|c| c.is_element()));
+
+    for leaf in leaves {
+        if leaf.tag_name().namespace() == Some(OVERLOG_NS) {
+            read_overlog_element(leaf, point);
+            continue;
+        }
+        let Some(value) = leaf.text().and_then(|t| t.trim().parse::<f64>().ok()) else { continue };
+        match leaf.tag_name().name().to_ascii_lowercase().as_str() {
+            "hr" | "heartrate" => point.set_channel(channel::HEART_RATE, value),
+            "cad" | "cadence" => point.set_channel(channel::CADENCE, value),
+            "atemp" | "temp" | "temperature" => point.set_channel(channel::TEMPERATURE, value),
+            "wtemp" => point.set_channel(channel::WATER_TEMPERATURE, value),
+            "power" | "powerinwatts" | "watts" => point.set_channel(channel::POWER, value),
+            "speed" => point.speed = Some(value),
+            "course" | "bearing" => point.heading = Some(value),
+            _ => {}
+        }
+    }
+}
+
+fn read_overlog_element(leaf: Node, point: &mut TelemetryPoint) {
+    let name = leaf.tag_name().name();
+    let text = leaf.text().unwrap_or_default().trim();
+    let Ok(field) = name.parse::<Field>() else { return };
+    match (field, text.parse::<f64>()) {
+        (Field::Channel(name), Ok(value)) => point.set_channel_with_unit(&name, value, leaf.attribute("unit")),
+        (Field::Channel(name), Err(_)) => point.set_channel_with_unit(&name, text, leaf.attribute("unit")),
+        (field, Ok(value)) => field.set(point, value),
+        (_, Err(_)) => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<gpx version="1.1" creator="test" xmlns="http://www.topografix.com/GPX/1/1"
+     xmlns:gpxtpx="http://www.garmin.com/xmlschemas/TrackPointExtension/v2"
+     xmlns:gpxdata="http://www.cluetrust.com/XML/GPXDATA/1/0">
+  <trk>
+    <trkseg>
+      <trkpt lat="40.7128" lon="-74.0060">
+        <ele>10.0</ele>
+        <time>2024-01-15T10:00:00Z</time>
+        <extensions>
+          <power>250</power>
+          <gpxtpx:TrackPointExtension>
+            <gpxtpx:atemp>21.5</gpxtpx:atemp>
+            <gpxtpx:wtemp>18</gpxtpx:wtemp>
+            <gpxtpx:hr>142</gpxtpx:hr>
+            <gpxtpx:cad>88</gpxtpx:cad>
+            <gpxtpx:speed>8.33</gpxtpx:speed>
+            <gpxtpx:course>92.5</gpxtpx:course>
+          </gpxtpx:TrackPointExtension>
+        </extensions>
+      </trkpt>
+      <trkpt lat="40.7129" lon="-74.0059">
+        <time>2024-01-15T10:00:01Z</time>
+        <extensions>
+          <gpxdata:hr>143</gpxdata:hr>
+          <gpxdata:power>260</gpxdata:power>
+        </extensions>
+      </trkpt>
+    </trkseg>
+  </trk>
+</gpx>"#;
+
+    #[test]
+    fn test_gpx_extensions() {
+        let telemetry = TelemetryData::from_gpx(SAMPLE).unwrap();
+        assert_eq!(telemetry.points.len(), 2);
+
+        let first = &telemetry.points[0];
+        assert_eq!(first.latitude, Some(40.7128));
+        assert_eq!(first.altitude, Some(10.0));
+        assert_eq!(first.speed, Some(8.33));
+        assert_eq!(first.heading, Some(92.5));
+        assert_eq!(first.channel(channel::HEART_RATE), Some(142.0));
+        assert_eq!(first.channel(channel::CADENCE), Some(88.0));
+        assert_eq!(first.channel(channel::TEMPERATURE), Some(21.5));
+        assert_eq!(first.channel(channel::WATER_TEMPERATURE), Some(18.0));
+        assert_eq!(first.channel(channel::POWER), Some(250.0));
+
+        let second = &telemetry.points[1];
+        assert_eq!(second.channel(channel::HEART_RATE), Some(143.0));
+        assert_eq!(second.channel(channel::POWER), Some(260.0));
+        assert_eq!(telemetry.metadata.max_speed, Some(8.33));
+    }
+
+    const MULTI_TRACK: &str = r#"<gpx version="1.1" xmlns="http://www.topografix.com/GPX/1/1">
+  <wpt lat="40.7" lon="-74.0"><ele>12</ele><name>Summit</name><desc>Top of the climb</desc><sym>Flag</sym></wpt>
+  <rte><name>Plan</name><rtept lat="40.7" lon="-74.0"/><rtept lat="40.8" lon="-74.1"><name>Turn</name></rtept></rte>
+  <trk>
+    <name>Morning</name>
+    <trkseg>
+      <trkpt lat="1.0" lon="1.0"><time>2024-01-15T10:00:00Z</time><speed>2</speed></trkpt>
+      <trkpt lat="1.0" lon="1.1"><time>2024-01-15T10:00:10Z</time><speed>4</speed></trkpt>
+    </trkseg>
+    <trkseg>
+      <trkpt lat="2.0" lon="2.0"><time>2024-01-15T10:05:00Z</time><speed>6</speed></trkpt>
+    </trkseg>
+  </trk>
+  <trk>
+    <name>Evening</name>
+    <trkseg><trkpt lat="3.0" lon="3.0"><time>2024-01-15T18:00:00Z</time></trkpt></trkseg>
+  </trk>
+</gpx>"#;
+
+    #[test]
+    fn test_gpx_segments_and_waypoints() {
+        let telemetry = TelemetryData::from_gpx(MULTI_TRACK).unwrap();
+        assert_eq!(telemetry.points.len(), 4);
+        assert_eq!(telemetry.segments.len(), 3);
+        assert_eq!(telemetry.segments[1].track.as_deref(), Some("Morning"));
+        assert_eq!(telemetry.segments[1].start_time.to_rfc3339(), "2024-01-15T10:05:00+00:00");
+
+        let waypoint = &telemetry.waypoints[0];
+        assert_eq!(waypoint.name.as_deref(), Some("Summit"));
+        assert_eq!(waypoint.description.as_deref(), Some("Top of the climb"));
+        assert_eq!(waypoint.altitude, Some(12.0));
+        assert_eq!(telemetry.routes[0].points.len(), 2);
+        assert_eq!(telemetry.routes[0].points[1].name.as_deref(), Some("Turn"));
+
+        // Inside a segment values are interpolated, across a break the last point is held
+        let start = telemetry.points[0].timestamp;
+        let inside = telemetry.interpolate_at_time(start + chrono::Duration::seconds(5)).unwrap();
+        assert_eq!(inside.speed, Some(3.0));
+        let gap = telemetry.interpolate_at_time(start + chrono::Duration::seconds(60)).unwrap();
+        assert_eq!(gap.speed, Some(4.0));
+        assert_eq!(gap.longitude, Some(1.1));
+    }
+
+    #[test]
+    fn test_gpx_track_selection() {
+        let evening = TelemetryData::from_gpx_track(MULTI_TRACK, &"Evening".parse().unwrap()).unwrap();
+        assert_eq!(evening.points.len(), 1);
+        assert_eq!(evening.points[0].latitude, Some(3.0));
+
+        let morning = TelemetryData::from_gpx_track(MULTI_TRACK, &GpxTrack::Index(0)).unwrap();
+        assert_eq!(morning.points.len(), 3);
+        assert_eq!(morning.segments.len(), 2);
+
+        assert!(TelemetryData::from_gpx_track(MULTI_TRACK, &GpxTrack::Index(2)).is_err());
+    }
+
+    #[test]
+    fn test_gpx_round_trip() {
+        let mut telemetry = TelemetryData::from_gpx(MULTI_TRACK).unwrap();
+        telemetry.points[0].rpm = Some(3000.0);
+        telemetry.points[0].set_channel(channel::HEART_RATE, 150.0);
+        telemetry.points[0].set_channel(channel::POWER, 300.0);
+        telemetry.points[0].set_channel_with_unit("boost", 1.2, Some("bar"));
+        telemetry.points[0].set_channel_with_unit("gear", "N", None);
+
+        let gpx = telemetry.to_gpx();
+        let restored = TelemetryData::from_gpx(&gpx).unwrap();
+
+        assert_eq!(restored.points.len(), 4);
+        assert_eq!(restored.segments, telemetry.segments);
+        assert_eq!(restored.waypoints, telemetry.waypoints);
+        assert_eq!(restored.routes, telemetry.routes);
+
+        let point = &restored.points[0];
+        assert_eq!(point.speed, Some(2.0));
+        assert_eq!(point.rpm, Some(3000.0));
+        assert_eq!(point.channels, telemetry.points[0].channels);
+    }
+
+    #[test]
+    fn test_gpx_10_speed_and_course() {
+        let gpx = r#"<gpx version="1.0" xmlns="http://www.topografix.com/GPX/1/0"><trk><trkseg>
+            <trkpt lat="1.5" lon="2.5"><time>2024-01-15T10:00:00Z</time><course>45</course><speed>3.5</speed></trkpt>
+        </trkseg></trk></gpx>"#;
+
+        let point = &TelemetryData::from_gpx(gpx).unwrap().points[0];
+        assert_eq!(point.speed, Some(3.5));
+        assert_eq!(point.heading, Some(45.0));
+        assert!(TelemetryData::from_gpx("<gpx><trk><trkseg><trkpt lat=\"x\" lon=\"1\"/></trkseg></trk></gpx>").is_err());
+    }
+
+    #[test]
+    fn test_gpx_sub_second_times() {
+        let gpx = r#"<gpx version="1.1"><trk><trkseg>
+            <trkpt lat="0" lon="0"><time>2024-01-15T10:00:00.000Z</time><speed>1</speed></trkpt>
+            <trkpt lat="0" lon="0"><time>2024-01-15T10:00:00.100Z</time><speed>2</speed></trkpt>
+            <trkpt lat="0" lon="0"><time>2024-01-15T10:00:00.250Z</time><speed>5</speed></trkpt>
+        </trkseg></trk></gpx>"#;
+
+        let telemetry = TelemetryData::from_gpx(gpx).unwrap();
+        assert_eq!(telemetry.metadata.duration, Some(0.25));
+        let first = telemetry.points[0].timestamp;
+        assert_eq!((telemetry.points[1].timestamp - first).num_milliseconds(), 100);
+
+        let between = telemetry.interpolate_at_time(first + chrono::Duration::milliseconds(50)).unwrap();
+        assert_eq!(between.speed, Some(1.5));
+        let later = telemetry.interpolate_at_time(first + chrono::Duration::milliseconds(150)).unwrap();
+        assert!((later.speed.unwrap() - 3.0).abs() < 1e-9);
+
+        let restored = TelemetryData::from_gpx(&telemetry.to_gpx()).unwrap();
+        assert_eq!(restored.points[2].timestamp, telemetry.points[2].timestamp);
+    }
+
+    #[test]
+    fn test_gpx_missing_times() {
+        let gpx = r#"<gpx version="1.1"><trk><trkseg>
+            <trkpt lat="0" lon="0"><time>2024-01-15T10:00:00Z</time></trkpt>
+            <trkpt lat="0" lon="0.001"/>
+            <trkpt lat="0" lon="0.002"/>
+        </trkseg></trk></gpx>"#;
+
+        assert!(TelemetryData::from_gpx(gpx).is_err());
+
+        let policy = TimestampPolicy { missing: "rate:2".parse().unwrap(), ..Default::default() };
+        let telemetry = TelemetryData::from_gpx_with(gpx, None, &policy).unwrap();
+        assert_eq!(telemetry.metadata.duration, Some(1.0));
+        assert_eq!(telemetry.segments[0].end_time, telemetry.points[2].timestamp);
+        assert_eq!(telemetry.metadata.times.synthesized.len(), 1);
+        assert_eq!(telemetry.metadata.times.synthesized[0].count, 2);
+        assert!(!telemetry.metadata.times.relative);
+    }
+}
@@ -0,0 +1,447 @@
+use std::{
+    collections::BTreeSet,
+    fmt::Write,
+    io::{Cursor, Read, Write as _},
+};
+use roxmltree::{Document, Node};
+use crate::{
+    error::OverlogError,
+    telemetry::{channel, Route, Segment, TelemetryData, TelemetryPoint, Waypoint},
+};
+use super::{
+    columns::Field,
+    format_rfc3339, parse_rfc3339, runs,
+    xml::{child, child_text, children, descendants, escape},
+};
+
+impl TelemetryData {
+    /// Parse KML `gx:Track` data as exported by Google Earth and trackers
+    ///
+    /// Each `gx:Track` (also inside a `gx:MultiTrack`) becomes a [`Segment`]
+    /// named after its Placemark; `<when>` and `<gx:coord>` elements are
+    /// paired in order. `ExtendedData` `gx:SimpleArrayData` arrays become
+    /// channels, Placemarks with a `<Point>` become [`Waypoint`]s and those
+    /// with a `<LineString>` [`Route`]s.
+    pub fn from_kml(kml_data: &str) -> Result<Self, OverlogError> {
+        let doc = Document::parse(kml_data)?;
+        let mut telemetry = TelemetryData::new();
+        telemetry.metadata.format = "kml".to_string();
+
+        for placemark in descendants(doc.root_element(), "Placemark") {
+            let name = child_text(placemark, "name").map(|s| s.trim().to_string());
+
+            for track in descendants(placemark, "Track") {
+                read_track(track, &name, &mut telemetry)?;
+            }
+
+            if let Some(point) = child(placemark, "Point") {
+                if let Some(waypoint) = read_point(point, placemark, name.clone()) {
+                    telemetry.waypoints.push(waypoint);
+                }
+            }
+
+            for line in descendants(placemark, "LineString") {
+                let points = read_line(line);
+                if !points.is_empty() {
+                    telemetry.routes.push(Route { name: name.clone(), points });
+                }
+            }
+        }
+
+        telemetry.calculate_metadata();
+        Ok(telemetry)
+    }
+
+    /// Parse a KMZ archive, reading `doc.kml` or else the first `.kml` entry
+    pub fn from_kmz(kmz_data: &[u8]) -> Result<Self, OverlogError> {
+        let mut archive = zip::ZipArchive::new(Cursor::new(kmz_data))?;
+        let name = archive
+            .file_names()
+            .filter(|name| name.to_ascii_lowercase().ends_with(".kml"))
+            .min_by_key(|name| !name.eq_ignore_ascii_case("doc.kml"))
+            .map(str::to_string)
+            .ok_or_else(|| OverlogError::Telemetry("KMZ archive contains no .kml file".to_string()))?;
+
+        let mut kml = String::new();
+        archive.by_name(&name)?.read_to_string(&mut kml)?;
+
+        let mut telemetry = Self::from_kml(&kml)?;
+        telemetry.metadata.format = "kmz".to_string();
+        Ok(telemetry)
+    }
+
+    /// Write KML with a `gx:Track` per segment
+    ///
+    /// Segments of the same track share a Placemark and `gx:MultiTrack`.
+    /// Fields and numeric channels are written as `gx:SimpleArrayData`
+    /// declared in a `Schema`; waypoints become Point Placemarks and routes
+    /// LineStrings.
+    pub fn to_kml(&self) -> String {
+        let positioned = |p: &&TelemetryPoint| p.latitude.is_some() && p.longitude.is_some();
+        let channels: BTreeSet<&String> = self
+            .points
+            .iter()
+            .flat_map(|p| p.channels.iter())
+            .filter(|(_, channel)| channel.value.as_f64().is_some())
+            .map(|(name, _)| name)
+            .collect();
+        let fields: Vec<Field> = Field::POINT_FIELDS[3..]
+            .iter()
+            .cloned()
+            .chain(channels.into_iter().map(|name| Field::Channel(name.clone())))
+            .filter(|field| self.points.iter().any(|p| field.get(p).is_some()))
+            .collect();
+
+        let mut kml = String::new();
+        kml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        kml.push_str("<kml xmlns=\"http://www.opengis.net/kml/2.2\" xmlns:gx=\"http://www.google.com/kml/ext/2.2\">\n");
+        kml.push_str("  <Document>\n");
+
+        if !fields.is_empty() {
+            kml.push_str("    <Schema id=\"overlog\">\n");
+            for field in &fields {
+                let _ = writeln!(kml, "      <gx:SimpleArrayField name=\"{}\" type=\"float\"/>", escape(field.name()));
+            }
+            kml.push_str("    </Schema>\n");
+        }
+
+        for waypoint in &self.waypoints {
+            kml.push_str("    <Placemark>\n");
+            write_name(&mut kml, &waypoint.name);
+            if let Some(description) = &waypoint.description {
+                let _ = writeln!(kml, "      <description>{}</description>", escape(description));
+            }
+            if let Some(timestamp) = &waypoint.timestamp {
+                let _ = writeln!(kml, "      <TimeStamp><when>{}</when></TimeStamp>", format_rfc3339(timestamp));
+            }
+            let _ = writeln!(kml, "      <Point><coordinates>{}</coordinates></Point>", coordinates(waypoint));
+            kml.push_str("    </Placemark>\n");
+        }
+
+        for route in &self.routes {
+            kml.push_str("    <Placemark>\n");
+            write_name(&mut kml, &route.name);
+            let line: Vec<String> = route.points.iter().map(coordinates).collect();
+            let _ = writeln!(kml, "      <LineString><coordinates>{}</coordinates></LineString>", line.join(" "));
+            kml.push_str("    </Placemark>\n");
+        }
+
+        let mut current_track: Option<Option<&str>> = None;
+        for (track, points) in runs(self) {
+            let points: Vec<&TelemetryPoint> = points.iter().filter(positioned).collect();
+            if points.is_empty() {
+                continue;
+            }
+            if current_track != Some(track) {
+                if current_track.is_some() {
+                    kml.push_str("      </gx:MultiTrack>\n    </Placemark>\n");
+                }
+                kml.push_str("    <Placemark>\n");
+                write_name(&mut kml, &track.map(str::to_string));
+                kml.push_str("      <gx:MultiTrack>\n");
+                current_track = Some(track);
+            }
+            write_track(&mut kml, &points, &fields);
+        }
+        if current_track.is_some() {
+            kml.push_str("      </gx:MultiTrack>\n    </Placemark>\n");
+        }
+
+        kml.push_str("  </Document>\n</kml>\n");
+        kml
+    }
+
+    /// Write `to_kml` output as `doc.kml` in a KMZ archive
+    pub fn to_kmz(&self) -> Result<Vec<u8>, OverlogError> {
+        let mut archive = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        archive.start_file("doc.kml", zip::write::SimpleFileOptions::default())?;
+        archive.write_all(self.to_kml().as_bytes())?;
+        Ok(archive.finish()?.into_inner())
+    }
+}
+
+fn write_name(kml: &mut String, name: &Option<String>) {
+    if let Some(name) = name {
+        let _ = writeln!(kml, "      <name>{}</name>", escape(name));
+    }
+}
+
+fn coordinates(waypoint: &Waypoint) -> String {
+    match waypoint.altitude {
+        Some(altitude) => format!("{},{},{}", waypoint.longitude, waypoint.latitude, altitude),
+        None => format!("{},{}", waypoint.longitude, waypoint.latitude),
+    }
+}
+
+fn write_track(kml: &mut String, points: &[&TelemetryPoint], fields: &[Field]) {
+    kml.push_str("        <gx:Track>\n");
+    for point in points {
+        let _ = writeln!(kml, "          <when>{}</when>", format_rfc3339(&point.timestamp));
+    }
+    for point in points {
+        let (longitude, latitude) = (point.longitude.unwrap_or_default(), point.latitude.unwrap_or_default());
+        match point.altitude {
+            Some(altitude) => {
+                let _ = writeln!(kml, "          <gx:coord>{} {} {}</gx:coord>", longitude, latitude, altitude);
+            }
+            None => {
+                let _ = writeln!(kml, "          <gx:coord>{} {}</gx:coord>", longitude, latitude);
+            }
+        }
+    }
+
+    let fields: Vec<&Field> = fields.iter().filter(|f| points.iter().any(|p| f.get(p).is_some())).collect();
+    if !fields.is_empty() {
+        kml.push_str("          <ExtendedData>\n            <SchemaData schemaUrl=\"#overlog\">\n");
+        for field in fields {
+            let _ = writeln!(kml, "              <gx:SimpleArrayData name=\"{}\">", escape(field.name()));
+            for point in points {
+                match field.get(point) {
+                    Some(value) => {
+                        let _ = writeln!(kml, "                <gx:value>{}</gx:value>", value);
+                    }
+                    None => kml.push_str("                <gx:value/>\n"),
+                }
+            }
+            kml.push_str("              </gx:SimpleArrayData>\n");
+        }
+        kml.push_str("            </SchemaData>\n          </ExtendedData>\n");
+    }
+    kml.push_str("        </gx:Track>\n");
+}
+
+fn read_track(track: Node, name: &Option<String>, telemetry: &mut TelemetryData) -> Result<(), OverlogError> {
+    let times = children(track, "when")
+        .map(|when| parse_rfc3339(when.text().unwrap_or_default()))
+        .collect::<Result<Vec<_>, _>>()?;
+    let coords: Vec<&str> = children(track, "coord").map(|c| c.text().unwrap_or_default()).collect();
+
+    if !coords.is_empty() && coords.len() != times.len() {
+        return Err(OverlogError::Telemetry(format!(
+            "gx:Track has {} <when> but {} <gx:coord> elements",
+            times.len(),
+            coords.len()
+        )));
+    }
+
+    let arrays: Vec<(String, Vec<Option<f64>>)> = child(track, "ExtendedData")
+        .into_iter()
+        .flat_map(|data| descendants(data, "SimpleArrayData"))
+        .filter_map(|array| {
+            let name = array.attribute("name")?;
+            let values = children(array, "value")
+                .map(|v| v.text().and_then(|t| t.trim().parse().ok()))
+                .collect();
+            Some((name.to_string(), values))
+        })
+        .collect();
+
+    let first = telemetry.points.len();
+    for (i, timestamp) in times.into_iter().enumerate() {
+        // "lon lat alt", separated by spaces
+        let coord: Vec<f64> = coords
+            .get(i)
+            .map(|c| c.split_whitespace().filter_map(|v| v.parse().ok()).collect())
+            .unwrap_or_default();
+
+        let mut point = TelemetryPoint {
+            timestamp,
+            longitude: coord.first().copied(),
+            latitude: coord.get(1).copied(),
+            altitude: coord.get(2).copied(),
+            ..Default::default()
+        };
+        for (name, values) in &arrays {
+            if let Some(value) = values.get(i).copied().flatten() {
+                set_array_value(&mut point, name, value);
+            }
+        }
+        telemetry.points.push(point);
+    }
+
+    let points = &telemetry.points[first..];
+    if let (Some(start), Some(end)) = (points.first(), points.last()) {
+        telemetry.segments.push(Segment {
+            track: name.clone(),
+            start_time: start.timestamp,
+            end_time: end.timestamp,
+        });
+    }
+
+    Ok(())
+}
+
+/// Store an `ExtendedData` array value, using the names Garmin, My Tracks
+/// and Strava exports use for common sensors
+fn set_array_value(point: &mut TelemetryPoint, name: &str, value: f64) {
+    match name.to_ascii_lowercase().as_str() {
+        "speed" => point.speed = Some(value),
+        "bearing" | "course" | "heading" => point.heading = Some(value),
+        "heartrate" | "heart_rate" | "hr" => point.set_channel(channel::HEART_RATE, value),
+        "cadence" | "cad" => point.set_channel(channel::CADENCE, value),
+        "power" | "watts" => point.set_channel(channel::POWER, value),
+        "temperature" | "temp" => point.set_channel(channel::TEMPERATURE, value),
+        "distance" => point.set_channel(channel::DISTANCE, value),
+        _ => match name.parse::<Field>() {
+            Ok(Field::Channel(_)) | Err(_) => point.set_channel(name, value),
+            Ok(field) => field.set(point, value),
+        },
+    }
+}
+
+/// The `lon,lat[,alt]` tuples of a `<LineString>`
+fn read_line(line: Node) -> Vec<Waypoint> {
+    let Some(text) = child_text(line, "coordinates") else { return Vec::new() };
+    text.split_whitespace()
+        .filter_map(|tuple| {
+            let coordinates: Vec<f64> = tuple.split(',').filter_map(|v| v.trim().parse().ok()).collect();
+            Some(Waypoint {
+                name: None,
+                description: None,
+                symbol: None,
+                longitude: *coordinates.first()?,
+                latitude: *coordinates.get(1)?,
+                altitude: coordinates.get(2).copied(),
+                timestamp: None,
+            })
+        })
+        .collect()
+}
+
+fn read_point(point: Node, placemark: Node, name: Option<String>) -> Option<Waypoint> {
+    let coordinates: Vec<f64> = child_text(point, "coordinates")?
+        .trim()
+        .split(',')
+        .filter_map(|v| v.trim().parse().ok())
+        .collect();
+
+    Some(Waypoint {
+        name,
+        description: child_text(placemark, "description").map(|s| s.trim().to_string()),
+        symbol: None,
+        longitude: *coordinates.first()?,
+        latitude: *coordinates.get(1)?,
+        altitude: coordinates.get(2).copied(),
+        timestamp: child(placemark, "TimeStamp")
+            .and_then(|t| child_text(t, "when"))
+            .and_then(|when| parse_rfc3339(when).ok()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    const SAMPLE: &str = r##"<?xml version="1.0" encoding="UTF-8"?>
+<kml xmlns="http://www.opengis.net/kml/2.2" xmlns:gx="http://www.google.com/kml/ext/2.2">
+  <Document>
+    <Placemark>
+      <name>Finish</name>
+      <description>Line</description>
+      <Point><coordinates>-74.0,40.72,5</coordinates></Point>
+    </Placemark>
+    <Placemark>
+      <name>Run</name>
+      <gx:MultiTrack>
+        <gx:Track>
+          <when>2024-01-15T10:00:00Z</when>
+          <when>2024-01-15T10:00:01.500Z</when>
+          <gx:coord>-74.006 40.7128 10</gx:coord>
+          <gx:coord>-74.0059 40.7129 11</gx:coord>
+          <ExtendedData>
+            <SchemaData schemaUrl="#schema">
+              <gx:SimpleArrayData name="heartrate">
+                <gx:value>140</gx:value>
+                <gx:value>141</gx:value>
+              </gx:SimpleArrayData>
+              <gx:SimpleArrayData name="boost">
+                <gx:value>1.2</gx:value>
+                <gx:value></gx:value>
+              </gx:SimpleArrayData>
+            </SchemaData>
+          </ExtendedData>
+        </gx:Track>
+        <gx:Track>
+          <when>2024-01-15T10:05:00Z</when>
+          <gx:coord>-74.0 40.72 12</gx:coord>
+        </gx:Track>
+      </gx:MultiTrack>
+    </Placemark>
+  </Document>
+</kml>"##;
+
+    #[test]
+    fn test_kml_tracks() {
+        let telemetry = TelemetryData::from_kml(SAMPLE).unwrap();
+        assert_eq!(telemetry.points.len(), 3);
+        assert_eq!(telemetry.segments.len(), 2);
+        assert_eq!(telemetry.segments[0].track.as_deref(), Some("Run"));
+
+        let first = &telemetry.points[0];
+        assert_eq!(first.latitude, Some(40.7128));
+        assert_eq!(first.longitude, Some(-74.006));
+        assert_eq!(first.altitude, Some(10.0));
+        assert_eq!(first.channel(channel::HEART_RATE), Some(140.0));
+        assert_eq!(first.channel("boost"), Some(1.2));
+        assert_eq!(telemetry.points[1].channel("boost"), None);
+        assert_eq!(telemetry.points[1].timestamp.to_rfc3339(), "2024-01-15T10:00:01.500+00:00");
+
+        let waypoint = &telemetry.waypoints[0];
+        assert_eq!(waypoint.name.as_deref(), Some("Finish"));
+        assert_eq!(waypoint.latitude, 40.72);
+        assert_eq!(waypoint.altitude, Some(5.0));
+    }
+
+    #[test]
+    fn test_kml_round_trip() {
+        let mut telemetry = TelemetryData::from_kml(SAMPLE).unwrap();
+        telemetry.points[0].rpm = Some(4000.0);
+        let corner = |latitude, longitude, altitude| Waypoint {
+            name: None,
+            description: None,
+            symbol: None,
+            latitude,
+            longitude,
+            altitude,
+            timestamp: None,
+        };
+        telemetry.routes.push(Route {
+            name: Some("Lap line".to_string()),
+            points: vec![corner(40.7128, -74.006, Some(10.0)), corner(40.72, -74.0, None)],
+        });
+
+        let restored = TelemetryData::from_kml(&telemetry.to_kml()).unwrap();
+        assert_eq!(restored.points.len(), 3);
+        assert_eq!(restored.segments, telemetry.segments);
+        assert_eq!(restored.waypoints, telemetry.waypoints);
+        assert_eq!(restored.routes, telemetry.routes);
+        for (restored, original) in restored.points.iter().zip(&telemetry.points) {
+            assert_eq!(restored.timestamp, original.timestamp);
+            assert_eq!(restored.altitude, original.altitude);
+            assert_eq!(restored.rpm, original.rpm);
+            assert_eq!(restored.channels, original.channels);
+        }
+    }
+
+    #[test]
+    fn test_kmz() {
+        let mut buffer = Cursor::new(Vec::new());
+        {
+            let mut zip = zip::ZipWriter::new(&mut buffer);
+            let options = zip::write::SimpleFileOptions::default();
+            zip.start_file("files/readme.txt", options).unwrap();
+            zip.write_all(b"not kml").unwrap();
+            zip.start_file("doc.kml", options).unwrap();
+            zip.write_all(SAMPLE.as_bytes()).unwrap();
+            zip.finish().unwrap();
+        }
+
+        let telemetry = TelemetryData::from_kmz(buffer.get_ref()).unwrap();
+        assert_eq!(telemetry.metadata.format, "kmz");
+        assert_eq!(telemetry.points.len(), 3);
+
+        let restored = TelemetryData::from_kmz(&telemetry.to_kmz().unwrap()).unwrap();
+        assert_eq!(restored.points.len(), 3);
+    }
+}
@@ -0,0 +1,445 @@
+//! Racelogic VBOX (`.vbo`) and lap-timer app CSV importers
+
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc};
+use crate::{
+    error::OverlogError,
+    telemetry::{channel, TelemetryData, TelemetryPoint},
+};
+use super::columns::{motorsport_field, Field, Unit};
+
+/// Most lines searched for an app banner before the column header
+const PREAMBLE_LINES: usize = 20;
+
+/// Lap-timer apps with a CSV export
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LapTimer {
+    RaceChrono,
+    HarrysLapTimer,
+    TrackAddict,
+}
+
+impl LapTimer {
+    pub fn format_name(self) -> &'static str {
+        match self {
+            LapTimer::RaceChrono => "racechrono",
+            LapTimer::HarrysLapTimer => "harrys",
+            LapTimer::TrackAddict => "trackaddict",
+        }
+    }
+
+    /// Recognize an export from the app banner in its preamble, the lines
+    /// up to the column header
+    pub fn detect(text: &str) -> Option<Self> {
+        let mut head = String::new();
+        for line in text.lines().take(PREAMBLE_LINES) {
+            head.push_str(&line.to_ascii_lowercase());
+            head.push('\n');
+            if is_column_header(line) {
+                break;
+            }
+        }
+        if head.contains("racechrono") {
+            Some(LapTimer::RaceChrono)
+        } else if head.contains("trackaddict") {
+            Some(LapTimer::TrackAddict)
+        } else if head.contains("harry") {
+            Some(LapTimer::HarrysLapTimer)
+        } else {
+            None
+        }
+    }
+}
+
+impl TelemetryData {
+    /// Parse a Racelogic VBOX `.vbo` file
+    ///
+    /// Latitude and longitude are stored in minutes with west positive,
+    /// velocity in km/h (or mph when the header says so) and time as UTC
+    /// `hhmmss.ss` on the date from the `File created on` line. Without that
+    /// line the times are relative to 1970-01-01, see
+    /// [`TimeReport`](crate::telemetry::TimeReport).
+    pub fn from_vbo(vbo_data: &str) -> Result<Self, OverlogError> {
+        let mut section = String::new();
+        let mut date: Option<NaiveDate> = None;
+        let mut speed_unit = Unit::KilometersPerHour;
+        let mut names: Vec<String> = Vec::new();
+        let mut rows: Vec<&str> = Vec::new();
+
+        for line in vbo_data.lines().map(str::trim).filter(|l| !l.is_empty()) {
+            if line.starts_with('[') && line.ends_with(']') {
+                section = line[1..line.len() - 1].to_ascii_lowercase();
+                continue;
+            }
+            match section.as_str() {
+                "" => {
+                    // "File created on 15/01/2024 at 10:00:00"
+                    if let Some(d) = line.split_whitespace().find_map(|w| NaiveDate::parse_from_str(w, "%d/%m/%Y").ok()) {
+                        date = Some(d);
+                    }
+                }
+                "header" if line.eq_ignore_ascii_case("velocity mph") => speed_unit = Unit::MilesPerHour,
+                "column names" => names = line.split_whitespace().map(str::to_ascii_lowercase).collect(),
+                "data" => rows.push(line),
+                _ => {}
+            }
+        }
+
+        if names.is_empty() {
+            return Err(OverlogError::Telemetry("VBO file has no [column names] section".to_string()));
+        }
+        let time_index = names
+            .iter()
+            .position(|n| n == "time")
+            .ok_or_else(|| OverlogError::Telemetry("VBO file has no time column".to_string()))?;
+
+        let mut telemetry = TelemetryData::new();
+        telemetry.metadata.format = "vbo".to_string();
+        telemetry.metadata.times.relative = date.is_none();
+        let mut day = date.unwrap_or_default();
+        let mut last_time: Option<NaiveTime> = None;
+
+        for row in rows {
+            let values: Vec<&str> = row.split_whitespace().collect();
+            let Some(time) = values.get(time_index).and_then(|t| parse_hhmmss(t)) else { continue };
+            // Rows that step back less than half a day are out of order
+            let Some(step) = last_time.map_or(Some(0), |last| super::day_step(last, time)) else { continue };
+            day += Duration::days(step);
+            last_time = Some(time);
+
+            let mut point = TelemetryPoint {
+                timestamp: day.and_time(time).and_utc(),
+                ..Default::default()
+            };
+
+            for (name, raw) in names.iter().zip(&values) {
+                let Ok(value) = raw.parse::<f64>() else { continue };
+                match name.as_str() {
+                    "time" => {}
+                    "lat" => point.latitude = Some(value / 60.0),
+                    "long" => point.longitude = Some(-value / 60.0),
+                    "velocity" => point.speed = Some(speed_unit.convert(value)),
+                    // The top bits flag brake trigger and DGPS use
+                    "sats" => point.set_channel(channel::SATELLITES, (value as u32 & 0x3F) as f64),
+                    _ => {
+                        if let Some(field) = motorsport_field(name) {
+                            field.set(&mut point, value);
+                        }
+                    }
+                }
+            }
+
+            telemetry.points.push(point);
+        }
+
+        telemetry.calculate_metadata();
+        Ok(telemetry)
+    }
+
+    /// Parse a RaceChrono CSV export
+    pub fn from_racechrono(csv_data: &str) -> Result<Self, OverlogError> {
+        Self::from_lap_timer(csv_data, LapTimer::RaceChrono)
+    }
+
+    /// Parse a Harry's LapTimer CSV export
+    pub fn from_harrys_laptimer(csv_data: &str) -> Result<Self, OverlogError> {
+        Self::from_lap_timer(csv_data, LapTimer::HarrysLapTimer)
+    }
+
+    /// Parse a TrackAddict (RaceRender format) CSV export
+    pub fn from_trackaddict(csv_data: &str) -> Result<Self, OverlogError> {
+        Self::from_lap_timer(csv_data, LapTimer::TrackAddict)
+    }
+
+    /// Parse a lap-timer app CSV export
+    ///
+    /// The column header is the first row naming known channels; banner and
+    /// `#` comment lines before it are skipped, and a units row after it (as
+    /// written by RaceChrono) refines unit detection. Time comes from a Unix
+    /// time column when present, otherwise from elapsed seconds added to the
+    /// session date in the preamble, which may carry its UTC offset (as
+    /// Harry's LapTimer writes it). Without a session date the times are
+    /// relative, see [`TimeReport`](crate::telemetry::TimeReport).
+    pub fn from_lap_timer(csv_data: &str, app: LapTimer) -> Result<Self, OverlogError> {
+        let lines: Vec<&str> = csv_data.lines().collect();
+        let header_index = lines
+            .iter()
+            .position(|line| is_column_header(line))
+            .ok_or_else(|| OverlogError::Telemetry(format!("No {} column header found", app.format_name())))?;
+
+        let headers = split_csv_line(lines[header_index]);
+        let session_start = lines[..header_index].iter().find_map(|line| preamble_date(line));
+
+        // Rows between the header and the data that aren't numeric hold units or sources
+        let mut data_start = header_index + 1;
+        let mut unit_labels: Option<Vec<String>> = None;
+        while let Some(line) = lines.get(data_start) {
+            let first = split_csv_line(line).into_iter().next().unwrap_or_default();
+            if first.parse::<f64>().is_ok() || line.trim().is_empty() {
+                break;
+            }
+            if unit_labels.is_none() {
+                unit_labels = Some(split_csv_line(line));
+            }
+            data_start += 1;
+        }
+
+        let time = TimeColumn::find(&headers)
+            .ok_or_else(|| OverlogError::Telemetry(format!("No time column in {} export", app.format_name())))?;
+
+        // First column wins when a channel is logged from several sources
+        let mut columns: Vec<(usize, Field, Unit)> = Vec::new();
+        for (index, header) in headers.iter().enumerate() {
+            let Some(field) = motorsport_field(header) else { continue };
+            if columns.iter().any(|(_, f, _)| *f == field) {
+                continue;
+            }
+            let mut unit = Unit::infer(header);
+            if unit == Unit::Native {
+                if let Some(label) = unit_labels.as_ref().and_then(|labels| labels.get(index)) {
+                    unit = Unit::infer(label);
+                }
+            }
+            columns.push((index, field, unit));
+        }
+
+        let mut telemetry = TelemetryData::new();
+        telemetry.metadata.format = app.format_name().to_string();
+
+        for line in &lines[data_start..] {
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let cells = split_csv_line(line);
+            let Some(timestamp) = time.timestamp(&cells, session_start) else { continue };
+
+            let mut point = TelemetryPoint { timestamp, ..Default::default() };
+            for (index, field, unit) in &columns {
+                if let Some(value) = cells.get(*index).and_then(|c| c.parse::<f64>().ok()) {
+                    field.set(&mut point, unit.convert(value));
+                }
+            }
+            telemetry.points.push(point);
+        }
+
+        telemetry.metadata.times.relative = matches!(time, TimeColumn::Elapsed(_)) && session_start.is_none();
+        telemetry.calculate_metadata();
+        Ok(telemetry)
+    }
+}
+
+/// Where a lap-timer export keeps its time base
+enum TimeColumn {
+    /// Seconds since the Unix epoch
+    Unix(usize),
+    /// Seconds since the start of the session
+    Elapsed(usize),
+}
+
+impl TimeColumn {
+    fn find(headers: &[String]) -> Option<Self> {
+        let names: Vec<String> = headers
+            .iter()
+            .map(|h| h.split('(').next().unwrap_or("").trim().to_ascii_lowercase())
+            .collect();
+
+        names
+            .iter()
+            .position(|n| matches!(n.as_str(), "timestamp" | "utc time" | "utc" | "unix time"))
+            .map(TimeColumn::Unix)
+            .or_else(|| {
+                names
+                    .iter()
+                    .position(|n| matches!(n.as_str(), "time" | "elapsed time" | "session time" | "laptime"))
+                    .map(TimeColumn::Elapsed)
+            })
+    }
+
+    fn timestamp(&self, cells: &[String], session_start: Option<DateTime<Utc>>) -> Option<DateTime<Utc>> {
+        let (index, unix) = match self {
+            TimeColumn::Unix(i) => (*i, true),
+            TimeColumn::Elapsed(i) => (*i, false),
+        };
+        let seconds: f64 = cells.get(index)?.parse().ok()?;
+        let micros = (seconds * 1_000_000.0).round() as i64;
+
+        if unix {
+            Utc.timestamp_micros(micros).single()
+        } else {
+            Some(session_start.unwrap_or(DateTime::<Utc>::UNIX_EPOCH) + Duration::microseconds(micros))
+        }
+    }
+}
+
+/// Whether a line is the column header: not a comment, and naming at least
+/// two known channels
+fn is_column_header(line: &str) -> bool {
+    !line.starts_with('#') && split_csv_line(line).iter().filter(|cell| motorsport_field(cell).is_some()).count() >= 2
+}
+
+/// Find a session date in a preamble line such as `Created,15/01/2024,10:00`
+/// or `Date,2024-01-15 11:00:00 +0100`
+fn preamble_date(line: &str) -> Option<DateTime<Utc>> {
+    let cells = split_csv_line(line);
+    for cell in &cells {
+        if let Some(time) = ["%Y-%m-%d %H:%M:%S %z", "%d/%m/%Y %H:%M:%S %z"]
+            .iter()
+            .find_map(|fmt| DateTime::parse_from_str(cell, fmt).ok())
+        {
+            return Some(time.with_timezone(&Utc));
+        }
+        if let Some(time) = ["%Y-%m-%d %H:%M:%S", "%d/%m/%Y %H:%M:%S"]
+            .iter()
+            .find_map(|fmt| NaiveDateTime::parse_from_str(cell, fmt).ok())
+        {
+            return Some(time.and_utc());
+        }
+    }
+    for (i, cell) in cells.iter().enumerate() {
+        let Some(date) = ["%d/%m/%Y", "%Y-%m-%d", "%m/%d/%Y"]
+            .iter()
+            .find_map(|fmt| NaiveDate::parse_from_str(cell, fmt).ok())
+        else {
+            continue;
+        };
+        let time = cells
+            .get(i + 1)
+            .and_then(|t| NaiveTime::parse_from_str(t, "%H:%M:%S").or_else(|_| NaiveTime::parse_from_str(t, "%H:%M")).ok())
+            .unwrap_or_default();
+        return Some(NaiveDateTime::new(date, time).and_utc());
+    }
+    None
+}
+
+fn split_csv_line(line: &str) -> Vec<String> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(line.as_bytes());
+    reader
+        .records()
+        .next()
+        .and_then(Result::ok)
+        .map(|record| record.iter().map(|c| c.trim().to_string()).collect())
+        .unwrap_or_default()
+}
+
+/// Parse a VBOX `hhmmss.ss` UTC time of day
+fn parse_hhmmss(value: &str) -> Option<NaiveTime> {
+    let value = value.trim_start_matches('+');
+    let hours = value.get(0..2)?.parse().ok()?;
+    let minutes = value.get(2..4)?.parse().ok()?;
+    let seconds: f64 = value.get(4..)?.parse().ok()?;
+    NaiveTime::from_hms_nano_opt(hours, minutes, seconds.trunc() as u32, (seconds.fract() * 1e9).round() as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vbo() {
+        let vbo = "File created on 15/01/2024 at 10:00:00\n\n[header]\nsatellites\ntime\nlatitude\nlongitude\n\
+            velocity kmh\nheading\nlong accel g\nlat accel g\n\n[column names]\n\
+            sats time lat long velocity heading long_acc lat_acc\n\n[data]\n\
+            008 235959.90 +02442.76800 +04440.36000 036.000 090.00 +0.10 -0.50\n\
+            008 235959.50 +02442.76800 +04440.36000 036.000 090.00 +0.10 -0.50\n\
+            136 000000.00 +02442.76900 +04440.35000 072.000 091.00 +0.20 -0.40\n";
+
+        // The row that goes back in time is dropped rather than starting a new day
+        let telemetry = TelemetryData::from_vbo(vbo).unwrap();
+        assert_eq!(telemetry.points.len(), 2);
+
+        let first = &telemetry.points[0];
+        assert_eq!(first.timestamp.to_rfc3339(), "2024-01-15T23:59:59.900+00:00");
+        assert!((first.latitude.unwrap() - 40.7128).abs() < 1e-9);
+        assert!((first.longitude.unwrap() + 74.006).abs() < 1e-9);
+        assert!((first.speed.unwrap() - 10.0).abs() < 1e-9);
+        assert_eq!(first.g_force_x, Some(-0.5));
+        assert_eq!(first.g_force_y, Some(0.1));
+
+        let second = &telemetry.points[1];
+        assert_eq!(second.timestamp.to_rfc3339(), "2024-01-16T00:00:00+00:00");
+        assert_eq!(second.channel(channel::SATELLITES), Some(8.0));
+    }
+
+    #[test]
+    fn test_vbo_without_date_is_relative() {
+        let vbo = "[column names]\ntime lat long\n\n[data]\n000001.00 +02442.76800 +04440.36000\n";
+        let mut telemetry = TelemetryData::from_vbo(vbo).unwrap();
+        assert!(telemetry.metadata.times.relative);
+
+        let start = "2024-01-15T10:00:00Z".parse().unwrap();
+        telemetry.apply_timestamp_policy(&crate::formats::timing::TimestampPolicy { start: Some(start), ..Default::default() });
+        assert_eq!(telemetry.points[0].timestamp.to_rfc3339(), "2024-01-15T10:00:01+00:00");
+        assert!(!TelemetryData::from_vbo(&format!("File created on 15/01/2024\n{}", vbo)).unwrap().metadata.times.relative);
+    }
+
+    #[test]
+    fn test_racechrono() {
+        let csv = "This file is created using RaceChrono v7.4\nFormat,3\nCreated,15/01/2024,10:00\n\n\
+            Timestamp,Fragment ID,Lap #,Latitude,Longitude,Speed,Lateral acceleration,Longitudinal acceleration,Speed\n\
+            unix time,,,deg,deg,km/h,G,G,m/s\n\
+            gps,,,gps,gps,gps,calc,calc,obd\n\
+            1705312800.000,0,1,40.7128,-74.0060,36.0,0.5,-0.2,99\n\
+            1705312800.100,0,1,40.7129,-74.0059,72.0,0.6,-0.1,99\n";
+
+        assert_eq!(LapTimer::detect(csv), Some(LapTimer::RaceChrono));
+        let telemetry = TelemetryData::from_racechrono(csv).unwrap();
+        assert_eq!(telemetry.points.len(), 2);
+
+        let first = &telemetry.points[0];
+        assert_eq!(first.timestamp.to_rfc3339(), "2024-01-15T10:00:00+00:00");
+        assert!((first.speed.unwrap() - 10.0).abs() < 1e-9);
+        assert_eq!(first.g_force_x, Some(0.5));
+        assert_eq!(first.channel(channel::LAP), Some(1.0));
+        assert_eq!((telemetry.points[1].timestamp - first.timestamp).num_milliseconds(), 100);
+    }
+
+    #[test]
+    fn test_harrys_laptimer() {
+        // Banner below the session details, elapsed time from a local start
+        let csv = "Export,Logged data\nTrack,Hockenheimring GP\nVehicle,Test car\nDriver,\n\
+            Date,2024-01-15 11:00:00 +0100\nGenerator,Harry's LapTimer Petrolhead 23.1.1\nSession,1\n\n\
+            Time (s),Lap,Latitude (deg),Longitude (deg),Altitude (m),Speed (km/h),Heading (deg),Lateral G,Longitudinal G,RPM,Throttle (%)\n\
+            0.000,1,49.3278,8.5656,103.2,36.0,90.0,0.52,-0.21,4500,80\n\
+            0.100,1,49.3278,8.5657,103.2,37.8,90.5,0.55,-0.18,4600,85\n\
+            0.200,1,49.3278,8.5658,103.3,39.6,91.0,0.57,-0.15,4700,90\n";
+
+        assert_eq!(LapTimer::detect(csv), Some(LapTimer::HarrysLapTimer));
+        let telemetry = TelemetryData::from_harrys_laptimer(csv).unwrap();
+        assert_eq!(telemetry.points.len(), 3);
+        assert!(!telemetry.metadata.times.relative);
+
+        let first = &telemetry.points[0];
+        assert_eq!(first.timestamp.to_rfc3339(), "2024-01-15T10:00:00+00:00");
+        assert!((first.latitude.unwrap() - 49.3278).abs() < 1e-9);
+        assert!((first.speed.unwrap() - 10.0).abs() < 1e-9);
+        assert_eq!(first.heading, Some(90.0));
+        assert_eq!(first.g_force_x, Some(0.52));
+        assert_eq!(first.g_force_y, Some(-0.21));
+        assert_eq!(first.rpm, Some(4500.0));
+        assert_eq!(first.throttle, Some(0.8));
+        assert_eq!(first.channel(channel::LAP), Some(1.0));
+        assert_eq!((telemetry.points[2].timestamp - first.timestamp).num_milliseconds(), 200);
+
+        // A banner past the column header isn't one
+        assert_eq!(LapTimer::detect("Time,Speed,RPM\n0,1,2\n# Harry's LapTimer\n"), None);
+    }
+
+    #[test]
+    fn test_trackaddict() {
+        let csv = "# RaceRender Data: TrackAddict 4.6.1 on iOS\n# Vehicle: Test\n\
+            \"Time\",\"UTC Time\",\"Lap\",\"Latitude\",\"Longitude\",\"Altitude (ft)\",\"Speed (MPH)\",\"Accel X\",\"Engine Speed (RPM) *OBD\",\"Throttle Position (%) *OBD\"\n\
+            0.000,1705312800.000,0,40.7128,-74.0060,100.0,10.0,0.3,3000,25\n";
+
+        assert_eq!(LapTimer::detect(csv), Some(LapTimer::TrackAddict));
+        let telemetry = TelemetryData::from_trackaddict(csv).unwrap();
+        let point = &telemetry.points[0];
+        assert_eq!(point.timestamp.to_rfc3339(), "2024-01-15T10:00:00+00:00");
+        assert!((point.altitude.unwrap() - 30.48).abs() < 1e-9);
+        assert!((point.speed.unwrap() - 4.4704).abs() < 1e-3);
+        assert_eq!(point.g_force_x, Some(0.3));
+        assert_eq!(point.rpm, Some(3000.0));
+        assert_eq!(point.throttle, Some(0.25));
+    }
+}
@@ -0,0 +1,207 @@
+pub mod csv_mapping;
+pub mod dataflash;
+pub mod dji_srt;
+pub mod fit;
+pub mod geojson;
+pub mod gpmf;
+pub mod gpx;
+pub mod kml;
+pub mod laptimer;
+pub mod nmea;
+pub mod registry;
+pub mod stream;
+pub mod tcx;
+pub mod timing;
+pub mod ulog;
+
+pub(crate) mod columns;
+mod mp4;
+mod xml;
+
+use std::collections::{BTreeMap, VecDeque};
+use chrono::{DateTime, Duration, NaiveTime, SecondsFormat, Utc};
+use crate::{
+    error::OverlogError,
+    telemetry::{TelemetryData, TelemetryPoint},
+};
+
+/// Parse an RFC 3339 / ISO 8601 timestamp into UTC
+pub(crate) fn parse_rfc3339(value: &str) -> Result<DateTime<Utc>, OverlogError> {
+    DateTime::parse_from_rfc3339(value.trim())
+        .map(|dt| dt.with_timezone(&Utc))
+        .map_err(|e| OverlogError::Telemetry(format!("Invalid timestamp '{}': {}", value, e)))
+}
+
+/// Format a timestamp as RFC 3339 UTC, with only as many fraction digits as needed
+pub(crate) fn format_rfc3339(timestamp: &DateTime<Utc>) -> String {
+    timestamp.to_rfc3339_opts(SecondsFormat::AutoSi, true)
+}
+
+/// Days to add when a log of times of day moves from `last` to `time`
+///
+/// Only a backward jump of more than 12 hours crosses midnight; a shorter
+/// one is a stale or out-of-order record, and `None` is returned.
+pub(crate) fn day_step(last: NaiveTime, time: NaiveTime) -> Option<i64> {
+    let step = time - last;
+    if step < -Duration::hours(12) {
+        Some(1)
+    } else if step < Duration::zero() {
+        None
+    } else {
+        Some(0)
+    }
+}
+
+/// Split points into continuous runs at segment breaks, with the track name
+/// of each run when known
+pub(crate) fn runs(telemetry: &TelemetryData) -> Vec<(Option<&str>, &[TelemetryPoint])> {
+    telemetry
+        .runs()
+        .into_iter()
+        .map(|run| {
+            let first = telemetry.points[run.start].timestamp;
+            let track = telemetry
+                .segments
+                .iter()
+                .find(|s| s.start_time <= first && first <= s.end_time)
+                .and_then(|s| s.track.as_deref());
+            (track, &telemetry.points[run])
+        })
+        .collect()
+}
+
+/// How far behind the newest sample a timeline point must be before it is
+/// yielded by [`TimelinePoints`]
+const SETTLE_MICROS: i64 = 1_000_000;
+
+/// Samples from several streams collected on a shared microsecond timeline
+///
+/// Used by sensor formats where each stream has its own rate. Samples from
+/// different streams that land on the same microsecond share a point.
+#[derive(Default)]
+pub(crate) struct Timeline {
+    samples: BTreeMap<i64, TelemetryPoint>,
+    origin: Option<DateTime<Utc>>,
+    latest: Option<i64>,
+}
+
+impl Timeline {
+    /// The point at `micros` on the timeline, created on first use
+    pub fn at(&mut self, micros: i64) -> &mut TelemetryPoint {
+        self.latest = self.latest.max(Some(micros));
+        self.samples.entry(micros).or_default()
+    }
+
+    /// Record that `micros` on the timeline corresponds to `utc`; the first anchor wins
+    pub fn anchor(&mut self, micros: i64, utc: DateTime<Utc>) {
+        if self.origin.is_none() {
+            self.origin = Some(utc - Duration::microseconds(micros));
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    /// Convert to telemetry, placing timeline zero at the anchored UTC time,
+    /// then `fallback_origin`, then the Unix epoch (leaving the times relative)
+    pub fn into_telemetry(mut self, format: &str, fallback_origin: Option<DateTime<Utc>>) -> TelemetryData {
+        let origin = self.origin.or(fallback_origin);
+        self.origin = origin;
+
+        let mut telemetry = TelemetryData::new();
+        telemetry.metadata.format = format.to_string();
+        telemetry.metadata.times.relative = origin.is_none();
+        telemetry.points = self.take(i64::MAX);
+        telemetry.calculate_metadata();
+        telemetry
+    }
+
+    /// Remove and time the points before `micros`, with timeline zero at the
+    /// anchored UTC time or the Unix epoch
+    fn take(&mut self, micros: i64) -> Vec<TelemetryPoint> {
+        let origin = self.origin.unwrap_or(DateTime::<Utc>::UNIX_EPOCH);
+        let mut points = Vec::new();
+        while let Some(entry) = self.samples.first_entry().filter(|entry| *entry.key() < micros) {
+            let (micros, mut point) = entry.remove_entry();
+            point.timestamp = origin + Duration::microseconds(micros);
+            points.push(point);
+        }
+        points
+    }
+
+    /// Remove the points that no later sample is likely to add to, once the
+    /// timeline is tied to UTC
+    fn take_settled(&mut self) -> Vec<TelemetryPoint> {
+        match (self.origin, self.latest) {
+            (Some(_), Some(latest)) => self.take(latest.saturating_sub(SETTLE_MICROS)),
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// A sensor log decoded one message at a time onto a [`Timeline`]
+pub(crate) trait TimelineSource {
+    /// Decode the next message onto the timeline, `false` at the end of the log
+    fn read_message(&mut self, timeline: &mut Timeline) -> Result<bool, OverlogError>;
+
+    /// Read the rest of the log onto a timeline
+    fn read_timeline(&mut self) -> Result<Timeline, OverlogError> {
+        let mut timeline = Timeline::default();
+        while self.read_message(&mut timeline)? {}
+        Ok(timeline)
+    }
+}
+
+/// Points of a sensor log, yielded while it is read
+///
+/// Streams are written slightly out of order, so a point is held until it
+/// is [`SETTLE_MICROS`] behind the newest sample. Points are also held until
+/// the timeline is tied to UTC, which for a log that never is means until
+/// the end. A sample that arrives after its point was yielded is yielded as
+/// a point of its own.
+pub(crate) struct TimelinePoints<S> {
+    source: S,
+    timeline: Timeline,
+    ready: VecDeque<TelemetryPoint>,
+    /// Error for a log without points
+    empty: &'static str,
+    yielded: bool,
+    done: bool,
+}
+
+impl<S: TimelineSource> TimelinePoints<S> {
+    pub fn new(source: S, empty: &'static str) -> Self {
+        Self { source, timeline: Timeline::default(), ready: VecDeque::new(), empty, yielded: false, done: false }
+    }
+}
+
+impl<S: TimelineSource> Iterator for TimelinePoints<S> {
+    type Item = Result<TelemetryPoint, OverlogError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(point) = self.ready.pop_front() {
+                self.yielded = true;
+                return Some(Ok(point));
+            }
+            if self.done {
+                return None;
+            }
+            match self.source.read_message(&mut self.timeline) {
+                Ok(true) => self.ready.extend(self.timeline.take_settled()),
+                Ok(false) => {
+                    self.done = true;
+                    self.ready.extend(self.timeline.take(i64::MAX));
+                    if self.ready.is_empty() && !self.yielded {
+                        return Some(Err(OverlogError::Telemetry(self.empty.to_string())));
+                    }
+                }
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,352 @@
+//! Minimal ISO base media (MP4/MOV) demuxer used to pull timed metadata
+//! samples such as GoPro `gpmd` out of a video container.
+//!
+//! From a seekable reader only the `moov` box and the samples of the track
+//! are read, so the video data is never loaded.
+
+use std::{
+    borrow::Cow,
+    io::{Read, Seek, SeekFrom},
+};
+use crate::error::OverlogError;
+
+/// A single sample of a track, positioned on the media timeline
+#[derive(Debug, Clone)]
+pub(crate) struct Sample<'a> {
+    /// Sample start in seconds from the beginning of the track
+    pub time: f64,
+    /// Sample duration in seconds
+    pub duration: f64,
+    pub data: Cow<'a, [u8]>,
+}
+
+/// Where a sample lies in the file
+struct Location {
+    time: f64,
+    duration: f64,
+    offset: u64,
+    size: u64,
+}
+
+/// Movie-level information needed to place samples in absolute time
+#[derive(Debug, Clone, Default)]
+pub(crate) struct MovieInfo {
+    /// Creation time from `mvhd`, in seconds since 1904-01-01
+    pub creation_time: Option<u64>,
+}
+
+/// Check for an ISO BMFF container by looking for a leading `ftyp` box
+pub(crate) fn is_mp4(data: &[u8]) -> bool {
+    data.len() >= 8 && &data[4..8] == b"ftyp"
+}
+
+/// Read the movie header and all samples of the first track whose sample
+/// description uses `format` (e.g. `b"gpmd"`)
+pub(crate) fn read_track<'a>(
+    data: &'a [u8],
+    format: &[u8; 4],
+) -> Result<(MovieInfo, Vec<Sample<'a>>), OverlogError> {
+    let moov = find_box(data, b"moov").ok_or_else(no_moov)?;
+    let (info, locations) = track(moov, format)?;
+    let samples = locations
+        .into_iter()
+        .map(|location| {
+            let body = usize::try_from(location.offset)
+                .ok()
+                .and_then(|offset| data.get(offset..offset.checked_add(location.size as usize)?))
+                .ok_or_else(outside)?;
+            Ok(Sample { time: location.time, duration: location.duration, data: Cow::Borrowed(body) })
+        })
+        .collect::<Result<_, OverlogError>>()?;
+    Ok((info, samples))
+}
+
+/// Read the movie header and all samples of a track like [`read_track`],
+/// seeking past everything but the `moov` box and the samples themselves
+pub(crate) fn read_track_from(
+    reader: &mut (impl Read + Seek),
+    format: &[u8; 4],
+) -> Result<(MovieInfo, Vec<Sample<'static>>), OverlogError> {
+    let len = reader.seek(SeekFrom::End(0))?;
+    let moov = read_moov(reader, len)?;
+    let (info, locations) = track(&moov, format)?;
+    let mut samples = Vec::with_capacity(locations.len());
+    for location in locations {
+        if location.offset.checked_add(location.size).is_none_or(|end| end > len) {
+            return Err(outside());
+        }
+        let mut body = vec![0; location.size as usize];
+        reader.seek(SeekFrom::Start(location.offset))?;
+        reader.read_exact(&mut body)?;
+        samples.push(Sample { time: location.time, duration: location.duration, data: Cow::Owned(body) });
+    }
+    Ok((info, samples))
+}
+
+/// Body of the top-level `moov` box of a file `len` bytes long
+fn read_moov(reader: &mut (impl Read + Seek), len: u64) -> Result<Vec<u8>, OverlogError> {
+    let mut pos = 0u64;
+    while pos + 8 <= len {
+        let mut header = [0u8; 16];
+        reader.seek(SeekFrom::Start(pos))?;
+        reader.read_exact(&mut header[..8])?;
+        let (header_len, size) = match read_u32(&header, 0).unwrap_or(0) {
+            0 => (8, len - pos),
+            1 => {
+                reader.read_exact(&mut header[8..])?;
+                (16, read_u64(&header, 8).unwrap_or(0))
+            }
+            size => (8, size as u64),
+        };
+        if size < header_len || pos + size > len {
+            break;
+        }
+        if &header[4..8] == b"moov" {
+            let mut body = vec![0; (size - header_len) as usize];
+            reader.read_exact(&mut body)?;
+            return Ok(body);
+        }
+        pos += size;
+    }
+    Err(no_moov())
+}
+
+fn no_moov() -> OverlogError {
+    OverlogError::Telemetry("MP4 file has no moov box".to_string())
+}
+
+fn outside() -> OverlogError {
+    OverlogError::Telemetry("MP4 sample lies outside the file".to_string())
+}
+
+/// The movie header and the sample locations of the first track whose
+/// sample description uses `format`
+fn track(moov: &[u8], format: &[u8; 4]) -> Result<(MovieInfo, Vec<Location>), OverlogError> {
+    let mut info = MovieInfo::default();
+    if let Some(mvhd) = find_box(moov, b"mvhd") {
+        info.creation_time = match mvhd.first() {
+            Some(1) => read_u64(mvhd, 4),
+            Some(_) => read_u32(mvhd, 4).map(u64::from),
+            None => None,
+        };
+    }
+
+    for trak in boxes(moov).filter(|(kind, _)| kind == b"trak").map(|(_, body)| body) {
+        let Some(mdia) = find_box(trak, b"mdia") else { continue };
+        let Some(stbl) = find_box(mdia, b"minf").and_then(|minf| find_box(minf, b"stbl")) else { continue };
+        let Some(stsd) = find_box(stbl, b"stsd") else { continue };
+
+        // stsd: version/flags, entry count, then the first entry's size and format
+        if stsd.get(12..16) != Some(&format[..]) {
+            continue;
+        }
+
+        let timescale = find_box(mdia, b"mdhd")
+            .and_then(|mdhd| match mdhd.first() {
+                Some(1) => read_u32(mdhd, 20),
+                Some(_) => read_u32(mdhd, 12),
+                None => None,
+            })
+            .filter(|&ts| ts > 0)
+            .ok_or_else(|| OverlogError::Telemetry("MP4 track has no valid timescale".to_string()))?;
+
+        return Ok((info, read_samples(stbl, timescale as f64)?));
+    }
+
+    Err(OverlogError::Telemetry(format!(
+        "MP4 file has no '{}' track",
+        String::from_utf8_lossy(format)
+    )))
+}
+
+fn read_samples(stbl: &[u8], timescale: f64) -> Result<Vec<Location>, OverlogError> {
+    let missing = |name: &str| OverlogError::Telemetry(format!("MP4 sample table has no {} box", name));
+
+    // Sample durations
+    let stts = find_box(stbl, b"stts").ok_or_else(|| missing("stts"))?;
+    let mut durations = Vec::new();
+    for entry in table(stts, 8) {
+        let count = read_u32(entry, 0).unwrap_or(0);
+        let delta = read_u32(entry, 4).unwrap_or(0);
+        durations.extend(std::iter::repeat_n(delta, count as usize));
+    }
+
+    // Sample sizes
+    let stsz = find_box(stbl, b"stsz").ok_or_else(|| missing("stsz"))?;
+    let uniform_size = read_u32(stsz, 4).unwrap_or(0);
+    let sample_count = read_u32(stsz, 8).unwrap_or(0) as usize;
+    let sizes: Vec<u32> = if uniform_size != 0 {
+        vec![uniform_size; sample_count]
+    } else {
+        (0..sample_count).filter_map(|i| read_u32(stsz, 12 + i * 4)).collect()
+    };
+
+    // Chunk offsets
+    let offsets: Vec<u64> = if let Some(stco) = find_box(stbl, b"stco") {
+        table(stco, 4).filter_map(|entry| read_u32(entry, 0).map(u64::from)).collect()
+    } else if let Some(co64) = find_box(stbl, b"co64") {
+        table(co64, 8).filter_map(|entry| read_u64(entry, 0)).collect()
+    } else {
+        return Err(missing("stco"));
+    };
+
+    // Sample-to-chunk runs: (first chunk, samples per chunk), chunks are 1-based
+    let stsc = find_box(stbl, b"stsc").ok_or_else(|| missing("stsc"))?;
+    let runs: Vec<(u32, u32)> = table(stsc, 12)
+        .filter_map(|entry| Some((read_u32(entry, 0)?, read_u32(entry, 4)?)))
+        .collect();
+
+    let mut samples = Vec::with_capacity(sizes.len());
+    let mut sample_index = 0;
+    let mut time = 0u64;
+
+    'chunks: for (chunk_index, &chunk_offset) in offsets.iter().enumerate() {
+        let chunk_number = chunk_index as u32 + 1;
+        let per_chunk = runs
+            .iter()
+            .rev()
+            .find(|(first, _)| *first <= chunk_number)
+            .map(|(_, count)| *count)
+            .unwrap_or(0);
+
+        let mut offset = chunk_offset;
+        for _ in 0..per_chunk {
+            let Some(&size) = sizes.get(sample_index) else { break 'chunks };
+            let delta = durations.get(sample_index).copied().unwrap_or(0);
+
+            samples.push(Location {
+                time: time as f64 / timescale,
+                duration: delta as f64 / timescale,
+                offset,
+                size: size as u64,
+            });
+
+            time += delta as u64;
+            offset += size as u64;
+            sample_index += 1;
+        }
+    }
+
+    Ok(samples)
+}
+
+/// Iterate over the fixed-size entries of a full box table
+/// (version/flags, u32 entry count, entries)
+fn table(body: &[u8], entry_size: usize) -> impl Iterator<Item = &[u8]> {
+    let count = read_u32(body, 4).unwrap_or(0) as usize;
+    body.get(8..)
+        .unwrap_or(&[])
+        .chunks_exact(entry_size)
+        .take(count)
+}
+
+/// Iterate over the child boxes of a container body
+fn boxes(data: &[u8]) -> impl Iterator<Item = ([u8; 4], &[u8])> {
+    let mut pos = 0usize;
+    std::iter::from_fn(move || {
+        let size = read_u32(data, pos)? as u64;
+        let kind: [u8; 4] = data.get(pos + 4..pos + 8)?.try_into().ok()?;
+        let (header, size) = match size {
+            0 => (8, (data.len() - pos) as u64),
+            1 => (16, read_u64(data, pos + 8)?),
+            _ => (8, size),
+        };
+        if size < header {
+            return None;
+        }
+        let end = pos.checked_add(size as usize)?;
+        let body = data.get(pos + header as usize..end)?;
+        pos = end;
+        Some((kind, body))
+    })
+}
+
+fn find_box<'a>(data: &'a [u8], kind: &[u8; 4]) -> Option<&'a [u8]> {
+    boxes(data).find(|(k, _)| k == kind).map(|(_, body)| body)
+}
+
+fn read_u32(data: &[u8], pos: usize) -> Option<u32> {
+    data.get(pos..pos + 4).map(|b| u32::from_be_bytes(b.try_into().unwrap()))
+}
+
+fn read_u64(data: &[u8], pos: usize) -> Option<u64> {
+    data.get(pos..pos + 8).map(|b| u64::from_be_bytes(b.try_into().unwrap()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mp4_box(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut out = ((body.len() + 8) as u32).to_be_bytes().to_vec();
+        out.extend_from_slice(kind);
+        out.extend_from_slice(body);
+        out
+    }
+
+    fn full_box(kind: &[u8; 4], fields: &[u32]) -> Vec<u8> {
+        let body: Vec<u8> = std::iter::once(0u32).chain(fields.iter().copied()).flat_map(u32::to_be_bytes).collect();
+        mp4_box(kind, &body)
+    }
+
+    /// An MP4 with three `gpmd` samples in two chunks, the `moov` box last
+    fn sample_file() -> Vec<u8> {
+        let payload = b"AAAABBBBBBBBCCCC";
+        let ftyp = mp4_box(b"ftyp", b"mp41");
+        let mdat_offset = ftyp.len() as u32 + 8;
+
+        let mut stsd_entry = 16u32.to_be_bytes().to_vec();
+        stsd_entry.extend_from_slice(b"gpmd");
+        stsd_entry.extend_from_slice(&[0; 8]);
+        let mut stsd_body = vec![0; 4];
+        stsd_body.extend_from_slice(&1u32.to_be_bytes());
+        stsd_body.extend_from_slice(&stsd_entry);
+
+        let stbl = [
+            mp4_box(b"stsd", &stsd_body),
+            full_box(b"stts", &[2, 2, 1000, 1, 500]),
+            full_box(b"stsz", &[0, 3, 4, 8, 4]),
+            full_box(b"stsc", &[2, 1, 2, 1, 2, 1, 1]),
+            full_box(b"stco", &[2, mdat_offset, mdat_offset + 12]),
+        ]
+        .concat();
+        let minf = mp4_box(b"minf", &mp4_box(b"stbl", &stbl));
+        let mdhd = full_box(b"mdhd", &[0, 0, 1000, 2500]);
+        let trak = mp4_box(b"trak", &mp4_box(b"mdia", &[mdhd, minf].concat()));
+        let moov = mp4_box(b"moov", &[full_box(b"mvhd", &[3_786_825_600]), trak].concat());
+
+        [ftyp, mp4_box(b"mdat", payload), moov].concat()
+    }
+
+    #[test]
+    fn test_read_track_samples() {
+        let file = sample_file();
+        assert!(is_mp4(&file));
+
+        let (info, samples) = read_track(&file, b"gpmd").unwrap();
+        assert_eq!(info.creation_time, Some(3_786_825_600));
+        assert_eq!(samples.len(), 3);
+        assert_eq!(&samples[0].data[..], b"AAAA");
+        assert_eq!(&samples[1].data[..], b"BBBBBBBB");
+        assert_eq!(&samples[2].data[..], b"CCCC");
+        assert_eq!(samples[1].time, 1.0);
+        assert_eq!(samples[2].time, 2.0);
+        assert_eq!(samples[2].duration, 0.5);
+        assert!(read_track(&file, b"avc1").is_err());
+    }
+
+    #[test]
+    fn test_read_track_from_reader() {
+        let file = sample_file();
+        let (info, samples) = read_track_from(&mut std::io::Cursor::new(&file), b"gpmd").unwrap();
+        assert_eq!(info.creation_time, Some(3_786_825_600));
+        let data: Vec<&[u8]> = samples.iter().map(|s| &s.data[..]).collect();
+        assert_eq!(data, [&b"AAAA"[..], b"BBBBBBBB", b"CCCC"]);
+        assert_eq!(samples[2].time, 2.0);
+
+        // Truncated files
+        let truncated = &file[..file.len() - 4];
+        assert!(read_track_from(&mut std::io::Cursor::new(truncated), b"gpmd").is_err());
+        assert!(read_track_from(&mut std::io::Cursor::new(&file[..20]), b"gpmd").is_err());
+    }
+}
@@ -0,0 +1,423 @@
+//! NMEA 0183 sentence log decoder
+
+use std::{collections::VecDeque, io::BufRead};
+use chrono::{Duration, NaiveDate, NaiveTime};
+use crate::{
+    error::OverlogError,
+    telemetry::{channel, TelemetryData, TelemetryPoint},
+};
+
+const KNOTS_TO_MS: f64 = 0.514444;
+
+impl TelemetryData {
+    /// Parse a log of NMEA 0183 sentences
+    ///
+    /// GGA, RMC, VTG, GSA and GSV sentences sharing a fix time are merged
+    /// into a single point. Sentences without a time of their own (VTG, GSA,
+    /// GSV) belong to the most recent timed sentence. Sentences with a bad
+    /// checksum are skipped.
+    ///
+    /// Dates come from RMC. Epochs before the first RMC take its date, and a
+    /// time of day that jumps back by more than 12 hours is treated as a
+    /// midnight rollover. Timed sentences that go back less than that are
+    /// stale or out of order and are dropped with the sentences after them.
+    pub fn from_nmea(nmea_data: &str) -> Result<Self, OverlogError> {
+        Self::from_nmea_reader(nmea_data.as_bytes())
+    }
+
+    /// Parse NMEA sentences from a reader, line by line
+    pub fn from_nmea_reader(reader: impl BufRead) -> Result<Self, OverlogError> {
+        let mut points = NmeaPoints::new(reader);
+        let mut telemetry = TelemetryData::new();
+        telemetry.metadata.format = "nmea".to_string();
+        telemetry.points = points.by_ref().collect::<Result<_, _>>()?;
+        telemetry.metadata.times.relative = points.is_relative();
+        telemetry.calculate_metadata();
+        Ok(telemetry)
+    }
+}
+
+/// Check whether text looks like an NMEA log by inspecting its first sentences
+pub(crate) fn looks_like_nmea(text: &str) -> bool {
+    text.lines()
+        .filter(|line| !line.trim().is_empty())
+        .take(5)
+        .any(|line| line.find('$').is_some_and(|start| checked_fields(line[start..].trim_end()).is_some()))
+}
+
+/// Points of an NMEA log, read one sentence at a time
+///
+/// A point is yielded once the sentences of the next fix start. Points
+/// before the first RMC date are held until it arrives, which for a log
+/// without one means until the end. See [`TelemetryData::from_nmea`].
+pub struct NmeaPoints<R> {
+    reader: R,
+    line: Vec<u8>,
+    /// The fix that sentences are being merged into
+    epoch: Option<Epoch>,
+    /// Finished fixes waiting for a date
+    undated: Vec<Epoch>,
+    ready: VecDeque<TelemetryPoint>,
+    /// Date of day offset zero, from the latest RMC that carries a date
+    base_date: Option<NaiveDate>,
+    day_offset: i64,
+    last_time: Option<NaiveTime>,
+    rejected: usize,
+    stale: usize,
+    skipping: bool,
+    relative: bool,
+    yielded: bool,
+    done: bool,
+}
+
+impl<R: BufRead> NmeaPoints<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            line: Vec::new(),
+            epoch: None,
+            undated: Vec::new(),
+            ready: VecDeque::new(),
+            base_date: None,
+            day_offset: 0,
+            last_time: None,
+            rejected: 0,
+            stale: 0,
+            skipping: false,
+            relative: false,
+            yielded: false,
+            done: false,
+        }
+    }
+
+    /// Whether the log had no RMC date, leaving times relative to 1970-01-01;
+    /// only known once every point has been read
+    pub fn is_relative(&self) -> bool {
+        self.relative
+    }
+
+    fn sentence(&mut self, line: &str) {
+        let Some(sentence) = line.find('$').map(|start| line[start..].trim_end()) else { return };
+        let Some(fields) = checked_fields(sentence) else {
+            self.rejected += 1;
+            return;
+        };
+        // Ignore the talker (GP, GN, GL, ...) and look at the sentence type
+        let Some(kind) = fields[0].get(2..) else { return };
+
+        if let Some(time) = matches!(kind, "GGA" | "RMC").then(|| parse_time(fields.get(1)?)).flatten() {
+            if self.epoch.as_ref().map(|e| e.time) != Some(time) {
+                let Some(step) = self.last_time.map_or(Some(0), |last| super::day_step(last, time)) else {
+                    self.stale += 1;
+                    self.skipping = true;
+                    return;
+                };
+                self.day_offset += step;
+                self.last_time = Some(time);
+                let epoch = Epoch { time, day_offset: self.day_offset, date: None, point: TelemetryPoint::default() };
+                if let Some(finished) = self.epoch.replace(epoch) {
+                    self.finish(finished);
+                }
+            }
+            self.skipping = false;
+        }
+
+        if self.skipping {
+            return;
+        }
+        let Some(epoch) = self.epoch.as_mut() else { return };
+        match kind {
+            "GGA" => apply_gga(&mut epoch.point, &fields),
+            "RMC" => {
+                if let Some(date) = fields.get(9).and_then(|d| NaiveDate::parse_from_str(d, "%d%m%y").ok()) {
+                    epoch.date = Some(date);
+                }
+                apply_rmc(&mut epoch.point, &fields);
+            }
+            "VTG" => apply_vtg(&mut epoch.point, &fields),
+            "GSA" => apply_gsa(&mut epoch.point, &fields),
+            "GSV" => {
+                if let Some(count) = number(&fields, 3) {
+                    epoch.point.set_channel(channel::SATELLITES_IN_VIEW, count);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Date a finished fix, or hold it until there is a date
+    fn finish(&mut self, epoch: Epoch) {
+        // Re-anchor on every RMC date so long logs don't drift
+        if let Some(date) = epoch.date {
+            self.base_date = Some(date - Duration::days(epoch.day_offset));
+        }
+        self.undated.push(epoch);
+        if let Some(base_date) = self.base_date {
+            self.date_undated(base_date);
+        }
+    }
+
+    fn date_undated(&mut self, base_date: NaiveDate) {
+        for epoch in self.undated.drain(..) {
+            let date = base_date + Duration::days(epoch.day_offset);
+            let mut point = epoch.point;
+            point.timestamp = date.and_time(epoch.time).and_utc();
+            self.ready.push_back(point);
+        }
+    }
+
+    fn end(&mut self) -> Result<(), OverlogError> {
+        self.done = true;
+        if let Some(epoch) = self.epoch.take() {
+            self.finish(epoch);
+        }
+        if self.rejected > 0 {
+            tracing::warn!("Skipped {} NMEA sentences with invalid checksums", self.rejected);
+        }
+        if self.stale > 0 {
+            tracing::warn!("Skipped {} out-of-order NMEA sentences", self.stale);
+        }
+        if !self.undated.is_empty() {
+            tracing::warn!("NMEA log has no RMC date, timestamps are relative to 1970-01-01");
+            self.relative = true;
+            self.date_undated(NaiveDate::default());
+        }
+        if self.ready.is_empty() && !self.yielded {
+            return Err(OverlogError::Telemetry("No timed NMEA sentences (GGA/RMC) found".to_string()));
+        }
+        Ok(())
+    }
+}
+
+impl<R: BufRead> Iterator for NmeaPoints<R> {
+    type Item = Result<TelemetryPoint, OverlogError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(point) = self.ready.pop_front() {
+                self.yielded = true;
+                return Some(Ok(point));
+            }
+            if self.done {
+                return None;
+            }
+            self.line.clear();
+            match self.reader.read_until(b'\n', &mut self.line) {
+                Ok(0) => {
+                    if let Err(e) = self.end() {
+                        return Some(Err(e));
+                    }
+                }
+                Ok(_) => {
+                    let line = std::mem::take(&mut self.line);
+                    self.sentence(&String::from_utf8_lossy(&line));
+                    self.line = line;
+                }
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e.into()));
+                }
+            }
+        }
+    }
+}
+
+struct Epoch {
+    time: NaiveTime,
+    day_offset: i64,
+    date: Option<NaiveDate>,
+    point: TelemetryPoint,
+}
+
+/// Validate the `*hh` checksum (when present) and split a sentence into fields
+fn checked_fields(sentence: &str) -> Option<Vec<&str>> {
+    let body = sentence.strip_prefix('$')?;
+    let body = match body.split_once('*') {
+        Some((body, checksum)) => {
+            let expected = u8::from_str_radix(checksum.get(..2)?, 16).ok()?;
+            if body.bytes().fold(0, |acc, b| acc ^ b) != expected {
+                return None;
+            }
+            body
+        }
+        None => body,
+    };
+
+    let fields: Vec<&str> = body.split(',').collect();
+    (fields[0].len() == 5 && fields[0].bytes().all(|b| b.is_ascii_uppercase())).then_some(fields)
+}
+
+fn apply_gga(point: &mut TelemetryPoint, fields: &[&str]) {
+    let quality = number(fields, 6);
+    if let Some(quality) = quality {
+        point.set_channel(channel::GPS_QUALITY, quality);
+    }
+    if quality.is_some_and(|q| q > 0.0) {
+        point.latitude = coordinate(fields, 2);
+        point.longitude = coordinate(fields, 4);
+        point.altitude = number(fields, 9);
+    }
+    if let Some(satellites) = number(fields, 7) {
+        point.set_channel(channel::SATELLITES, satellites);
+    }
+    if let Some(hdop) = number(fields, 8) {
+        point.set_channel(channel::GPS_HDOP, hdop);
+    }
+}
+
+fn apply_rmc(point: &mut TelemetryPoint, fields: &[&str]) {
+    // Status 'V' means the receiver has no valid fix
+    if fields.get(2) != Some(&"A") {
+        return;
+    }
+    point.latitude = point.latitude.or(coordinate(fields, 3));
+    point.longitude = point.longitude.or(coordinate(fields, 5));
+    if let Some(knots) = number(fields, 7) {
+        point.speed = Some(knots * KNOTS_TO_MS);
+    }
+    point.heading = number(fields, 8).or(point.heading);
+}
+
+fn apply_vtg(point: &mut TelemetryPoint, fields: &[&str]) {
+    point.heading = point.heading.or(number(fields, 1));
+    if point.speed.is_none() {
+        point.speed = number(fields, 7)
+            .map(crate::geo::kmh_to_ms)
+            .or_else(|| number(fields, 5).map(|knots| knots * KNOTS_TO_MS));
+    }
+}
+
+fn apply_gsa(point: &mut TelemetryPoint, fields: &[&str]) {
+    // Mode 2 (fix type: 1 none, 2 2D, 3 3D), 12 satellite ids, then PDOP, HDOP, VDOP
+    let dops = [(15, channel::GPS_DOP), (16, channel::GPS_HDOP), (17, channel::GPS_VDOP)];
+    if let Some(fix) = number(fields, 2) {
+        point.set_channel(channel::GPS_FIX, fix);
+    }
+    for (index, name) in dops {
+        if let Some(value) = number(fields, index) {
+            point.set_channel(name, value);
+        }
+    }
+}
+
+fn number(fields: &[&str], index: usize) -> Option<f64> {
+    fields.get(index).and_then(|f| f.parse().ok())
+}
+
+/// Convert `ddmm.mmmm` / `dddmm.mmmm` plus a hemisphere field into degrees
+fn coordinate(fields: &[&str], index: usize) -> Option<f64> {
+    let raw: f64 = fields.get(index)?.parse().ok()?;
+    let degrees = (raw / 100.0).trunc();
+    let value = degrees + (raw - degrees * 100.0) / 60.0;
+    match *fields.get(index + 1)? {
+        "N" | "E" => Some(value),
+        "S" | "W" => Some(-value),
+        _ => None,
+    }
+}
+
+/// Parse an `hhmmss.sss` time of day
+fn parse_time(value: &str) -> Option<NaiveTime> {
+    let hours = value.get(0..2)?.parse().ok()?;
+    let minutes = value.get(2..4)?.parse().ok()?;
+    let seconds: f64 = value.get(4..)?.parse().ok()?;
+    let nanos = (seconds.fract() * 1e9).round() as u32;
+    NaiveTime::from_hms_nano_opt(hours, minutes, seconds.trunc() as u32, nanos)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sentence(body: &str) -> String {
+        let checksum = body.bytes().fold(0, |acc, b| acc ^ b);
+        format!("${}*{:02X}", body, checksum)
+    }
+
+    #[test]
+    fn test_merges_sentences_per_epoch() {
+        let log = [
+            sentence("GPRMC,235959.50,A,4042.768,N,07400.360,W,10.0,90.0,150124,,,A"),
+            sentence("GPVTG,90.0,T,,M,10.0,N,18.5,K,A"),
+            sentence("GPGGA,235959.50,4042.768,N,07400.360,W,1,08,0.9,10.0,M,,M,,"),
+            sentence("GPGSA,A,3,01,02,03,04,05,06,07,08,,,,,1.5,0.9,1.2"),
+            sentence("GPGGA,000000.50,4042.770,N,07400.350,W,2,09,0.8,11.0,M,,M,,"),
+        ]
+        .join("\n");
+
+        let telemetry = TelemetryData::from_nmea(&log).unwrap();
+        assert_eq!(telemetry.points.len(), 2);
+
+        let first = &telemetry.points[0];
+        assert_eq!(first.timestamp.to_rfc3339(), "2024-01-15T23:59:59.500+00:00");
+        assert!((first.latitude.unwrap() - 40.7128).abs() < 1e-6);
+        assert!((first.longitude.unwrap() + 74.006).abs() < 1e-6);
+        assert_eq!(first.altitude, Some(10.0));
+        assert!((first.speed.unwrap() - 5.14444).abs() < 1e-6);
+        assert_eq!(first.heading, Some(90.0));
+        assert_eq!(first.channel(channel::SATELLITES), Some(8.0));
+        assert_eq!(first.channel(channel::GPS_FIX), Some(3.0));
+        assert_eq!(first.channel(channel::GPS_HDOP), Some(0.9));
+
+        // GGA carries no date, so crossing midnight moves to the next day
+        assert_eq!(telemetry.points[1].timestamp.to_rfc3339(), "2024-01-16T00:00:00.500+00:00");
+        assert_eq!(telemetry.points[1].channel(channel::GPS_QUALITY), Some(2.0));
+
+        // Without RMC there is no date to go on
+        let gga_only = log.lines().filter(|l| l.contains("GGA")).collect::<Vec<_>>().join("\n");
+        assert!(TelemetryData::from_nmea(&gga_only).unwrap().metadata.times.relative);
+    }
+
+    #[test]
+    fn test_points_are_yielded_as_read() {
+        let log = [
+            sentence("GPGGA,100000.00,4042.768,N,07400.360,W,1,08,0.9,10.0,M,,M,,"),
+            sentence("GPRMC,100001.00,A,4042.768,N,07400.360,W,0.0,0.0,150124,,,A"),
+            sentence("GPGGA,100002.00,4042.768,N,07400.360,W,1,08,0.9,10.0,M,,M,,"),
+        ]
+        .join("\n");
+
+        // The fix before the first date is held until the date is known
+        let mut points = NmeaPoints::new(log.as_bytes());
+        let first = points.next().unwrap().unwrap();
+        assert_eq!(first.timestamp.to_rfc3339(), "2024-01-15T10:00:00+00:00");
+        assert_eq!(points.count(), 2);
+        assert!(NmeaPoints::new(&b"$GPGSV,1,1,00*79\n"[..]).next().unwrap().is_err());
+    }
+
+    #[test]
+    fn test_out_of_order_sentences_are_dropped() {
+        let log = [
+            sentence("GPRMC,100000.00,A,4042.768,N,07400.360,W,0.0,0.0,150124,,,A"),
+            sentence("GPGGA,100001.00,4042.768,N,07400.360,W,1,08,0.9,10.0,M,,M,,"),
+            // A stale repeat of the first fix and its speed
+            sentence("GPGGA,100000.00,4042.000,N,07400.000,W,1,08,0.9,10.0,M,,M,,"),
+            sentence("GPVTG,90.0,T,,M,99.0,N,,K,A"),
+            sentence("GPGGA,100002.00,4042.768,N,07400.360,W,1,08,0.9,10.0,M,,M,,"),
+        ]
+        .join("\n");
+
+        let telemetry = TelemetryData::from_nmea(&log).unwrap();
+        let times: Vec<String> = telemetry.points.iter().map(|p| p.timestamp.to_rfc3339()).collect();
+        assert_eq!(times, [
+            "2024-01-15T10:00:00+00:00",
+            "2024-01-15T10:00:01+00:00",
+            "2024-01-15T10:00:02+00:00",
+        ]);
+        assert!(telemetry.points.iter().all(|p| (p.latitude.unwrap() - 40.7128).abs() < 1e-6));
+        assert_eq!(telemetry.points[1].speed, None);
+    }
+
+    #[test]
+    fn test_rejects_bad_checksum() {
+        let log = format!(
+            "{}\n$GPGGA,100001.00,4042.768,N,07400.360,W,1,08,0.9,10.0,M,,M,,*00",
+            sentence("GPRMC,100000.00,A,4042.768,N,07400.360,W,0.0,0.0,150124,,,A"),
+        );
+        let telemetry = TelemetryData::from_nmea(&log).unwrap();
+        assert_eq!(telemetry.points.len(), 1);
+        assert!(!telemetry.metadata.times.relative);
+        assert!(looks_like_nmea(&log));
+        assert!(!looks_like_nmea("timestamp,latitude,longitude"));
+    }
+}
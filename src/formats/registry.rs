@@ -0,0 +1,616 @@
+//! Format detection by content and the registry of telemetry parsers
+//!
+//! Every format is a [`TelemetryParser`] that scores the leading bytes of a
+//! file. [`TelemetryData::from_path`] picks the best scoring parser from the
+//! global registry, so logs are recognized whatever their extension, and
+//! downstream crates can add formats of their own with [`register`].
+//! Files are read through [`stream::open`](super::stream::open), so gzip
+//! and zip compressed logs work too.
+//!
+//! CSV, NMEA, ArduPilot DataFlash and PX4 ULog logs are decoded as they are
+//! read, so [`TelemetryData::stream_path`] yields their points without
+//! holding the log in memory. Every other format is read and parsed as a
+//! whole before its first point is yielded. Of an uncompressed GoPro MP4,
+//! [`TelemetryData::from_path`] only reads the `moov` box and the GPMF
+//! samples.
+
+use std::{
+    fs::File,
+    io::{BufRead, Read},
+    path::Path,
+    sync::{Arc, OnceLock, PoisonError, RwLock},
+};
+use crate::{
+    error::OverlogError,
+    telemetry::{TelemetryData, TelemetryPoint},
+};
+use super::{
+    csv_mapping::{CsvMapping, CsvPoints},
+    dataflash, geojson,
+    laptimer::LapTimer,
+    mp4,
+    nmea::{self, NmeaPoints},
+    stream, ulog,
+};
+
+/// Number of leading bytes handed to [`TelemetryParser::probe`]
+pub const PROBE_LEN: usize = 4096;
+
+/// Score added when the file extension is one of the parser's extensions
+const EXTENSION_SCORE: u8 = 20;
+
+/// A telemetry format that can recognize and parse its files
+pub trait TelemetryParser: Send + Sync {
+    /// Format name, as accepted by `--format`
+    fn name(&self) -> &str;
+
+    /// Lower case file extensions without the dot
+    fn extensions(&self) -> &[&str] {
+        &[]
+    }
+
+    /// Score from 0 (not this format) to 100 (certainly this format) for a
+    /// file starting with `head` (up to [`PROBE_LEN`] bytes)
+    fn probe(&self, head: &[u8], extension: Option<&str>) -> u8;
+
+    fn parse(&self, data: &[u8]) -> Result<TelemetryData, OverlogError>;
+
+    /// Parse from a reader; by default the whole input is read and handed to `parse`
+    fn parse_reader(&self, reader: Box<dyn BufRead>) -> Result<TelemetryData, OverlogError> {
+        self.parse(&read_all(reader)?)
+    }
+
+    /// Parse an uncompressed file, which can be read out of order; by default
+    /// it is read through `parse_reader`
+    fn parse_file(&self, file: File) -> Result<TelemetryData, OverlogError> {
+        self.parse_reader(stream::buffered(file))
+    }
+
+    /// Yield points as they are read; by default the whole input is parsed first
+    fn points(&self, reader: Box<dyn BufRead>) -> Result<Points, OverlogError> {
+        let telemetry = self.parse_reader(reader)?;
+        Ok(Box::new(telemetry.points.into_iter().map(Ok)))
+    }
+}
+
+/// Points yielded one at a time
+pub type Points = Box<dyn Iterator<Item = Result<TelemetryPoint, OverlogError>>>;
+
+type PointsResult = Result<Points, OverlogError>;
+
+type TelemetryResult = Result<TelemetryData, OverlogError>;
+
+/// A detected parser and a reader positioned at the start of its input
+pub type Detected = (Arc<dyn TelemetryParser>, Box<dyn BufRead>);
+
+fn read_all(mut reader: Box<dyn BufRead>) -> Result<Vec<u8>, OverlogError> {
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+    Ok(data)
+}
+
+/// A built-in format: content sniffer plus parser
+struct Builtin {
+    name: &'static str,
+    extensions: &'static [&'static str],
+    /// Content score from the head as bytes and as (lossy) text
+    sniff: fn(&[u8], &str) -> u8,
+    parse: fn(&[u8]) -> Result<TelemetryData, OverlogError>,
+    /// Parser for formats that can be decoded as they are read
+    read: Option<fn(Box<dyn BufRead>) -> TelemetryResult>,
+    /// Incremental reader for the same formats
+    stream: Option<fn(Box<dyn BufRead>) -> PointsResult>,
+    /// Parser for formats that only read the parts of a file they need
+    seek: Option<fn(File) -> TelemetryResult>,
+}
+
+impl TelemetryParser for Builtin {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn extensions(&self) -> &[&str] {
+        self.extensions
+    }
+
+    fn probe(&self, head: &[u8], extension: Option<&str>) -> u8 {
+        let score = (self.sniff)(head, &String::from_utf8_lossy(head));
+        match extension {
+            Some(extension) if self.extensions.contains(&extension) => score.saturating_add(EXTENSION_SCORE).min(100),
+            _ => score,
+        }
+    }
+
+    fn parse(&self, data: &[u8]) -> Result<TelemetryData, OverlogError> {
+        (self.parse)(data)
+    }
+
+    fn parse_reader(&self, reader: Box<dyn BufRead>) -> Result<TelemetryData, OverlogError> {
+        match self.read {
+            Some(read) => read(reader),
+            None => self.parse(&read_all(reader)?),
+        }
+    }
+
+    fn parse_file(&self, file: File) -> Result<TelemetryData, OverlogError> {
+        match self.seek {
+            Some(seek) => seek(file),
+            None => self.parse_reader(stream::buffered(file)),
+        }
+    }
+
+    fn points(&self, reader: Box<dyn BufRead>) -> Result<Points, OverlogError> {
+        match self.stream {
+            Some(stream) => stream(reader),
+            None => Ok(Box::new(self.parse(&read_all(reader)?)?.points.into_iter().map(Ok))),
+        }
+    }
+}
+
+/// Decode a text format, dropping a UTF-8 byte order mark
+pub(crate) fn text(data: &[u8]) -> Result<&str, OverlogError> {
+    let text = std::str::from_utf8(data)
+        .map_err(|e| OverlogError::Telemetry(format!("File is not UTF-8 text: {}", e)))?;
+    Ok(text.strip_prefix('\u{feff}').unwrap_or(text))
+}
+
+fn lap_timer(text: &str, app: LapTimer) -> u8 {
+    if LapTimer::detect(text) == Some(app) { 95 } else { 0 }
+}
+
+const BUILTIN: [Builtin; 17] = [
+    Builtin {
+        name: "gpx",
+        extensions: &["gpx"],
+        sniff: |_, text| if text.contains("<gpx") { 100 } else { 0 },
+        parse: |data| TelemetryData::from_gpx(text(data)?),
+        read: None,
+        stream: None,
+        seek: None,
+    },
+    Builtin {
+        name: "tcx",
+        extensions: &["tcx"],
+        sniff: |_, text| if text.contains("<TrainingCenterDatabase") { 100 } else { 0 },
+        parse: |data| TelemetryData::from_tcx(text(data)?),
+        read: None,
+        stream: None,
+        seek: None,
+    },
+    Builtin {
+        name: "kml",
+        extensions: &["kml"],
+        sniff: |_, text| if text.contains("<kml") { 100 } else { 0 },
+        parse: |data| TelemetryData::from_kml(text(data)?),
+        read: None,
+        stream: None,
+        seek: None,
+    },
+    Builtin {
+        name: "kmz",
+        extensions: &["kmz"],
+        // The first local file header carries the entry name in the clear
+        sniff: |head, text| match head.starts_with(b"PK\x03\x04") {
+            true if text.contains(".kml") => 90,
+            true => 30,
+            false => 0,
+        },
+        parse: TelemetryData::from_kmz,
+        read: None,
+        stream: None,
+        seek: None,
+    },
+    Builtin {
+        name: "fit",
+        extensions: &["fit"],
+        sniff: |head, _| if head.get(8..12) == Some(b".FIT") { 100 } else { 0 },
+        parse: TelemetryData::from_fit,
+        read: None,
+        stream: None,
+        seek: None,
+    },
+    // Ahead of GPMF so raw `.bin` files with neither signature stay GPMF
+    Builtin {
+        name: "dataflash",
+        extensions: &["bin"],
+        sniff: |head, _| if dataflash::is_dataflash(head) { 90 } else { 0 },
+        parse: TelemetryData::from_dataflash,
+        read: Some(TelemetryData::from_dataflash_reader),
+        stream: Some(|reader| Ok(Box::new(dataflash::points(reader)?))),
+        seek: None,
+    },
+    Builtin {
+        name: "gpmf",
+        extensions: &["mp4", "bin"],
+        sniff: |head, _| {
+            if head.starts_with(b"DEVC") {
+                90
+            } else if mp4::is_mp4(head) {
+                60
+            } else {
+                0
+            }
+        },
+        parse: TelemetryData::from_gpmf,
+        read: None,
+        stream: None,
+        seek: Some(TelemetryData::from_gpmf_reader),
+    },
+    Builtin {
+        name: "ulog",
+        extensions: &["ulg"],
+        sniff: |head, _| if ulog::is_ulog(head) { 100 } else { 0 },
+        parse: TelemetryData::from_ulog,
+        read: Some(TelemetryData::from_ulog_reader),
+        stream: Some(|reader| Ok(Box::new(ulog::points(reader)?))),
+        seek: None,
+    },
+    Builtin {
+        name: "nmea",
+        extensions: &["nmea"],
+        sniff: |_, text| if nmea::looks_like_nmea(text) { 80 } else { 0 },
+        parse: |data| TelemetryData::from_nmea(text(data)?),
+        read: Some(TelemetryData::from_nmea_reader),
+        stream: Some(|reader| Ok(Box::new(NmeaPoints::new(reader)))),
+        seek: None,
+    },
+    Builtin {
+        name: "srt",
+        extensions: &["srt"],
+        sniff: |_, text| match text.contains(" --> ") {
+            true if text.contains("GPS(") || text.contains("[latitude") => 90,
+            true => 40,
+            false => 0,
+        },
+        parse: |data| TelemetryData::from_dji_srt(text(data)?),
+        read: None,
+        stream: None,
+        seek: None,
+    },
+    Builtin {
+        name: "vbo",
+        extensions: &["vbo"],
+        sniff: |_, text| {
+            if text.contains("[column names]") || text.starts_with("File created on") { 90 } else { 0 }
+        },
+        parse: |data| TelemetryData::from_vbo(text(data)?),
+        read: None,
+        stream: None,
+        seek: None,
+    },
+    Builtin {
+        name: "racechrono",
+        extensions: &["csv"],
+        sniff: |_, text| lap_timer(text, LapTimer::RaceChrono),
+        parse: |data| TelemetryData::from_racechrono(text(data)?),
+        read: None,
+        stream: None,
+        seek: None,
+    },
+    Builtin {
+        name: "harrys",
+        extensions: &["csv"],
+        sniff: |_, text| lap_timer(text, LapTimer::HarrysLapTimer),
+        parse: |data| TelemetryData::from_harrys_laptimer(text(data)?),
+        read: None,
+        stream: None,
+        seek: None,
+    },
+    Builtin {
+        name: "trackaddict",
+        extensions: &["csv"],
+        sniff: |_, text| lap_timer(text, LapTimer::TrackAddict),
+        parse: |data| TelemetryData::from_trackaddict(text(data)?),
+        read: None,
+        stream: None,
+        seek: None,
+    },
+    Builtin {
+        name: "geojson",
+        extensions: &["geojson", "json"],
+        sniff: |_, text| {
+            if text.trim_start().starts_with('{') && geojson::looks_like_geojson(text) { 95 } else { 0 }
+        },
+        parse: |data| TelemetryData::from_geojson(text(data)?),
+        read: None,
+        stream: None,
+        seek: None,
+    },
+    Builtin {
+        name: "json",
+        extensions: &["json"],
+        sniff: |_, text| match text.trim_start().starts_with('{') {
+            true if text.contains("\"points\"") => 90,
+            true => 30,
+            false => 0,
+        },
+        parse: |data| TelemetryData::from_json(text(data)?),
+        read: None,
+        stream: None,
+        seek: None,
+    },
+    Builtin {
+        name: "csv",
+        extensions: &["csv", "txt"],
+        sniff: |_, text| {
+            let header = text.trim_start().lines().next().unwrap_or_default().to_ascii_lowercase();
+            match header.contains([',', ';', '\t']) {
+                true if header.contains("time") => 60,
+                true => 30,
+                false => 0,
+            }
+        },
+        parse: |data| TelemetryData::from_csv(text(data)?),
+        read: Some(|reader| TelemetryData::from_csv_reader(reader, &CsvMapping::default())),
+        stream: Some(|reader| Ok(Box::new(CsvPoints::new(reader, &CsvMapping::default())?))),
+        seek: None,
+    },
+];
+
+/// An ordered set of parsers
+///
+/// When two parsers give a file the same score the one registered last
+/// wins, so registered formats can take over from the built-in ones.
+#[derive(Clone)]
+pub struct ParserRegistry {
+    parsers: Vec<Arc<dyn TelemetryParser>>,
+}
+
+impl ParserRegistry {
+    /// A registry with the built-in formats
+    pub fn new() -> Self {
+        let mut registry = Self::empty();
+        for builtin in BUILTIN {
+            registry.register(builtin);
+        }
+        registry
+    }
+
+    pub fn empty() -> Self {
+        Self { parsers: Vec::new() }
+    }
+
+    pub fn register(&mut self, parser: impl TelemetryParser + 'static) {
+        self.parsers.push(Arc::new(parser));
+    }
+
+    /// Names of the registered formats
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.parsers.iter().map(|p| p.name())
+    }
+
+    /// Parser for a format name, the last registered one for repeated names
+    pub fn get(&self, name: &str) -> Option<Arc<dyn TelemetryParser>> {
+        self.parsers.iter().rev().find(|p| p.name() == name).cloned()
+    }
+
+    /// Best scoring parser for a file, `None` when no parser recognizes it
+    pub fn detect(&self, head: &[u8], extension: Option<&str>) -> Option<Arc<dyn TelemetryParser>> {
+        let head = &head[..head.len().min(PROBE_LEN)];
+        let extension = extension.map(str::to_ascii_lowercase);
+        self.parsers
+            .iter()
+            .map(|parser| (parser.probe(head, extension.as_deref()), parser))
+            .filter(|(score, _)| *score > 0)
+            .max_by_key(|(score, _)| *score)
+            .map(|(_, parser)| parser.clone())
+    }
+
+    /// Detect the format of a file and parse it
+    pub fn parse_path(&self, path: impl AsRef<Path>) -> Result<TelemetryData, OverlogError> {
+        let path = path.as_ref();
+        let (parser, reader) = self.open_path(path)?;
+        match stream::is_compressed(path)? {
+            true => parser.parse_reader(reader),
+            false => parser.parse_file(File::open(path)?),
+        }
+    }
+
+    /// Detect the format of a (possibly compressed) stream and parse it
+    pub fn parse_reader(&self, reader: impl Read + 'static) -> Result<TelemetryData, OverlogError> {
+        let (parser, reader) = self.detect_reader(stream::decompress(reader)?, None)?;
+        parser.parse_reader(reader)
+    }
+
+    /// Detect the format of a file and read its points as they are parsed
+    ///
+    /// Only formats with an incremental reader (see the [module
+    /// docs](self)) are streamed; others are parsed as a whole first.
+    pub fn stream_path(&self, path: impl AsRef<Path>) -> Result<PointStream, OverlogError> {
+        let (parser, reader) = self.open_path(path.as_ref())?;
+        PointStream::new(parser.as_ref(), reader)
+    }
+
+    /// Detect the format of a (possibly compressed) stream and read its
+    /// points as they are parsed
+    ///
+    /// Only formats with an incremental reader (see the [module
+    /// docs](self)) are streamed; others are parsed as a whole first.
+    pub fn stream_reader(&self, reader: impl Read + 'static) -> Result<PointStream, OverlogError> {
+        let (parser, reader) = self.detect_reader(stream::decompress(reader)?, None)?;
+        PointStream::new(parser.as_ref(), reader)
+    }
+
+    /// Detect the format of a stream from its first [`PROBE_LEN`] bytes,
+    /// returning the parser and a reader positioned at the start
+    pub fn detect_reader(
+        &self,
+        reader: Box<dyn BufRead>,
+        extension: Option<&str>,
+    ) -> Result<Detected, OverlogError> {
+        let (head, reader) = stream::peek(reader, PROBE_LEN)?;
+        let parser = self
+            .detect(&head, extension)
+            .ok_or_else(|| OverlogError::UnsupportedFormat("Unrecognized telemetry data".to_string()))?;
+        Ok((parser, reader))
+    }
+
+    fn open_path(&self, path: &Path) -> Result<Detected, OverlogError> {
+        let extension = stream::extension(path);
+        self.detect_reader(stream::open(path)?, extension.as_deref()).map_err(|e| match e {
+            OverlogError::UnsupportedFormat(_) => {
+                OverlogError::UnsupportedFormat(format!("Unrecognized telemetry file: {}", path.display()))
+            }
+            e => e,
+        })
+    }
+}
+
+/// Points of a telemetry file, yielded as they are read
+///
+/// CSV, NMEA, DataFlash and ULog logs are parsed incrementally; other
+/// formats are parsed as a whole first.
+pub struct PointStream {
+    format: String,
+    points: Points,
+}
+
+impl PointStream {
+    pub fn new(parser: &dyn TelemetryParser, reader: Box<dyn BufRead>) -> Result<Self, OverlogError> {
+        Ok(Self {
+            format: parser.name().to_string(),
+            points: parser.points(reader)?,
+        })
+    }
+
+    /// Name of the detected format
+    pub fn format(&self) -> &str {
+        &self.format
+    }
+}
+
+impl Iterator for PointStream {
+    type Item = Result<TelemetryPoint, OverlogError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.points.next()
+    }
+}
+
+impl Default for ParserRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn global() -> &'static RwLock<ParserRegistry> {
+    static REGISTRY: OnceLock<RwLock<ParserRegistry>> = OnceLock::new();
+    REGISTRY.get_or_init(|| RwLock::new(ParserRegistry::new()))
+}
+
+/// Add a parser to the registry used by [`TelemetryData::from_path`]
+pub fn register(parser: impl TelemetryParser + 'static) {
+    global().write().unwrap_or_else(PoisonError::into_inner).register(parser);
+}
+
+/// A copy of the registry used by [`TelemetryData::from_path`]
+pub fn registry() -> ParserRegistry {
+    global().read().unwrap_or_else(PoisonError::into_inner).clone()
+}
+
+impl TelemetryData {
+    /// Read a telemetry file, detecting its format from its contents and
+    /// extension with the registered parsers
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, OverlogError> {
+        registry().parse_path(path)
+    }
+
+    /// Read telemetry from a (possibly gzip or zip compressed) stream,
+    /// detecting its format from its contents
+    pub fn from_reader(reader: impl Read + 'static) -> Result<Self, OverlogError> {
+        registry().parse_reader(reader)
+    }
+
+    /// Iterate over the points of a file as it is read, for the formats
+    /// listed in the [registry docs](super::registry)
+    pub fn stream_path(path: impl AsRef<Path>) -> Result<PointStream, OverlogError> {
+        registry().stream_path(path)
+    }
+
+    /// Iterate over the points of a (possibly compressed) stream as it is
+    /// read, for the formats listed in the [registry docs](super::registry)
+    pub fn stream_reader(reader: impl Read + 'static) -> Result<PointStream, OverlogError> {
+        registry().stream_reader(reader)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detected(head: &str, extension: Option<&str>) -> Option<String> {
+        ParserRegistry::new().detect(head.as_bytes(), extension).map(|p| p.name().to_string())
+    }
+
+    #[test]
+    fn test_detect_by_content() {
+        let nmea = "$GPGGA,235959.50,4042.768,N,07400.360,W,1,08,0.9,10.0,M,,M,,\n";
+        assert_eq!(detected(nmea, None).as_deref(), Some("nmea"));
+        assert_eq!(detected("<?xml version=\"1.0\"?>\n<gpx version=\"1.1\">", Some("txt")).as_deref(), Some("gpx"));
+        assert_eq!(detected("timestamp,speed\n2024-01-15T10:00:00Z,5\n", Some("log")).as_deref(), Some("csv"));
+        assert_eq!(detected("This file is created using RaceChrono\ntimestamp,speed\n", Some("csv")).as_deref(), Some("racechrono"));
+        assert_eq!(detected("{\"type\": \"FeatureCollection\"}", Some("json")).as_deref(), Some("geojson"));
+        assert_eq!(detected("{\"points\": []}", Some("json")).as_deref(), Some("json"));
+        assert_eq!(detected("plain words", None), None);
+        assert_eq!(detected("", Some("gpx")).as_deref(), Some("gpx"));
+
+        let fit = [&[14u8, 0x20, 0, 0, 0, 0, 0, 0][..], b".FIT"].concat();
+        assert_eq!(ParserRegistry::new().detect(&fit, None).map(|p| p.name().to_string()).as_deref(), Some("fit"));
+    }
+
+    struct Custom;
+
+    impl TelemetryParser for Custom {
+        fn name(&self) -> &str {
+            "custom"
+        }
+
+        fn probe(&self, head: &[u8], _extension: Option<&str>) -> u8 {
+            if head.starts_with(b"timestamp,speed") { 60 } else { 0 }
+        }
+
+        fn parse(&self, _data: &[u8]) -> Result<TelemetryData, OverlogError> {
+            let mut telemetry = TelemetryData::new();
+            telemetry.metadata.format = "custom".to_string();
+            Ok(telemetry)
+        }
+    }
+
+    #[test]
+    fn test_stream_reader() {
+        let mut csv = String::from("timestamp,speed\n");
+        for i in 0..1000 {
+            csv.push_str(&format!("2024-01-15T10:00:{:02}.{:03}Z,{}\n", i / 1000, i % 1000, i));
+        }
+
+        let mut points = TelemetryData::stream_reader(std::io::Cursor::new(csv.clone())).unwrap();
+        assert_eq!(points.format(), "csv");
+        assert_eq!(points.next().unwrap().unwrap().speed, Some(0.0));
+        assert_eq!(points.count(), 999);
+
+        let telemetry = TelemetryData::from_reader(std::io::Cursor::new(csv)).unwrap();
+        assert_eq!(telemetry.points.len(), 1000);
+        assert_eq!(telemetry.metadata.format, "csv");
+
+        let nmea = "$GPRMC,100000.00,A,4042.768,N,07400.360,W,0.0,0.0,150124,,,A\n\
+            $GPRMC,100001.00,A,4042.768,N,07400.360,W,0.0,0.0,150124,,,A\n";
+        let points = TelemetryData::stream_reader(std::io::Cursor::new(nmea)).unwrap();
+        assert_eq!(points.format(), "nmea");
+        assert_eq!(points.count(), 2);
+
+        let broken = "timestamp,speed\nyesterday,1\n";
+        let mut points = TelemetryData::stream_reader(std::io::Cursor::new(broken)).unwrap();
+        assert!(points.next().unwrap().is_err());
+    }
+
+    #[test]
+    fn test_registered_parser_wins_ties() {
+        let mut registry = ParserRegistry::new();
+        registry.register(Custom);
+        let parser = registry.detect(b"timestamp,speed\n", None).unwrap();
+        assert_eq!(parser.name(), "custom");
+        assert_eq!(parser.parse(b"").unwrap().metadata.format, "custom");
+        assert!(registry.names().any(|name| name == "custom"));
+        assert!(ParserRegistry::empty().detect(b"timestamp,speed\n", None).is_none());
+    }
+}
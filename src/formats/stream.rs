@@ -0,0 +1,266 @@
+//! Buffered, transparently decompressed readers for large logs
+//!
+//! Gzip streams (`.gz`) and zip archives (the first file entry) are
+//! decompressed on the fly, so a multi-gigabyte log is never held in memory
+//! as a whole. Binary decoders read through a [`Lookahead`] for the same
+//! reason.
+
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Cursor, Read},
+    path::Path,
+};
+use flate2::read::{DeflateDecoder, MultiGzDecoder};
+use crate::error::OverlogError;
+
+/// Read buffer size, large enough for high-rate logs
+const BUFFER_SIZE: usize = 256 * 1024;
+
+/// Bytes read at a time by [`Lookahead`]
+const LOOKAHEAD_CHUNK: usize = 64 * 1024;
+
+const GZIP_MAGIC: &[u8] = &[0x1F, 0x8B];
+const ZIP_LOCAL_HEADER: &[u8] = b"PK\x03\x04";
+
+/// Open a file for reading, decompressing gzip and zip files
+///
+/// `.kmz` files are zip archives of their own and are left compressed.
+pub fn open(path: impl AsRef<Path>) -> Result<Box<dyn BufRead>, OverlogError> {
+    let path = path.as_ref();
+    let file = File::open(path)?;
+    if extension(path).as_deref() == Some("kmz") {
+        return Ok(buffered(file));
+    }
+    decompress(file)
+}
+
+/// Whether [`open`] decompresses a file
+pub fn is_compressed(path: impl AsRef<Path>) -> Result<bool, OverlogError> {
+    let path = path.as_ref();
+    if extension(path).as_deref() == Some("kmz") {
+        return Ok(false);
+    }
+    let mut magic = Vec::new();
+    File::open(path)?.take(ZIP_LOCAL_HEADER.len() as u64).read_to_end(&mut magic)?;
+    Ok(compressed(&magic))
+}
+
+/// Wrap an uncompressed reader in a buffer
+pub fn buffered(reader: impl Read + 'static) -> Box<dyn BufRead> {
+    Box::new(BufReader::with_capacity(BUFFER_SIZE, reader))
+}
+
+fn compressed(magic: &[u8]) -> bool {
+    magic.starts_with(GZIP_MAGIC) || magic.starts_with(ZIP_LOCAL_HEADER)
+}
+
+/// Wrap a reader, decompressing it when it starts with a gzip or zip signature
+pub fn decompress(reader: impl Read + 'static) -> Result<Box<dyn BufRead>, OverlogError> {
+    let (magic, reader) = peek(reader, ZIP_LOCAL_HEADER.len())?;
+    if magic.starts_with(GZIP_MAGIC) {
+        Ok(Box::new(BufReader::with_capacity(BUFFER_SIZE, MultiGzDecoder::new(reader))))
+    } else if magic.starts_with(ZIP_LOCAL_HEADER) {
+        first_zip_entry(reader)
+    } else {
+        Ok(reader)
+    }
+}
+
+/// Read up to `len` leading bytes and return them with a reader that still
+/// yields the whole stream
+pub fn peek(reader: impl Read + 'static, len: usize) -> io::Result<(Vec<u8>, Box<dyn BufRead>)> {
+    let mut reader = reader;
+    let mut head = Vec::with_capacity(len);
+    reader.by_ref().take(len as u64).read_to_end(&mut head)?;
+    let rest = Cursor::new(head.clone()).chain(reader);
+    Ok((head, Box::new(BufReader::with_capacity(BUFFER_SIZE, rest))))
+}
+
+/// A window onto a stream for binary decoders that look at a message before
+/// consuming it
+///
+/// Only the bytes not consumed yet are held, so logs of any length can be
+/// decoded message by message.
+pub(crate) struct Lookahead<R> {
+    reader: R,
+    buffer: Vec<u8>,
+    start: usize,
+}
+
+impl<R: Read> Lookahead<R> {
+    pub fn new(reader: R) -> Self {
+        Self { reader, buffer: Vec::new(), start: 0 }
+    }
+
+    /// The next `len` bytes, `None` when the stream ends first
+    pub fn peek(&mut self, len: usize) -> io::Result<Option<&[u8]>> {
+        while self.buffer.len() - self.start < len {
+            self.buffer.drain(..self.start);
+            self.start = 0;
+            let filled = self.buffer.len();
+            self.buffer.resize(filled + (len - filled).max(LOOKAHEAD_CHUNK), 0);
+            let read = loop {
+                match self.reader.read(&mut self.buffer[filled..]) {
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    read => break read,
+                }
+            };
+            self.buffer.truncate(filled + *read.as_ref().unwrap_or(&0));
+            if read? == 0 {
+                return Ok(None);
+            }
+        }
+        Ok(Some(&self.buffer[self.start..self.start + len]))
+    }
+
+    /// Move past `len` bytes that have been peeked at
+    pub fn consume(&mut self, len: usize) {
+        self.start = (self.start + len).min(self.buffer.len());
+    }
+}
+
+/// Lower case extension of a path, looking through `.gz` and `.zip`
+/// (`log.csv.gz` gives `csv`)
+pub fn extension(path: &Path) -> Option<String> {
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+    match extension.as_str() {
+        "gz" | "gzip" | "zip" => path.file_stem().and_then(|stem| extension_of(stem.as_ref())),
+        _ => Some(extension),
+    }
+}
+
+fn extension_of(path: &Path) -> Option<String> {
+    path.extension()?.to_str().map(str::to_ascii_lowercase)
+}
+
+/// Stream the first file of a zip archive from its local file header
+///
+/// Reading local headers rather than the central directory at the end of
+/// the archive works without seeking, so pipes are fine too.
+fn first_zip_entry(mut reader: Box<dyn BufRead>) -> Result<Box<dyn BufRead>, OverlogError> {
+    let invalid = |msg: &str| OverlogError::Telemetry(format!("Invalid zip archive: {}", msg));
+
+    loop {
+        let mut header = [0u8; 30];
+        reader.read_exact(&mut header).map_err(|_| invalid("no file entries"))?;
+        if &header[..4] != ZIP_LOCAL_HEADER {
+            return Err(invalid("no file entries"));
+        }
+        let u16_at = |at: usize| u16::from_le_bytes([header[at], header[at + 1]]);
+        let u32_at = |at: usize| u32::from_le_bytes(header[at..at + 4].try_into().unwrap());
+        let flags = u16_at(6);
+        let method = u16_at(8);
+        let mut compressed_size = u64::from(u32_at(18));
+
+        let mut name = vec![0u8; usize::from(u16_at(26))];
+        reader.read_exact(&mut name)?;
+        let mut extra = vec![0u8; usize::from(u16_at(28))];
+        reader.read_exact(&mut extra)?;
+        if compressed_size == u64::from(u32::MAX) {
+            compressed_size = zip64_compressed_size(&extra).ok_or_else(|| invalid("missing zip64 sizes"))?;
+        }
+
+        let name = String::from_utf8_lossy(&name);
+        let has_descriptor = flags & 0x08 != 0;
+        if name.ends_with('/') || name.starts_with("__MACOSX/") {
+            if has_descriptor && method != 0 {
+                return Err(invalid(&format!("can't skip entry '{}'", name)));
+            }
+            io::copy(&mut reader.by_ref().take(compressed_size), &mut io::sink())?;
+            continue;
+        }
+
+        tracing::debug!("Reading '{}' from zip archive", name);
+        return match method {
+            0 if !has_descriptor => Ok(Box::new(reader.take(compressed_size))),
+            8 => Ok(Box::new(BufReader::with_capacity(BUFFER_SIZE, DeflateDecoder::new(reader)))),
+            _ => Err(invalid(&format!("unsupported compression for '{}'", name))),
+        };
+    }
+}
+
+/// Compressed size from a zip64 extended information extra field
+fn zip64_compressed_size(extra: &[u8]) -> Option<u64> {
+    let mut rest = extra;
+    while rest.len() >= 4 {
+        let id = u16::from_le_bytes([rest[0], rest[1]]);
+        let size = usize::from(u16::from_le_bytes([rest[2], rest[3]]));
+        let data = rest.get(4..4 + size)?;
+        if id == 0x0001 {
+            // Uncompressed size, then compressed size
+            return Some(u64::from_le_bytes(data.get(8..16)?.try_into().ok()?));
+        }
+        rest = &rest[4 + size..];
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use flate2::{write::GzEncoder, Compression};
+
+    const CSV: &str = "timestamp,speed\n2024-01-15T10:00:00Z,5\n";
+
+    fn read_all(reader: Box<dyn BufRead>) -> String {
+        let mut text = String::new();
+        let mut reader = reader;
+        reader.read_to_string(&mut text).unwrap();
+        text
+    }
+
+    #[test]
+    fn test_gzip_and_plain() {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(CSV.as_bytes()).unwrap();
+        let gzip = encoder.finish().unwrap();
+
+        assert_eq!(read_all(decompress(Cursor::new(gzip)).unwrap()), CSV);
+        assert_eq!(read_all(decompress(Cursor::new(CSV)).unwrap()), CSV);
+        assert_eq!(read_all(decompress(Cursor::new("")).unwrap()), "");
+    }
+
+    #[test]
+    fn test_zip_first_file() {
+        for method in [zip::CompressionMethod::Stored, zip::CompressionMethod::Deflated] {
+            let mut buffer = Cursor::new(Vec::new());
+            {
+                let mut archive = zip::ZipWriter::new(&mut buffer);
+                let options = zip::write::SimpleFileOptions::default().compression_method(method);
+                archive.add_directory("logs/", options).unwrap();
+                archive.start_file("logs/run.csv", options).unwrap();
+                archive.write_all(CSV.as_bytes()).unwrap();
+                archive.finish().unwrap();
+            }
+            assert_eq!(read_all(decompress(Cursor::new(buffer.into_inner())).unwrap()), CSV);
+        }
+    }
+
+    #[test]
+    fn test_lookahead() {
+        // A reader that hands out a few bytes at a time
+        struct Trickle(Cursor<Vec<u8>>);
+        impl Read for Trickle {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                let len = buf.len().min(3);
+                self.0.read(&mut buf[..len])
+            }
+        }
+
+        let mut input = Lookahead::new(Trickle(Cursor::new((0..10).collect())));
+        assert_eq!(input.peek(4).unwrap(), Some(&[0, 1, 2, 3][..]));
+        input.consume(2);
+        assert_eq!(input.peek(5).unwrap(), Some(&[2, 3, 4, 5, 6][..]));
+        input.consume(5);
+        assert_eq!(input.peek(4).unwrap(), None);
+        assert_eq!(input.peek(3).unwrap(), Some(&[7, 8, 9][..]));
+    }
+
+    #[test]
+    fn test_extension() {
+        assert_eq!(extension(Path::new("run.CSV.gz")).as_deref(), Some("csv"));
+        assert_eq!(extension(Path::new("run.gpx")).as_deref(), Some("gpx"));
+        assert_eq!(extension(Path::new("archive.zip")), None);
+    }
+}
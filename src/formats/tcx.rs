@@ -0,0 +1,169 @@
+use roxmltree::{Document, Node};
+use crate::{
+    error::OverlogError,
+    telemetry::{channel, Lap, TelemetryData, TelemetryPoint},
+};
+use super::{
+    parse_rfc3339,
+    xml::{child, child_f64, child_text, children, descendants},
+};
+
+impl TelemetryData {
+    /// Parse a Garmin Training Center XML (TCX) activity file
+    ///
+    /// Every `<Lap>` of every `<Activity>` is kept as a [`Lap`], and its
+    /// trackpoints are appended to `points` in document order.
+    pub fn from_tcx(tcx_data: &str) -> Result<Self, OverlogError> {
+        let doc = Document::parse(tcx_data)?;
+        let mut telemetry = TelemetryData::new();
+        telemetry.metadata.format = "tcx".to_string();
+
+        for activity in descendants(doc.root_element(), "Activity") {
+            for lap in children(activity, "Lap") {
+                let start_time = match lap.attribute("StartTime") {
+                    Some(value) => parse_rfc3339(value)?,
+                    None => return Err(OverlogError::Telemetry("TCX lap without StartTime".to_string())),
+                };
+                let first_point = telemetry.points.len();
+
+                for trackpoint in descendants(lap, "Trackpoint") {
+                    telemetry.points.push(parse_trackpoint(trackpoint)?);
+                }
+
+                let total_time = child_f64(lap, "TotalTimeSeconds");
+                let end_time = match total_time {
+                    Some(seconds) => Some(crate::utils::add_seconds(start_time, seconds)),
+                    None => telemetry.points[first_point..].last().map(|p| p.timestamp),
+                };
+
+                telemetry.laps.push(Lap {
+                    start_time,
+                    end_time,
+                    total_time,
+                    distance: child_f64(lap, "DistanceMeters"),
+                });
+            }
+        }
+
+        telemetry.calculate_metadata();
+        Ok(telemetry)
+    }
+}
+
+fn parse_trackpoint(node: Node) -> Result<TelemetryPoint, OverlogError> {
+    let timestamp = match child_text(node, "Time") {
+        Some(value) => parse_rfc3339(value)?,
+        None => return Err(OverlogError::Telemetry("TCX trackpoint without Time".to_string())),
+    };
+
+    let mut point = TelemetryPoint {
+        timestamp,
+        altitude: child_f64(node, "AltitudeMeters"),
+        ..Default::default()
+    };
+
+    if let Some(position) = child(node, "Position") {
+        point.latitude = child_f64(position, "LatitudeDegrees");
+        point.longitude = child_f64(position, "LongitudeDegrees");
+    }
+
+    if let Some(distance) = child_f64(node, "DistanceMeters") {
+        point.set_channel(channel::DISTANCE, distance);
+    }
+
+    if let Some(heart_rate) = child(node, "HeartRateBpm").and_then(|hr| child_f64(hr, "Value")) {
+        point.set_channel(channel::HEART_RATE, heart_rate);
+    }
+
+    if let Some(cadence) = child_f64(node, "Cadence") {
+        point.set_channel(channel::CADENCE, cadence);
+    }
+
+    // Garmin ActivityExtension (usually bound to the ns3 prefix)
+    if let Some(tpx) = child(node, "Extensions").and_then(|ext| child(ext, "TPX")) {
+        point.speed = child_f64(tpx, "Speed");
+
+        if let Some(watts) = child_f64(tpx, "Watts") {
+            point.set_channel(channel::POWER, watts);
+        }
+
+        // Running cadence is stored here instead of in <Cadence>
+        if let Some(cadence) = child_f64(tpx, "RunCadence") {
+            point.set_channel(channel::CADENCE, cadence);
+        }
+    }
+
+    Ok(point)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<TrainingCenterDatabase xmlns="http://www.garmin.com/xmlschemas/TrainingCenterDatabase/v2"
+    xmlns:ns3="http://www.garmin.com/xmlschemas/ActivityExtension/v2">
+  <Activities>
+    <Activity Sport="Biking">
+      <Id>2024-01-15T10:00:00Z</Id>
+      <Lap StartTime="2024-01-15T10:00:00Z">
+        <TotalTimeSeconds>1.0</TotalTimeSeconds>
+        <DistanceMeters>8.5</DistanceMeters>
+        <Track>
+          <Trackpoint>
+            <Time>2024-01-15T10:00:00Z</Time>
+            <Position>
+              <LatitudeDegrees>40.7128</LatitudeDegrees>
+              <LongitudeDegrees>-74.0060</LongitudeDegrees>
+            </Position>
+            <AltitudeMeters>10.0</AltitudeMeters>
+            <DistanceMeters>0.0</DistanceMeters>
+            <HeartRateBpm><Value>120</Value></HeartRateBpm>
+            <Cadence>85</Cadence>
+            <Extensions>
+              <ns3:TPX>
+                <ns3:Speed>8.5</ns3:Speed>
+                <ns3:Watts>210</ns3:Watts>
+              </ns3:TPX>
+            </Extensions>
+          </Trackpoint>
+        </Track>
+      </Lap>
+      <Lap StartTime="2024-01-15T10:00:01Z">
+        <Track>
+          <Trackpoint>
+            <Time>2024-01-15T10:00:01Z</Time>
+            <HeartRateBpm><Value>125</Value></HeartRateBpm>
+          </Trackpoint>
+        </Track>
+      </Lap>
+    </Activity>
+  </Activities>
+</TrainingCenterDatabase>"#;
+
+    #[test]
+    fn test_tcx_trackpoints() {
+        let telemetry = TelemetryData::from_tcx(SAMPLE).unwrap();
+
+        assert_eq!(telemetry.points.len(), 2);
+        let point = &telemetry.points[0];
+        assert_eq!(point.latitude, Some(40.7128));
+        assert_eq!(point.longitude, Some(-74.0060));
+        assert_eq!(point.altitude, Some(10.0));
+        assert_eq!(point.speed, Some(8.5));
+        assert_eq!(point.channel(channel::HEART_RATE), Some(120.0));
+        assert_eq!(point.channel(channel::CADENCE), Some(85.0));
+        assert_eq!(point.channel(channel::POWER), Some(210.0));
+        assert_eq!(telemetry.points[1].latitude, None);
+    }
+
+    #[test]
+    fn test_tcx_laps() {
+        let telemetry = TelemetryData::from_tcx(SAMPLE).unwrap();
+
+        assert_eq!(telemetry.laps.len(), 2);
+        assert_eq!(telemetry.laps[0].total_time, Some(1.0));
+        assert_eq!(telemetry.laps[0].distance, Some(8.5));
+        assert_eq!(telemetry.laps[1].end_time, Some(telemetry.points[1].timestamp));
+    }
+}
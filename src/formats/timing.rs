@@ -0,0 +1,321 @@
+//! Policies for points without absolute timestamps
+//!
+//! Some logs leave out point times (GPX `<trkpt>` without `<time>`), others
+//! only count time from the start of the recording (DJI SRT cues, elapsed
+//! time columns, sensor logs without GPS). Rather than making times up
+//! silently, missing times are rejected unless a [`TimestampPolicy`] says how
+//! to synthesize them, and relative logs are placed at its start time. What
+//! was done is recorded in [`TelemetryMetadata::times`].
+//!
+//! [`TelemetryMetadata::times`]: crate::telemetry::TelemetryMetadata::times
+
+use std::{fmt, str::FromStr};
+use chrono::{DateTime, Duration, FixedOffset, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use crate::{
+    error::OverlogError,
+    geo,
+    telemetry::{SynthesizedTimes, TelemetryData, TelemetryPoint, TimeReport},
+    utils,
+};
+
+/// How to time points that have no time of their own
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum MissingTimes {
+    /// Fail to read the log
+    #[default]
+    Reject,
+    /// Space points at a fixed sample rate in Hz
+    SampleRate(f64),
+    /// Space points by the distance between them, at a constant speed in m/s
+    ConstantSpeed(f64),
+}
+
+impl FromStr for MissingTimes {
+    type Err = OverlogError;
+
+    /// `reject`, `rate:HZ` or `speed:M/S`
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || OverlogError::Config(format!(
+            "Invalid missing times policy '{}', expected reject, rate:HZ or speed:M/S",
+            value
+        ));
+        let (kind, argument) = value.split_once(':').unwrap_or((value, ""));
+        let number = || argument.trim().parse::<f64>().ok().filter(|n| n.is_finite() && *n > 0.0).ok_or_else(invalid);
+        match kind.trim().to_ascii_lowercase().as_str() {
+            "reject" if argument.is_empty() => Ok(MissingTimes::Reject),
+            "rate" => Ok(MissingTimes::SampleRate(number()?)),
+            "speed" => Ok(MissingTimes::ConstantSpeed(number()?)),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl fmt::Display for MissingTimes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MissingTimes::Reject => write!(f, "reject"),
+            MissingTimes::SampleRate(hz) => write!(f, "rate:{}", hz),
+            MissingTimes::ConstantSpeed(speed) => write!(f, "speed:{}", speed),
+        }
+    }
+}
+
+/// How to time logs without absolute timestamps
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TimestampPolicy {
+    pub missing: MissingTimes,
+    /// Start of a log with relative times, and of a log whose times are all
+    /// synthesized; the Unix epoch when not given. Ignored for logs with
+    /// absolute times.
+    pub start: Option<DateTime<Utc>>,
+    /// IANA name (`Europe/Berlin`) or offset (`+02:00`) of wall-clock times
+    /// logged without a zone, such as DJI SRT cues; without it such logs stay
+    /// relative
+    pub timezone: Option<String>,
+}
+
+/// Timezone of local wall-clock times
+pub(crate) enum Zone {
+    Utc,
+    Fixed(FixedOffset),
+    Named(Tz),
+}
+
+impl Zone {
+    /// Zone from an IANA name or offset, UTC when not given
+    pub fn new(name: Option<&str>) -> Result<Self, OverlogError> {
+        Ok(match name {
+            None | Some("UTC") | Some("Z") => Zone::Utc,
+            Some(name) => match name.parse::<FixedOffset>() {
+                Ok(offset) => Zone::Fixed(offset),
+                Err(_) => Zone::Named(
+                    name.parse::<Tz>()
+                        .map_err(|_| OverlogError::Config(format!("Unknown timezone '{}'", name)))?,
+                ),
+            },
+        })
+    }
+
+    pub fn to_utc(&self, local: NaiveDateTime) -> Result<DateTime<Utc>, OverlogError> {
+        let utc = match self {
+            Zone::Utc => Some(local.and_utc()),
+            Zone::Fixed(offset) => offset.from_local_datetime(&local).single().map(|t| t.with_timezone(&Utc)),
+            // Repeated wall-clock times at a DST change resolve to the first occurrence
+            Zone::Named(tz) => tz.from_local_datetime(&local).earliest().map(|t| t.with_timezone(&Utc)),
+        };
+        utc.ok_or_else(|| OverlogError::Telemetry(format!("Local time '{}' does not exist in the timezone", local)))
+    }
+}
+
+impl TelemetryData {
+    /// Place a log with relative times at the policy's start time
+    ///
+    /// Missing times are filled in by the parsers themselves, see
+    /// [`TelemetryData::from_gpx_with`].
+    pub fn apply_timestamp_policy(&mut self, policy: &TimestampPolicy) {
+        let Some(start) = policy.start else { return };
+        if !self.metadata.times.relative || self.metadata.times.start.is_some() {
+            return;
+        }
+        self.shift_times(start - DateTime::<Utc>::UNIX_EPOCH);
+        self.metadata.times.start = Some(start);
+    }
+
+    /// Move every time in the log by `offset`
+    pub fn shift_times(&mut self, offset: Duration) {
+        self.map_times(|t| t + offset);
+    }
+
+    /// Correct the clock of a log: times after the first point are scaled
+    /// for a clock that runs `drift_ppm` parts per million fast, then every
+    /// time is moved by `offset` seconds
+    ///
+    /// Lap durations reported by the device are scaled with them.
+    pub fn correct_clock(&mut self, offset: f64, drift_ppm: f64) {
+        if drift_ppm == 0.0 {
+            return self.shift_times(Duration::nanoseconds((offset * 1e9).round() as i64));
+        }
+        let Some(origin) = self.points.first().map(|p| p.timestamp).or(self.metadata.start_time) else {
+            return;
+        };
+        let rate = 1.0 / (1.0 + drift_ppm * 1e-6);
+        self.map_times(|t| utils::add_seconds(origin, utils::seconds_between(origin, t) * rate + offset));
+        for lap in &mut self.laps {
+            lap.total_time = lap.total_time.map(|seconds| seconds * rate);
+        }
+    }
+
+    fn map_times(&mut self, map: impl Fn(DateTime<Utc>) -> DateTime<Utc>) {
+        for point in &mut self.points {
+            point.timestamp = map(point.timestamp);
+        }
+        for lap in &mut self.laps {
+            lap.start_time = map(lap.start_time);
+            lap.end_time = lap.end_time.map(&map);
+        }
+        for event in &mut self.events {
+            event.timestamp = map(event.timestamp);
+        }
+        for segment in &mut self.segments {
+            segment.start_time = map(segment.start_time);
+            segment.end_time = map(segment.end_time);
+        }
+        let route_points = self.routes.iter_mut().flat_map(|r| r.points.iter_mut());
+        for waypoint in self.waypoints.iter_mut().chain(route_points) {
+            waypoint.timestamp = waypoint.timestamp.map(&map);
+        }
+        for run in &mut self.metadata.times.synthesized {
+            run.first = map(run.first);
+            run.last = map(run.last);
+        }
+        for outlier in &mut self.metadata.outliers {
+            outlier.timestamp = map(outlier.timestamp);
+        }
+        self.metadata.start_time = self.metadata.start_time.map(&map);
+        self.metadata.end_time = self.metadata.end_time.map(&map);
+    }
+}
+
+/// Synthesize the times of the points not flagged in `timed`
+///
+/// Points after a timed point follow it, points before the first timed
+/// point lead up to it; when no point has a time the first one is placed at
+/// the policy's start.
+pub(crate) fn fill_missing(
+    points: &mut [TelemetryPoint],
+    timed: &[bool],
+    policy: &TimestampPolicy,
+) -> Result<TimeReport, OverlogError> {
+    let mut report = TimeReport::default();
+    let missing = timed.iter().filter(|t| !**t).count();
+    if missing == 0 {
+        return Ok(report);
+    }
+
+    if policy.missing == MissingTimes::Reject {
+        return Err(OverlogError::Telemetry(format!(
+            "{} of {} points have no time; give a sample rate (rate:HZ) or a constant speed (speed:M/S) to synthesize them",
+            missing,
+            points.len()
+        )));
+    }
+    let step = |a: &TelemetryPoint, b: &TelemetryPoint| match (policy.missing, a.latitude, a.longitude, b.latitude, b.longitude) {
+        (MissingTimes::ConstantSpeed(speed), Some(lat1), Some(lon1), Some(lat2), Some(lon2)) => {
+            geo::calculate_distance(lat1, lon1, lat2, lon2) / speed
+        }
+        (MissingTimes::SampleRate(hz), ..) => 1.0 / hz,
+        _ => 0.0,
+    };
+
+    let anchor = match timed.iter().position(|t| *t) {
+        Some(anchor) => anchor,
+        None => {
+            points[0].timestamp = policy.start.unwrap_or(DateTime::<Utc>::UNIX_EPOCH);
+            report.relative = true;
+            report.start = policy.start;
+            0
+        }
+    };
+    for i in (0..anchor).rev() {
+        points[i].timestamp = utils::add_seconds(points[i + 1].timestamp, -step(&points[i], &points[i + 1]));
+    }
+    for i in anchor + 1..points.len() {
+        if !timed[i] {
+            points[i].timestamp = utils::add_seconds(points[i - 1].timestamp, step(&points[i - 1], &points[i]));
+        }
+    }
+
+    let mut i = 0;
+    while i < points.len() {
+        if timed[i] {
+            i += 1;
+            continue;
+        }
+        let first = i;
+        while i < points.len() && !timed[i] {
+            i += 1;
+        }
+        report.synthesized.push(SynthesizedTimes {
+            first: points[first].timestamp,
+            last: points[i - 1].timestamp,
+            count: i - first,
+        });
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    fn points(count: usize) -> Vec<TelemetryPoint> {
+        (0..count)
+            .map(|i| TelemetryPoint {
+                latitude: Some(0.0),
+                longitude: Some(i as f64 * 0.001),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_missing_times_policy_parsing() {
+        assert_eq!("reject".parse::<MissingTimes>().unwrap(), MissingTimes::Reject);
+        assert_eq!("rate:10".parse::<MissingTimes>().unwrap(), MissingTimes::SampleRate(10.0));
+        assert_eq!("speed:2.5".parse::<MissingTimes>().unwrap(), MissingTimes::ConstantSpeed(2.5));
+        assert!("rate:0".parse::<MissingTimes>().is_err());
+        assert!("rate".parse::<MissingTimes>().is_err());
+        assert!("guess".parse::<MissingTimes>().is_err());
+    }
+
+    #[test]
+    fn test_fill_missing_times() {
+        let start = testing::at(0.0);
+        let mut with_gap = points(4);
+        with_gap[1].timestamp = start;
+        let timed = [false, true, false, false];
+
+        assert!(fill_missing(&mut with_gap.clone(), &timed, &TimestampPolicy::default()).is_err());
+
+        let policy = TimestampPolicy { missing: MissingTimes::SampleRate(10.0), ..Default::default() };
+        let report = fill_missing(&mut with_gap, &timed, &policy).unwrap();
+        assert_eq!((start - with_gap[0].timestamp).num_milliseconds(), 100);
+        assert_eq!((with_gap[3].timestamp - start).num_milliseconds(), 200);
+        assert!(!report.relative);
+        assert_eq!(report.synthesized.len(), 2);
+        assert_eq!(report.synthesized[1].count, 2);
+        assert_eq!(report.synthesized[1].last, with_gap[3].timestamp);
+
+        // 0.001° of longitude at the equator is about 111 m
+        let mut untimed = points(3);
+        let policy = TimestampPolicy { missing: MissingTimes::ConstantSpeed(11.1), start: Some(start), ..Default::default() };
+        let report = fill_missing(&mut untimed, &[false; 3], &policy).unwrap();
+        assert_eq!(untimed[0].timestamp, start);
+        assert!((utils::seconds_between(start, untimed[2].timestamp) - 20.0).abs() < 0.1);
+        assert_eq!(report.start, Some(start));
+        assert_eq!(report.synthesized[0].count, 3);
+    }
+
+    #[test]
+    fn test_relative_log_start() {
+        let mut telemetry = TelemetryData::new();
+        telemetry.points = points(2);
+        telemetry.points[1].timestamp += Duration::seconds(1);
+        telemetry.calculate_metadata();
+        telemetry.metadata.times.relative = true;
+
+        let start = testing::at(0.0);
+        telemetry.apply_timestamp_policy(&TimestampPolicy { start: Some(start), ..Default::default() });
+        assert_eq!(telemetry.points[0].timestamp, start);
+        assert_eq!(telemetry.metadata.end_time, Some(start + Duration::seconds(1)));
+        assert_eq!(telemetry.metadata.times.start, Some(start));
+
+        // Absolute logs stay where they are
+        let mut absolute = TelemetryData::new();
+        absolute.points = points(1);
+        absolute.apply_timestamp_policy(&TimestampPolicy { start: Some(start), ..Default::default() });
+        assert_eq!(absolute.points[0].timestamp, DateTime::<Utc>::UNIX_EPOCH);
+    }
+}
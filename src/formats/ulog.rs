@@ -0,0 +1,362 @@
+//! PX4 ULog (`.ulg`) flight log decoder
+//!
+//! After a 16 byte header, a ULog file is a sequence of messages with a u16
+//! size and a one byte type. `F` messages declare the layout of each topic,
+//! `A` messages subscribe a topic instance to a message id and `D` messages
+//! carry the data for that id.
+
+use std::{collections::HashMap, io::Read};
+use chrono::{TimeZone, Utc};
+use crate::{
+    error::OverlogError,
+    telemetry::{channel, TelemetryData, TelemetryPoint},
+};
+use super::{stream::Lookahead, Timeline, TimelinePoints, TimelineSource};
+
+const MAGIC: &[u8] = b"ULog\x01\x12\x35";
+const HEADER_SIZE: usize = 16;
+const STANDARD_GRAVITY: f64 = 9.80665;
+const EMPTY: &str = "No ULog data messages found";
+
+/// Check for the ULog file magic
+pub(crate) fn is_ulog(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+impl TelemetryData {
+    /// Parse a PX4 ULog flight log
+    ///
+    /// GPS, attitude, barometer, IMU, RC input, actuator output, battery and
+    /// navigation state topics are each kept at their own rate on the
+    /// boot-time timeline. The first GPS sample with a 3D fix and a UTC time
+    /// ties that timeline to UTC.
+    pub fn from_ulog(data: &[u8]) -> Result<Self, OverlogError> {
+        Self::from_ulog_reader(data)
+    }
+
+    /// Parse a ULog flight log from a reader, message by message
+    pub fn from_ulog_reader(reader: impl Read) -> Result<Self, OverlogError> {
+        let timeline = ULogReader::new(reader)?.read_timeline()?;
+        if timeline.is_empty() {
+            return Err(OverlogError::Telemetry(EMPTY.to_string()));
+        }
+        Ok(timeline.into_telemetry("ulog", None))
+    }
+}
+
+/// Points of a ULog flight log, yielded while it is read
+pub(crate) fn points<R: Read>(reader: R) -> Result<TimelinePoints<ULogReader<R>>, OverlogError> {
+    Ok(TimelinePoints::new(ULogReader::new(reader)?, EMPTY))
+}
+
+/// ULog messages decoded one at a time
+pub(crate) struct ULogReader<R> {
+    input: Lookahead<R>,
+    definitions: HashMap<String, Vec<(String, String)>>,
+    layouts: HashMap<String, Vec<Field>>,
+    subscriptions: HashMap<u16, (String, u8)>,
+}
+
+impl<R: Read> ULogReader<R> {
+    fn new(reader: R) -> Result<Self, OverlogError> {
+        let mut input = Lookahead::new(reader);
+        if !input.peek(HEADER_SIZE)?.is_some_and(is_ulog) {
+            return Err(OverlogError::Telemetry("Not a PX4 ULog file".to_string()));
+        }
+        input.consume(HEADER_SIZE);
+        Ok(Self {
+            input,
+            definitions: HashMap::new(),
+            layouts: HashMap::new(),
+            subscriptions: HashMap::new(),
+        })
+    }
+}
+
+impl<R: Read> TimelineSource for ULogReader<R> {
+    fn read_message(&mut self, timeline: &mut Timeline) -> Result<bool, OverlogError> {
+        let Some(header) = self.input.peek(3)? else { return Ok(false) };
+        let size = u16::from_le_bytes([header[0], header[1]]) as usize;
+        let msg_type = header[2];
+        let Some(message) = self.input.peek(3 + size)? else { return Ok(false) };
+        let body = &message[3..];
+
+        match msg_type {
+            b'F' => {
+                let text = String::from_utf8_lossy(body);
+                if let Some((name, fields)) = text.split_once(':') {
+                    let fields = fields
+                        .split(';')
+                        .filter_map(|f| f.trim().split_once(' '))
+                        .map(|(t, n)| (t.to_string(), n.to_string()))
+                        .collect();
+                    self.definitions.insert(name.to_string(), fields);
+                }
+            }
+            b'A' if body.len() > 3 => {
+                let multi_id = body[0];
+                let msg_id = u16::from_le_bytes([body[1], body[2]]);
+                let name = String::from_utf8_lossy(&body[3..]).into_owned();
+                if !self.layouts.contains_key(&name) {
+                    let mut fields = Vec::new();
+                    layout(&self.definitions, &name, "", &mut 0, &mut fields);
+                    self.layouts.insert(name.clone(), fields);
+                }
+                self.subscriptions.insert(msg_id, (name, multi_id));
+            }
+            b'D' if body.len() > 2 => {
+                let msg_id = u16::from_le_bytes([body[0], body[1]]);
+                // Only the first instance of multi-instance topics is used
+                if let Some((name, 0)) = self.subscriptions.get(&msg_id) {
+                    let sample = Sample { fields: &self.layouts[name], data: &body[2..] };
+                    if let Some(timestamp) = sample.get("timestamp") {
+                        apply_topic(timeline, name, &sample, timestamp as i64);
+                    }
+                }
+            }
+            _ => {}
+        }
+        self.input.consume(3 + size);
+        Ok(true)
+    }
+}
+
+fn apply_topic(timeline: &mut Timeline, name: &str, sample: &Sample, time_us: i64) {
+    match name {
+        "vehicle_gps_position" | "sensor_gps" => {
+            let fix = sample.get("fix_type").unwrap_or(0.0);
+            if fix < 2.0 {
+                return;
+            }
+            if let Some(utc) = sample
+                .get("time_utc_usec")
+                .filter(|&t| fix >= 3.0 && t > 0.0)
+                .and_then(|t| Utc.timestamp_micros(t as i64).single())
+            {
+                timeline.anchor(time_us, utc);
+            }
+
+            let point = timeline.at(time_us);
+            // Newer PX4 releases use double degrees and metres, older ones scaled integers
+            point.latitude = sample.get("latitude_deg").or_else(|| sample.get("lat").map(|v| v / 1e7));
+            point.longitude = sample.get("longitude_deg").or_else(|| sample.get("lon").map(|v| v / 1e7));
+            point.altitude = sample.get("altitude_msl_m").or_else(|| sample.get("alt").map(|v| v / 1000.0));
+            point.speed = sample.get("vel_m_s");
+            point.heading = sample.get("cog_rad").map(|c| crate::utils::normalize_angle(c.to_degrees()));
+            point.set_channel(channel::GPS_FIX, fix);
+            sample.copy(point, &[("satellites_used", channel::SATELLITES), ("hdop", channel::GPS_HDOP)]);
+        }
+        "vehicle_attitude" => {
+            let q: Vec<f64> = (0..4).filter_map(|i| sample.get(&format!("q[{}]", i))).collect();
+            if let [w, x, y, z] = q[..] {
+                let point = timeline.at(time_us);
+                let (roll, pitch, yaw) = quaternion_to_euler(w, x, y, z);
+                point.set_channel(channel::ROLL, roll);
+                point.set_channel(channel::PITCH, pitch);
+                point.set_channel(channel::YAW, yaw);
+            }
+        }
+        "vehicle_air_data" => {
+            let point = timeline.at(time_us);
+            sample.copy(point, &[("baro_alt_meter", channel::BARO_ALTITUDE), ("baro_pressure_pa", channel::PRESSURE)]);
+        }
+        "sensor_combined" => {
+            let point = timeline.at(time_us);
+            point.g_force_x = sample.get("accelerometer_m_s2[0]").map(|a| a / STANDARD_GRAVITY);
+            point.g_force_y = sample.get("accelerometer_m_s2[1]").map(|a| a / STANDARD_GRAVITY);
+            point.g_force_z = sample.get("accelerometer_m_s2[2]").map(|a| a / STANDARD_GRAVITY);
+            sample.copy(point, &[
+                ("gyro_rad[0]", channel::GYRO_X),
+                ("gyro_rad[1]", channel::GYRO_Y),
+                ("gyro_rad[2]", channel::GYRO_Z),
+            ]);
+        }
+        "input_rc" => {
+            let count = sample.get("channel_count").unwrap_or(0.0) as usize;
+            let point = timeline.at(time_us);
+            for i in 0..count {
+                if let Some(value) = sample.get(&format!("values[{}]", i)) {
+                    point.set_channel(&channel::rc_in(i + 1), value);
+                }
+            }
+        }
+        "actuator_outputs" => {
+            let count = sample.get("noutputs").unwrap_or(0.0) as usize;
+            let point = timeline.at(time_us);
+            for i in 0..count {
+                if let Some(value) = sample.get(&format!("output[{}]", i)) {
+                    point.set_channel(&channel::rc_out(i + 1), value);
+                }
+            }
+        }
+        "battery_status" => {
+            let point = timeline.at(time_us);
+            sample.copy(point, &[
+                ("voltage_v", channel::BATTERY_VOLTAGE),
+                ("current_a", channel::BATTERY_CURRENT),
+                ("discharged_mah", channel::BATTERY_CONSUMED),
+            ]);
+        }
+        "vehicle_status" => {
+            if let Some(state) = sample.get("nav_state") {
+                timeline.at(time_us).set_channel(channel::FLIGHT_MODE, state);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Roll, pitch and yaw in degrees from a unit quaternion
+fn quaternion_to_euler(w: f64, x: f64, y: f64, z: f64) -> (f64, f64, f64) {
+    let roll = (2.0 * (w * x + y * z)).atan2(1.0 - 2.0 * (x * x + y * y));
+    let pitch = (2.0 * (w * y - z * x)).clamp(-1.0, 1.0).asin();
+    let yaw = (2.0 * (w * z + x * y)).atan2(1.0 - 2.0 * (y * y + z * z));
+    (roll.to_degrees(), pitch.to_degrees(), yaw.to_degrees())
+}
+
+/// A primitive value at a fixed offset in a topic's data
+struct Field {
+    name: String,
+    kind: String,
+    offset: usize,
+}
+
+/// Flatten a topic definition into primitive fields, expanding arrays as
+/// `name[i]` and nested topics as `parent.child`
+fn layout(
+    definitions: &HashMap<String, Vec<(String, String)>>,
+    topic: &str,
+    prefix: &str,
+    offset: &mut usize,
+    fields: &mut Vec<Field>,
+) {
+    let Some(definition) = definitions.get(topic) else { return };
+
+    for (kind, name) in definition {
+        let (base, count) = match kind.split_once('[') {
+            Some((base, rest)) => (base, rest.trim_end_matches(']').parse().unwrap_or(1)),
+            None => (kind.as_str(), 1),
+        };
+
+        for i in 0..count {
+            let qualified = if count > 1 || kind.contains('[') {
+                format!("{}{}[{}]", prefix, name, i)
+            } else {
+                format!("{}{}", prefix, name)
+            };
+
+            match primitive_size(base) {
+                Some(size) => {
+                    fields.push(Field { name: qualified, kind: base.to_string(), offset: *offset });
+                    *offset += size;
+                }
+                None => layout(definitions, base, &format!("{}.", qualified), offset, fields),
+            }
+        }
+    }
+}
+
+fn primitive_size(kind: &str) -> Option<usize> {
+    match kind {
+        "int8_t" | "uint8_t" | "bool" | "char" => Some(1),
+        "int16_t" | "uint16_t" => Some(2),
+        "int32_t" | "uint32_t" | "float" => Some(4),
+        "int64_t" | "uint64_t" | "double" => Some(8),
+        _ => None,
+    }
+}
+
+struct Sample<'a> {
+    fields: &'a [Field],
+    data: &'a [u8],
+}
+
+impl Sample<'_> {
+    fn get(&self, name: &str) -> Option<f64> {
+        let field = self.fields.iter().find(|f| f.name == name)?;
+        let raw = self.data.get(field.offset..field.offset + primitive_size(&field.kind)?)?;
+        let value = match field.kind.as_str() {
+            "int8_t" => raw[0] as i8 as f64,
+            "uint8_t" | "bool" | "char" => raw[0] as f64,
+            "int16_t" => i16::from_le_bytes(raw.try_into().ok()?) as f64,
+            "uint16_t" => u16::from_le_bytes(raw.try_into().ok()?) as f64,
+            "int32_t" => i32::from_le_bytes(raw.try_into().ok()?) as f64,
+            "uint32_t" => u32::from_le_bytes(raw.try_into().ok()?) as f64,
+            "int64_t" => i64::from_le_bytes(raw.try_into().ok()?) as f64,
+            "uint64_t" => u64::from_le_bytes(raw.try_into().ok()?) as f64,
+            "float" => f32::from_le_bytes(raw.try_into().ok()?) as f64,
+            "double" => f64::from_le_bytes(raw.try_into().ok()?),
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    /// Copy named fields into the named channels of a point
+    fn copy(&self, point: &mut TelemetryPoint, mapping: &[(&str, &str)]) {
+        for (field, name) in mapping {
+            if let Some(value) = self.get(field) {
+                point.set_channel(name, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(msg_type: u8, body: &[u8]) -> Vec<u8> {
+        let mut out = (body.len() as u16).to_le_bytes().to_vec();
+        out.push(msg_type);
+        out.extend_from_slice(body);
+        out
+    }
+
+    #[test]
+    fn test_ulog_topics() {
+        let mut log = MAGIC.to_vec();
+        log.push(1);
+        log.extend(0u64.to_le_bytes());
+
+        log.extend(message(b'F', b"vec3:float x;float y;float z;"));
+        log.extend(message(b'F', b"sensor_gps:uint64_t timestamp;uint64_t time_utc_usec;double latitude_deg;double longitude_deg;uint8_t fix_type;uint8_t[3] _padding0;"));
+        log.extend(message(b'F', b"vehicle_attitude:uint64_t timestamp;float[4] q;vec3 rate;"));
+        log.extend(message(b'A', &[&[0u8, 1, 0][..], b"sensor_gps"].concat()));
+        log.extend(message(b'A', &[&[0u8, 2, 0][..], b"vehicle_attitude"].concat()));
+
+        let mut gps = vec![1, 0];
+        gps.extend(5_000_000u64.to_le_bytes());
+        gps.extend(1_705_312_800_000_000u64.to_le_bytes());
+        gps.extend(40.7128f64.to_le_bytes());
+        gps.extend((-74.006f64).to_le_bytes());
+        gps.push(3);
+        log.extend(message(b'D', &gps));
+
+        // Level attitude rotated 90 degrees in yaw, sampled after the GPS fix
+        let half = std::f32::consts::FRAC_1_SQRT_2;
+        let mut att = vec![2, 0];
+        att.extend(5_004_000u64.to_le_bytes());
+        for q in [half, 0.0, 0.0, half, 0.0, 0.0, 0.0] {
+            att.extend(q.to_le_bytes());
+        }
+        log.extend(message(b'D', &att));
+
+        let telemetry = TelemetryData::from_ulog(&log).unwrap();
+        assert_eq!(telemetry.points.len(), 2);
+
+        let gps_point = &telemetry.points[0];
+        assert_eq!(gps_point.timestamp.to_rfc3339(), "2024-01-15T10:00:00+00:00");
+        assert_eq!(gps_point.latitude, Some(40.7128));
+        assert_eq!(gps_point.longitude, Some(-74.006));
+
+        let att_point = &telemetry.points[1];
+        assert_eq!((att_point.timestamp - gps_point.timestamp).num_milliseconds(), 4);
+        assert!((att_point.channel(channel::YAW).unwrap() - 90.0).abs() < 1e-3);
+        assert!(att_point.channel(channel::ROLL).unwrap().abs() < 1e-3);
+
+        let streamed: Vec<_> = points(std::io::Cursor::new(log)).unwrap().collect::<Result<_, _>>().unwrap();
+        let times: Vec<_> = streamed.iter().map(|p| p.timestamp).collect();
+        assert_eq!(times, telemetry.points.iter().map(|p| p.timestamp).collect::<Vec<_>>());
+        assert!(points(std::io::Cursor::new(b"ULog".to_vec())).is_err());
+    }
+}
@@ -53,6 +53,28 @@ pub fn calculate_destination(lat: f64, lon: f64, bearing: f64, distance: f64) ->
     (lat2_rad.to_degrees(), lon2_rad.to_degrees())
 }
 
+/// Calculate the point a fraction of the way along the great circle between two points
+pub fn calculate_intermediate(lat1: f64, lon1: f64, lat2: f64, lon2: f64, fraction: f64) -> (f64, f64) {
+    let r = 6371000.0; // Earth's radius in meters
+    
+    let delta = calculate_distance(lat1, lon1, lat2, lon2) / r;
+    // Too close together for the sines below, and flat enough to interpolate directly
+    if delta < 1e-9 {
+        return (lat1 + (lat2 - lat1) * fraction, lon1 + (lon2 - lon1) * fraction);
+    }
+    
+    let (lat1_rad, lon1_rad) = (lat1.to_radians(), lon1.to_radians());
+    let (lat2_rad, lon2_rad) = (lat2.to_radians(), lon2.to_radians());
+    let a = ((1.0 - fraction) * delta).sin() / delta.sin();
+    let b = (fraction * delta).sin() / delta.sin();
+    
+    let x = a * lat1_rad.cos() * lon1_rad.cos() + b * lat2_rad.cos() * lon2_rad.cos();
+    let y = a * lat1_rad.cos() * lon1_rad.sin() + b * lat2_rad.cos() * lon2_rad.sin();
+    let z = a * lat1_rad.sin() + b * lat2_rad.sin();
+    
+    (z.atan2((x * x + y * y).sqrt()).to_degrees(), y.atan2(x).to_degrees())
+}
+
 /// Convert speed from m/s to km/h
 pub fn ms_to_kmh(speed_ms: f64) -> f64 {
     speed_ms * 3.6
@@ -151,6 +173,16 @@ mod tests {
         assert!((bearing - 0.0).abs() < 1.0);
     }
     
+    #[test]
+    fn test_intermediate_calculation() {
+        let (lat, lon) = calculate_intermediate(0.0, 0.0, 0.0, 90.0, 0.5);
+        assert!(lat.abs() < 1e-9 && (lon - 45.0).abs() < 1e-9);
+        
+        // The great circle between two points on a parallel bulges towards the pole
+        let (lat, lon) = calculate_intermediate(60.0, -30.0, 60.0, 30.0, 0.5);
+        assert!(lat > 60.5 && lon.abs() < 1e-9);
+    }
+    
     #[test]
     fn test_speed_conversions() {
         let speed_ms = 10.0;
@@ -169,6 +201,6 @@ mod tests {
         
         let magnitude = calculate_g_force_magnitude(gx, gy, gz);
         
-        assert!((magnitude - 3.0.sqrt()).abs() < 0.001);
+        assert!((magnitude - 3.0_f64.sqrt()).abs() < 0.001);
     }
 } 
\ No newline at end of file
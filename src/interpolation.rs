@@ -0,0 +1,531 @@
+//! Values between samples
+//!
+//! Each field and channel is interpolated with its own [`Interpolation`]:
+//! headings the short way round, positions along the great circle, other
+//! numbers linearly unless an [`InterpolationPolicy`] says otherwise. A value
+//! is only interpolated between two samples of its own, never copied from one
+//! side, and an optional maximum gap leaves long holes empty rather than
+//! filling them with stale values.
+
+use std::{collections::{BTreeMap, BTreeSet}, fmt, str::FromStr};
+use chrono::{DateTime, Utc};
+use crate::{
+    error::OverlogError,
+    formats::columns::Field,
+    geo,
+    telemetry::{channel, ChannelValue, TelemetryData, TelemetryPoint},
+    utils,
+};
+
+/// How a value between two samples is computed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Interpolation {
+    Linear,
+    /// Linear in degrees, the short way round, so 359° to 1° passes 0°
+    Circular,
+    /// Along the great circle between two positions; linear for anything else
+    GreatCircle,
+    /// Catmull-Rom spline through the samples around the interval
+    Cubic,
+    /// The closer sample in time
+    Nearest,
+    /// The last sample at or before the time, for discrete values such as a
+    /// gear or a lap number
+    HoldLast,
+}
+
+impl Interpolation {
+    /// Interpolation of a field or channel unless told otherwise
+    ///
+    /// Steering angles can exceed a full turn, so only headings and yaw are
+    /// circular. Discrete values such as lap and sector numbers, gears, flight
+    /// modes and GPS fix states are held.
+    pub fn default_for(name: &str) -> Self {
+        match name {
+            "latitude" | "longitude" => Interpolation::GreatCircle,
+            "heading" | channel::YAW => Interpolation::Circular,
+            channel::LAP
+            | channel::SECTOR
+            | channel::GEAR
+            | channel::FLIGHT_MODE
+            | channel::GPS_FIX
+            | channel::GPS_QUALITY
+            | channel::SATELLITES => Interpolation::HoldLast,
+            _ => Interpolation::Linear,
+        }
+    }
+}
+
+impl FromStr for Interpolation {
+    type Err = OverlogError;
+
+    /// `linear`, `circular`, `great-circle`, `cubic`, `nearest` or `hold`
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "linear" => Ok(Interpolation::Linear),
+            "circular" | "angle" => Ok(Interpolation::Circular),
+            "great-circle" | "geodesic" => Ok(Interpolation::GreatCircle),
+            "cubic" | "catmull-rom" => Ok(Interpolation::Cubic),
+            "nearest" => Ok(Interpolation::Nearest),
+            "hold" | "hold-last" | "step" => Ok(Interpolation::HoldLast),
+            _ => Err(OverlogError::Config(format!(
+                "Invalid interpolation '{}', expected linear, circular, great-circle, cubic, nearest or hold",
+                value
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for Interpolation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Interpolation::Linear => write!(f, "linear"),
+            Interpolation::Circular => write!(f, "circular"),
+            Interpolation::GreatCircle => write!(f, "great-circle"),
+            Interpolation::Cubic => write!(f, "cubic"),
+            Interpolation::Nearest => write!(f, "nearest"),
+            Interpolation::HoldLast => write!(f, "hold"),
+        }
+    }
+}
+
+/// The interpolation of one field or channel, or of all of them without a
+/// name, written `[NAME=]METHOD` (`gear=hold`)
+#[derive(Debug, Clone, PartialEq)]
+pub struct InterpolationRule {
+    pub name: Option<String>,
+    pub method: Interpolation,
+}
+
+impl FromStr for InterpolationRule {
+    type Err = OverlogError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.split_once('=') {
+            Some((name, method)) => Ok(InterpolationRule {
+                name: Some(name.trim().to_string()),
+                method: method.parse()?,
+            }),
+            None => Ok(InterpolationRule { name: None, method: value.parse()? }),
+        }
+    }
+}
+
+/// What to do between two segments
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Gaps {
+    /// Repeat the end of the previous segment
+    #[default]
+    Hold,
+    /// Give no values
+    Skip,
+    /// Interpolate across the gap as if the log were continuous
+    Bridge,
+}
+
+impl FromStr for Gaps {
+    type Err = OverlogError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "hold" => Ok(Gaps::Hold),
+            "skip" => Ok(Gaps::Skip),
+            "bridge" => Ok(Gaps::Bridge),
+            _ => Err(OverlogError::Config(format!("Invalid gap handling '{}', expected hold, skip or bridge", value))),
+        }
+    }
+}
+
+impl fmt::Display for Gaps {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Gaps::Hold => write!(f, "hold"),
+            Gaps::Skip => write!(f, "skip"),
+            Gaps::Bridge => write!(f, "bridge"),
+        }
+    }
+}
+
+/// How to compute values between samples
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct InterpolationPolicy {
+    /// Interpolation of the fields and numeric channels that would otherwise
+    /// be linear
+    pub method: Option<Interpolation>,
+    /// Interpolation of single fields and channels
+    pub methods: BTreeMap<String, Interpolation>,
+    pub gaps: Gaps,
+    /// Longest time in seconds between two samples of a value to interpolate
+    /// between, or to hold a sample for; there is no value in longer holes
+    pub max_gap: Option<f64>,
+}
+
+impl From<Interpolation> for InterpolationPolicy {
+    fn from(method: Interpolation) -> Self {
+        InterpolationPolicy { method: Some(method), ..Default::default() }
+    }
+}
+
+impl InterpolationPolicy {
+    /// Apply `[NAME=]METHOD` rules in order
+    pub fn with_rules(mut self, rules: impl IntoIterator<Item = InterpolationRule>) -> Self {
+        for rule in rules {
+            match rule.name {
+                Some(name) => {
+                    self.methods.insert(name, rule.method);
+                }
+                None => self.method = Some(rule.method),
+            }
+        }
+        self
+    }
+
+    /// Interpolation of a field or channel
+    pub fn method_of(&self, name: &str) -> Interpolation {
+        match (self.methods.get(name), Interpolation::default_for(name)) {
+            (Some(method), _) => *method,
+            (None, Interpolation::Linear) => self.method.unwrap_or(Interpolation::Linear),
+            (None, method) => method,
+        }
+    }
+
+    /// Whether positions are interpolated along the great circle
+    pub(crate) fn great_circle(&self) -> bool {
+        self.method_of("latitude") == Interpolation::GreatCircle && self.method_of("longitude") == Interpolation::GreatCircle
+    }
+
+    /// Whether two samples are close enough in time to interpolate between
+    pub(crate) fn within_gap(&self, seconds: f64) -> bool {
+        !self.max_gap.is_some_and(|gap| seconds > gap)
+    }
+}
+
+/// A sample time in seconds and its value
+pub(crate) type Sample = (f64, f64);
+
+/// Value at `t` between samples `b` and `c`, where `a` and `d` are the
+/// samples before and after them for the tangents of a cubic
+pub(crate) fn between(method: Interpolation, t: f64, a: Option<Sample>, b: Sample, c: Sample, d: Option<Sample>) -> f64 {
+    let ratio = (t - b.0) / (c.0 - b.0);
+    match method {
+        Interpolation::Nearest if ratio < 0.5 => b.1,
+        Interpolation::Nearest => c.1,
+        Interpolation::HoldLast => b.1,
+        Interpolation::Circular => {
+            let step = (c.1 - b.1 + 180.0).rem_euclid(360.0) - 180.0;
+            (b.1 + step * ratio).rem_euclid(360.0)
+        }
+        Interpolation::Cubic => {
+            // Catmull-Rom tangents, one-sided at the ends
+            let slope = |(t1, v1): Sample, (t2, v2): Sample| (v2 - v1) / (t2 - t1);
+            let (m1, m2) = (slope(a.unwrap_or(b), c), slope(b, d.unwrap_or(c)));
+            let span = c.0 - b.0;
+            let (r2, r3) = (ratio * ratio, ratio * ratio * ratio);
+            (2.0 * r3 - 3.0 * r2 + 1.0) * b.1
+                + (r3 - 2.0 * r2 + ratio) * span * m1
+                + (-2.0 * r3 + 3.0 * r2) * c.1
+                + (r3 - r2) * span * m2
+        }
+        Interpolation::Linear | Interpolation::GreatCircle => b.1 + (c.1 - b.1) * ratio,
+    }
+}
+
+impl TelemetryData {
+    /// Point at `timestamp` with every value interpolated as the policy says
+    ///
+    /// `None` before the first and after the last point, between two segments
+    /// with [`Gaps::Skip`], and when a held segment end is older than the
+    /// maximum gap.
+    pub fn interpolate_at_time_with(&self, timestamp: DateTime<Utc>, policy: &InterpolationPolicy) -> Option<TelemetryPoint> {
+        let i = match self.points.binary_search_by(|point| point.timestamp.cmp(&timestamp)) {
+            Ok(i) => return Some(self.points[i].clone()),
+            Err(i) if i == 0 || i >= self.points.len() => return None,
+            Err(i) => i,
+        };
+        let (p1, p2) = (&self.points[i - 1], &self.points[i]);
+
+        if policy.gaps != Gaps::Bridge && self.is_segment_break(p1.timestamp, p2.timestamp) {
+            // Hold the last point through a pause or signal loss
+            let held = policy.gaps == Gaps::Hold && policy.within_gap(utils::seconds_between(p1.timestamp, timestamp));
+            return held.then(|| TelemetryPoint { timestamp, ..p1.clone() });
+        }
+
+        Some(Neighbours::new(self, timestamp, policy).point(&Values::of(&self.points[i - 1..=i])))
+    }
+}
+
+/// The fields and channels with samples in some points
+pub(crate) struct Values<'a> {
+    fields: Vec<Field>,
+    /// Channels with any numeric sample, with their unit
+    numbers: Vec<(&'a str, Option<String>)>,
+    texts: Vec<&'a str>,
+}
+
+impl<'a> Values<'a> {
+    pub(crate) fn of(points: &'a [TelemetryPoint]) -> Self {
+        let fields = Field::POINT_FIELDS.into_iter().filter(|field| points.iter().any(|p| field.get(p).is_some())).collect();
+        let mut numbers: BTreeMap<&str, Option<String>> = BTreeMap::new();
+        let mut texts = BTreeSet::new();
+        for point in points {
+            for (name, channel) in &point.channels {
+                match channel.value {
+                    ChannelValue::Number(_) => {
+                        let unit = numbers.entry(name).or_default();
+                        if unit.is_none() {
+                            unit.clone_from(&channel.unit);
+                        }
+                    }
+                    ChannelValue::Text(_) => {
+                        texts.insert(name.as_str());
+                    }
+                }
+            }
+        }
+        texts.retain(|name| !numbers.contains_key(name));
+        Values { fields, numbers: numbers.into_iter().collect(), texts: texts.into_iter().collect() }
+    }
+}
+
+/// Finds the samples of a value around a time
+pub(crate) struct Neighbours<'a> {
+    telemetry: &'a TelemetryData,
+    /// The first point at or after the time
+    index: usize,
+    timestamp: DateTime<Utc>,
+    policy: &'a InterpolationPolicy,
+}
+
+impl<'a> Neighbours<'a> {
+    /// The samples around `timestamp`, which lies within the log
+    pub(crate) fn new(telemetry: &'a TelemetryData, timestamp: DateTime<Utc>, policy: &'a InterpolationPolicy) -> Self {
+        let index = telemetry.points.partition_point(|point| point.timestamp < timestamp);
+        Neighbours { telemetry, index, timestamp, policy }
+    }
+
+    /// Point at the time with every one of `values` that has samples around it
+    pub(crate) fn point(&self, values: &Values) -> TelemetryPoint {
+        let mut point = TelemetryPoint { timestamp: self.timestamp, ..Default::default() };
+        let great_circle = self.policy.great_circle();
+        for field in &values.fields {
+            if great_circle && matches!(field, Field::Latitude | Field::Longitude) {
+                continue;
+            }
+            if let Some((value, derived)) = self.value(field.name(), |p| field.get(p)) {
+                field.set(&mut point, value);
+                if derived {
+                    point.derived.insert(field.name().to_string());
+                }
+            }
+        }
+        if great_circle {
+            if let Some((latitude, longitude, derived)) = self.position() {
+                point.latitude = Some(latitude);
+                point.longitude = Some(longitude);
+                if derived {
+                    point.derived.extend(["latitude".to_string(), "longitude".to_string()]);
+                }
+            }
+        }
+
+        for (name, unit) in &values.numbers {
+            if let Some((value, derived)) = self.value(name, |p| p.channel(name)) {
+                point.set_channel_with_unit(name, value, unit.as_deref());
+                if derived {
+                    point.derived.insert(name.to_string());
+                }
+            }
+        }
+        for name in &values.texts {
+            // Text can't be interpolated, only picked
+            let text = |p: &TelemetryPoint| matches!(p.channels.get(*name), Some(c) if matches!(c.value, ChannelValue::Text(_)));
+            if let Some(j) = self.pick(name, text) {
+                let source = &self.telemetry.points[j];
+                point.channels.insert(name.to_string(), source.channels[*name].clone());
+                if source.is_derived(name) {
+                    point.derived.insert(name.to_string());
+                }
+            }
+        }
+        point
+    }
+
+    fn seconds(&self, j: usize) -> f64 {
+        utils::seconds_between(self.timestamp, self.telemetry.points[j].timestamp)
+    }
+
+    /// Whether the points at `j` and `j + 1` are in the same run
+    fn continuous(&self, j: usize) -> bool {
+        let points = &self.telemetry.points;
+        self.policy.gaps == Gaps::Bridge || !self.telemetry.is_segment_break(points[j].timestamp, points[j + 1].timestamp)
+    }
+
+    /// The point at the time, when it has the value
+    fn exact(&self, has: &impl Fn(&TelemetryPoint) -> bool) -> Option<usize> {
+        let point = self.telemetry.points.get(self.index)?;
+        (point.timestamp == self.timestamp && has(point)).then_some(self.index)
+    }
+
+    /// The last point before `end` with the value, in the same run and
+    /// within the maximum gap
+    fn before(&self, end: usize, has: &impl Fn(&TelemetryPoint) -> bool) -> Option<usize> {
+        let mut j = end;
+        while j > 0 && self.continuous(j - 1) && self.policy.within_gap(-self.seconds(j - 1)) {
+            j -= 1;
+            if has(&self.telemetry.points[j]) {
+                return Some(j);
+            }
+        }
+        None
+    }
+
+    /// The first point from `start` on with the value
+    fn after(&self, start: usize, has: &impl Fn(&TelemetryPoint) -> bool) -> Option<usize> {
+        let points = &self.telemetry.points;
+        let mut j = start;
+        while j < points.len() && (j == 0 || self.continuous(j - 1)) && self.policy.within_gap(self.seconds(j)) {
+            if has(&points[j]) {
+                return Some(j);
+            }
+            j += 1;
+        }
+        None
+    }
+
+    /// The samples around the time, when they are close enough together
+    fn bracket(&self, has: &impl Fn(&TelemetryPoint) -> bool) -> Option<(usize, usize)> {
+        let (b, c) = (self.before(self.index, has)?, self.after(self.index, has)?);
+        self.policy.within_gap(self.seconds(c) - self.seconds(b)).then_some((b, c))
+    }
+
+    /// Index of the sample to take a value from without interpolating
+    fn pick(&self, name: &str, has: impl Fn(&TelemetryPoint) -> bool) -> Option<usize> {
+        if let Some(j) = self.exact(&has) {
+            return Some(j);
+        }
+        if self.policy.method_of(name) == Interpolation::HoldLast {
+            return self.before(self.index, &has);
+        }
+        let (b, c) = self.bracket(&has)?;
+        Some(if -self.seconds(b) < self.seconds(c) { b } else { c })
+    }
+
+    fn value(&self, name: &str, get: impl Fn(&TelemetryPoint) -> Option<f64>) -> Option<(f64, bool)> {
+        let points = &self.telemetry.points;
+        let method = self.policy.method_of(name);
+        let has = |p: &TelemetryPoint| get(p).is_some();
+        if let Some(j) = self.exact(&has) {
+            return Some((get(&points[j])?, points[j].is_derived(name)));
+        }
+        if method == Interpolation::HoldLast {
+            let b = self.before(self.index, &has)?;
+            return Some((get(&points[b])?, points[b].is_derived(name)));
+        }
+
+        let (b, c) = self.bracket(&has)?;
+        let sample = |j: usize| Some((self.seconds(j), get(&points[j])?));
+        let (a, d) = match method {
+            Interpolation::Cubic => (self.before(b, &has), self.after(c + 1, &has)),
+            _ => (None, None),
+        };
+        let value = between(method, 0.0, a.and_then(sample), sample(b)?, sample(c)?, d.and_then(sample));
+        Some((value, points[b].is_derived(name) || points[c].is_derived(name)))
+    }
+
+    /// Position along the great circle between the positions around the time
+    fn position(&self) -> Option<(f64, f64, bool)> {
+        let points = &self.telemetry.points;
+        let has = |p: &TelemetryPoint| p.latitude.is_some() && p.longitude.is_some();
+        if let Some(j) = self.exact(&has) {
+            let point = &points[j];
+            return Some((point.latitude?, point.longitude?, point.is_derived("latitude") || point.is_derived("longitude")));
+        }
+        let (b, c) = self.bracket(&has)?;
+        let fraction = -self.seconds(b) / (self.seconds(c) - self.seconds(b));
+        let (from, to) = (&points[b], &points[c]);
+        let (latitude, longitude) =
+            geo::calculate_intermediate(from.latitude?, from.longitude?, to.latitude?, to.longitude?, fraction);
+        let derived = [from, to].iter().any(|p| p.is_derived("latitude") || p.is_derived("longitude"));
+        Some((latitude, longitude, derived))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{telemetry::Segment, testing};
+
+    fn at(telemetry: &TelemetryData, seconds: f64, policy: &InterpolationPolicy) -> Option<TelemetryPoint> {
+        telemetry.interpolate_at_time_with(utils::add_seconds(telemetry.points[0].timestamp, seconds), policy)
+    }
+
+    #[test]
+    fn test_interpolation_defaults() {
+        assert_eq!(Interpolation::default_for("heading"), Interpolation::Circular);
+        assert_eq!(Interpolation::default_for("longitude"), Interpolation::GreatCircle);
+        assert_eq!(Interpolation::default_for("steering"), Interpolation::Linear);
+        for name in [channel::GEAR, channel::FLIGHT_MODE, channel::GPS_FIX, channel::GPS_QUALITY, channel::SATELLITES] {
+            assert_eq!(Interpolation::default_for(name), Interpolation::HoldLast, "{}", name);
+        }
+        assert_eq!("step".parse::<Interpolation>().unwrap(), Interpolation::HoldLast);
+        assert!("spline".parse::<Interpolation>().is_err());
+
+        // A blanket method only replaces linear interpolation
+        let policy = InterpolationPolicy::from(Interpolation::Cubic).with_rules(["lap=hold".parse().unwrap()]);
+        assert_eq!(policy.method_of("speed"), Interpolation::Cubic);
+        assert_eq!(policy.method_of("heading"), Interpolation::Circular);
+        assert_eq!(policy.method_of("lap"), Interpolation::HoldLast);
+    }
+
+    #[test]
+    fn test_interpolation_modes() {
+        let mut points: Vec<TelemetryPoint> = [(358.0, 0.0), (2.0, 10.0), (6.0, 40.0)]
+            .iter()
+            .map(|(heading, speed)| TelemetryPoint { heading: Some(*heading), speed: Some(*speed), ..Default::default() })
+            .collect();
+        for (point, (lat, gear)) in points.iter_mut().zip([(60.0, 1.0), (60.0, 2.0), (60.0, 3.0)]) {
+            point.latitude = Some(lat);
+            point.longitude = Some(gear * 30.0 - 60.0);
+            point.set_channel("gear", gear);
+        }
+        let data = testing::track(1.0, points);
+
+        let policy = InterpolationPolicy::default().with_rules(["gear=hold".parse().unwrap()]);
+        let point = at(&data, 0.5, &policy).unwrap();
+        assert!(point.heading.unwrap() < 1e-9 || point.heading.unwrap() > 360.0 - 1e-9);
+        assert_eq!(point.channel("gear"), Some(1.0));
+        assert_eq!(point.speed, Some(5.0));
+        // Halfway between 60°N 30°W and 60°N 0° lies north of the parallel
+        assert!(point.latitude.unwrap() > 60.1);
+
+        let cubic = at(&data, 1.5, &Interpolation::Cubic.into()).unwrap();
+        assert!(cubic.speed.unwrap() > 10.0 && cubic.speed.unwrap() < 25.0);
+    }
+
+    #[test]
+    fn test_missing_sides_and_gaps() {
+        let mut points = vec![TelemetryPoint::default(); 4];
+        points[0].speed = Some(0.0);
+        points[0].rpm = Some(1000.0);
+        points[1].altitude = Some(5.0);
+        points[2].speed = Some(20.0);
+        let mut data = testing::track(1.0, points);
+
+        // A value missing on one side comes from its own earlier sample
+        let point = at(&data, 1.5, &InterpolationPolicy::default()).unwrap();
+        assert!((point.speed.unwrap() - 15.0).abs() < 1e-9);
+        assert_eq!(at(&data, 0.5, &InterpolationPolicy::default()).unwrap().rpm, None);
+
+        let policy = InterpolationPolicy { max_gap: Some(1.5), ..Default::default() };
+        assert_eq!(at(&data, 1.5, &policy).unwrap().speed, None);
+
+        data.segments = vec![
+            Segment { track: None, start_time: data.points[0].timestamp, end_time: data.points[1].timestamp },
+            Segment { track: None, start_time: data.points[2].timestamp, end_time: data.points[3].timestamp },
+        ];
+        let held = at(&data, 1.5, &InterpolationPolicy::default()).unwrap();
+        assert_eq!(held.altitude, Some(5.0));
+        assert!(at(&data, 1.5, &InterpolationPolicy { gaps: Gaps::Skip, ..Default::default() }).is_none());
+        assert!(at(&data, 1.5, &InterpolationPolicy { max_gap: Some(0.2), ..Default::default() }).is_none());
+    }
+}
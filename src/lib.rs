@@ -1,13 +1,18 @@
 pub mod commands;
 pub mod error;
+pub mod formats;
 pub mod telemetry;
 pub mod renderer;
 pub mod video;
 pub mod geo;
+pub mod interpolation;
+pub mod processing;
 pub mod utils;
+#[cfg(test)]
+mod testing;
 
 pub use error::OverlogError;
-pub use telemetry::{TelemetryData, TelemetryPoint};
+pub use telemetry::{Channel, ChannelValue, Event, Lap, Route, Segment, TelemetryData, TelemetryPoint, Waypoint};
 pub use renderer::OverlayRenderer;
 pub use video::VideoProcessor;
 
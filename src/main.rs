@@ -1,7 +1,22 @@
-use clap::{Parser, Subcommand};
+use chrono::{DateTime, Utc};
+use clap::{Args, Parser, Subcommand};
 use overlog::{
-    commands::{parse, render},
+    commands::{convert, merge, parse, render, slice},
     error::OverlogError,
+    formats::{
+        csv_mapping::CsvMapping,
+        gpx::GpxTrack,
+        timing::{MissingTimes, TimestampPolicy},
+    },
+    interpolation::{Gaps, InterpolationPolicy, InterpolationRule},
+    processing::{
+        merge::{Preference, SourceValue},
+        laps::{Gate, LapGates},
+        outliers::{Hampel, OutlierRules, Rejection},
+        slice::Slice,
+        smooth::Smoothing,
+        Processing,
+    },
 };
 
 #[derive(Parser)]
@@ -25,9 +40,163 @@ enum Commands {
         #[arg(short, long)]
         output: Option<String>,
         
-        /// Input format (auto-detected if not specified)
+        /// Input format (detected from the contents if not specified): gpx, csv, json, tcx, gpmf, fit,
+        /// nmea, srt, dataflash, ulog, vbo, racechrono, harrys, trackaddict, geojson, kml, kmz
         #[arg(short, long)]
         format: Option<String>,
+        
+        /// GPX track to read, by 0-based index or name (all tracks if not specified)
+        #[arg(long)]
+        track: Option<GpxTrack>,
+        
+        #[command(flatten)]
+        csv: CsvArgs,
+        
+        #[command(flatten)]
+        times: TimeArgs,
+        
+        #[command(flatten)]
+        processing: ProcessingArgs,
+    },
+    
+    /// Convert telemetry data to GPX, CSV, GeoJSON, KML/KMZ, FIT or JSON
+    Convert {
+        /// Input file path
+        #[arg(short, long)]
+        input: String,
+        
+        /// Output file path
+        #[arg(short, long)]
+        output: String,
+        
+        /// Input format (auto-detected if not specified), as for `parse`
+        #[arg(short, long)]
+        from: Option<String>,
+        
+        /// Output format: gpx, csv, geojson, kml, kmz, fit or json (from the output extension if not specified)
+        #[arg(short, long)]
+        to: Option<String>,
+        
+        /// GPX track to read, by 0-based index or name (all tracks if not specified)
+        #[arg(long)]
+        track: Option<GpxTrack>,
+        
+        /// CSV output column (repeatable), e.g. "speed:km/h" (all fields and channels if not specified)
+        #[arg(long = "output-column", value_name = "FIELD[:UNIT]")]
+        output_columns: Vec<String>,
+        
+        #[command(flatten)]
+        csv: CsvArgs,
+        
+        #[command(flatten)]
+        times: TimeArgs,
+        
+        #[command(flatten)]
+        processing: ProcessingArgs,
+    },
+    
+    /// Merge telemetry from several devices onto one timeline
+    Merge {
+        /// Input file (repeatable), highest priority first
+        #[arg(short, long = "input", required = true)]
+        inputs: Vec<String>,
+        
+        /// Output file path (optional, defaults to stdout)
+        #[arg(short, long)]
+        output: Option<String>,
+        
+        /// Seconds added to the times of an input (repeatable), counted from 1, e.g. "2=-1.5"
+        #[arg(long = "offset", value_name = "N=SECONDS")]
+        offsets: Vec<SourceValue>,
+        
+        /// Clock drift of an input in parts per million (repeatable), positive when its clock runs fast,
+        /// e.g. "3=40"
+        #[arg(long = "drift", value_name = "N=PPM")]
+        drifts: Vec<SourceValue>,
+        
+        /// Take a field or channel from an input first (repeatable), "position" for latitude and
+        /// longitude, e.g. "speed=2"
+        #[arg(long = "prefer", value_name = "NAME=N")]
+        preferences: Vec<Preference>,
+        
+        #[command(flatten)]
+        times: TimeArgs,
+        
+        #[command(flatten)]
+        processing: ProcessingArgs,
+    },
+    
+    /// Cut telemetry down to a time range, a stretch of track or a lap
+    Trim {
+        /// Input file path
+        #[arg(short, long)]
+        input: String,
+        
+        /// Output file path
+        #[arg(short, long)]
+        output: String,
+        
+        /// Part to keep: time:START..END (RFC 3339), offset:START..END (seconds from the start),
+        /// distance:START..END (meters) or lap:N (0-based, timed at --start-finish without device laps); either end may be left out, e.g. "offset:12.5..70"
+        #[arg(long, value_name = "SLICE")]
+        range: Slice,
+        
+        /// Input format (auto-detected if not specified), as for `parse`
+        #[arg(short, long)]
+        from: Option<String>,
+        
+        /// Output format, as for `convert` (from the output extension if not specified)
+        #[arg(short, long)]
+        to: Option<String>,
+        
+        /// GPX track to read, by 0-based index or name (all tracks if not specified)
+        #[arg(long)]
+        track: Option<GpxTrack>,
+        
+        #[command(flatten)]
+        csv: CsvArgs,
+        
+        #[command(flatten)]
+        times: TimeArgs,
+        
+        #[command(flatten)]
+        processing: ProcessingArgs,
+    },
+    
+    /// Split telemetry at gaps in recording
+    Split {
+        /// Input file path
+        #[arg(short, long)]
+        input: String,
+        
+        /// Output file path, numbered for each piece: ride.gpx gives ride-1.gpx, ride-2.gpx, ...
+        #[arg(short, long)]
+        output: String,
+        
+        /// Split where two points are further apart than this (seconds)
+        #[arg(long, value_name = "SECONDS")]
+        gap: f64,
+        
+        /// Input format (auto-detected if not specified), as for `parse`
+        #[arg(short, long)]
+        from: Option<String>,
+        
+        /// Output format, as for `convert` (from the output extension if not specified)
+        #[arg(short, long)]
+        to: Option<String>,
+        
+        /// GPX track to read, by 0-based index or name (all tracks if not specified)
+        #[arg(long)]
+        track: Option<GpxTrack>,
+        
+        #[command(flatten)]
+        csv: CsvArgs,
+        
+        #[command(flatten)]
+        times: TimeArgs,
+        
+        #[command(flatten)]
+        processing: ProcessingArgs,
     },
     
     /// Render telemetry overlay
@@ -59,6 +228,10 @@ enum Commands {
         /// Overlay style
         #[arg(long, default_value = "default")]
         style: String,
+        
+        /// Named channel to display (repeatable), e.g. --channel heart_rate
+        #[arg(long = "channel", value_name = "NAME")]
+        channels: Vec<String>,
     },
     
     /// Burn overlay into video file
@@ -81,6 +254,193 @@ enum Commands {
     },
 }
 
+/// Options for reading CSV logs with their own layout
+#[derive(Args)]
+struct CsvArgs {
+    /// CSV mapping spec (JSON) for logger CSVs with their own layout
+    #[arg(long)]
+    csv_mapping: Option<String>,
+    
+    /// Map a CSV column to a field or channel, e.g. "Speed (kph)=speed:km/h"
+    #[arg(long = "column", value_name = "SOURCE=FIELD[:UNIT]")]
+    columns: Vec<String>,
+    
+    /// CSV timestamp column
+    #[arg(long)]
+    timestamp_column: Option<String>,
+    
+    /// CSV timestamp format: rfc3339, epoch-s, epoch-ms, relative or a strftime pattern
+    #[arg(long)]
+    timestamp_format: Option<String>,
+    
+    /// CSV field delimiter
+    #[arg(long)]
+    delimiter: Option<char>,
+    
+    /// CSV numbers use a decimal comma
+    #[arg(long)]
+    decimal_comma: bool,
+    
+    /// Lines to skip before the CSV header
+    #[arg(long)]
+    skip_lines: Option<usize>,
+}
+
+impl CsvArgs {
+    /// Build a mapping from the spec file and flags, if any were given,
+    /// reading local times in `timezone`
+    fn mapping(self, timezone: Option<&String>) -> Result<Option<CsvMapping>, OverlogError> {
+        let has_flags = !self.columns.is_empty()
+            || self.timestamp_column.is_some()
+            || self.timestamp_format.is_some()
+            || timezone.is_some()
+            || self.delimiter.is_some()
+            || self.decimal_comma
+            || self.skip_lines.is_some();
+        if self.csv_mapping.is_none() && !has_flags {
+            return Ok(None);
+        }
+        
+        let mut mapping = match self.csv_mapping {
+            Some(path) => CsvMapping::from_file(path)?,
+            None => CsvMapping::default(),
+        };
+        for rule in &self.columns {
+            mapping.add_column_rule(rule)?;
+        }
+        if let Some(column) = self.timestamp_column {
+            mapping.timestamp.column = column;
+        }
+        if let Some(format) = self.timestamp_format {
+            mapping.timestamp.format = format.parse()?;
+        }
+        if timezone.is_some() {
+            mapping.timestamp.timezone = timezone.cloned();
+        }
+        if self.delimiter.is_some() {
+            mapping.delimiter = self.delimiter;
+        }
+        mapping.decimal_comma |= self.decimal_comma;
+        if let Some(lines) = self.skip_lines {
+            mapping.skip_lines = lines;
+        }
+        Ok(Some(mapping))
+    }
+}
+
+/// Options for logs without absolute timestamps
+#[derive(Args)]
+struct TimeArgs {
+    /// Points without a time: reject, rate:HZ (fixed sample rate) or speed:M/S (from distance at a constant speed)
+    #[arg(long, default_value = "reject", value_name = "POLICY")]
+    missing_times: MissingTimes,
+    
+    /// Start time (RFC 3339) for logs with relative timestamps
+    #[arg(long)]
+    start_time: Option<DateTime<Utc>>,
+    
+    /// Timezone for local timestamps in CSV and DJI SRT logs (IANA name or offset like +02:00)
+    #[arg(long)]
+    timezone: Option<String>,
+}
+
+impl TimeArgs {
+    fn policy(self) -> TimestampPolicy {
+        TimestampPolicy {
+            missing: self.missing_times,
+            start: self.start_time,
+            timezone: self.timezone,
+        }
+    }
+}
+
+/// Passes run on telemetry after it is read
+#[derive(Args)]
+struct ProcessingArgs {
+    /// Compute missing speed, heading, g-forces, vertical speed, grade and distance from the GPS track
+    #[arg(long)]
+    derive: bool,
+    
+    /// Smooth a field or channel (repeatable): ma:WINDOW, ema:ALPHA, sg:WINDOW[:ORDER] or butter:CUTOFF_HZ,
+    /// e.g. "speed=sg:7"
+    #[arg(long, value_name = "NAME=FILTER")]
+    smooth: Vec<Smoothing>,
+    
+    /// Reject positions implying a faster speed, and speeds above it (m/s)
+    #[arg(long, value_name = "M/S")]
+    max_speed: Option<f64>,
+    
+    /// Reject positions implying a harder acceleration (m/s²)
+    #[arg(long, value_name = "M/S2")]
+    max_acceleration: Option<f64>,
+    
+    /// Reject positions with a higher HDOP
+    #[arg(long)]
+    max_hdop: Option<f64>,
+    
+    /// Reject positions with fewer satellites
+    #[arg(long)]
+    min_satellites: Option<u32>,
+    
+    /// Reject spikes in a field or channel with a Hampel filter (repeatable), e.g. "speed" or "speed=7:3"
+    #[arg(long, value_name = "NAME[=WINDOW[:SIGMAS]]")]
+    hampel: Vec<Hampel>,
+    
+    /// What to do with rejected positions and values: interpolate or remove
+    #[arg(long, default_value = "interpolate", value_name = "ACTION")]
+    outliers: Rejection,
+    
+    /// Time laps at a start/finish line between two positions
+    #[arg(long, value_name = "LAT,LON,LAT,LON")]
+    start_finish: Option<Gate>,
+    
+    /// Sector line between two positions (repeatable, in lap order)
+    #[arg(long = "sector", value_name = "LAT,LON,LAT,LON", requires = "start_finish")]
+    sectors: Vec<Gate>,
+    
+    /// Resample to an evenly spaced series at this rate (Hz)
+    #[arg(long, value_name = "HZ")]
+    resample: Option<f64>,
+    
+    /// Interpolation when resampling or merging (repeatable): linear, circular, great-circle, cubic, nearest or hold,
+    /// for every linear value or one field or channel, e.g. "cubic" or "gear=hold"
+    #[arg(long, value_name = "[NAME=]METHOD")]
+    interpolation: Vec<InterpolationRule>,
+    
+    /// What to resample between segments: hold the last point, skip or bridge
+    #[arg(long, default_value = "hold", value_name = "GAPS")]
+    gaps: Gaps,
+    
+    /// Longest hole in a value (s) to interpolate across when resampling or merging
+    #[arg(long, value_name = "SECONDS")]
+    max_gap: Option<f64>,
+}
+
+impl ProcessingArgs {
+    fn processing(self) -> Processing {
+        Processing {
+            outliers: OutlierRules {
+                max_speed: self.max_speed,
+                max_acceleration: self.max_acceleration,
+                max_hdop: self.max_hdop,
+                min_satellites: self.min_satellites,
+                hampel: self.hampel,
+                rejection: self.outliers,
+            },
+            derive: self.derive,
+            smooth: self.smooth,
+            laps: self.start_finish.map(|start_finish| LapGates { start_finish, sectors: self.sectors }),
+            resample: self.resample,
+            interpolation: InterpolationPolicy {
+                gaps: self.gaps,
+                max_gap: self.max_gap,
+                ..Default::default()
+            }
+            .with_rules(self.interpolation),
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<(), OverlogError> {
     // Initialize logging
@@ -89,11 +449,32 @@ async fn main() -> Result<(), OverlogError> {
     let cli = Cli::parse();
     
     match cli.command {
-        Commands::Parse { input, output, format } => {
-            parse::parse_telemetry(input, output, format).await?;
+        Commands::Parse { input, output, format, track, csv, times, processing } => {
+            let times = times.policy();
+            let csv = csv.mapping(times.timezone.as_ref())?;
+            parse::parse_telemetry(input, output, format, track, csv, times, processing.processing()).await?;
+        }
+        Commands::Convert { input, output, from, to, track, output_columns, csv, times, processing } => {
+            let times = times.policy();
+            let (csv, processing) = (csv.mapping(times.timezone.as_ref())?, processing.processing());
+            convert::convert_telemetry(input, output, from, to, track, csv, output_columns, times, processing).await?;
+        }
+        Commands::Merge { inputs, output, offsets, drifts, preferences, times, processing } => {
+            let (times, processing) = (times.policy(), processing.processing());
+            merge::merge_telemetry(inputs, output, offsets, drifts, preferences, times, processing).await?;
+        }
+        Commands::Trim { input, output, range, from, to, track, csv, times, processing } => {
+            let times = times.policy();
+            let (csv, processing) = (csv.mapping(times.timezone.as_ref())?, processing.processing());
+            slice::trim_telemetry(input, output, from, to, track, csv, range, times, processing).await?;
+        }
+        Commands::Split { input, output, gap, from, to, track, csv, times, processing } => {
+            let times = times.policy();
+            let (csv, processing) = (csv.mapping(times.timezone.as_ref())?, processing.processing());
+            slice::split_telemetry(input, output, from, to, track, csv, gap, times, processing).await?;
         }
-        Commands::Render { input, output, width, height, duration, fps, style } => {
-            render::render_overlay(input, output, width, height, duration, fps, style).await?;
+        Commands::Render { input, output, width, height, duration, fps, style, channels } => {
+            render::render_overlay(input, output, width, height, duration, fps, style, channels).await?;
         }
         Commands::Burn { video, overlay, output, offset } => {
             render::burn_overlay(video, overlay, output, offset).await?;
@@ -0,0 +1,229 @@
+//! Channels derived from the GPS trajectory
+//!
+//! Values are taken as central differences over the neighbouring points of a
+//! run, one-sided at its ends. Measured values are never replaced, and every
+//! computed value is listed in [`TelemetryPoint::derived`].
+
+use crate::{
+    geo,
+    telemetry::{channel, TelemetryData, TelemetryPoint},
+    utils,
+};
+
+const STANDARD_GRAVITY: f64 = 9.80665;
+
+/// Shorter moves than this (in m) give no heading or grade
+const MIN_DISTANCE: f64 = 1.0;
+
+impl TelemetryData {
+    /// Fill in missing values computed from position, altitude and time
+    ///
+    /// - `speed` (m/s) from the distance between neighbouring points
+    /// - `heading` (deg) from their bearing, held while standing still
+    /// - `acceleration` (m/s²) and `g_force_y` from the change in speed
+    /// - `g_force_x` from speed and the rate of turn, positive to the right
+    /// - `vertical_speed` (m/s) and `grade` (%) channels from altitude
+    /// - a cumulative `distance` channel (m), which doesn't count the jump
+    ///   across a segment break
+    pub fn derive_channels(&mut self) {
+        let travelled = self.travelled();
+        for run in self.runs() {
+            let travelled = &travelled[run.clone()];
+            let points = &mut self.points[run];
+            let steps = steps(points);
+
+            for i in 0..points.len() {
+                let (before, after) = neighbours(i, points.len());
+                let seconds = utils::seconds_between(points[before].timestamp, points[after].timestamp);
+                let path = (before..after).map(|j| steps[j + 1]).sum::<Option<f64>>();
+                let point = &mut points[i];
+
+                derive(point, "speed", |p| &mut p.speed, || Some(path? / positive(seconds)?));
+                if !point.channels.contains_key(channel::DISTANCE) && point.latitude.is_some() && point.longitude.is_some() {
+                    point.set_channel(channel::DISTANCE, travelled[i]);
+                    point.derived.insert(channel::DISTANCE.to_string());
+                }
+                let climb = difference(&points[before], &points[after], |p| p.altitude);
+                derive_channel(&mut points[i], channel::VERTICAL_SPEED, || Some(climb? / positive(seconds)?));
+                derive_channel(&mut points[i], channel::GRADE, || {
+                    Some(climb? / path.filter(|d| *d >= MIN_DISTANCE)? * 100.0)
+                });
+            }
+
+            // Heading from the bearing of the path, held through stops
+            let mut last_heading = None;
+            for i in 0..points.len() {
+                let (before, after) = neighbours(i, points.len());
+                let bearing = bearing(&points[before], &points[after]).or(last_heading);
+                derive(&mut points[i], "heading", |p| &mut p.heading, || bearing);
+                last_heading = points[i].heading.or(last_heading);
+            }
+
+            // Accelerations from the speeds and headings filled in above
+            for i in 0..points.len() {
+                let (before, after) = neighbours(i, points.len());
+                let seconds = positive(utils::seconds_between(points[before].timestamp, points[after].timestamp));
+                let longitudinal = difference(&points[before], &points[after], |p| p.speed).zip(seconds).map(|(dv, dt)| dv / dt);
+                let turn = difference(&points[before], &points[after], |p| p.heading)
+                    .map(|dh| (dh + 540.0) % 360.0 - 180.0)
+                    .zip(seconds)
+                    .map(|(dh, dt)| dh.to_radians() / dt);
+                let lateral = points[i].speed.zip(turn).map(|(v, rate)| v * rate);
+
+                let point = &mut points[i];
+                derive(point, "acceleration", |p| &mut p.acceleration, || longitudinal);
+                derive(point, "g_force_y", |p| &mut p.g_force_y, || longitudinal.map(|a| a / STANDARD_GRAVITY));
+                derive(point, "g_force_x", |p| &mut p.g_force_x, || lateral.map(|a| a / STANDARD_GRAVITY));
+            }
+        }
+        self.calculate_metadata();
+    }
+
+    /// Distance in meters travelled along the track up to each point,
+    /// measured between consecutive positions and not counting the jump
+    /// across a segment break
+    pub(crate) fn travelled(&self) -> Vec<f64> {
+        let mut travelled = Vec::with_capacity(self.points.len());
+        let mut total = 0.0;
+        for run in self.runs() {
+            let mut last = None;
+            for point in &self.points[run] {
+                if let (Some(latitude), Some(longitude)) = (point.latitude, point.longitude) {
+                    if let Some((lat, lon)) = last {
+                        total += geo::calculate_distance(lat, lon, latitude, longitude);
+                    }
+                    last = Some((latitude, longitude));
+                }
+                travelled.push(total);
+            }
+        }
+        travelled
+    }
+}
+
+/// Distance from the previous point of the run to each point; `Some(0.0)`
+/// for the first point when it has a position
+fn steps(points: &[TelemetryPoint]) -> Vec<Option<f64>> {
+    let position = |p: &TelemetryPoint| p.latitude.zip(p.longitude);
+    (0..points.len())
+        .map(|i| match (i.checked_sub(1).map(|j| position(&points[j])), position(&points[i])) {
+            (None, Some(_)) => Some(0.0),
+            (Some(Some((lat1, lon1))), Some((lat2, lon2))) => Some(geo::calculate_distance(lat1, lon1, lat2, lon2)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Indices of the points to difference for point `i` of a run
+fn neighbours(i: usize, len: usize) -> (usize, usize) {
+    (i.saturating_sub(1), (i + 1).min(len.saturating_sub(1)))
+}
+
+fn positive(value: f64) -> Option<f64> {
+    (value > 0.0).then_some(value)
+}
+
+fn difference(a: &TelemetryPoint, b: &TelemetryPoint, value: impl Fn(&TelemetryPoint) -> Option<f64>) -> Option<f64> {
+    Some(value(b)? - value(a)?)
+}
+
+fn bearing(a: &TelemetryPoint, b: &TelemetryPoint) -> Option<f64> {
+    let (lat1, lon1, lat2, lon2) = (a.latitude?, a.longitude?, b.latitude?, b.longitude?);
+    (geo::calculate_distance(lat1, lon1, lat2, lon2) >= MIN_DISTANCE).then(|| geo::calculate_bearing(lat1, lon1, lat2, lon2))
+}
+
+/// Set a field that has no value yet and flag it as derived
+fn derive(
+    point: &mut TelemetryPoint,
+    name: &str,
+    field: impl Fn(&mut TelemetryPoint) -> &mut Option<f64>,
+    value: impl FnOnce() -> Option<f64>,
+) {
+    if field(point).is_some() {
+        return;
+    }
+    if let Some(value) = value().filter(|v| v.is_finite()) {
+        *field(point) = Some(value);
+        point.derived.insert(name.to_string());
+    }
+}
+
+fn derive_channel(point: &mut TelemetryPoint, name: &str, value: impl FnOnce() -> Option<f64>) {
+    if point.channels.contains_key(name) {
+        return;
+    }
+    if let Some(value) = value().filter(|v| v.is_finite()) {
+        point.set_channel(name, value);
+        point.derived.insert(name.to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        telemetry::Segment,
+        testing::{self, DEGREES_PER_METER},
+    };
+
+    /// Points 1 s apart heading north at 10 m/s, then turning east
+    fn track() -> TelemetryData {
+        let positions = [(0.0, 0.0), (10.0, 0.0), (20.0, 0.0), (30.0, 0.0), (30.0, 10.0), (30.0, 20.0)];
+        testing::track(1.0, positions.iter().enumerate().map(|(i, (north, east))| TelemetryPoint {
+            latitude: Some(north * DEGREES_PER_METER),
+            longitude: Some(east * DEGREES_PER_METER),
+            altitude: Some(100.0 + i as f64),
+            ..Default::default()
+        }))
+    }
+
+    #[test]
+    fn test_derive_from_trajectory() {
+        let mut telemetry = track();
+        telemetry.points[0].speed = Some(9.0);
+        telemetry.derive_channels();
+        let points = &telemetry.points;
+
+        // Measured values stay and aren't flagged
+        assert_eq!(points[0].speed, Some(9.0));
+        assert!(!points[0].is_derived("speed"));
+
+        let speed = points[1].speed.unwrap();
+        assert!((speed - 10.0).abs() < 0.01);
+        assert!(points[1].is_derived("speed"));
+        assert!(points[1].heading.unwrap() < 0.01);
+        assert_eq!(points[1].g_force_x.map(|g| g.abs() < 1e-6), Some(true));
+        assert!((points[1].channel(channel::VERTICAL_SPEED).unwrap() - 1.0).abs() < 1e-9);
+        assert!((points[1].channel(channel::GRADE).unwrap() - 10.0).abs() < 0.01);
+        assert!((points[5].channel(channel::DISTANCE).unwrap() - 50.0).abs() < 0.1);
+
+        // Turning right at the corner pulls to the right
+        assert!(points[3].g_force_x.unwrap() > 0.5);
+        assert!((points[3].heading.unwrap() - 45.0).abs() < 0.1);
+        assert!(points[3].is_derived("g_force_x"));
+        assert!(telemetry.metadata.max_g_force.is_some());
+    }
+
+    #[test]
+    fn test_derive_stops_at_segment_breaks() {
+        let mut telemetry = track();
+        let points = &telemetry.points;
+        telemetry.segments = vec![
+            Segment { track: None, start_time: points[0].timestamp, end_time: points[2].timestamp },
+            Segment { track: None, start_time: points[3].timestamp, end_time: points[5].timestamp },
+        ];
+        telemetry.derive_channels();
+
+        // The first point after the break only looks forward
+        assert!((telemetry.points[3].channel(channel::DISTANCE).unwrap() - 20.0).abs() < 0.1);
+        assert!((telemetry.points[3].heading.unwrap() - 90.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn test_derive_without_positions() {
+        let mut telemetry = TelemetryData::new();
+        telemetry.points = vec![TelemetryPoint::default(); 3];
+        telemetry.derive_channels();
+        assert!(telemetry.points.iter().all(|p| p.derived.is_empty()));
+    }
+}
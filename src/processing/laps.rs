@@ -0,0 +1,318 @@
+//! Lap and sector timing at lines across the track
+//!
+//! A lap starts each time the track crosses the start/finish line in the
+//! direction of its first crossing, and sector lines split it in the order
+//! they are given; after crossing back over a line, the next crossing only
+//! returns to where the track was. Crossings are found between consecutive
+//! positions of a run and timed by interpolating along the move between
+//! them, so lap and sector times are not rounded to the sample rate.
+
+use std::{fmt, str::FromStr};
+use chrono::{DateTime, Utc};
+use crate::{
+    error::OverlogError,
+    geo,
+    telemetry::{channel, LapTime, TelemetryData},
+    utils,
+};
+
+/// A line across the track between two positions, written
+/// `LAT,LON,LAT,LON`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Gate {
+    /// Latitude and longitude of one end
+    pub from: (f64, f64),
+    /// Latitude and longitude of the other end
+    pub to: (f64, f64),
+}
+
+impl FromStr for Gate {
+    type Err = OverlogError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || OverlogError::Config(format!("Invalid line '{}', expected LAT,LON,LAT,LON", value));
+        let numbers = value
+            .split(',')
+            .map(|n| n.trim().parse::<f64>().ok().filter(|n| n.is_finite()))
+            .collect::<Option<Vec<f64>>>()
+            .ok_or_else(invalid)?;
+        match numbers[..] {
+            [lat1, lon1, lat2, lon2] if lat1.abs() <= 90.0 && lat2.abs() <= 90.0 && (lat1, lon1) != (lat2, lon2) => {
+                Ok(Gate { from: (lat1, lon1), to: (lat2, lon2) })
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl fmt::Display for Gate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{},{},{},{}", self.from.0, self.from.1, self.to.0, self.to.1)
+    }
+}
+
+impl Gate {
+    /// Where the move from `a` to `b` crosses the line, as the fraction of
+    /// the move, and the side it crosses towards
+    ///
+    /// A move that ends on the line crosses it; one that starts on it
+    /// doesn't, so a point on the line isn't counted twice.
+    fn crossing(&self, a: (f64, f64), b: (f64, f64)) -> Option<(f64, bool)> {
+        // Flat around the line, which is short enough for that
+        let local = |(lat, lon): (f64, f64)| geo::wgs84_to_local(lat, lon, self.from.0, self.from.1);
+        let (p, q, line) = (local(a), local(b), local(self.to));
+        let cross = |u: (f64, f64), v: (f64, f64)| u.0 * v.1 - u.1 * v.0;
+
+        let step = (q.0 - p.0, q.1 - p.1);
+        let denominator = cross(step, line);
+        if denominator == 0.0 {
+            return None;
+        }
+        let to_line = (-p.0, -p.1);
+        let along = cross(to_line, line) / denominator;
+        let across = cross(to_line, step) / denominator;
+        (along > 0.0 && along <= 1.0 && (0.0..=1.0).contains(&across)).then_some((along, denominator > 0.0))
+    }
+}
+
+/// The start/finish line of a circuit and its sector lines in order
+#[derive(Debug, Clone, PartialEq)]
+pub struct LapGates {
+    pub start_finish: Gate,
+    pub sectors: Vec<Gate>,
+}
+
+/// A crossing of the start/finish line (`None`) or a sector line
+struct Crossing {
+    time: DateTime<Utc>,
+    /// Distance travelled from the start of the log
+    distance: f64,
+    line: Option<usize>,
+    side: bool,
+    /// The first point after the crossing, or the point on the line
+    index: usize,
+}
+
+/// Where the points from a crossing on are in the lap
+#[derive(Debug, Clone, Copy)]
+struct Position {
+    lap: u32,
+    start: DateTime<Utc>,
+    /// Distance travelled from the start of the log to the start of the lap
+    distance: f64,
+    sector: u32,
+    sector_start: DateTime<Utc>,
+}
+
+impl TelemetryData {
+    /// Time laps and sectors at the gates, returning the number of complete
+    /// laps
+    ///
+    /// Every point gets a `lap` channel, 0 before the first crossing of the
+    /// start/finish line, and from there on `lap_time` (s), `sector` (from 1)
+    /// and `lap_distance` (m), all flagged as derived; lap numbers read from
+    /// the log are replaced. Complete laps are listed in
+    /// `metadata.lap_times` and the fastest in `metadata.best_lap`.
+    pub fn time_laps(&mut self, gates: &LapGates) -> usize {
+        let travelled = self.travelled();
+        let crossings = self.crossings(gates, &travelled);
+
+        let mut laps = Vec::new();
+        let mut changes: Vec<(usize, Position)> = Vec::new();
+        let mut current: Option<Position> = None;
+        let mut sectors = Vec::new();
+        // The direction of each line, and how many times the track has
+        // crossed back over it since
+        let mut sides = vec![None; gates.sectors.len() + 1];
+        let mut behind = vec![0; gates.sectors.len() + 1];
+        for crossing in crossings {
+            let line = crossing.line.map_or(0, |k| k + 1);
+            if *sides[line].get_or_insert(crossing.side) != crossing.side {
+                behind[line] += 1;
+                continue;
+            }
+            if behind[line] > 0 {
+                behind[line] -= 1;
+                continue;
+            }
+            let since = |start: DateTime<Utc>| utils::seconds_between(start, crossing.time);
+
+            match (crossing.line, &mut current) {
+                (None, _) => {
+                    if let Some(position) = current {
+                        // The last sector is only timed when every line before it was
+                        if !gates.sectors.is_empty() && sectors.len() == gates.sectors.len() {
+                            sectors.push(since(position.sector_start));
+                        }
+                        laps.push(LapTime {
+                            number: position.lap,
+                            start_time: position.start,
+                            end_time: crossing.time,
+                            time: since(position.start),
+                            sectors: std::mem::take(&mut sectors),
+                            distance: crossing.distance - position.distance,
+                        });
+                    }
+                    current = Some(Position {
+                        lap: current.map_or(1, |p| p.lap + 1),
+                        start: crossing.time,
+                        distance: crossing.distance,
+                        sector: 1,
+                        sector_start: crossing.time,
+                    });
+                }
+                (Some(k), Some(position)) if position.sector as usize == k + 1 => {
+                    sectors.push(since(position.sector_start));
+                    position.sector += 1;
+                    position.sector_start = crossing.time;
+                }
+                _ => continue,
+            }
+            changes.extend(current.map(|position| (crossing.index, position)));
+        }
+
+        let mut next = 0;
+        let mut position = None;
+        for (i, point) in self.points.iter_mut().enumerate() {
+            while changes.get(next).is_some_and(|(index, _)| *index <= i) {
+                position = Some(changes[next].1);
+                next += 1;
+            }
+            for name in [channel::LAP, channel::LAP_TIME, channel::SECTOR, channel::LAP_DISTANCE] {
+                point.channels.remove(name);
+                point.derived.remove(name);
+            }
+            let values = match position {
+                None => vec![(channel::LAP, 0.0)],
+                Some(position) => vec![
+                    (channel::LAP, position.lap as f64),
+                    (channel::LAP_TIME, utils::seconds_between(position.start, point.timestamp)),
+                    (channel::SECTOR, position.sector as f64),
+                    (channel::LAP_DISTANCE, travelled[i] - position.distance),
+                ],
+            };
+            for (name, value) in values {
+                point.set_channel(name, value);
+                point.derived.insert(name.to_string());
+            }
+        }
+
+        self.metadata.best_lap = laps.iter().min_by(|a, b| a.time.total_cmp(&b.time)).map(|lap| lap.number);
+        self.metadata.lap_times = laps;
+        self.calculate_metadata();
+        self.metadata.lap_times.len()
+    }
+
+    /// Crossings of every line in time order
+    fn crossings(&self, gates: &LapGates, travelled: &[f64]) -> Vec<Crossing> {
+        let lines: Vec<(Option<usize>, &Gate)> =
+            std::iter::once((None, &gates.start_finish)).chain(gates.sectors.iter().enumerate().map(|(k, gate)| (Some(k), gate))).collect();
+        let mut crossings = Vec::new();
+        for run in self.runs() {
+            let mut last: Option<(usize, (f64, f64))> = None;
+            for i in run {
+                let point = &self.points[i];
+                let (Some(latitude), Some(longitude)) = (point.latitude, point.longitude) else {
+                    continue;
+                };
+                if let Some((j, from)) = last {
+                    let before = &self.points[j];
+                    let seconds = utils::seconds_between(before.timestamp, point.timestamp);
+                    for (line, gate) in &lines {
+                        if let Some((along, side)) = gate.crossing(from, (latitude, longitude)) {
+                            crossings.push(Crossing {
+                                time: utils::add_seconds(before.timestamp, seconds * along),
+                                distance: travelled[j] + (travelled[i] - travelled[j]) * along,
+                                line: *line,
+                                side,
+                                index: i,
+                            });
+                        }
+                    }
+                }
+                last = Some((i, (latitude, longitude)));
+            }
+        }
+        crossings.sort_by_key(|c| c.time);
+        crossings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{telemetry::TelemetryPoint, testing};
+
+    /// Laps of a square 0.001° (about 111 m) a side from the origin, one
+    /// corner a second
+    fn telemetry(laps: usize) -> TelemetryData {
+        let corners = [(0.0, 0.0), (0.0, 0.001), (0.001, 0.001), (0.001, 0.0)];
+        testing::track(1.0, (0..laps * 4 + 1).map(|i| TelemetryPoint {
+            latitude: Some(corners[i % 4].0),
+            longitude: Some(corners[i % 4].1),
+            ..Default::default()
+        }))
+    }
+
+    fn point(seconds: f64, latitude: f64, longitude: f64) -> TelemetryPoint {
+        TelemetryPoint { timestamp: testing::at(seconds), latitude: Some(latitude), longitude: Some(longitude), ..Default::default() }
+    }
+
+    /// Across the bottom edge, three quarters of the way along
+    const START_FINISH: &str = "0.0001,0.00075,-0.0001,0.00075";
+
+    #[test]
+    fn test_gate_parsing() {
+        let gate: Gate = "0.0,0.00075, -0.0001,0.00075".parse().unwrap();
+        assert_eq!(gate.from, (0.0, 0.00075));
+        assert_eq!(gate.to_string(), "0,0.00075,-0.0001,0.00075");
+        assert!("0,0,0,0".parse::<Gate>().is_err());
+        assert!("1,2,3".parse::<Gate>().is_err());
+    }
+
+    #[test]
+    fn test_crossing_times_are_interpolated() {
+        // The sector line is halfway up the right edge
+        let gates = LapGates {
+            start_finish: START_FINISH.parse().unwrap(),
+            sectors: vec!["0.0005,0.0009,0.0005,0.0011".parse().unwrap()],
+        };
+        let mut data = telemetry(3);
+        assert_eq!(data.time_laps(&gates), 2);
+
+        let laps = &data.metadata.lap_times;
+        let first = data.points[0].timestamp;
+        assert!((utils::seconds_between(first, laps[0].start_time) - 0.75).abs() < 1e-6);
+        assert!((laps[0].time - 4.0).abs() < 1e-6);
+        assert_eq!(laps[0].sectors.len(), 2);
+        assert!((laps[0].sectors[0] - 0.75).abs() < 1e-6);
+        assert!((laps[0].sectors[1] - 3.25).abs() < 1e-6);
+        let side = geo::calculate_distance(0.0, 0.0, 0.0, 0.001);
+        assert!((laps[0].distance - 4.0 * side).abs() < 0.1);
+        assert!(data.metadata.best_lap.is_some());
+
+        let point = &data.points[3];
+        assert_eq!(point.channel(channel::LAP), Some(1.0));
+        assert_eq!(point.channel(channel::SECTOR), Some(2.0));
+        assert!((point.channel(channel::LAP_TIME).unwrap() - 2.25).abs() < 1e-6);
+        assert!((point.channel(channel::LAP_DISTANCE).unwrap() - 2.25 * side).abs() < 0.1);
+        assert!(point.is_derived(channel::LAP));
+        assert_eq!(data.points[0].channel(channel::LAP), Some(0.0));
+        assert_eq!(data.points[0].channel(channel::LAP_TIME), None);
+        assert_eq!(data.points[12].channel(channel::LAP), Some(3.0));
+    }
+
+    #[test]
+    fn test_crossing_back_over_the_line() {
+        let gates = LapGates { start_finish: START_FINISH.parse().unwrap(), sectors: Vec::new() };
+        let mut data = telemetry(3);
+        // Back over the line and forward again
+        data.points.splice(2..2, [point(1.3, 0.0, 0.0005), point(1.6, 0.0, 0.001)]);
+
+        assert_eq!(data.time_laps(&gates), 2);
+        let laps = &data.metadata.lap_times;
+        assert!((laps[0].time - 4.0).abs() < 1e-6);
+        assert!(laps[0].sectors.is_empty());
+        assert_eq!(laps[1].number, 2);
+    }
+}
@@ -0,0 +1,382 @@
+//! Merging logs from several devices onto one timeline
+//!
+//! GPS from one device, OBD-II from another and an IMU from a third each
+//! keep time by their own clock. Every clock is corrected by an offset and a
+//! drift, then the logs are laid over each other: the merged log has a point
+//! at every time any of them has a sample, and each field and channel there
+//! comes from the first log in priority order that has a value for it,
+//! interpolated from that log's own samples as [`interpolate_at_time_with`]
+//! would. A log has no values between its segments or across holes longer
+//! than the maximum gap, so the next log fills them instead.
+//!
+//! [`interpolate_at_time_with`]: TelemetryData::interpolate_at_time_with
+
+use std::{collections::{BTreeMap, BTreeSet}, str::FromStr};
+use chrono::{DateTime, Utc};
+use crate::{
+    error::OverlogError,
+    formats::columns::Field,
+    interpolation::{Gaps, InterpolationPolicy},
+    telemetry::{Segment, TelemetryData, TelemetryPoint},
+};
+
+/// Name under which latitude and longitude are preferred together
+pub const POSITION: &str = "position";
+
+/// A log to merge and the correction of its clock
+#[derive(Debug, Clone)]
+pub struct MergeSource {
+    pub telemetry: TelemetryData,
+    /// Seconds added to every time, after correcting the drift
+    pub offset: f64,
+    /// How fast the clock runs in parts per million, positive when it gains
+    pub drift_ppm: f64,
+}
+
+impl From<TelemetryData> for MergeSource {
+    fn from(telemetry: TelemetryData) -> Self {
+        MergeSource { telemetry, offset: 0.0, drift_ppm: 0.0 }
+    }
+}
+
+/// How to merge logs
+#[derive(Debug, Clone, Default)]
+pub struct MergeOptions {
+    /// Index of the source to take a field or channel from first, by name
+    /// ([`POSITION`] for latitude and longitude); everything else comes from
+    /// the sources in order
+    pub prefer: BTreeMap<String, usize>,
+    /// How values are interpolated between the samples of a source; a
+    /// source never holds or bridges across its own gaps
+    pub interpolation: InterpolationPolicy,
+}
+
+/// A value for one source, written `N=VALUE` with sources counted from 1
+/// (`2=-1.5`)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SourceValue {
+    /// Index of the source, from 0
+    pub source: usize,
+    pub value: f64,
+}
+
+impl FromStr for SourceValue {
+    type Err = OverlogError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || OverlogError::Config(format!("Invalid source setting '{}', expected N=VALUE with N from 1", value));
+        let (source, number) = value.split_once('=').ok_or_else(invalid)?;
+        let source = source.trim().parse::<usize>().ok().filter(|n| *n > 0).ok_or_else(invalid)?;
+        let number = number.trim().parse::<f64>().ok().filter(|n| n.is_finite()).ok_or_else(invalid)?;
+        Ok(SourceValue { source: source - 1, value: number })
+    }
+}
+
+/// A field or channel to take from one source first, written `NAME=N` with
+/// sources counted from 1 (`speed=2`)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Preference {
+    pub name: String,
+    /// Index of the source, from 0
+    pub source: usize,
+}
+
+impl FromStr for Preference {
+    type Err = OverlogError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || OverlogError::Config(format!("Invalid preference '{}', expected NAME=N with N from 1", value));
+        let (name, source) = value.split_once('=').ok_or_else(invalid)?;
+        let source = source.trim().parse::<usize>().ok().filter(|n| *n > 0).ok_or_else(invalid)?;
+        match name.trim() {
+            "" => Err(invalid()),
+            name => Ok(Preference { name: name.to_string(), source: source - 1 }),
+        }
+    }
+}
+
+impl MergeOptions {
+    /// Apply `NAME=N` preferences in order
+    pub fn with_preferences(mut self, preferences: impl IntoIterator<Item = Preference>) -> Self {
+        self.prefer.extend(preferences.into_iter().map(|p| (p.name, p.source)));
+        self
+    }
+}
+
+impl TelemetryData {
+    /// Merge logs, highest priority first, onto one timeline
+    ///
+    /// Each source's clock is corrected first. Logs with relative times can
+    /// only be merged with each other, or once they are placed at a start
+    /// time. The merged log is continuous
+    /// wherever any source has data, and split into segments where none has;
+    /// laps come from the first source with laps, while events, waypoints
+    /// and routes are taken from all of them.
+    pub fn merge(sources: Vec<MergeSource>, options: &MergeOptions) -> Result<TelemetryData, OverlogError> {
+        if sources.is_empty() {
+            return Err(OverlogError::InvalidInput("No telemetry to merge".to_string()));
+        }
+        if let Some((name, source)) = options.prefer.iter().find(|(_, source)| **source >= sources.len()) {
+            return Err(OverlogError::Config(format!(
+                "Can't take {} from source {}, there are {} sources",
+                name,
+                source + 1,
+                sources.len()
+            )));
+        }
+        if let Some(source) = sources.iter().find(|s| !(s.offset.is_finite() && s.drift_ppm.is_finite() && s.drift_ppm > -1e6)) {
+            return Err(OverlogError::Config(format!(
+                "Invalid clock correction: offset {} s, drift {} ppm",
+                source.offset, source.drift_ppm
+            )));
+        }
+
+        let relative = |s: &&MergeSource| s.telemetry.metadata.times.relative && s.telemetry.metadata.times.start.is_none();
+        if sources.iter().any(|s| relative(&s)) && !sources.iter().all(|s| relative(&s)) {
+            let names: Vec<&str> = sources.iter().filter(relative).map(|s| s.telemetry.metadata.source.as_str()).collect();
+            return Err(OverlogError::InvalidInput(format!(
+                "Can't merge logs with relative times ({}) with absolute ones, give them a start time",
+                names.join(", ")
+            )));
+        }
+
+        let logs: Vec<TelemetryData> = sources
+            .into_iter()
+            .map(|source| {
+                let mut telemetry = source.telemetry;
+                telemetry.correct_clock(source.offset, source.drift_ppm);
+                telemetry.points.sort_by_key(|p| p.timestamp);
+                telemetry
+            })
+            .collect();
+
+        // A source in a gap of its own gives way to the next one
+        let interpolation = InterpolationPolicy { gaps: Gaps::Skip, ..options.interpolation.clone() };
+        let mut merged = TelemetryData::new();
+        let times: BTreeSet<DateTime<Utc>> = logs.iter().flat_map(|log| log.points.iter().map(|p| p.timestamp)).collect();
+        for timestamp in times {
+            let samples: Vec<Option<TelemetryPoint>> =
+                logs.iter().map(|log| log.interpolate_at_time_with(timestamp, &interpolation)).collect();
+            merged.points.push(merge_point(timestamp, &samples, &options.prefer));
+        }
+
+        merged.segments = coverage(&logs);
+        merged.laps = logs.iter().find(|log| !log.laps.is_empty()).map(|log| log.laps.clone()).unwrap_or_default();
+        for log in &logs {
+            merged.events.extend(log.events.iter().cloned());
+            merged.waypoints.extend(log.waypoints.iter().cloned());
+            merged.routes.extend(log.routes.iter().cloned());
+            merged.metadata.outliers.extend(log.metadata.outliers.iter().cloned());
+
+            let times = &mut merged.metadata.times;
+            times.relative |= log.metadata.times.relative;
+            times.start = times.start.or(log.metadata.times.start);
+            times.synthesized.extend(log.metadata.times.synthesized.iter().cloned());
+        }
+        merged.events.sort_by_key(|e| e.timestamp);
+        merged.metadata.outliers.sort_by_key(|o| o.timestamp);
+        let names: Vec<&str> = logs.iter().map(|log| log.metadata.source.as_str()).filter(|s| !s.is_empty()).collect();
+        merged.metadata.source = names.join(" + ");
+        merged.metadata.format = "merged".to_string();
+        merged.calculate_metadata();
+        Ok(merged)
+    }
+}
+
+/// The points of the sources at a time, the preferred source first
+fn ranked(samples: &[Option<TelemetryPoint>], preferred: Option<usize>) -> impl Iterator<Item = &TelemetryPoint> {
+    preferred.into_iter().chain(0..samples.len()).filter_map(|i| samples[i].as_ref())
+}
+
+/// Point at `timestamp` with every value from the first source that has it
+fn merge_point(timestamp: DateTime<Utc>, samples: &[Option<TelemetryPoint>], prefer: &BTreeMap<String, usize>) -> TelemetryPoint {
+    let preferred = |name: &str| prefer.get(name).copied();
+    let mut point = TelemetryPoint { timestamp, ..Default::default() };
+
+    // Latitude and longitude always come from the same source
+    let position = ranked(samples, preferred(POSITION)).find(|p| p.latitude.is_some() && p.longitude.is_some());
+    if let Some(source) = position {
+        point.latitude = source.latitude;
+        point.longitude = source.longitude;
+        for name in ["latitude", "longitude"] {
+            if source.is_derived(name) {
+                point.derived.insert(name.to_string());
+            }
+        }
+    }
+
+    for field in Field::POINT_FIELDS {
+        if matches!(field, Field::Latitude | Field::Longitude) {
+            continue;
+        }
+        let name = field.name();
+        if let Some((source, value)) = ranked(samples, preferred(name)).find_map(|p| Some((p, field.get(p)?))) {
+            field.set(&mut point, value);
+            if source.is_derived(name) {
+                point.derived.insert(name.to_string());
+            }
+        }
+    }
+
+    let names: BTreeSet<&String> = samples.iter().flatten().flat_map(|p| p.channels.keys()).collect();
+    for name in names {
+        if let Some(source) = ranked(samples, preferred(name)).find(|p| p.channels.contains_key(name.as_str())) {
+            point.channels.insert(name.clone(), source.channels[name.as_str()].clone());
+            if source.is_derived(name) {
+                point.derived.insert(name.clone());
+            }
+        }
+    }
+    point
+}
+
+/// Segments of the time covered by at least one run of a log, when there is
+/// more than one
+fn coverage(logs: &[TelemetryData]) -> Vec<Segment> {
+    let mut spans: Vec<(DateTime<Utc>, DateTime<Utc>)> = logs
+        .iter()
+        .flat_map(|log| log.runs().into_iter().map(|run| (log.points[run.start].timestamp, log.points[run.end - 1].timestamp)))
+        .collect();
+    spans.sort();
+
+    let mut covered: Vec<(DateTime<Utc>, DateTime<Utc>)> = Vec::new();
+    for (start, end) in spans {
+        match covered.last_mut() {
+            Some((_, last)) if start <= *last => *last = (*last).max(end),
+            _ => covered.push((start, end)),
+        }
+    }
+    if covered.len() < 2 {
+        return Vec::new();
+    }
+    covered
+        .into_iter()
+        .map(|(start_time, end_time)| Segment { track: None, start_time, end_time })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+    use crate::{telemetry::Lap, testing, utils};
+
+    /// One point a second from `start` seconds after 10:00, made by `point`
+    fn telemetry(start: i64, count: i64, point: impl Fn(i64) -> TelemetryPoint) -> TelemetryData {
+        testing::log((0..count).map(|i| TelemetryPoint { timestamp: testing::at((start + i) as f64), ..point(i) }))
+    }
+
+    fn gps() -> TelemetryData {
+        let mut gps = telemetry(0, 5, |i| TelemetryPoint {
+            latitude: Some(60.0),
+            longitude: Some(i as f64 * 0.001),
+            speed: Some(10.0),
+            ..Default::default()
+        });
+        gps.metadata.source = "gps".to_string();
+        gps
+    }
+
+    /// OBD-II log whose clock is 2.5 s behind the GPS
+    fn obd() -> TelemetryData {
+        let mut obd = telemetry(-3, 5, |i| {
+            let mut point = TelemetryPoint { speed: Some(12.0), rpm: Some(1000.0 * i as f64), ..Default::default() };
+            point.set_channel("coolant_temp", 90.0);
+            point
+        });
+        obd.laps.push(Lap { start_time: obd.points[0].timestamp, end_time: None, total_time: None, distance: None });
+        obd
+    }
+
+    #[test]
+    fn test_correct_clock() {
+        let mut data = telemetry(0, 2, |_| TelemetryPoint::default());
+        data.points[1].timestamp = data.points[0].timestamp + Duration::seconds(1000);
+        let start = data.points[0].timestamp;
+
+        data.correct_clock(2.0, 100.0);
+        assert_eq!(data.points[0].timestamp, start + Duration::seconds(2));
+        let elapsed = utils::seconds_between(start, data.points[1].timestamp);
+        assert!((elapsed - (2.0 + 1000.0 / 1.0001)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_merge_aligns_sources() {
+        let sources = vec![gps().into(), MergeSource { offset: 2.5, ..obd().into() }];
+        let merged = TelemetryData::merge(sources, &MergeOptions::default()).unwrap();
+
+        // GPS samples on the second, OBD-II ones on the half second from -0.5 s
+        assert_eq!(merged.points.len(), 10);
+        assert!(merged.segments.is_empty());
+        assert_eq!(merged.metadata.source, "gps");
+        assert_eq!(merged.laps.len(), 1);
+        assert_eq!(merged.points[0].speed, Some(12.0));
+
+        let point = &merged.points[3];
+        assert_eq!(utils::seconds_between(merged.points[1].timestamp, point.timestamp), 1.0);
+        assert_eq!(point.speed, Some(10.0));
+        assert_eq!(point.rpm, Some(1500.0));
+        assert_eq!(point.channel("coolant_temp"), Some(90.0));
+        // Past the end of the OBD-II log only the GPS is left
+        assert_eq!(merged.points[9].rpm, None);
+
+        let options = MergeOptions::default().with_preferences(["speed=2".parse().unwrap()]);
+        let merged = TelemetryData::merge(vec![gps().into(), MergeSource { offset: 2.5, ..obd().into() }], &options).unwrap();
+        assert_eq!(merged.points[3].speed, Some(12.0));
+        assert_eq!(merged.points[9].speed, Some(10.0));
+    }
+
+    #[test]
+    fn test_merge_falls_through_gaps() {
+        // The GPS pauses between 1 s and 4 s, where the OBD-II speed takes over
+        let mut gps = gps();
+        let times: Vec<_> = gps.points.iter().map(|p| p.timestamp).collect();
+        gps.segments = vec![
+            Segment { track: None, start_time: times[0], end_time: times[1] },
+            Segment { track: None, start_time: times[4], end_time: times[4] },
+        ];
+        gps.points.drain(2..4);
+        let obd = MergeSource { offset: 2.5, ..obd().into() };
+
+        let merged = TelemetryData::merge(vec![gps.clone().into(), obd.clone()], &MergeOptions::default()).unwrap();
+        let at = |merged: &TelemetryData, seconds: f64| {
+            merged.points.iter().find(|p| utils::seconds_between(times[0], p.timestamp) == seconds).unwrap().speed
+        };
+        assert_eq!(at(&merged, 1.5), Some(12.0));
+        assert_eq!(at(&merged, 4.0), Some(10.0));
+
+        // So does a hole longer than the maximum gap
+        let mut gps = gps;
+        gps.segments.clear();
+        let options = MergeOptions {
+            interpolation: InterpolationPolicy { max_gap: Some(2.0), ..Default::default() },
+            ..Default::default()
+        };
+        let merged = TelemetryData::merge(vec![gps.into(), obd], &options).unwrap();
+        assert_eq!(at(&merged, 2.5), Some(12.0));
+        assert_eq!(at(&merged, 0.5), Some(10.0));
+    }
+
+    #[test]
+    fn test_merge_gaps_and_errors() {
+        let merged = TelemetryData::merge(vec![gps().into(), MergeSource { offset: 20.0, ..obd().into() }], &MergeOptions::default()).unwrap();
+        assert_eq!(merged.segments.len(), 2);
+        assert_eq!(merged.runs().len(), 2);
+
+        assert!(TelemetryData::merge(Vec::new(), &MergeOptions::default()).is_err());
+        let options = MergeOptions::default().with_preferences(["rpm=3".parse().unwrap()]);
+        assert!(TelemetryData::merge(vec![gps().into(), obd().into()], &options).is_err());
+        assert!("speed=0".parse::<Preference>().is_err());
+
+        // Relative times can't be lined up with absolute ones
+        let mut relative = obd();
+        relative.metadata.times.relative = true;
+        relative.metadata.source = "obd".to_string();
+        let error = TelemetryData::merge(vec![gps().into(), relative.clone().into()], &MergeOptions::default()).unwrap_err();
+        assert!(error.to_string().contains("(obd)"));
+        assert!(TelemetryData::merge(vec![relative.clone().into(), relative.clone().into()], &MergeOptions::default()).is_ok());
+        relative.metadata.times.start = relative.points.first().map(|p| p.timestamp);
+        assert!(TelemetryData::merge(vec![gps().into(), relative.into()], &MergeOptions::default()).is_ok());
+        assert_eq!("2=-1.5".parse::<SourceValue>().unwrap(), SourceValue { source: 1, value: -1.5 });
+    }
+}
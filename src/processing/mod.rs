@@ -0,0 +1,56 @@
+//! Passes that clean up and extend parsed telemetry
+//!
+//! Each pass works on the continuous runs of a log ([`TelemetryData::runs`])
+//! and never carries values across a segment break.
+
+pub mod derive;
+pub mod laps;
+pub mod merge;
+pub mod outliers;
+pub mod resample;
+pub mod slice;
+pub mod smooth;
+
+use crate::{error::OverlogError, interpolation::InterpolationPolicy, telemetry::TelemetryData};
+use laps::LapGates;
+use outliers::OutlierRules;
+use smooth::Smoothing;
+
+/// Passes to run on telemetry after it is read
+#[derive(Debug, Clone, Default)]
+pub struct Processing {
+    /// Positions and values to reject before anything is derived from them
+    pub outliers: OutlierRules,
+    /// Compute speed, heading, accelerations, vertical speed, grade and
+    /// distance where the log doesn't have them
+    pub derive: bool,
+    /// Filters for noisy fields and channels, applied after deriving
+    pub smooth: Vec<Smoothing>,
+    /// Lines to time laps and sectors at, after smoothing
+    pub laps: Option<LapGates>,
+    /// Rate in Hz to resample to, last
+    pub resample: Option<f64>,
+    pub interpolation: InterpolationPolicy,
+}
+
+impl Processing {
+    /// Run the selected passes in order
+    pub fn apply(&self, telemetry: &mut TelemetryData) -> Result<(), OverlogError> {
+        if !self.outliers.is_empty() {
+            telemetry.reject_outliers(&self.outliers)?;
+        }
+        if self.derive {
+            telemetry.derive_channels();
+        }
+        for smoothing in &self.smooth {
+            telemetry.smooth(&smoothing.name, &smoothing.filter)?;
+        }
+        if let Some(gates) = &self.laps {
+            telemetry.time_laps(gates);
+        }
+        if let Some(rate_hz) = self.resample {
+            *telemetry = telemetry.resample(rate_hz, &self.interpolation)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,509 @@
+//! Rejection of GPS glitches and sensor spikes
+//!
+//! In urban canyons and tunnels receivers jump hundreds of metres and report
+//! speed spikes. A position is rejected when the receiver reports a poor fix
+//! (HDOP, satellite count) or when getting there from the last accepted
+//! position would take an implausible speed or acceleration, so a whole
+//! excursion is rejected until the track comes back. Spikes in a single field
+//! or channel are found with a Hampel filter. Every change is listed in
+//! [`TelemetryMetadata::outliers`].
+//!
+//! [`TelemetryMetadata::outliers`]: crate::telemetry::TelemetryMetadata::outliers
+
+use std::{fmt, str::FromStr};
+use crate::{
+    error::OverlogError,
+    formats::columns::Field,
+    geo,
+    telemetry::{channel, Outlier, OutlierAction, OutlierReason, TelemetryData, TelemetryPoint},
+    utils,
+};
+use super::smooth;
+
+/// Scale from the median absolute deviation to the standard deviation of
+/// normally distributed values
+const MAD_SCALE: f64 = 1.4826;
+
+/// The same for the mean absolute deviation
+const MEAN_SCALE: f64 = 1.2533;
+
+/// What to do with a rejected position or value
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Rejection {
+    /// Drop rejected points, and rejected values from their points
+    Remove,
+    /// Interpolate rejected positions and values from their accepted
+    /// neighbours in time, dropping them where there is no neighbour on one
+    /// side
+    #[default]
+    Interpolate,
+}
+
+impl FromStr for Rejection {
+    type Err = OverlogError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "remove" => Ok(Rejection::Remove),
+            "interpolate" => Ok(Rejection::Interpolate),
+            _ => Err(OverlogError::Config(format!("Invalid rejection '{}', expected remove or interpolate", value))),
+        }
+    }
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Rejection::Remove => write!(f, "remove"),
+            Rejection::Interpolate => write!(f, "interpolate"),
+        }
+    }
+}
+
+/// A Hampel filter for one field or channel, written `NAME[=WINDOW[:SIGMAS]]`
+/// (`speed=7:3`)
+///
+/// A value is rejected when it is further than `threshold` standard
+/// deviations, estimated from the median absolute deviation, from the median
+/// of the `window` values around it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hampel {
+    pub name: String,
+    pub window: usize,
+    pub threshold: f64,
+}
+
+impl FromStr for Hampel {
+    type Err = OverlogError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = |reason: &str| OverlogError::Config(format!("Invalid Hampel filter '{}': {}", value, reason));
+        let (name, parameters) = value.split_once('=').unwrap_or((value, ""));
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(invalid("missing field name"));
+        }
+        let (window, threshold) = parameters.split_once(':').unwrap_or((parameters, ""));
+
+        let window = match window.trim() {
+            "" => 7,
+            window => window.parse::<usize>().ok().filter(|w| w % 2 == 1 && *w >= 3).ok_or_else(|| invalid("the window must be odd and at least 3"))?,
+        };
+        let threshold = match threshold.trim() {
+            "" => 3.0,
+            threshold => threshold.parse::<f64>().ok().filter(|t| t.is_finite() && *t > 0.0).ok_or_else(|| invalid("expected a positive threshold"))?,
+        };
+        Ok(Hampel { name: name.to_string(), window, threshold })
+    }
+}
+
+/// Limits beyond which positions and values are rejected
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OutlierRules {
+    /// Fastest plausible speed (m/s), both implied by consecutive positions
+    /// and as measured
+    pub max_speed: Option<f64>,
+    /// Hardest plausible change in the implied speed (m/s²)
+    pub max_acceleration: Option<f64>,
+    /// Worst acceptable horizontal dilution of precision (`gps_hdop` channel)
+    pub max_hdop: Option<f64>,
+    /// Fewest acceptable satellites (`satellites` channel)
+    pub min_satellites: Option<u32>,
+    pub hampel: Vec<Hampel>,
+    pub rejection: Rejection,
+}
+
+impl OutlierRules {
+    /// Whether no rule is set
+    pub fn is_empty(&self) -> bool {
+        self.max_speed.is_none()
+            && self.max_acceleration.is_none()
+            && self.max_hdop.is_none()
+            && self.min_satellites.is_none()
+            && self.hampel.is_empty()
+    }
+}
+
+impl TelemetryData {
+    /// Reject implausible positions and spikes, returning how many positions
+    /// and values were changed
+    ///
+    /// The changes are added to [`TelemetryMetadata::outliers`], and
+    /// interpolated values are listed in [`TelemetryPoint::derived`].
+    ///
+    /// [`TelemetryMetadata::outliers`]: crate::telemetry::TelemetryMetadata::outliers
+    pub fn reject_outliers(&mut self, rules: &OutlierRules) -> Result<usize, OverlogError> {
+        let hampel = rules
+            .hampel
+            .iter()
+            .map(|hampel| Ok((hampel.name.parse::<Field>()?, hampel)))
+            .collect::<Result<Vec<_>, OverlogError>>()?;
+
+        let mut outliers = Vec::new();
+        let mut keep = vec![true; self.points.len()];
+        for run in self.runs() {
+            let keep = &mut keep[run.clone()];
+            let points = &mut self.points[run];
+
+            let rejected = position_outliers(points, rules);
+            for &(i, (reason, measure)) in &rejected {
+                let action = match rules.rejection {
+                    Rejection::Remove => None,
+                    Rejection::Interpolate => interpolate_position(points, i, |j| rejected.iter().all(|(k, _)| *k != j)),
+                };
+                let action = action.unwrap_or_else(|| {
+                    keep[i] = false;
+                    OutlierAction::Removed
+                });
+                outliers.push(Outlier { timestamp: points[i].timestamp, name: "position".to_string(), reason, measure, action });
+            }
+
+            let mut values: Vec<(Field, usize, OutlierReason, f64)> = Vec::new();
+            if let Some(max_speed) = rules.max_speed {
+                let too_fast = points.iter().enumerate().filter_map(|(i, p)| p.speed.filter(|v| *v > max_speed).map(|v| (i, v)));
+                values.extend(too_fast.map(|(i, v)| (Field::Speed, i, OutlierReason::Speed, v)));
+            }
+            for (field, hampel) in &hampel {
+                let spikes = hampel_outliers(points, field, hampel.window, hampel.threshold);
+                for (i, deviation) in spikes {
+                    if !values.iter().any(|(f, j, ..)| f == field && *j == i) {
+                        values.push((field.clone(), i, OutlierReason::Hampel, deviation));
+                    }
+                }
+            }
+            values.retain(|(_, i, ..)| keep[*i]);
+
+            for (field, i, reason, measure) in &values {
+                let flagged = |j: usize| values.iter().any(|(f, k, ..)| f == field && *k == j);
+                let replacement = match rules.rejection {
+                    Rejection::Remove => None,
+                    Rejection::Interpolate => interpolate_value(points, *i, field, |j| keep[j] && !flagged(j)),
+                };
+                let point = &mut points[*i];
+                let action = match replacement {
+                    Some(value) => {
+                        smooth::set(field, point, value);
+                        point.derived.insert(field.name().to_string());
+                        OutlierAction::Interpolated
+                    }
+                    None => {
+                        field.clear(point);
+                        OutlierAction::Cleared
+                    }
+                };
+                outliers.push(Outlier {
+                    timestamp: point.timestamp,
+                    name: field.name().to_string(),
+                    reason: *reason,
+                    measure: *measure,
+                    action,
+                });
+            }
+        }
+
+        let mut keep = keep.into_iter();
+        self.points.retain(|_| keep.next().unwrap_or(true));
+        let count = outliers.len();
+        self.metadata.outliers.extend(outliers);
+        self.metadata.outliers.sort_by_key(|outlier| outlier.timestamp);
+        self.calculate_metadata();
+        Ok(count)
+    }
+}
+
+/// Whether the receiver reported a fix too poor to use
+fn poor_fix(point: &TelemetryPoint, rules: &OutlierRules) -> Option<(OutlierReason, f64)> {
+    let hdop = point.channel(channel::GPS_HDOP).filter(|hdop| rules.max_hdop.is_some_and(|max| *hdop > max));
+    let satellites = point
+        .channel(channel::SATELLITES)
+        .filter(|count| rules.min_satellites.is_some_and(|min| *count < min as f64));
+    hdop.map(|hdop| (OutlierReason::Hdop, hdop))
+        .or(satellites.map(|count| (OutlierReason::Satellites, count)))
+}
+
+/// Positions of a run to reject, in order
+///
+/// Speed and acceleration are implied by the move from the last accepted
+/// position, so after a jump every point is rejected until the track is
+/// plausibly reachable again. The first position is only trusted as the
+/// anchor when the track agrees with it; a bad first fix is rejected instead.
+fn position_outliers(points: &[TelemetryPoint], rules: &OutlierRules) -> Vec<(usize, (OutlierReason, f64))> {
+    let mut outliers = Vec::new();
+    let usable: Vec<usize> = (0..points.len())
+        .filter(|&i| points[i].latitude.is_some() && points[i].longitude.is_some() && poor_fix(&points[i], rules).is_none())
+        .collect();
+    // Move the anchor forward while it is implausible and the track after it is not
+    let mut start = 0;
+    while start + 2 < usable.len() {
+        let (a, b, c) = (usable[start], usable[start + 1], usable[start + 2]);
+        let Some(problem) = implausible_move(points, rules, a, b, Some(c)) else { break };
+        if implausible_move(points, rules, b, c, usable.get(start + 3).copied()).is_some() {
+            break;
+        }
+        outliers.push((a, problem));
+        start += 1;
+    }
+    let anchor = usable.get(start).copied().unwrap_or(points.len());
+
+    // Last accepted point and the speed implied on the way to it
+    let mut last: Option<(usize, Option<f64>)> = None;
+    for (i, point) in points.iter().enumerate() {
+        let Some((lat, lon)) = point.latitude.zip(point.longitude) else { continue };
+        if let Some(poor) = poor_fix(point, rules) {
+            outliers.push((i, poor));
+            continue;
+        }
+        if i < anchor {
+            continue;
+        }
+        let Some((j, last_speed)) = last else {
+            last = Some((i, None));
+            continue;
+        };
+
+        let seconds = utils::seconds_between(points[j].timestamp, point.timestamp);
+        let (last_lat, last_lon) = (points[j].latitude.unwrap_or(lat), points[j].longitude.unwrap_or(lon));
+        if seconds <= 0.0 {
+            continue;
+        }
+        let speed = geo::calculate_distance(last_lat, last_lon, lat, lon) / seconds;
+        let acceleration = last_speed.map(|last_speed| (speed - last_speed).abs() / seconds);
+        if rules.max_speed.is_some_and(|max| speed > max) {
+            outliers.push((i, (OutlierReason::Speed, speed)));
+        } else if let Some(acceleration) = acceleration.filter(|a| rules.max_acceleration.is_some_and(|max| *a > max)) {
+            outliers.push((i, (OutlierReason::Acceleration, acceleration)));
+        } else {
+            last = Some((i, Some(speed)));
+        }
+    }
+    outliers.sort_by_key(|(i, _)| *i);
+    outliers
+}
+
+/// Speed implied by the move between two positions, if time passes
+fn implied_speed(points: &[TelemetryPoint], from: usize, to: usize) -> Option<f64> {
+    let seconds = utils::seconds_between(points[from].timestamp, points[to].timestamp);
+    let distance = geo::calculate_distance(points[from].latitude?, points[from].longitude?, points[to].latitude?, points[to].longitude?);
+    (seconds > 0.0).then(|| distance / seconds)
+}
+
+/// Why the move from `a` to `b`, and on to `c`, breaks the rules
+fn implausible_move(
+    points: &[TelemetryPoint],
+    rules: &OutlierRules,
+    a: usize,
+    b: usize,
+    c: Option<usize>,
+) -> Option<(OutlierReason, f64)> {
+    let speed = implied_speed(points, a, b)?;
+    if rules.max_speed.is_some_and(|max| speed > max) {
+        return Some((OutlierReason::Speed, speed));
+    }
+    let c = c?;
+    let next_speed = implied_speed(points, b, c)?;
+    let acceleration = (next_speed - speed).abs() / utils::seconds_between(points[b].timestamp, points[c].timestamp);
+    rules
+        .max_acceleration
+        .filter(|max| acceleration > *max)
+        .map(|_| (OutlierReason::Acceleration, acceleration))
+}
+
+/// Move a rejected position onto the line between its accepted neighbours
+fn interpolate_position(
+    points: &mut [TelemetryPoint],
+    i: usize,
+    accepted: impl Fn(usize) -> bool,
+) -> Option<OutlierAction> {
+    let positioned = |j: &usize| points[*j].latitude.is_some() && points[*j].longitude.is_some() && accepted(*j);
+    let before = (0..i).rev().find(positioned)?;
+    let after = (i + 1..points.len()).find(positioned)?;
+    let ratio = utils::seconds_between(points[before].timestamp, points[i].timestamp)
+        / utils::seconds_between(points[before].timestamp, points[after].timestamp);
+    let lerp = |a: Option<f64>, b: Option<f64>| Some(a? + (b? - a?) * ratio);
+    let latitude = lerp(points[before].latitude, points[after].latitude);
+    let longitude = lerp(points[before].longitude, points[after].longitude);
+
+    let point = &mut points[i];
+    point.latitude = latitude;
+    point.longitude = longitude;
+    point.derived.extend(["latitude".to_string(), "longitude".to_string()]);
+    Some(OutlierAction::Interpolated)
+}
+
+/// Interpolate a value in time from the nearest accepted values around it,
+/// the short way round for headings
+fn interpolate_value(points: &[TelemetryPoint], i: usize, field: &Field, accepted: impl Fn(usize) -> bool) -> Option<f64> {
+    let usable = |j: &usize| field.get(&points[*j]).is_some() && accepted(*j);
+    let before = (0..i).rev().find(usable)?;
+    let after = (i + 1..points.len()).find(usable)?;
+    let (a, b) = (field.get(&points[before])?, field.get(&points[after])?);
+    let ratio = utils::seconds_between(points[before].timestamp, points[i].timestamp)
+        / utils::seconds_between(points[before].timestamp, points[after].timestamp);
+    if *field == Field::Heading {
+        let step = (b - a + 180.0).rem_euclid(360.0) - 180.0;
+        return Some((a + step * ratio).rem_euclid(360.0));
+    }
+    Some(a + (b - a) * ratio)
+}
+
+/// Indices and distances from the local median of the spikes in a field,
+/// looking at each stretch of consecutive values separately
+fn hampel_outliers(points: &[TelemetryPoint], field: &Field, window: usize, threshold: f64) -> Vec<(usize, f64)> {
+    let mut outliers = Vec::new();
+    let mut start = 0;
+    while start < points.len() {
+        if field.get(&points[start]).is_none() {
+            start += 1;
+            continue;
+        }
+        let end = (start..points.len()).find(|&i| field.get(&points[i]).is_none()).unwrap_or(points.len());
+        let mut values: Vec<f64> = points[start..end].iter().filter_map(|p| field.get(p)).collect();
+        if *field == Field::Heading {
+            smooth::unwrap_degrees(&mut values);
+        }
+
+        let half = window / 2;
+        for (i, value) in values.iter().enumerate() {
+            let around = &values[i.saturating_sub(half)..(i + half + 1).min(values.len())];
+            let center = median(around.to_vec());
+            let deviations: Vec<f64> = around.iter().map(|v| (v - center).abs()).collect();
+            // Mostly equal values have no median deviation, so fall back to the mean
+            let spread = match MAD_SCALE * median(deviations.clone()) {
+                spread if spread > 0.0 => spread,
+                _ => MEAN_SCALE * deviations.iter().sum::<f64>() / deviations.len() as f64,
+            };
+            let deviation = (value - center).abs();
+            if spread > 0.0 && deviation > threshold * spread {
+                outliers.push((start + i, deviation));
+            }
+        }
+        start = end;
+    }
+    outliers
+}
+
+fn median(mut values: Vec<f64>) -> f64 {
+    values.sort_by(f64::total_cmp);
+    (values[(values.len() - 1) / 2] + values[values.len() / 2]) / 2.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, DEGREES_PER_METER};
+
+    /// Points 1 s apart heading north at 10 m/s
+    fn track(count: usize) -> TelemetryData {
+        testing::track(1.0, (0..count).map(|i| TelemetryPoint {
+            latitude: Some(10.0 * i as f64 * DEGREES_PER_METER),
+            longitude: Some(0.0),
+            speed: Some(10.0),
+            ..Default::default()
+        }))
+    }
+
+    #[test]
+    fn test_rule_parsing() {
+        assert_eq!(
+            "speed".parse::<Hampel>().unwrap(),
+            Hampel { name: "speed".to_string(), window: 7, threshold: 3.0 }
+        );
+        assert_eq!(
+            "rpm=9:2.5".parse::<Hampel>().unwrap(),
+            Hampel { name: "rpm".to_string(), window: 9, threshold: 2.5 }
+        );
+        assert!("speed=4".parse::<Hampel>().is_err());
+        assert!("speed=7:-1".parse::<Hampel>().is_err());
+        assert_eq!("remove".parse::<Rejection>().unwrap(), Rejection::Remove);
+        assert!("ignore".parse::<Rejection>().is_err());
+    }
+
+    #[test]
+    fn test_position_jump_is_interpolated() {
+        let mut telemetry = track(10);
+        // Two points 500 m off to the east
+        for point in &mut telemetry.points[4..6] {
+            point.longitude = Some(500.0 / 111_195.0);
+        }
+        let rules = OutlierRules { max_speed: Some(50.0), ..Default::default() };
+        assert_eq!(telemetry.reject_outliers(&rules).unwrap(), 2);
+
+        assert_eq!(telemetry.points.len(), 10);
+        assert!(telemetry.points[4].longitude.unwrap().abs() < 1e-9);
+        assert!(telemetry.points[5].is_derived("latitude"));
+        assert!((telemetry.metadata.total_distance.unwrap() - 90.0).abs() < 0.5);
+
+        let outlier = &telemetry.metadata.outliers[0];
+        assert_eq!(outlier.name, "position");
+        assert_eq!(outlier.reason, OutlierReason::Speed);
+        assert_eq!(outlier.action, OutlierAction::Interpolated);
+        assert!(outlier.measure > 400.0);
+    }
+
+    #[test]
+    fn test_bad_first_fix_is_rejected() {
+        let mut telemetry = track(20);
+        telemetry.points[0].longitude = Some(500.0 / 111_195.0);
+        let rules = OutlierRules { max_speed: Some(50.0), ..Default::default() };
+        assert_eq!(telemetry.reject_outliers(&rules).unwrap(), 1);
+
+        // Nothing before the glitch to interpolate from
+        assert_eq!(telemetry.points.len(), 19);
+        assert!(telemetry.points.iter().all(|p| p.longitude == Some(0.0) && p.derived.is_empty()));
+        let outlier = &telemetry.metadata.outliers[0];
+        assert_eq!(outlier.reason, OutlierReason::Speed);
+        assert_eq!(outlier.action, OutlierAction::Removed);
+
+        // The same with only an acceleration limit
+        let mut telemetry = track(20);
+        telemetry.points[0].longitude = Some(500.0 / 111_195.0);
+        let rules = OutlierRules { max_acceleration: Some(20.0), ..Default::default() };
+        assert_eq!(telemetry.reject_outliers(&rules).unwrap(), 1);
+        assert_eq!(telemetry.points.len(), 19);
+        assert_eq!(telemetry.metadata.outliers[0].reason, OutlierReason::Acceleration);
+    }
+
+    #[test]
+    fn test_poor_fixes_are_removed() {
+        let mut telemetry = track(5);
+        telemetry.points[2].set_channel(channel::GPS_HDOP, 8.0);
+        telemetry.points[3].set_channel(channel::SATELLITES, 3.0);
+        let rules = OutlierRules {
+            max_hdop: Some(5.0),
+            min_satellites: Some(4),
+            rejection: Rejection::Remove,
+            ..Default::default()
+        };
+        telemetry.reject_outliers(&rules).unwrap();
+
+        assert_eq!(telemetry.points.len(), 3);
+        let reasons: Vec<OutlierReason> = telemetry.metadata.outliers.iter().map(|o| o.reason).collect();
+        assert_eq!(reasons, vec![OutlierReason::Hdop, OutlierReason::Satellites]);
+        assert!(telemetry.metadata.outliers.iter().all(|o| o.action == OutlierAction::Removed));
+    }
+
+    #[test]
+    fn test_speed_spikes() {
+        let mut telemetry = track(9);
+        for (i, point) in telemetry.points.iter_mut().enumerate() {
+            point.speed = Some(10.0 + (i % 2) as f64 * 0.2);
+        }
+        telemetry.points[4].speed = Some(60.0);
+        telemetry.points[8].speed = Some(80.0);
+        let rules = OutlierRules {
+            max_speed: Some(70.0),
+            hampel: vec!["speed".parse().unwrap()],
+            ..Default::default()
+        };
+        telemetry.reject_outliers(&rules).unwrap();
+
+        // The spike is interpolated, the last value has no later neighbour
+        assert!((telemetry.points[4].speed.unwrap() - 10.2).abs() < 1e-9);
+        assert_eq!(telemetry.points[8].speed, None);
+        assert_eq!(telemetry.metadata.max_speed, Some(10.2));
+        let actions: Vec<(OutlierReason, OutlierAction)> =
+            telemetry.metadata.outliers.iter().map(|o| (o.reason, o.action)).collect();
+        assert!(actions.contains(&(OutlierReason::Hampel, OutlierAction::Interpolated)));
+        assert!(actions.contains(&(OutlierReason::Speed, OutlierAction::Cleared)));
+    }
+}
@@ -0,0 +1,158 @@
+//! Resampling to an evenly spaced series
+//!
+//! Every field and channel is interpolated from its own samples, found the
+//! same way as for [`interpolate_at_time_with`], so channels logged at
+//! different rates line up on one grid. Samples are never extrapolated past
+//! the first or last value of a run, and values are only carried across a
+//! segment break when [`Gaps`] says so, and for no longer than the maximum
+//! gap.
+//!
+//! [`interpolate_at_time_with`]: TelemetryData::interpolate_at_time_with
+
+use std::ops::Range;
+use crate::{
+    error::OverlogError,
+    interpolation::{Gaps, InterpolationPolicy, Neighbours, Values},
+    telemetry::{TelemetryData, TelemetryPoint},
+    utils,
+};
+
+impl TelemetryData {
+    /// Evenly spaced copy of the log at `rate_hz`, starting at its first point
+    ///
+    /// Laps, events, segments, waypoints and routes are kept, and values
+    /// interpolated from derived samples stay flagged as derived.
+    pub fn resample(&self, rate_hz: f64, policy: &InterpolationPolicy) -> Result<TelemetryData, OverlogError> {
+        if !(rate_hz.is_finite() && rate_hz > 0.0) {
+            return Err(OverlogError::InvalidInput(format!("Invalid sample rate {} Hz", rate_hz)));
+        }
+        let mut resampled = TelemetryData {
+            points: Vec::new(),
+            metadata: self.metadata.clone(),
+            laps: self.laps.clone(),
+            events: self.events.clone(),
+            segments: self.segments.clone(),
+            waypoints: self.waypoints.clone(),
+            routes: self.routes.clone(),
+        };
+        let (Some(first), Some(last)) = (self.points.first(), self.points.last()) else {
+            return Ok(resampled);
+        };
+
+        let runs: Vec<Range<usize>> = match policy.gaps {
+            Gaps::Bridge => std::iter::once(0..self.points.len()).collect(),
+            _ => self.runs(),
+        };
+        let mut index = 0;
+        let mut values = Values::of(&self.points[runs[0].clone()]);
+        let samples = (utils::seconds_between(first.timestamp, last.timestamp) * rate_hz + 1e-6).floor() as usize;
+        for k in 0..=samples {
+            let timestamp = utils::add_seconds(first.timestamp, k as f64 / rate_hz);
+            if runs.get(index + 1).is_some_and(|next| self.points[next.start].timestamp <= timestamp) {
+                index = runs.iter().rposition(|r| self.points[r.start].timestamp <= timestamp).unwrap_or(index);
+                values = Values::of(&self.points[runs[index].clone()]);
+            }
+
+            let end = self.points[runs[index].end - 1].timestamp;
+            let at = match policy.gaps {
+                _ if timestamp <= end => timestamp,
+                Gaps::Hold if policy.within_gap(utils::seconds_between(end, timestamp)) => end,
+                _ => continue,
+            };
+            let point = Neighbours::new(self, at, policy).point(&values);
+            resampled.points.push(TelemetryPoint { timestamp, ..point });
+        }
+        resampled.calculate_metadata();
+        Ok(resampled)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{interpolation::Interpolation, telemetry::{ChannelValue, Segment}, testing};
+
+    /// Points at 0, 0.3, 1.0 and 1.5 s with speed = 10 * time and a gear
+    fn telemetry() -> TelemetryData {
+        testing::log([0, 300, 1000, 1500].iter().enumerate().map(|(i, ms)| {
+            let mut point = TelemetryPoint {
+                timestamp: testing::at(*ms as f64 / 1000.0),
+                speed: Some(*ms as f64 / 100.0),
+                heading: Some([350.0, 355.0, 5.0, 10.0][i]),
+                ..Default::default()
+            };
+            point.set_channel("gear", (i + 1) as f64);
+            point.set_channel_with_unit("mode", if i < 2 { "auto" } else { "manual" }, None);
+            point
+        }))
+    }
+
+    #[test]
+    fn test_resample_is_evenly_spaced() {
+        let data = telemetry();
+        let policy = InterpolationPolicy::default().with_rules(["gear=hold".parse().unwrap()]);
+        let resampled = data.resample(4.0, &policy).unwrap();
+
+        assert_eq!(resampled.points.len(), 7);
+        for (k, point) in resampled.points.iter().enumerate() {
+            assert_eq!((point.timestamp - data.points[0].timestamp).num_milliseconds(), 250 * k as i64);
+            assert!((point.speed.unwrap() - 2.5 * k as f64).abs() < 1e-9);
+        }
+        let point = &resampled.points[3];
+        assert_eq!(point.channel("gear"), Some(2.0));
+        // Headings turn the short way through north
+        assert!((point.heading.unwrap() - (355.0 + 10.0 * 0.45 / 0.7 - 360.0)).abs() < 1e-9);
+        assert_eq!(point.channels["mode"].value, ChannelValue::Text("manual".to_string()));
+        assert_eq!(resampled.metadata.max_speed, Some(15.0));
+    }
+
+    #[test]
+    fn test_resample_methods() {
+        let data = telemetry();
+        for method in [Interpolation::Cubic, Interpolation::Nearest, Interpolation::HoldLast] {
+            let resampled = data.resample(10.0, &method.into()).unwrap();
+            let speed = resampled.points[2].speed.unwrap();
+            let expected = match method {
+                // A straight line stays straight
+                Interpolation::Cubic => 2.0,
+                Interpolation::Nearest => 3.0,
+                _ => 0.0,
+            };
+            assert!((speed - expected).abs() < 1e-9, "{} gave {}", method, speed);
+        }
+
+        let policy = InterpolationPolicy { max_gap: Some(0.5), ..Default::default() };
+        let resampled = data.resample(10.0, &policy).unwrap();
+        assert_eq!(resampled.points[5].speed, None);
+        assert_eq!(resampled.points[10].speed, Some(10.0));
+    }
+
+    #[test]
+    fn test_resample_gaps() {
+        let mut data = telemetry();
+        data.segments = vec![
+            Segment { track: None, start_time: data.points[0].timestamp, end_time: data.points[1].timestamp },
+            Segment { track: None, start_time: data.points[2].timestamp, end_time: data.points[3].timestamp },
+        ];
+
+        let held = data.resample(10.0, &InterpolationPolicy::default()).unwrap();
+        assert_eq!(held.points.len(), 16);
+        assert_eq!(held.points[6].speed, Some(3.0));
+
+        // Held no longer than the maximum gap
+        let policy = InterpolationPolicy { max_gap: Some(0.25), ..Default::default() };
+        let stale = data.resample(10.0, &policy).unwrap();
+        assert_eq!(stale.points.len(), 12);
+        assert_eq!(stale.points[5].speed, Some(3.0));
+        assert_eq!((stale.points[6].timestamp - data.points[0].timestamp).num_milliseconds(), 1000);
+
+        let skipped = data.resample(10.0, &InterpolationPolicy { gaps: Gaps::Skip, ..Default::default() }).unwrap();
+        assert_eq!(skipped.points.len(), 10);
+
+        let bridged = data.resample(10.0, &InterpolationPolicy { gaps: Gaps::Bridge, ..Default::default() }).unwrap();
+        assert!((bridged.points[6].speed.unwrap() - 6.0).abs() < 1e-9);
+        assert_eq!(bridged.segments.len(), 2);
+
+        assert!(data.resample(0.0, &InterpolationPolicy::default()).is_err());
+    }
+}
@@ -0,0 +1,338 @@
+//! Cutting logs down to a time range, a stretch of track or a lap
+//!
+//! A slice starts and ends exactly at its bounds: when a bound falls between
+//! two points of a run, a point is interpolated there. Laps, events,
+//! segments and outliers are cut to the slice, timed laps are kept when
+//! they lie within it, and the metadata of every piece is computed afresh.
+
+use std::{fmt, str::FromStr};
+use chrono::{DateTime, Utc};
+use crate::{
+    error::OverlogError,
+    interpolation::{Gaps, InterpolationPolicy},
+    telemetry::{Lap, Segment, TelemetryData},
+    utils,
+};
+
+/// The part of a log to keep; open ends run to the start or end of the log
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Slice {
+    /// Between two absolute times
+    Time { start: Option<DateTime<Utc>>, end: Option<DateTime<Utc>> },
+    /// Between two offsets in seconds from the first point
+    Offset { start: Option<f64>, end: Option<f64> },
+    /// Between two distances in meters along the track from the first position
+    Distance { start: Option<f64>, end: Option<f64> },
+    /// A lap, by 0-based index: one of the device's laps or, when the log
+    /// has none, one timed at the gates
+    Lap(usize),
+}
+
+impl FromStr for Slice {
+    type Err = OverlogError;
+
+    /// `time:START..END` (RFC 3339), `offset:START..END` (seconds),
+    /// `distance:START..END` (meters) or `lap:N`; either end of a range may
+    /// be left out
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || OverlogError::Config(format!(
+            "Invalid slice '{}', expected time:START..END, offset:START..END, distance:START..END or lap:N",
+            value
+        ));
+        let (kind, argument) = value.split_once(':').ok_or_else(invalid)?;
+        let argument = argument.trim();
+        let range = || argument.split_once("..").map(|(start, end)| (start.trim(), end.trim())).ok_or_else(invalid);
+        let bound = |text: &str| match text {
+            "" => Ok(None),
+            text => text.parse::<f64>().ok().filter(|n| n.is_finite() && *n >= 0.0).map(Some).ok_or_else(invalid),
+        };
+        match kind.trim().to_ascii_lowercase().as_str() {
+            "time" => {
+                let (start, end) = range()?;
+                let time = |text: &str| match text {
+                    "" => Ok(None),
+                    text => text.parse::<DateTime<Utc>>().map(Some).map_err(|_| invalid()),
+                };
+                Ok(Slice::Time { start: time(start)?, end: time(end)? })
+            }
+            "offset" => {
+                let (start, end) = range()?;
+                Ok(Slice::Offset { start: bound(start)?, end: bound(end)? })
+            }
+            "distance" => {
+                let (start, end) = range()?;
+                Ok(Slice::Distance { start: bound(start)?, end: bound(end)? })
+            }
+            "lap" => argument.parse().map(Slice::Lap).map_err(|_| invalid()),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl fmt::Display for Slice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fn bound<T: fmt::Display>(value: &Option<T>) -> String {
+            value.as_ref().map(T::to_string).unwrap_or_default()
+        }
+        match self {
+            Slice::Time { start, end } => {
+                let time = |t: &Option<DateTime<Utc>>| t.map(|t| t.to_rfc3339()).unwrap_or_default();
+                write!(f, "time:{}..{}", time(start), time(end))
+            }
+            Slice::Offset { start, end } => write!(f, "offset:{}..{}", bound(start), bound(end)),
+            Slice::Distance { start, end } => write!(f, "distance:{}..{}", bound(start), bound(end)),
+            Slice::Lap(index) => write!(f, "lap:{}", index),
+        }
+    }
+}
+
+impl TelemetryData {
+    /// The part of the log a slice selects, with its own metadata
+    pub fn slice(&self, slice: &Slice) -> Result<TelemetryData, OverlogError> {
+        let (Some(first), Some(last)) = (self.points.first(), self.points.last()) else {
+            return Err(OverlogError::InvalidInput("The log has no points to slice".to_string()));
+        };
+        let (start, end) = match *slice {
+            Slice::Time { start, end } => (start.unwrap_or(first.timestamp), end.unwrap_or(last.timestamp)),
+            Slice::Offset { start, end } => (
+                utils::add_seconds(first.timestamp, start.unwrap_or(0.0)),
+                end.map_or(last.timestamp, |end| utils::add_seconds(first.timestamp, end)),
+            ),
+            Slice::Distance { start, end } => self.distance_range(start.unwrap_or(0.0), end)?,
+            Slice::Lap(index) => {
+                let timed = &self.metadata.lap_times;
+                let count = if self.laps.is_empty() { timed.len() } else { self.laps.len() };
+                let missing = || OverlogError::InvalidInput(format!("There is no lap {}, the log has {} laps", index, count));
+                if self.laps.is_empty() {
+                    let lap = timed.get(index).ok_or_else(missing)?;
+                    (lap.start_time, lap.end_time)
+                } else {
+                    let lap = self.laps.get(index).ok_or_else(missing)?;
+                    let end = lap.end_time.or_else(|| self.laps.get(index + 1).map(|next| next.start_time));
+                    (lap.start_time, end.unwrap_or(last.timestamp))
+                }
+            }
+        };
+
+        let piece = match start <= end {
+            true => self.crop(start, end),
+            false => TelemetryData::new(),
+        };
+        if piece.points.is_empty() {
+            return Err(OverlogError::InvalidInput(format!("No points in {}", slice)));
+        }
+        Ok(piece)
+    }
+
+    /// Split the log wherever two points are more than `seconds` apart
+    pub fn split_at_gaps(&self, seconds: f64) -> Vec<TelemetryData> {
+        let mut pieces = Vec::new();
+        let mut start = 0;
+        for i in 1..=self.points.len() {
+            if i == self.points.len() || utils::seconds_between(self.points[i - 1].timestamp, self.points[i].timestamp) > seconds {
+                pieces.push(self.crop(self.points[start].timestamp, self.points[i - 1].timestamp));
+                start = i;
+            }
+        }
+        pieces
+    }
+
+    /// Times at which the track has covered `start` and `end` meters, the
+    /// end of the log when it is shorter than `end`
+    fn distance_range(&self, start: f64, end: Option<f64>) -> Result<(DateTime<Utc>, DateTime<Utc>), OverlogError> {
+        // Along the track, not counting the jump across a segment break
+        let travelled: Vec<(DateTime<Utc>, f64)> = self
+            .points
+            .iter()
+            .zip(self.travelled())
+            .filter(|(point, _)| point.latitude.is_some() && point.longitude.is_some())
+            .map(|(point, distance)| (point.timestamp, distance))
+            .collect();
+        let Some(&(last, total)) = travelled.last() else {
+            return Err(OverlogError::InvalidInput("The log has no positions to measure distance along".to_string()));
+        };
+
+        // Interpolated in time between the positions either side
+        let time_at = |distance: f64| {
+            let i = travelled.partition_point(|(_, d)| *d < distance);
+            match i {
+                0 => Some(travelled[0].0),
+                i if i == travelled.len() => None,
+                i => {
+                    let ((t1, d1), (t2, d2)) = (travelled[i - 1], travelled[i]);
+                    let ratio = (distance - d1) / (d2 - d1);
+                    Some(utils::add_seconds(t1, utils::seconds_between(t1, t2) * ratio))
+                }
+            }
+        };
+        let start = time_at(start).ok_or_else(|| {
+            OverlogError::InvalidInput(format!("The track is only {:.0} m long, shorter than {} m", total, start))
+        })?;
+        Ok((start, end.and_then(time_at).unwrap_or(last)))
+    }
+
+    /// The points from `start` to `end`, with points interpolated at bounds
+    /// that fall between two points
+    fn crop(&self, start: DateTime<Utc>, end: DateTime<Utc>) -> TelemetryData {
+        let within = |t: DateTime<Utc>| t >= start && t <= end;
+        let mut piece = TelemetryData {
+            points: self.points.iter().filter(|p| within(p.timestamp)).cloned().collect(),
+            metadata: self.metadata.clone(),
+            laps: Vec::new(),
+            events: self.events.iter().filter(|e| within(e.timestamp)).cloned().collect(),
+            segments: Vec::new(),
+            waypoints: self.waypoints.clone(),
+            routes: self.routes.clone(),
+        };
+        let policy = InterpolationPolicy { gaps: Gaps::Skip, ..Default::default() };
+        for bound in [start, end] {
+            if self.get_point_at_time(bound).is_none() {
+                piece.points.extend(self.interpolate_at_time_with(bound, &policy));
+            }
+        }
+        piece.points.sort_by_key(|p| p.timestamp);
+
+        // Laps cut short no longer match the device's time and distance
+        piece.laps = self
+            .laps
+            .iter()
+            .enumerate()
+            .filter_map(|(i, lap)| {
+                let lap_end = lap.end_time.or_else(|| self.laps.get(i + 1).map(|next| next.start_time));
+                if lap.start_time > end || lap_end.is_some_and(|t| t <= start) {
+                    return None;
+                }
+                if lap.start_time >= start && lap_end.is_none_or(|t| t <= end) {
+                    return Some(lap.clone());
+                }
+                Some(Lap {
+                    start_time: lap.start_time.max(start),
+                    end_time: Some(lap_end.map_or(end, |t| t.min(end))),
+                    total_time: None,
+                    distance: None,
+                })
+            })
+            .collect();
+        piece.segments = self
+            .segments
+            .iter()
+            .filter(|s| s.start_time <= end && s.end_time >= start)
+            .map(|s| Segment { start_time: s.start_time.max(start), end_time: s.end_time.min(end), ..s.clone() })
+            .collect();
+
+        let metadata = &mut piece.metadata;
+        metadata.outliers.retain(|o| within(o.timestamp));
+        metadata.lap_times.retain(|lap| within(lap.start_time) && within(lap.end_time));
+        metadata.best_lap = metadata.lap_times.iter().min_by(|a, b| a.time.total_cmp(&b.time)).map(|lap| lap.number);
+        metadata.times.synthesized.retain_mut(|run| {
+            run.first = run.first.max(start);
+            run.last = run.last.min(end);
+            run.count = piece.points.iter().filter(|p| p.timestamp >= run.first && p.timestamp <= run.last).count();
+            run.first <= run.last && run.count > 0
+        });
+        piece.calculate_metadata();
+        piece
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+    use crate::{geo, telemetry::{LapTime, TelemetryPoint}, testing};
+
+    /// Points every second, moving 0.001° of longitude along the equator
+    /// (about 111 m) with speed = 10 * index, and a 60 s pause after 4 s
+    fn telemetry() -> TelemetryData {
+        let mut telemetry = testing::log((0..10).map(|i| TelemetryPoint {
+            timestamp: testing::at(if i < 5 { i } else { i + 60 } as f64),
+            latitude: Some(0.0),
+            longitude: Some(i as f64 * 0.001),
+            speed: Some(10.0 * i as f64),
+            ..Default::default()
+        }));
+        telemetry.laps = vec![
+            Lap { start_time: testing::at(0.0), end_time: None, total_time: Some(64.0), distance: None },
+            Lap { start_time: testing::at(65.0), end_time: None, total_time: None, distance: None },
+        ];
+        telemetry
+    }
+
+    #[test]
+    fn test_slice_parsing() {
+        let slice: Slice = "offset:1.5..".parse().unwrap();
+        assert_eq!(slice, Slice::Offset { start: Some(1.5), end: None });
+        assert_eq!(slice.to_string(), "offset:1.5..");
+        let slice: Slice = "time:2024-01-15T10:00:01Z..2024-01-15T10:00:03Z".parse().unwrap();
+        assert!(matches!(slice, Slice::Time { start: Some(_), end: Some(_) }));
+        assert_eq!("lap:2".parse::<Slice>().unwrap(), Slice::Lap(2));
+        assert!("offset:5".parse::<Slice>().is_err());
+        assert!("distance:-1..".parse::<Slice>().is_err());
+    }
+
+    #[test]
+    fn test_slice_by_offset_and_distance() {
+        let data = telemetry();
+        let piece = data.slice(&Slice::Offset { start: Some(0.5), end: Some(2.0) }).unwrap();
+        assert_eq!(piece.points.len(), 3);
+        assert!((piece.points[0].speed.unwrap() - 5.0).abs() < 1e-9);
+        assert_eq!(piece.metadata.duration, Some(1.5));
+        assert_eq!(piece.metadata.max_speed, Some(20.0));
+        // The first lap is cut short
+        assert_eq!(piece.laps.len(), 1);
+        assert_eq!(piece.laps[0].total_time, None);
+
+        let step = geo::calculate_distance(0.0, 0.0, 0.0, 0.001);
+        let piece = data.slice(&Slice::Distance { start: Some(step * 1.5), end: Some(step * 2.5) }).unwrap();
+        assert_eq!(piece.points.len(), 3);
+        assert!((piece.points[0].speed.unwrap() - 15.0).abs() < 1e-6);
+        assert!((piece.metadata.total_distance.unwrap() - step).abs() < 1e-3);
+
+        assert!(data.slice(&Slice::Distance { start: Some(1e6), end: None }).is_err());
+
+        // The jump across a segment break isn't travelled
+        let mut jumped = data.clone();
+        for point in &mut jumped.points[5..] {
+            point.longitude = point.longitude.map(|lon| lon + 1.0);
+        }
+        jumped.segments = vec![
+            Segment { track: None, start_time: data.points[0].timestamp, end_time: data.points[4].timestamp },
+            Segment { track: None, start_time: data.points[5].timestamp, end_time: data.points[9].timestamp },
+        ];
+        let piece = jumped.slice(&Slice::Distance { start: Some(step * 4.5), end: None }).unwrap();
+        assert_eq!(utils::seconds_between(data.points[0].timestamp, piece.points[0].timestamp), 65.5);
+        assert!(data.slice(&Slice::Offset { start: Some(100.0), end: None }).is_err());
+    }
+
+    #[test]
+    fn test_slice_by_lap_and_split_at_gaps() {
+        let data = telemetry();
+        let lap = data.slice(&Slice::Lap(1)).unwrap();
+        assert_eq!(lap.points.len(), 5);
+        assert_eq!(lap.laps.len(), 1);
+        assert!(data.slice(&Slice::Lap(2)).is_err());
+
+        // Without device laps, the laps timed at the gates
+        let mut timed = data.clone();
+        timed.laps.clear();
+        timed.metadata.lap_times = vec![LapTime {
+            number: 1,
+            start_time: data.points[1].timestamp,
+            end_time: data.points[3].timestamp,
+            time: 2.0,
+            sectors: Vec::new(),
+            distance: 222.0,
+        }];
+        let lap = timed.slice(&Slice::Lap(0)).unwrap();
+        assert_eq!(lap.points.len(), 3);
+        assert_eq!(lap.metadata.best_lap, Some(1));
+        assert!(timed.slice(&Slice::Lap(1)).is_err());
+
+        let pieces = data.split_at_gaps(30.0);
+        assert_eq!(pieces.len(), 2);
+        assert_eq!(pieces[0].points.len(), 5);
+        assert_eq!(pieces[1].metadata.duration, Some(4.0));
+        assert_eq!(pieces[1].metadata.max_speed, Some(90.0));
+        assert_eq!(data.split_at_gaps(61.0).len(), 1);
+    }
+}
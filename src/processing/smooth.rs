@@ -0,0 +1,401 @@
+//! Smoothing filters for noisy fields and channels
+//!
+//! Filters run separately over every stretch of consecutive values within a
+//! run, so they never blur across a segment break or a hole in a channel.
+//! Headings are unwrapped before filtering so that 359° and 1° average to 0°.
+
+use std::{f64::consts::PI, fmt, str::FromStr};
+use crate::{
+    error::OverlogError,
+    formats::columns::Field,
+    telemetry::{ChannelValue, TelemetryData, TelemetryPoint},
+    utils,
+};
+
+/// A smoothing filter
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Filter {
+    /// Centered mean over `window` samples
+    MovingAverage(usize),
+    /// Exponential smoothing with factor `alpha` (0-1, smaller is smoother)
+    Exponential(f64),
+    /// Local polynomial fit of `order` over `window` samples, which keeps peaks
+    SavitzkyGolay { window: usize, order: usize },
+    /// Zero-phase second-order Butterworth low-pass, run forwards and backwards
+    Butterworth { cutoff_hz: f64 },
+}
+
+impl FromStr for Filter {
+    type Err = OverlogError;
+
+    /// `ma:WINDOW`, `ema:ALPHA`, `sg:WINDOW[:ORDER]` or `butter:CUTOFF_HZ`
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = |reason: &str| OverlogError::Config(format!("Invalid filter '{}': {}", value, reason));
+        let mut parts = value.split(':').map(str::trim);
+        let kind = parts.next().unwrap_or_default().to_ascii_lowercase();
+        let args: Vec<&str> = parts.collect();
+        let arg = |index: usize| -> Result<f64, OverlogError> {
+            let arg = args.get(index).ok_or_else(|| invalid("missing parameter"))?;
+            arg.parse::<f64>().ok().filter(|n| n.is_finite() && *n > 0.0).ok_or_else(|| invalid("expected a positive number"))
+        };
+        let count = |index: usize| -> Result<usize, OverlogError> {
+            let n = arg(index)?;
+            if n.fract() == 0.0 { Ok(n as usize) } else { Err(invalid("expected a whole number")) }
+        };
+
+        let filter = match kind.as_str() {
+            "ma" | "mean" | "moving-average" => Filter::MovingAverage(count(0)?),
+            "ema" | "exp" | "exponential" => {
+                let alpha = arg(0)?;
+                if alpha > 1.0 {
+                    return Err(invalid("alpha must be between 0 and 1"));
+                }
+                Filter::Exponential(alpha)
+            }
+            "sg" | "savgol" | "savitzky-golay" => {
+                let window = count(0)?;
+                let order = if args.len() > 1 { count(1)? } else { 2 };
+                if window % 2 == 0 || window <= order {
+                    return Err(invalid("the window must be odd and longer than the order"));
+                }
+                Filter::SavitzkyGolay { window, order }
+            }
+            "butter" | "butterworth" | "lowpass" => Filter::Butterworth { cutoff_hz: arg(0)? },
+            _ => return Err(invalid("expected ma, ema, sg or butter")),
+        };
+        Ok(filter)
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Filter::MovingAverage(window) => write!(f, "ma:{}", window),
+            Filter::Exponential(alpha) => write!(f, "ema:{}", alpha),
+            Filter::SavitzkyGolay { window, order } => write!(f, "sg:{}:{}", window, order),
+            Filter::Butterworth { cutoff_hz } => write!(f, "butter:{}", cutoff_hz),
+        }
+    }
+}
+
+/// A filter for one field or channel, written `NAME=FILTER` (`speed=sg:7`)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Smoothing {
+    pub name: String,
+    pub filter: Filter,
+}
+
+impl FromStr for Smoothing {
+    type Err = OverlogError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (name, filter) = value
+            .split_once('=')
+            .ok_or_else(|| OverlogError::Config(format!("Invalid smoothing '{}', expected NAME=FILTER", value)))?;
+        Ok(Smoothing {
+            name: name.trim().to_string(),
+            filter: filter.parse()?,
+        })
+    }
+}
+
+impl TelemetryData {
+    /// Smooth a field or numeric channel in place
+    pub fn smooth(&mut self, name: &str, filter: &Filter) -> Result<(), OverlogError> {
+        let field: Field = name.parse()?;
+        if !self.points.is_empty() && self.points.iter().all(|p| field.get(p).is_none()) {
+            return Err(OverlogError::InvalidInput(format!("No numeric '{}' values to smooth", name)));
+        }
+        let angle = field == Field::Heading;
+
+        for run in self.runs() {
+            let points = &mut self.points[run];
+            let mut start = 0;
+            while start < points.len() {
+                if field.get(&points[start]).is_none() {
+                    start += 1;
+                    continue;
+                }
+                let end = (start..points.len()).find(|&i| field.get(&points[i]).is_none()).unwrap_or(points.len());
+                let stretch = &mut points[start..end];
+
+                let times: Vec<f64> = stretch
+                    .iter()
+                    .map(|p| utils::seconds_between(stretch[0].timestamp, p.timestamp))
+                    .collect();
+                let mut values: Vec<f64> = stretch.iter().filter_map(|p| field.get(p)).collect();
+                if angle {
+                    unwrap_degrees(&mut values);
+                }
+                let mut smoothed = apply(filter, &times, &values);
+                if angle {
+                    smoothed.iter_mut().for_each(|v| *v = v.rem_euclid(360.0));
+                }
+                for (point, value) in stretch.iter_mut().zip(smoothed) {
+                    set(&field, point, value);
+                }
+                start = end;
+            }
+        }
+        self.calculate_metadata();
+        Ok(())
+    }
+}
+
+/// Replace a value, keeping the unit of a channel
+pub(super) fn set(field: &Field, point: &mut TelemetryPoint, value: f64) {
+    match field {
+        Field::Channel(name) => {
+            if let Some(channel) = point.channels.get_mut(name) {
+                channel.value = ChannelValue::Number(value);
+            }
+        }
+        field => field.set(point, value),
+    }
+}
+
+/// Remove the 360° jumps from a series of angles
+pub(super) fn unwrap_degrees(values: &mut [f64]) {
+    for i in 1..values.len() {
+        let step = (values[i] - values[i - 1] + 180.0).rem_euclid(360.0) - 180.0;
+        values[i] = values[i - 1] + step;
+    }
+}
+
+fn apply(filter: &Filter, times: &[f64], values: &[f64]) -> Vec<f64> {
+    match *filter {
+        Filter::MovingAverage(window) => moving_average(values, window),
+        Filter::Exponential(alpha) => exponential(values, alpha),
+        Filter::SavitzkyGolay { window, order } => savitzky_golay(times, values, window, order),
+        Filter::Butterworth { cutoff_hz } => butterworth(times, values, cutoff_hz),
+    }
+}
+
+fn moving_average(values: &[f64], window: usize) -> Vec<f64> {
+    let before = (window.max(1) - 1) / 2;
+    let after = window.max(1) / 2;
+    (0..values.len())
+        .map(|i| {
+            let range = &values[i.saturating_sub(before)..(i + after + 1).min(values.len())];
+            range.iter().sum::<f64>() / range.len() as f64
+        })
+        .collect()
+}
+
+fn exponential(values: &[f64], alpha: f64) -> Vec<f64> {
+    let mut smoothed = Vec::with_capacity(values.len());
+    let mut last: Option<f64> = None;
+    for &value in values {
+        let next = last.map_or(value, |last| last + alpha * (value - last));
+        smoothed.push(next);
+        last = Some(next);
+    }
+    smoothed
+}
+
+/// Least-squares polynomial fit over the window around each sample, using the
+/// actual sample times so irregular logs are handled too; windows are shifted
+/// inwards at the ends of a stretch
+///
+/// Time offsets are measured in window spans rather than seconds, so the
+/// normal equations stay well conditioned at any sample rate.
+fn savitzky_golay(times: &[f64], values: &[f64], window: usize, order: usize) -> Vec<f64> {
+    let window = window.min(values.len() - (values.len() + 1) % 2);
+    let order = order.min(window.saturating_sub(1));
+    if window < 3 {
+        return values.to_vec();
+    }
+
+    (0..values.len())
+        .map(|i| {
+            let start = i.saturating_sub(window / 2).min(values.len() - window);
+            let span = times[start + window - 1] - times[start];
+            if span <= 0.0 {
+                return values[i];
+            }
+            let samples = (start..start + window).map(|j| ((times[j] - times[i]) / span, values[j]));
+            polynomial_at_zero(samples, order).unwrap_or(values[i])
+        })
+        .collect()
+}
+
+/// Value at x = 0 of the least-squares polynomial of `order` through the samples
+fn polynomial_at_zero(samples: impl Iterator<Item = (f64, f64)>, order: usize) -> Option<f64> {
+    let n = order + 1;
+    // Normal equations, augmented with the right-hand side
+    let mut matrix = vec![vec![0.0; n + 1]; n];
+    for (x, y) in samples {
+        let powers: Vec<f64> = (0..n).map(|k| x.powi(k as i32)).collect();
+        for row in 0..n {
+            for col in 0..n {
+                matrix[row][col] += powers[row] * powers[col];
+            }
+            matrix[row][n] += powers[row] * y;
+        }
+    }
+
+    // Gaussian elimination with partial pivoting
+    for col in 0..n {
+        let pivot = (col..n).max_by(|&a, &b| matrix[a][col].abs().total_cmp(&matrix[b][col].abs()))?;
+        if matrix[pivot][col].abs() < 1e-12 {
+            return None;
+        }
+        matrix.swap(col, pivot);
+        for row in col + 1..n {
+            let factor = matrix[row][col] / matrix[col][col];
+            let (above, below) = matrix.split_at_mut(row);
+            for (target, source) in below[0][col..].iter_mut().zip(&above[col][col..]) {
+                *target -= factor * source;
+            }
+        }
+    }
+    let mut coefficients = vec![0.0; n];
+    for row in (0..n).rev() {
+        let known: f64 = (row + 1..n).map(|k| matrix[row][k] * coefficients[k]).sum();
+        coefficients[row] = (matrix[row][n] - known) / matrix[row][row];
+    }
+    Some(coefficients[0])
+}
+
+/// Forward-backward second-order Butterworth low-pass at the stretch's
+/// median sample rate
+fn butterworth(times: &[f64], values: &[f64], cutoff_hz: f64) -> Vec<f64> {
+    let mut intervals: Vec<f64> = times.windows(2).map(|w| w[1] - w[0]).filter(|dt| *dt > 0.0).collect();
+    if intervals.is_empty() {
+        return values.to_vec();
+    }
+    intervals.sort_by(f64::total_cmp);
+    let sample_rate = 1.0 / intervals[intervals.len() / 2];
+    // Nothing to remove below the Nyquist frequency
+    if cutoff_hz >= sample_rate / 2.0 {
+        return values.to_vec();
+    }
+
+    let w0 = 2.0 * PI * cutoff_hz / sample_rate;
+    let alpha = w0.sin() / 2.0_f64.sqrt();
+    let a0 = 1.0 + alpha;
+    let b0 = (1.0 - w0.cos()) / 2.0 / a0;
+    let b1 = (1.0 - w0.cos()) / a0;
+    let a1 = -2.0 * w0.cos() / a0;
+    let a2 = (1.0 - alpha) / a0;
+
+    // Direct form II transposed, starting in the steady state of the first value
+    let pass = |values: &mut dyn Iterator<Item = f64>| -> Vec<f64> {
+        let mut values = values.peekable();
+        let first = values.peek().copied().unwrap_or_default();
+        let mut z2 = (b0 - a2) * first;
+        let mut z1 = (b1 - a1) * first + z2;
+        values
+            .map(|x| {
+                let y = b0 * x + z1;
+                z1 = b1 * x - a1 * y + z2;
+                z2 = b0 * x - a2 * y;
+                y
+            })
+            .collect()
+    };
+    let forward = pass(&mut values.iter().copied());
+    let mut backward = pass(&mut forward.iter().rev().copied());
+    backward.reverse();
+    backward
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+    use crate::{telemetry::Segment, testing};
+
+    /// Speeds sampled at 10 Hz
+    fn telemetry(speeds: &[Option<f64>]) -> TelemetryData {
+        testing::track(0.1, speeds.iter().map(|speed| TelemetryPoint { speed: *speed, ..Default::default() }))
+    }
+
+    fn speeds(telemetry: &TelemetryData) -> Vec<Option<f64>> {
+        telemetry.points.iter().map(|p| p.speed).collect()
+    }
+
+    #[test]
+    fn test_filter_parsing() {
+        assert_eq!("sg:7".parse::<Filter>().unwrap(), Filter::SavitzkyGolay { window: 7, order: 2 });
+        assert_eq!("sg:9:3".parse::<Filter>().unwrap(), Filter::SavitzkyGolay { window: 9, order: 3 });
+        assert_eq!("ma:5".parse::<Filter>().unwrap(), Filter::MovingAverage(5));
+        assert_eq!("ema:0.2".parse::<Filter>().unwrap(), Filter::Exponential(0.2));
+        assert_eq!("butter:0.5".parse::<Filter>().unwrap(), Filter::Butterworth { cutoff_hz: 0.5 });
+        assert!("sg:6".parse::<Filter>().is_err());
+        assert!("ema:2".parse::<Filter>().is_err());
+        assert!("ma:2.5".parse::<Filter>().is_err());
+        assert!("median:5".parse::<Filter>().is_err());
+
+        let smoothing: Smoothing = "speed=sg:7".parse().unwrap();
+        assert_eq!(smoothing.name, "speed");
+        assert!("speed".parse::<Smoothing>().is_err());
+    }
+
+    #[test]
+    fn test_savitzky_golay_keeps_polynomials() {
+        // A parabola is reproduced exactly, including at the ends
+        let values: Vec<Option<f64>> = (0..10).map(|i| Some((i * i) as f64)).collect();
+        let mut data = telemetry(&values);
+        data.smooth("speed", &Filter::SavitzkyGolay { window: 5, order: 2 }).unwrap();
+        for (smoothed, original) in speeds(&data).iter().zip(&values) {
+            assert!((smoothed.unwrap() - original.unwrap()).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn test_savitzky_golay_at_high_sample_rates() {
+        let noisy: Vec<Option<f64>> = (0..50).map(|i| Some(10.0 + if i % 2 == 0 { 1.0 } else { -1.0 })).collect();
+        for (interval_us, filter) in [(1_000, "sg:9:3"), (10_000, "sg:7:4")] {
+            let mut data = telemetry(&noisy);
+            let start = data.points[0].timestamp;
+            for (i, point) in data.points.iter_mut().enumerate() {
+                point.timestamp = start + Duration::microseconds(interval_us * i as i64);
+            }
+            data.calculate_metadata();
+            data.smooth("speed", &filter.parse().unwrap()).unwrap();
+            let middle = data.points[25].speed.unwrap();
+            assert!((middle - 10.0).abs() < 0.6, "{} gave {}", filter, middle);
+        }
+    }
+
+    #[test]
+    fn test_smoothing_removes_noise() {
+        let noisy: Vec<Option<f64>> = (0..50).map(|i| Some(10.0 + if i % 2 == 0 { 1.0 } else { -1.0 })).collect();
+        for filter in ["ma:4", "ema:0.5", "sg:7", "butter:1"] {
+            let mut data = telemetry(&noisy);
+            data.smooth("speed", &filter.parse().unwrap()).unwrap();
+            let middle = data.points[25].speed.unwrap();
+            assert!((middle - 10.0).abs() < 0.6, "{} gave {}", filter, middle);
+        }
+    }
+
+    #[test]
+    fn test_smoothing_is_gap_aware() {
+        let mut data = telemetry(&[Some(0.0), Some(0.0), Some(0.0), None, Some(10.0), Some(10.0), Some(10.0), Some(20.0), Some(20.0)]);
+        data.segments = vec![
+            Segment { track: None, start_time: data.points[0].timestamp, end_time: data.points[6].timestamp },
+            Segment { track: None, start_time: data.points[7].timestamp, end_time: data.points[8].timestamp },
+        ];
+        data.smooth("speed", &Filter::MovingAverage(3)).unwrap();
+        assert_eq!(
+            speeds(&data),
+            vec![Some(0.0), Some(0.0), Some(0.0), None, Some(10.0), Some(10.0), Some(10.0), Some(20.0), Some(20.0)]
+        );
+    }
+
+    #[test]
+    fn test_smoothing_headings_and_channels() {
+        let mut data = telemetry(&[None, None, None]);
+        for (point, heading) in data.points.iter_mut().zip([358.0, 0.0, 2.0]) {
+            point.heading = Some(heading);
+            point.set_channel_with_unit("boost", heading / 100.0, Some("bar"));
+        }
+        data.smooth("heading", &Filter::MovingAverage(3)).unwrap();
+        assert!(data.points[1].heading.unwrap() < 1e-9);
+
+        data.smooth("boost", &Filter::MovingAverage(3)).unwrap();
+        assert_eq!(data.points[1].channels["boost"].unit.as_deref(), Some("bar"));
+        assert!((data.points[1].channel("boost").unwrap() - 1.2).abs() < 1e-9);
+    }
+}
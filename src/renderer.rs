@@ -6,6 +6,8 @@ pub struct OverlayRenderer {
     width: u32,
     height: u32,
     style: String,
+    /// Named channels listed below the built-in displays
+    channels: Vec<String>,
     // font: Font<'static>,
 }
 
@@ -20,10 +22,17 @@ impl OverlayRenderer {
             width,
             height,
             style,
+            channels: Vec::new(),
             // font,
         })
     }
     
+    /// Display the given named channels, in order, when a point has them
+    pub fn with_channels(mut self, channels: Vec<String>) -> Self {
+        self.channels = channels;
+        self
+    }
+    
     pub fn render_frame(&self, point: &TelemetryPoint, frame_number: u32) -> RgbaImage {
         let mut image = RgbaImage::new(self.width, self.height);
         
@@ -37,9 +46,9 @@ impl OverlayRenderer {
             self.render_speed_display(&mut image, speed);
         }
         
-        // Render g-force indicator
-        if let (Some(gx), Some(gy), Some(gz)) = (point.g_force_x, point.g_force_y, point.g_force_z) {
-            self.render_g_force_indicator(&mut image, gx, gy, gz);
+        // Render g-force indicator; derived g-forces have no vertical component
+        if let (Some(gx), Some(gy)) = (point.g_force_x, point.g_force_y) {
+            self.render_g_force_indicator(&mut image, gx, gy, point.g_force_z.unwrap_or(0.0));
         }
         
         // Render GPS coordinates
@@ -52,6 +61,9 @@ impl OverlayRenderer {
             self.render_altitude_display(&mut image, altitude);
         }
         
+        // Render named channels
+        self.render_channels(&mut image, point);
+        
         // Render timestamp
         self.render_timestamp(&mut image, &point.timestamp);
         
@@ -92,6 +104,15 @@ impl OverlayRenderer {
         self.draw_simple_text(image, &text, 50, 200, Rgba([255, 255, 255, 255]));
     }
     
+    fn render_channels(&self, image: &mut RgbaImage, point: &TelemetryPoint) {
+        for (row, name) in self.channels.iter().enumerate() {
+            if let Some(channel) = point.channels.get(name) {
+                let text = format!("{}: {}", name, channel);
+                self.draw_simple_text(image, &text, 50, 250 + row as i32 * 50, Rgba([255, 255, 255, 255]));
+            }
+        }
+    }
+    
     fn render_timestamp(&self, image: &mut RgbaImage, timestamp: &chrono::DateTime<chrono::Utc>) {
         let text = timestamp.format("%H:%M:%S").to_string();
         self.draw_simple_text(image, &text, self.width as i32 - 150, 50, Rgba([150, 150, 150, 255]));
@@ -1,8 +1,8 @@
 use serde::{Deserialize, Serialize};
 use chrono::{DateTime, Utc};
 use crate::error::OverlogError;
-use std::io::Cursor;
-use time::OffsetDateTime;
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Range;
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct TelemetryPoint {
@@ -20,12 +20,278 @@ pub struct TelemetryPoint {
     pub throttle: Option<f64>,
     pub brake: Option<f64>,
     pub steering: Option<f64>,
+    /// Additional sensor channels keyed by name (see [`channel`] for common names)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub channels: BTreeMap<String, Channel>,
+    /// Names of the fields and channels that were computed rather than
+    /// measured, see [`TelemetryData::derive_channels`]
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub derived: BTreeSet<String>,
+}
+
+/// A named channel sample with an optional unit
+///
+/// Serialized as `{"value": 142, "unit": "bpm"}`, or as the bare value when
+/// there is no unit.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(from = "ChannelRepr", into = "ChannelRepr")]
+pub struct Channel {
+    pub value: ChannelValue,
+    pub unit: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ChannelValue {
+    Number(f64),
+    Text(String),
+}
+
+impl ChannelValue {
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            ChannelValue::Number(n) => Some(*n),
+            ChannelValue::Text(_) => None,
+        }
+    }
+}
+
+impl From<f64> for ChannelValue {
+    fn from(value: f64) -> Self {
+        ChannelValue::Number(value)
+    }
+}
+
+impl From<String> for ChannelValue {
+    fn from(value: String) -> Self {
+        ChannelValue::Text(value)
+    }
+}
+
+impl From<&str> for ChannelValue {
+    fn from(value: &str) -> Self {
+        ChannelValue::Text(value.to_string())
+    }
+}
+
+impl std::fmt::Display for Channel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.value {
+            ChannelValue::Number(n) if n.fract() == 0.0 && n.abs() < 1e15 => write!(f, "{:.0}", n)?,
+            ChannelValue::Number(n) => write!(f, "{:.2}", n)?,
+            ChannelValue::Text(text) => write!(f, "{}", text)?,
+        }
+        match &self.unit {
+            Some(unit) => write!(f, " {}", unit),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum ChannelRepr {
+    Full { value: ChannelValue, unit: Option<String> },
+    Bare(ChannelValue),
+}
+
+impl From<ChannelRepr> for Channel {
+    fn from(repr: ChannelRepr) -> Self {
+        match repr {
+            ChannelRepr::Full { value, unit } => Channel { value, unit },
+            ChannelRepr::Bare(value) => Channel { value, unit: None },
+        }
+    }
+}
+
+impl From<Channel> for ChannelRepr {
+    fn from(channel: Channel) -> Self {
+        match channel.unit {
+            Some(unit) => ChannelRepr::Full { value: channel.value, unit: Some(unit) },
+            None => ChannelRepr::Bare(channel.value),
+        }
+    }
+}
+
+/// Well-known names for entries in `TelemetryPoint::channels`
+pub mod channel {
+    pub const DISTANCE: &str = "distance";
+    pub const LAP: &str = "lap";
+    pub const HEART_RATE: &str = "heart_rate";
+    pub const CADENCE: &str = "cadence";
+    pub const POWER: &str = "power";
+    pub const TEMPERATURE: &str = "temperature";
+    pub const WATER_TEMPERATURE: &str = "water_temperature";
+    pub const SPEED_3D: &str = "speed_3d";
+    pub const GPS_FIX: &str = "gps_fix";
+    pub const GPS_DOP: &str = "gps_dop";
+    pub const GPS_HDOP: &str = "gps_hdop";
+    pub const GPS_VDOP: &str = "gps_vdop";
+    pub const GPS_QUALITY: &str = "gps_quality";
+    pub const SATELLITES: &str = "satellites";
+    pub const SATELLITES_IN_VIEW: &str = "satellites_in_view";
+    pub const RELATIVE_ALTITUDE: &str = "relative_altitude";
+    pub const ISO: &str = "iso";
+    pub const EXPOSURE_TIME: &str = "exposure_time";
+    pub const EXPOSURE_BIAS: &str = "exposure_bias";
+    pub const F_NUMBER: &str = "f_number";
+    pub const FOCAL_LENGTH: &str = "focal_length";
+    pub const COLOR_TEMPERATURE: &str = "color_temperature";
+    pub const GYRO_X: &str = "gyro_x";
+    pub const GYRO_Y: &str = "gyro_y";
+    pub const GYRO_Z: &str = "gyro_z";
+    pub const GRAVITY_X: &str = "gravity_x";
+    pub const GRAVITY_Y: &str = "gravity_y";
+    pub const GRAVITY_Z: &str = "gravity_z";
+    pub const ORIENTATION_W: &str = "orientation_w";
+    pub const ORIENTATION_X: &str = "orientation_x";
+    pub const ORIENTATION_Y: &str = "orientation_y";
+    pub const ORIENTATION_Z: &str = "orientation_z";
+    pub const ROLL: &str = "roll";
+    pub const PITCH: &str = "pitch";
+    pub const YAW: &str = "yaw";
+    pub const BARO_ALTITUDE: &str = "baro_altitude";
+    pub const PRESSURE: &str = "pressure";
+    pub const BATTERY_VOLTAGE: &str = "battery_voltage";
+    pub const BATTERY_CURRENT: &str = "battery_current";
+    pub const BATTERY_CONSUMED: &str = "battery_consumed";
+    pub const FLIGHT_MODE: &str = "flight_mode";
+    pub const VERTICAL_SPEED: &str = "vertical_speed";
+    pub const GRADE: &str = "grade";
+    pub const LAP_TIME: &str = "lap_time";
+    pub const LAP_DISTANCE: &str = "lap_distance";
+    pub const SECTOR: &str = "sector";
+    pub const GEAR: &str = "gear";
+
+    /// Unit of a well-known channel
+    pub fn unit_of(name: &str) -> Option<&'static str> {
+        let unit = match name {
+            DISTANCE | RELATIVE_ALTITUDE | BARO_ALTITUDE | LAP_DISTANCE => "m",
+            HEART_RATE => "bpm",
+            CADENCE => "rpm",
+            POWER => "W",
+            TEMPERATURE | WATER_TEMPERATURE => "°C",
+            SPEED_3D | VERTICAL_SPEED => "m/s",
+            GRADE => "%",
+            EXPOSURE_TIME | LAP_TIME => "s",
+            FOCAL_LENGTH => "mm",
+            COLOR_TEMPERATURE => "K",
+            GYRO_X | GYRO_Y | GYRO_Z => "rad/s",
+            GRAVITY_X | GRAVITY_Y | GRAVITY_Z => "g",
+            ROLL | PITCH | YAW => "deg",
+            PRESSURE => "Pa",
+            BATTERY_VOLTAGE => "V",
+            BATTERY_CURRENT => "A",
+            BATTERY_CONSUMED => "mAh",
+            _ => return None,
+        };
+        Some(unit)
+    }
+
+    /// Name of the 1-based RC input channel `n`
+    pub fn rc_in(n: usize) -> String {
+        format!("rc_in_{}", n)
+    }
+
+    /// Name of the 1-based RC/servo output channel `n`
+    pub fn rc_out(n: usize) -> String {
+        format!("rc_out_{}", n)
+    }
+}
+
+impl TelemetryPoint {
+    /// Get the numeric value of a named channel
+    pub fn channel(&self, name: &str) -> Option<f64> {
+        self.channels.get(name).and_then(|c| c.value.as_f64())
+    }
+
+    /// Set a numeric channel, with the unit of well-known channels
+    pub fn set_channel(&mut self, name: &str, value: f64) {
+        self.set_channel_with_unit(name, value, channel::unit_of(name));
+    }
+
+    /// Set a numeric or text channel with an explicit unit
+    pub fn set_channel_with_unit(&mut self, name: &str, value: impl Into<ChannelValue>, unit: Option<&str>) {
+        let channel = Channel { value: value.into(), unit: unit.map(str::to_string) };
+        self.channels.insert(name.to_string(), channel);
+    }
+
+    /// Whether a field or channel was computed rather than measured
+    pub fn is_derived(&self, name: &str) -> bool {
+        self.derived.contains(name)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TelemetryData {
     pub points: Vec<TelemetryPoint>,
     pub metadata: TelemetryMetadata,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub laps: Vec<Lap>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub events: Vec<Event>,
+    /// Continuous runs of points; there is no data between two segments
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub segments: Vec<Segment>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub waypoints: Vec<Waypoint>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub routes: Vec<Route>,
+}
+
+/// A continuous run of points, such as a GPX `<trkseg>`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Segment {
+    /// Name of the track the segment belongs to
+    pub track: Option<String>,
+    pub start_time: DateTime<Utc>,
+    pub end_time: DateTime<Utc>,
+}
+
+/// A named point of interest, such as a GPX `<wpt>`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Waypoint {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    /// Display symbol name, e.g. `Flag, Blue`
+    pub symbol: Option<String>,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub altitude: Option<f64>,
+    pub timestamp: Option<DateTime<Utc>>,
+}
+
+/// A planned route, such as a GPX `<rte>`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Route {
+    pub name: Option<String>,
+    pub points: Vec<Waypoint>,
+}
+
+/// A lap as recorded by the source device
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Lap {
+    pub start_time: DateTime<Utc>,
+    pub end_time: Option<DateTime<Utc>>,
+    /// Lap duration in seconds as reported by the device
+    pub total_time: Option<f64>,
+    /// Lap distance in meters as reported by the device
+    pub distance: Option<f64>,
+}
+
+/// A discrete event recorded by the source device
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    TimerStart,
+    TimerStop,
+    Marker,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Event {
+    pub timestamp: DateTime<Utc>,
+    pub kind: EventKind,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -38,6 +304,111 @@ pub struct TelemetryMetadata {
     pub total_distance: Option<f64>,
     pub max_speed: Option<f64>,
     pub max_g_force: Option<f64>,
+    /// Unit and numeric range of every channel present in the points
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub channels: BTreeMap<String, ChannelSummary>,
+    /// Times that were not read from the log as absolute times
+    #[serde(default, skip_serializing_if = "TimeReport::is_absolute")]
+    pub times: TimeReport,
+    /// Values changed by outlier rejection
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub outliers: Vec<Outlier>,
+    /// Laps timed at a start/finish line
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub lap_times: Vec<LapTime>,
+    /// Number of the fastest timed lap
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub best_lap: Option<u32>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ChannelSummary {
+    pub unit: Option<String>,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+}
+
+/// How point times were obtained when the log had no absolute times,
+/// see [`TimestampPolicy`](crate::formats::timing::TimestampPolicy)
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TimeReport {
+    /// The log only counts time from its start, which was placed at `start`
+    /// or, when no start was given, at the Unix epoch
+    #[serde(default)]
+    pub relative: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start: Option<DateTime<Utc>>,
+    /// Runs of consecutive points whose times were synthesized
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub synthesized: Vec<SynthesizedTimes>,
+}
+
+impl TimeReport {
+    /// Whether every time was read from the log as an absolute time
+    pub fn is_absolute(&self) -> bool {
+        !self.relative && self.synthesized.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SynthesizedTimes {
+    pub first: DateTime<Utc>,
+    pub last: DateTime<Utc>,
+    pub count: usize,
+}
+
+/// A position or value rejected as an outlier,
+/// see [`OutlierRules`](crate::processing::outliers::OutlierRules)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Outlier {
+    pub timestamp: DateTime<Utc>,
+    /// `position` or the name of the field or channel
+    pub name: String,
+    pub reason: OutlierReason,
+    /// What broke the rule: the implied speed (m/s) or acceleration (m/s²),
+    /// the HDOP, the satellite count, the measured speed (m/s) or the
+    /// distance from the local median
+    pub measure: f64,
+    pub action: OutlierAction,
+}
+
+/// A lap between two crossings of the start/finish line,
+/// see [`LapGates`](crate::processing::laps::LapGates)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LapTime {
+    /// Lap number from 1; lap 0 leads up to the first crossing
+    pub number: u32,
+    pub start_time: DateTime<Utc>,
+    pub end_time: DateTime<Utc>,
+    /// Lap time in seconds
+    pub time: f64,
+    /// Sector times in seconds; only the sectors timed before a missed
+    /// sector line
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sectors: Vec<f64>,
+    /// Distance driven in meters
+    pub distance: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutlierReason {
+    Speed,
+    Acceleration,
+    Hdop,
+    Satellites,
+    Hampel,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutlierAction {
+    /// The point was dropped
+    Removed,
+    /// The value was interpolated from its neighbours
+    Interpolated,
+    /// The value was dropped and the point kept
+    Cleared,
 }
 
 impl TelemetryData {
@@ -53,74 +424,24 @@ impl TelemetryData {
                 total_distance: None,
                 max_speed: None,
                 max_g_force: None,
+                channels: BTreeMap::new(),
+                times: TimeReport::default(),
+                outliers: Vec::new(),
+                lap_times: Vec::new(),
+                best_lap: None,
             },
+            laps: Vec::new(),
+            events: Vec::new(),
+            segments: Vec::new(),
+            waypoints: Vec::new(),
+            routes: Vec::new(),
         }
     }
     
-    pub fn from_gpx(gpx_data: &str) -> Result<Self, OverlogError> {
-        let cursor = Cursor::new(gpx_data.as_bytes());
-        let gpx = gpx::read(cursor)?;
-        let mut telemetry = TelemetryData::new();
-        telemetry.metadata.format = "gpx".to_string();
-        
-        for track in gpx.tracks {
-            for segment in track.segments {
-                for point in segment.points {
-                    let timestamp = if let Some(time) = point.time {
-                        // Convert gpx::Time (which wraps time::OffsetDateTime) to chrono::DateTime<Utc>
-                        let offset_datetime: OffsetDateTime = time.into();
-                        let unix_timestamp = offset_datetime.unix_timestamp();
-                        let naive = chrono::DateTime::from_timestamp(unix_timestamp, 0)
-                            .unwrap_or_else(|| chrono::Utc::now())
-                            .naive_utc();
-                        chrono::DateTime::<Utc>::from_naive_utc_and_offset(naive, Utc)
-                    } else {
-                        Utc::now()
-                    };
-                    
-                    let mut tp = TelemetryPoint {
-                        timestamp,
-                        latitude: Some(point.point().y()),
-                        longitude: Some(point.point().x()),
-                        altitude: point.elevation,
-                        speed: None,
-                        heading: None,
-                        g_force_x: None,
-                        g_force_y: None,
-                        g_force_z: None,
-                        acceleration: None,
-                        rpm: None,
-                        throttle: None,
-                        brake: None,
-                        steering: None,
-                    };
-                    
-                    // Extract speed from extensions if available
-                    // Note: The new gpx API may have different extension handling
-                    // This is a simplified version - you may need to adjust based on actual GPX structure
-                    
-                    telemetry.points.push(tp);
-                }
-            }
-        }
-        
-        telemetry.calculate_metadata();
-        Ok(telemetry)
-    }
-    
+    /// Parse a CSV with an RFC 3339 `timestamp` column and columns named after
+    /// the point fields; any other column is kept as a channel
     pub fn from_csv(csv_data: &str) -> Result<Self, OverlogError> {
-        let mut telemetry = TelemetryData::new();
-        telemetry.metadata.format = "csv".to_string();
-        
-        let mut reader = csv::Reader::from_reader(csv_data.as_bytes());
-        
-        for result in reader.deserialize() {
-            let point: TelemetryPoint = result?;
-            telemetry.points.push(point);
-        }
-        
-        telemetry.calculate_metadata();
-        Ok(telemetry)
+        Self::from_csv_with_mapping(csv_data, &crate::formats::csv_mapping::CsvMapping::default())
     }
     
     pub fn from_json(json_data: &str) -> Result<Self, OverlogError> {
@@ -139,7 +460,7 @@ impl TelemetryData {
         self.metadata.end_time = Some(self.points.last().unwrap().timestamp);
         
         if let (Some(start), Some(end)) = (self.metadata.start_time, self.metadata.end_time) {
-            self.metadata.duration = Some((end - start).num_seconds() as f64);
+            self.metadata.duration = Some(crate::utils::seconds_between(start, end));
         }
         
         // Calculate max speed
@@ -152,7 +473,9 @@ impl TelemetryData {
         let max_g = self.points
             .iter()
             .filter_map(|p| {
-                if let (Some(x), Some(y), Some(z)) = (p.g_force_x, p.g_force_y, p.g_force_z) {
+                // Derived g-forces have no vertical component
+                if let (Some(x), Some(y)) = (p.g_force_x, p.g_force_y) {
+                    let z = p.g_force_z.unwrap_or(0.0);
                     Some((x * x + y * y + z * z).sqrt())
                 } else {
                     None
@@ -164,6 +487,25 @@ impl TelemetryData {
         
         // Calculate total distance
         self.metadata.total_distance = self.calculate_total_distance();
+        
+        self.metadata.channels = self.summarize_channels();
+    }
+    
+    fn summarize_channels(&self) -> BTreeMap<String, ChannelSummary> {
+        let mut summaries: BTreeMap<String, ChannelSummary> = BTreeMap::new();
+        
+        for (name, channel) in self.points.iter().flat_map(|p| &p.channels) {
+            let summary = summaries.entry(name.clone()).or_default();
+            if summary.unit.is_none() {
+                summary.unit = channel.unit.clone();
+            }
+            if let Some(value) = channel.value.as_f64() {
+                summary.min = Some(summary.min.map_or(value, |min| min.min(value)));
+                summary.max = Some(summary.max.map_or(value, |max| max.max(value)));
+            }
+        }
+        
+        summaries
     }
     
     fn calculate_total_distance(&self) -> Option<f64> {
@@ -185,59 +527,36 @@ impl TelemetryData {
         Some(total_distance)
     }
     
+    /// Whether a new segment starts after `t1`, up to and including `t2`
+    pub fn is_segment_break(&self, t1: DateTime<Utc>, t2: DateTime<Utc>) -> bool {
+        self.segments.iter().any(|s| s.start_time > t1 && s.start_time <= t2)
+    }
+    
+    /// Index ranges of the continuous runs of points, split at segment breaks
+    pub fn runs(&self) -> Vec<Range<usize>> {
+        let mut runs = Vec::new();
+        let mut start = 0;
+        for i in 1..=self.points.len() {
+            if i == self.points.len() || self.is_segment_break(self.points[i - 1].timestamp, self.points[i].timestamp) {
+                runs.push(start..i);
+                start = i;
+            }
+        }
+        runs
+    }
+    
     pub fn get_point_at_time(&self, timestamp: DateTime<Utc>) -> Option<&TelemetryPoint> {
         self.points.binary_search_by(|point| point.timestamp.cmp(&timestamp))
             .ok()
             .map(|index| &self.points[index])
     }
     
+    /// Point at `timestamp`, interpolated with the default policy: headings
+    /// the short way round, positions along the great circle, other numbers
+    /// linearly and text from the nearest sample; the last point is held
+    /// through a segment break
     pub fn interpolate_at_time(&self, timestamp: DateTime<Utc>) -> Option<TelemetryPoint> {
-        // Find the two points that bracket the timestamp
-        let index = self.points.binary_search_by(|point| point.timestamp.cmp(&timestamp));
-        
-        match index {
-            Ok(i) => Some(self.points[i].clone()),
-            Err(i) => {
-                if i == 0 || i >= self.points.len() {
-                    None
-                } else {
-                    // Interpolate between points[i-1] and points[i]
-                    let p1 = &self.points[i - 1];
-                    let p2 = &self.points[i];
-                    
-                    let t1 = p1.timestamp.timestamp_millis() as f64;
-                    let t2 = p2.timestamp.timestamp_millis() as f64;
-                    let t = timestamp.timestamp_millis() as f64;
-                    
-                    let ratio = (t - t1) / (t2 - t1);
-                    
-                    Some(TelemetryPoint {
-                        timestamp,
-                        latitude: interpolate_option(p1.latitude, p2.latitude, ratio),
-                        longitude: interpolate_option(p1.longitude, p2.longitude, ratio),
-                        altitude: interpolate_option(p1.altitude, p2.altitude, ratio),
-                        speed: interpolate_option(p1.speed, p2.speed, ratio),
-                        heading: interpolate_option(p1.heading, p2.heading, ratio),
-                        g_force_x: interpolate_option(p1.g_force_x, p2.g_force_x, ratio),
-                        g_force_y: interpolate_option(p1.g_force_y, p2.g_force_y, ratio),
-                        g_force_z: interpolate_option(p1.g_force_z, p2.g_force_z, ratio),
-                        acceleration: interpolate_option(p1.acceleration, p2.acceleration, ratio),
-                        rpm: interpolate_option(p1.rpm, p2.rpm, ratio),
-                        throttle: interpolate_option(p1.throttle, p2.throttle, ratio),
-                        brake: interpolate_option(p1.brake, p2.brake, ratio),
-                        steering: interpolate_option(p1.steering, p2.steering, ratio),
-                    })
-                }
-            }
-        }
+        self.interpolate_at_time_with(timestamp, &crate::interpolation::InterpolationPolicy::default())
     }
 }
 
-fn interpolate_option(a: Option<f64>, b: Option<f64>, ratio: f64) -> Option<f64> {
-    match (a, b) {
-        (Some(a_val), Some(b_val)) => Some(a_val + (b_val - a_val) * ratio),
-        (Some(a_val), None) => Some(a_val),
-        (None, Some(b_val)) => Some(b_val),
-        (None, None) => None,
-    }
-} 
\ No newline at end of file
@@ -0,0 +1,29 @@
+//! Logs shared by the unit tests
+
+use chrono::{DateTime, Utc};
+use crate::{
+    telemetry::{TelemetryData, TelemetryPoint},
+    utils,
+};
+
+/// Degrees of latitude in a meter
+pub(crate) const DEGREES_PER_METER: f64 = 1.0 / 111_195.0;
+
+/// Time `seconds` after the start of the test logs, 2024-01-15T10:00:00Z
+pub(crate) fn at(seconds: f64) -> DateTime<Utc> {
+    let start: DateTime<Utc> = "2024-01-15T10:00:00Z".parse().unwrap();
+    utils::add_seconds(start, seconds)
+}
+
+/// A log of `points` with their own times
+pub(crate) fn log(points: impl IntoIterator<Item = TelemetryPoint>) -> TelemetryData {
+    let mut telemetry = TelemetryData::new();
+    telemetry.points = points.into_iter().collect();
+    telemetry.calculate_metadata();
+    telemetry
+}
+
+/// A log of `points` placed `interval` seconds apart from [`at(0.0)`](at)
+pub(crate) fn track(interval: f64, points: impl IntoIterator<Item = TelemetryPoint>) -> TelemetryData {
+    log(points.into_iter().enumerate().map(|(i, point)| TelemetryPoint { timestamp: at(interval * i as f64), ..point }))
+}
@@ -58,20 +58,36 @@ pub fn is_valid_video_format(extension: &str) -> bool {
 
 /// Validate telemetry file format
 pub fn is_valid_telemetry_format(extension: &str) -> bool {
-    matches!(extension, "gpx" | "csv" | "json" | "tcx" | "bin")
+    matches!(extension, "gpx" | "csv" | "json" | "tcx" | "bin" | "mp4" | "fit" | "nmea" | "log" | "srt" | "ulg" | "vbo" | "geojson" | "kml" | "kmz")
+}
+
+/// Seconds from `start` to `end`, with sub-second precision
+pub fn seconds_between(start: DateTime<Utc>, end: DateTime<Utc>) -> f64 {
+    let duration = end.signed_duration_since(start);
+    match duration.num_nanoseconds() {
+        Some(nanos) => nanos as f64 / 1e9,
+        None => duration.num_milliseconds() as f64 / 1000.0,
+    }
+}
+
+/// Offset a timestamp by a fractional number of seconds, to the nearest nanosecond
+pub fn add_seconds(time: DateTime<Utc>, seconds: f64) -> DateTime<Utc> {
+    time + chrono::Duration::nanoseconds((seconds * 1e9).round() as i64)
 }
 
 /// Calculate frame number from timestamp
+///
+/// The frame is the one being shown at `timestamp`; a timestamp produced by
+/// [`frame_to_timestamp`] maps back to the same frame.
 pub fn timestamp_to_frame(timestamp: DateTime<Utc>, start_time: DateTime<Utc>, fps: f64) -> u32 {
-    let duration = timestamp.signed_duration_since(start_time);
-    let seconds = duration.num_milliseconds() as f64 / 1000.0;
-    (seconds * fps) as u32
+    let frames = seconds_between(start_time, timestamp) * fps;
+    // Absorb the nanosecond rounding of frame_to_timestamp
+    (frames + 1e-6).floor() as u32
 }
 
 /// Calculate timestamp from frame number
 pub fn frame_to_timestamp(frame: u32, start_time: DateTime<Utc>, fps: f64) -> DateTime<Utc> {
-    let seconds = frame as f64 / fps;
-    start_time + chrono::Duration::milliseconds((seconds * 1000.0) as i64)
+    add_seconds(start_time, frame as f64 / fps)
 }
 
 /// Clamp a value between min and max
@@ -172,4 +188,21 @@ mod tests {
         let diff = timestamp.signed_duration_since(start_time).num_milliseconds();
         assert!((diff - 1000).abs() < 50); // Allow small rounding errors
     }
+
+    #[test]
+    fn test_sub_second_timestamps() {
+        let start_time = Utc::now();
+
+        // 10 Hz samples land on distinct frames at 30 fps
+        assert_eq!(timestamp_to_frame(start_time + Duration::milliseconds(100), start_time, 30.0), 3);
+        assert_eq!(timestamp_to_frame(start_time + Duration::milliseconds(199), start_time, 30.0), 5);
+        assert_eq!(seconds_between(start_time, start_time + Duration::milliseconds(1250)), 1.25);
+
+        for frame in 0..1000 {
+            let timestamp = frame_to_timestamp(frame, start_time, 29.97);
+            assert_eq!(timestamp_to_frame(timestamp, start_time, 29.97), frame);
+        }
+        let frame = frame_to_timestamp(1, start_time, 30.0);
+        assert_eq!(frame.signed_duration_since(start_time).num_microseconds(), Some(33_333));
+    }
 } 
\ No newline at end of file
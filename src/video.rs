@@ -1,6 +1,12 @@
 use std::process::Command;
 use std::path::Path;
-use crate::{telemetry::TelemetryData, renderer::OverlayRenderer, error::OverlogError};
+use crate::{
+    telemetry::TelemetryData,
+    renderer::OverlayRenderer,
+    error::OverlogError,
+    interpolation::InterpolationPolicy,
+    processing::slice::Slice,
+};
 
 pub struct VideoProcessor;
 
@@ -31,17 +37,29 @@ impl VideoProcessor {
         std::fs::create_dir_all(&temp_dir)?;
         
         let total_frames = (duration * fps as f64) as u32;
-        let frame_duration = duration / total_frames as f64;
+        let start_time = telemetry
+            .metadata
+            .start_time
+            .or_else(|| telemetry.points.first().map(|p| p.timestamp))
+            .ok_or_else(|| OverlogError::Telemetry("No telemetry points to render".to_string()))?;
+        
+        // One sample per frame of the rendered span, holding the last point
+        // through gaps
+        let end_time = crate::utils::add_seconds(start_time, duration);
+        let frames = telemetry
+            .slice(&Slice::Time { start: Some(start_time), end: Some(end_time) })?
+            .resample(fps as f64, &InterpolationPolicy::default())?;
+        let offset = frames
+            .points
+            .first()
+            .map_or(0, |first| crate::utils::timestamp_to_frame(first.timestamp, start_time, fps as f64) as usize);
         
         // Generate frames
         for frame_num in 0..total_frames {
-            let timestamp = if let Some(start_time) = telemetry.metadata.start_time {
-                start_time + chrono::Duration::milliseconds((frame_num as f64 * frame_duration * 1000.0) as i64)
-            } else {
-                chrono::Utc::now()
-            };
-            
-            let point = telemetry.interpolate_at_time(timestamp)
+            let point = (frame_num as usize)
+                .checked_sub(offset)
+                .and_then(|i| frames.points.get(i))
+                .cloned()
                 .unwrap_or_else(|| telemetry.points.first().cloned().unwrap_or_default());
             
             let frame = renderer.render_frame(&point, frame_num);
@@ -70,6 +70,78 @@ async fn test_csv_parsing() -> Result<(), OverlogError> {
     Ok(())
 }
 
+#[tokio::test]
+async fn test_channels_round_trip() -> Result<(), OverlogError> {
+    let csv_data = "timestamp,speed,boost,gear\n2024-01-15T10:00:00Z,10,1.0,N\n2024-01-15T10:00:02Z,20,2.0,R";
+    
+    let telemetry = TelemetryData::from_csv(csv_data)?;
+    assert_eq!(telemetry.points[0].channel("boost"), Some(1.0));
+    assert_eq!(telemetry.metadata.channels["boost"].max, Some(2.0));
+    
+    let json = serde_json::to_string(&telemetry)?;
+    let restored = TelemetryData::from_json(&json)?;
+    assert_eq!(restored.points[0].channels, telemetry.points[0].channels);
+    
+    let start = telemetry.points[0].timestamp;
+    let midpoint = restored.interpolate_at_time(start + chrono::Duration::seconds(1)).unwrap();
+    assert_eq!(midpoint.channel("boost"), Some(1.5));
+    assert_eq!(midpoint.channels["gear"].value, overlog::ChannelValue::from("R"));
+    
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_from_path_detects_format() -> Result<(), OverlogError> {
+    let dir = TempDir::new()?;
+    
+    // A GPX track saved with a misleading extension, and a CSV without one
+    let gpx_path = dir.path().join("track.txt");
+    fs::write(&gpx_path, r#"<?xml version="1.0"?><gpx version="1.1"><trk><trkseg>
+        <trkpt lat="40.7128" lon="-74.0060"><time>2024-01-15T10:00:00Z</time></trkpt>
+    </trkseg></trk></gpx>"#)?;
+    let telemetry = TelemetryData::from_path(&gpx_path)?;
+    assert_eq!(telemetry.metadata.format, "gpx");
+    assert_eq!(telemetry.points.len(), 1);
+    
+    let csv_path = dir.path().join("log");
+    fs::write(&csv_path, "timestamp,speed
+2024-01-15T10:00:00Z,10
+")?;
+    let telemetry = TelemetryData::from_path(&csv_path)?;
+    assert_eq!(telemetry.metadata.format, "csv");
+    
+    let unknown_path = dir.path().join("notes");
+    fs::write(&unknown_path, "nothing to see")?;
+    assert!(TelemetryData::from_path(&unknown_path).is_err());
+    
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_compressed_log_streaming() -> Result<(), OverlogError> {
+    use std::io::Write;
+    
+    let dir = TempDir::new()?;
+    let path = dir.path().join("imu.csv.gz");
+    let mut encoder = flate2::write::GzEncoder::new(fs::File::create(&path)?, flate2::Compression::fast());
+    writeln!(encoder, "timestamp,g_force_x")?;
+    for ms in 0..5000 {
+        writeln!(encoder, "2024-01-15T10:00:{:02}.{:03}Z,{}", ms / 1000, ms % 1000, ms as f64 / 5000.0)?;
+    }
+    encoder.finish()?;
+    
+    let mut points = TelemetryData::stream_path(&path)?;
+    assert_eq!(points.format(), "csv");
+    let first = points.next().unwrap()?;
+    assert_eq!(first.g_force_x, Some(0.0));
+    assert_eq!(points.count(), 4999);
+    
+    let telemetry = TelemetryData::from_path(&path)?;
+    assert_eq!(telemetry.points.len(), 5000);
+    
+    Ok(())
+}
+
 #[tokio::test]
 async fn test_renderer_creation() -> Result<(), OverlogError> {
     let renderer = OverlayRenderer::new(1920, 1080, "default".to_string())?;
@@ -100,6 +172,7 @@ async fn test_frame_rendering() -> Result<(), OverlogError> {
         throttle: Some(0.3),
         brake: Some(0.0),
         steering: Some(0.1),
+        ..Default::default()
     };
     
     let frame = renderer.render_frame(&point, 0);